@@ -0,0 +1,306 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Derive macros for the horaedb client.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, GenericArgument, LitStr,
+    PathArguments, Result, Token, Type,
+};
+
+/// The supported types of the tags and fields, and the variants of the `Value`
+/// they are converted into.
+const SUPPORTED_TYPES: [(&str, &str); 11] = [
+    ("bool", "Boolean"),
+    ("i8", "Int8"),
+    ("i16", "Int16"),
+    ("i32", "Int32"),
+    ("i64", "Int64"),
+    ("u8", "UInt8"),
+    ("u16", "UInt16"),
+    ("u32", "UInt32"),
+    ("u64", "UInt64"),
+    ("f32", "Float"),
+    ("f64", "Double"),
+];
+
+/// The column names reserved by horaedb.
+const RESERVED_NAMES: [&str; 2] = ["timestamp", "tsid"];
+
+/// Derive `IntoPoint` for a struct with named fields.
+///
+/// The table is set by `#[horaedb(table = "...")]` on the struct, and the
+/// fields of the struct are marked by:
+/// - `#[horaedb(timestamp)]` for the timestamp in milliseconds, which must be
+///   `i64`.
+/// - `#[horaedb(tag)]` for a tag, and the other ones are the fields.
+/// - `#[horaedb(field)]` for a field explicitly.
+/// - `#[horaedb(field = "...")]` for a field of the other column name.
+/// - `#[horaedb(rename = "...")]` for the column name other than the name of
+///   the struct field.
+///
+/// The tags and fields can be `bool`, the integers, `f32`, `f64`, `String`,
+/// `Vec<u8>`, or an `Option` of them which is skipped if it is `None`.
+#[proc_macro_derive(IntoPoint, attributes(horaedb))]
+pub fn derive_into_point(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_into_point(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// The options set by the `horaedb` attributes of a struct field.
+#[derive(Default)]
+struct FieldOptions {
+    timestamp: bool,
+    tag: bool,
+    field: bool,
+    rename: Option<String>,
+}
+
+fn parse_field_options(field: &syn::Field) -> Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("horaedb"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("timestamp") {
+                options.timestamp = true;
+            } else if meta.path.is_ident("tag") {
+                options.tag = true;
+            } else if meta.path.is_ident("field") {
+                options.field = true;
+                if meta.input.peek(Token![=]) {
+                    set_rename(&mut options, meta.value()?.parse::<LitStr>()?)?;
+                }
+            } else if meta.path.is_ident("rename") {
+                set_rename(&mut options, meta.value()?.parse::<LitStr>()?)?;
+            } else {
+                return Err(meta.error("expected `timestamp`, `tag`, `field` or `rename`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    if options.timestamp && options.tag {
+        return Err(Error::new_spanned(
+            field,
+            "the timestamp can't be a tag at the same time",
+        ));
+    }
+    if options.field && (options.timestamp || options.tag) {
+        return Err(Error::new_spanned(
+            field,
+            "the field can't be the timestamp or a tag at the same time",
+        ));
+    }
+    Ok(options)
+}
+
+/// The column name can be set by either `rename` or `field`, but only once.
+fn set_rename(options: &mut FieldOptions, name: LitStr) -> Result<()> {
+    if options.rename.is_some() {
+        return Err(Error::new_spanned(name, "the column name is set twice"));
+    }
+    options.rename = Some(name.value());
+    Ok(())
+}
+
+fn parse_table(input: &DeriveInput) -> Result<String> {
+    let mut table = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("horaedb"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `table`"))
+            }
+        })?;
+    }
+
+    table.ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "the table must be set by `#[horaedb(table = \"...\")]`",
+        )
+    })
+}
+
+/// The last segment of the type path, along with its only generic type
+/// argument if any.
+fn type_segment(ty: &Type) -> Option<(String, Option<&Type>)> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let segment = type_path.path.segments.last()?;
+    let arg = match &segment.arguments {
+        PathArguments::None => None,
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(arg) => Some(arg),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((segment.ident.to_string(), arg))
+}
+
+/// Convert the `value` of the type `ty` into the `Value`, and it is `None` if
+/// the type is unsupported.
+fn convert_value(ty: &Type, value: TokenStream2) -> Option<TokenStream2> {
+    let (ident, arg) = type_segment(ty)?;
+    let value_type = quote!(::horaedb_client::model::value::Value);
+    match (ident.as_str(), arg) {
+        ("String", None) => Some(quote!(#value_type::String(#value))),
+        ("Vec", Some(arg)) if matches!(type_segment(arg), Some((ident, None)) if ident == "u8") => {
+            Some(quote!(#value_type::Varbinary(#value)))
+        }
+        (ident, None) => SUPPORTED_TYPES
+            .iter()
+            .find(|(ty, _)| *ty == ident)
+            .map(|(_, variant)| {
+                let variant = syn::Ident::new(variant, proc_macro2::Span::call_site());
+                quote!(#value_type::#variant(#value))
+            }),
+        _ => None,
+    }
+}
+
+fn expand_into_point(input: DeriveInput) -> Result<TokenStream2> {
+    let table = parse_table(&input)?;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "`IntoPoint` can only be derived for the struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "`IntoPoint` can only be derived for the struct",
+            ))
+        }
+    };
+
+    let mut timestamp = None;
+    let mut inserts = Vec::new();
+    let mut has_field = false;
+    for field in fields {
+        let options = parse_field_options(field)?;
+        let ident = field.ident.as_ref().unwrap();
+        if options.timestamp {
+            if timestamp.is_some() {
+                return Err(Error::new_spanned(field, "duplicate timestamp"));
+            }
+            if !matches!(type_segment(&field.ty), Some((ty, None)) if ty == "i64") {
+                return Err(Error::new_spanned(
+                    &field.ty,
+                    "the timestamp must be `i64` in milliseconds",
+                ));
+            }
+            timestamp = Some(ident);
+            continue;
+        }
+
+        let name = options.rename.unwrap_or_else(|| ident.to_string());
+        if RESERVED_NAMES
+            .iter()
+            .any(|reserved| name.eq_ignore_ascii_case(reserved))
+        {
+            return Err(Error::new_spanned(
+                field,
+                format!("the column name `{name}` is reserved by horaedb"),
+            ));
+        }
+
+        let columns = if options.tag {
+            quote!(tags)
+        } else {
+            has_field = true;
+            quote!(fields)
+        };
+        let unsupported = || {
+            Error::new(
+                field.ty.span(),
+                format!(
+                    "unsupported type of `{ident}`, expected one of bool, i8, i16, i32, i64, u8, \
+                     u16, u32, u64, f32, f64, String, Vec<u8>, or an Option of them"
+                ),
+            )
+        };
+        let insert = match type_segment(&field.ty) {
+            Some((ty, Some(arg))) if ty == "Option" => {
+                let value = convert_value(arg, quote!(value)).ok_or_else(unsupported)?;
+                quote! {
+                    if let Some(value) = self.#ident {
+                        point.#columns.insert(#name.to_string(), #value);
+                    }
+                }
+            }
+            _ => {
+                let value =
+                    convert_value(&field.ty, quote!(self.#ident)).ok_or_else(unsupported)?;
+                quote! {
+                    point.#columns.insert(#name.to_string(), #value);
+                }
+            }
+        };
+        inserts.push(insert);
+    }
+
+    let timestamp = timestamp.ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "the timestamp must be marked by `#[horaedb(timestamp)]`",
+        )
+    })?;
+    if !has_field {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "at least one field which is not a tag is required",
+        ));
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::horaedb_client::IntoPoint for #ident #ty_generics #where_clause {
+            fn into_point(self) -> ::horaedb_client::model::write::point::Point {
+                let mut point = ::horaedb_client::model::write::point::Point {
+                    table: #table.to_string(),
+                    timestamp: self.#timestamp,
+                    ..::core::default::Default::default()
+                };
+                #(#inserts)*
+                point
+            }
+        }
+    })
+}
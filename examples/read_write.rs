@@ -117,10 +117,7 @@ async fn main() {
     // you should ensure horaedb is running, and grpc port is set to 8831
     let client = Builder::new("127.0.0.1:8831".to_string(), Mode::Direct)
         // Set authorization if needed
-        .authorization(Authorization {
-            username: "user".to_string(),
-            password: "pass".to_string(),
-        })
+        .authorization(Authorization::basic("user", "pass"))
         .build();
     let rpc_ctx = RpcContext::default().database("public".to_string());
 
@@ -0,0 +1,172 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Synchronous wrapper of the [`DbClient`] for the non-async code.
+//!
+//! It is enabled by the `blocking` feature.
+//!
+//! ```rust,no_run
+//! use horaedb_client::{Builder, Mode, RpcContext, SqlQueryRequest};
+//!
+//! let client = Builder::new("127.0.0.1:8831".to_string(), Mode::Direct)
+//!     .build_blocking()
+//!     .unwrap();
+//! let ctx = RpcContext::default().database("public".to_string());
+//! let req = SqlQueryRequest {
+//!     tables: vec!["demo".to_string()],
+//!     sql: "select * from demo".to_string(),
+//! };
+//! let resp = client.sql_query(&ctx, &req).unwrap();
+//! ```
+
+use std::{future::Future, sync::Arc};
+
+use tokio::runtime::{Handle, Runtime};
+
+use crate::{
+    db_client::DbClient,
+    model::{
+        sql_query::{Request as SqlQueryRequest, Response as SqlQueryResponse},
+        write::{Request as WriteRequest, Response as WriteResponse},
+    },
+    rpc_client::RpcContext,
+    Error, Result,
+};
+
+enum RuntimeHandle {
+    Owned(Runtime),
+    Provided(Handle),
+}
+
+/// [`DbClient`] whose methods block the current thread until the requests are
+/// finished.
+///
+/// The methods must not be called in the async context, otherwise they panic
+/// just like [`Handle::block_on`].
+pub struct BlockingDbClient {
+    client: Arc<dyn DbClient>,
+    runtime: RuntimeHandle,
+}
+
+impl BlockingDbClient {
+    /// Wrap the `client` with an owned multi-thread runtime, which is shut
+    /// down when the wrapper is dropped.
+    pub fn new(client: Arc<dyn DbClient>) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("horaedb-client-blocking")
+            .build()
+            .map_err(|e| Error::Client(format!("Failed to build runtime, err:{e}")))?;
+
+        Ok(Self {
+            client,
+            runtime: RuntimeHandle::Owned(runtime),
+        })
+    }
+
+    /// Wrap the `client` with the runtime of the `handle`, which is shared with
+    /// the caller.
+    pub fn with_handle(client: Arc<dyn DbClient>, handle: Handle) -> Self {
+        Self {
+            client,
+            runtime: RuntimeHandle::Provided(handle),
+        }
+    }
+
+    /// The wrapped async client.
+    pub fn inner(&self) -> &Arc<dyn DbClient> {
+        &self.client
+    }
+
+    pub fn sql_query(&self, ctx: &RpcContext, req: &SqlQueryRequest) -> Result<SqlQueryResponse> {
+        self.block_on(self.client.sql_query(ctx, req))
+    }
+
+    pub fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
+        self.block_on(self.client.write(ctx, req))
+    }
+
+    fn block_on<T>(&self, future: impl Future<Output = T>) -> T {
+        match &self.runtime {
+            RuntimeHandle::Owned(runtime) => runtime.block_on(future),
+            RuntimeHandle::Provided(handle) => handle.block_on(future),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::BlockingDbClient;
+    use crate::{
+        db_client::raw::RawImpl,
+        model::{value::Value, write::point::PointBuilder},
+        rpc_client::{MockRpcClientFactory, RpcContext},
+        RpcConfig, SqlQueryRequest, WriteRequest,
+    };
+
+    fn make_client(factory: Arc<MockRpcClientFactory>) -> Arc<RawImpl<MockRpcClientFactory>> {
+        Arc::new(
+            RawImpl::new(
+                factory,
+                vec!["192.168.0.1:11".to_string()],
+                Some("db".to_string()),
+                &RpcConfig::default(),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_blocking_query_and_write() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = BlockingDbClient::new(make_client(factory.clone())).unwrap();
+        let ctx = RpcContext::default();
+
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+        let resp = client.sql_query(&ctx, &req).unwrap();
+        assert_eq!(resp.affected_rows, 0);
+
+        let mut req = WriteRequest::default();
+        let point = PointBuilder::new("demo")
+            .timestamp(42)
+            .field("value", Value::Int32(42))
+            .build()
+            .unwrap();
+        req.add_point(point);
+        let resp = client.write(&ctx, &req).unwrap();
+        assert_eq!(resp.success, 1);
+        assert_eq!(*factory.request_counts.get("192.168.0.1:11").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_provided_handle() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = BlockingDbClient::with_handle(make_client(factory), runtime.handle().clone());
+
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+        client.sql_query(&RpcContext::default(), &req).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::{future::Future, sync::Mutex, time::Instant};
+
+use tonic::Code;
+
+use crate::{config::CircuitBreakerConfig, Error, Result};
+
+/// The state of the [`CircuitBreaker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CircuitState {
+    /// The requests are let through, and the consecutive connection failures
+    /// are counted.
+    Closed {
+        failures: usize,
+        first_failure_at: Option<Instant>,
+    },
+    /// The requests fail fast until the cooldown is over.
+    Open { until: Instant },
+    /// One trial request is in flight, and the others fail fast.
+    HalfOpen { since: Instant },
+}
+
+impl Default for CircuitState {
+    fn default() -> Self {
+        CircuitState::Closed {
+            failures: 0,
+            first_failure_at: None,
+        }
+    }
+}
+
+/// Circuit breaker of one endpoint, so that the requests to a hard-down
+/// endpoint fail fast instead of waiting for the connect timeout.
+///
+/// The circuit is opened after `failure_threshold` consecutive connection
+/// failures within the `failure_window`, and one trial request is let through
+/// after the `cooldown`, whose success closes the circuit again.
+pub(crate) struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<CircuitState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(CircuitState::default()),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Tell whether the requests fail fast now.
+    pub fn is_open(&self) -> bool {
+        match self.state() {
+            CircuitState::Closed { .. } => false,
+            CircuitState::Open { until } => Instant::now() < until,
+            CircuitState::HalfOpen { since } => since.elapsed() < self.config.cooldown,
+        }
+    }
+
+    /// Try to let a request through, and the request must be recorded by
+    /// [`CircuitBreaker::record`] afterwards.
+    ///
+    /// The trial request not recorded within the `cooldown`, e.g. it is
+    /// cancelled, is given up and another one is let through.
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            CircuitState::Closed { .. } => true,
+            CircuitState::Open { until } if now < until => false,
+            CircuitState::HalfOpen { since } if now - since < self.config.cooldown => false,
+            CircuitState::Open { .. } | CircuitState::HalfOpen { .. } => {
+                *state = CircuitState::HalfOpen { since: now };
+                true
+            }
+        }
+    }
+
+    /// Record the result of the request let through.
+    pub fn record<T>(&self, result: &Result<T>) {
+        // The cancelled request tells nothing about the endpoint.
+        if matches!(result, Err(Error::Cancelled)) {
+            return;
+        }
+
+        let failed = matches!(result, Err(e) if is_connection_failure(e));
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        *state = match (*state, failed) {
+            // Any response means the endpoint is reachable.
+            (_, false) => CircuitState::default(),
+            (
+                CircuitState::Closed {
+                    failures,
+                    first_failure_at,
+                },
+                true,
+            ) => {
+                let (failures, first_failure_at) = match first_failure_at {
+                    Some(at) if now - at <= self.config.failure_window => (failures + 1, at),
+                    _ => (1, now),
+                };
+                if failures >= self.config.failure_threshold {
+                    CircuitState::Open {
+                        until: now + self.config.cooldown,
+                    }
+                } else {
+                    CircuitState::Closed {
+                        failures,
+                        first_failure_at: Some(first_failure_at),
+                    }
+                }
+            }
+            (CircuitState::Open { .. } | CircuitState::HalfOpen { .. }, true) => {
+                CircuitState::Open {
+                    until: now + self.config.cooldown,
+                }
+            }
+        };
+    }
+
+    /// Issue the request if the circuit isn't open, and record its result.
+    pub async fn call<T>(
+        &self,
+        endpoint: &str,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        if !self.try_acquire() {
+            return Err(Error::CircuitOpen {
+                endpoint: endpoint.to_string(),
+            });
+        }
+
+        let result = request.await;
+        self.record(&result);
+        result
+    }
+}
+
+/// Tell whether the endpoint is unreachable, rather than the request fails.
+fn is_connection_failure(err: &Error) -> bool {
+    match err {
+        Error::Connect { .. } => true,
+        Error::Rpc(status) => status.code() == Code::Unavailable,
+        Error::Shared(err) => is_connection_failure(err),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tonic::Status;
+
+    use super::{CircuitBreaker, CircuitState};
+    use crate::{config::CircuitBreakerConfig, errors::ServerError, Error, Result};
+
+    fn unavailable() -> Result<()> {
+        Err(Error::Rpc(Status::unavailable("connection reset")))
+    }
+
+    #[test]
+    fn test_transitions() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            failure_window: Duration::from_secs(10),
+            cooldown: Duration::from_millis(50),
+        });
+
+        // The server errors don't count.
+        assert!(breaker.try_acquire());
+        breaker.record(&unavailable());
+        assert!(breaker.try_acquire());
+        breaker.record::<()>(&Err(Error::Server(ServerError {
+            code: 500,
+            msg: "internal error".to_string(),
+        })));
+        assert_eq!(breaker.state(), CircuitState::default());
+
+        breaker.record(&unavailable());
+        breaker.record(&unavailable());
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+        assert!(breaker.is_open());
+        assert!(!breaker.try_acquire());
+
+        // Only one trial request is let through after the cooldown, and its
+        // failure opens the circuit again.
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.try_acquire());
+        assert!(matches!(breaker.state(), CircuitState::HalfOpen { .. }));
+        assert!(!breaker.try_acquire());
+        breaker.record(&unavailable());
+        assert!(!breaker.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.try_acquire());
+        breaker.record(&Ok(()));
+        assert_eq!(breaker.state(), CircuitState::default());
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn test_failure_window() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            failure_window: Duration::from_millis(20),
+            cooldown: Duration::from_secs(30),
+        });
+
+        breaker.record(&unavailable());
+        std::thread::sleep(Duration::from_millis(30));
+        // The failure out of the window starts counting again.
+        breaker.record(&unavailable());
+        assert!(matches!(
+            breaker.state(),
+            CircuitState::Closed { failures: 1, .. }
+        ));
+        breaker.record(&unavailable());
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+    }
+}
@@ -15,7 +15,15 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::time::Duration;
+use std::{fmt, path::PathBuf, sync::Arc, time::Duration};
+
+use tonic::codec::CompressionEncoding;
+
+use crate::{
+    db_client::Mode,
+    util::{is_transport_error, random_ratio},
+    Error,
+};
 
 /// Config for the underlying grpc client
 #[derive(Debug, Clone)]
@@ -53,18 +61,532 @@ pub struct RpcConfig {
     ///
     /// Default value is 60s.
     pub default_sql_query_timeout: Duration,
+    /// Scale the default timeout of sql_query by the
+    /// [`RpcContext::expected_rows`](crate::RpcContext::expected_rows).
+    ///
+    /// It is disabled by default.
+    pub sql_query_timeout_scaling: Option<TimeoutScaling>,
     /// Timeout for connection.
     ///
     /// Default value is 3s.
     pub connect_timeout: Duration,
+    /// Coalesce the identical sql queries issued concurrently into one rpc.
+    ///
+    /// The queries with the same database, tables and sql will share the
+    /// response of the in flight one, and writes are never coalesced. It is
+    /// disabled by default.
+    pub coalesce_sql_query: bool,
+    /// Strip the comments in the sql before sending it, see
+    /// [`strip_comments`](crate::model::sql_query::comment::strip_comments).
+    ///
+    /// It is disabled by default.
+    pub strip_sql_comments: bool,
+    /// The max length in bytes of the sql, and the longer sql is rejected
+    /// before sending.
+    ///
+    /// It is unlimited by default.
+    pub max_sql_length: Option<usize>,
+    /// Split the write request of more points than it into multiple ones, and
+    /// the succeeded points of all the split requests are merged into one
+    /// response.
+    ///
+    /// The write request is never split by default.
+    pub max_write_batch_rows: Option<usize>,
+    /// The max number of the split write requests issued concurrently, see
+    /// `max_write_batch_rows`.
+    ///
+    /// Default value is 4.
+    pub max_write_batch_concurrency: usize,
+    /// The compression of the request and response messages.
+    ///
+    /// It is disabled by default.
+    pub compression: Option<Compression>,
+    /// Only the requests encoded in more bytes than it are compressed, and the
+    /// others are sent uncompressed even if the `compression` is set, while the
+    /// compressed responses are always accepted. The size of the streamed
+    /// requests is unknown, so they are always compressed.
+    ///
+    /// All the requests are compressed by default.
+    pub compression_min_size: Option<usize>,
+    /// Connect to the endpoints by TLS if set.
+    ///
+    /// It is disabled by default.
+    pub tls: Option<TlsConfig>,
+    /// The time to live of the cached routes in `Direct` mode.
+    ///
+    /// The cached routes never expire by default, and they are evicted only
+    /// after the failed requests or by
+    /// [`DbClient::evict_routes`](crate::DbClient::evict_routes).
+    pub route_cache_ttl: Option<Duration>,
+    /// The policy to retry the failed requests.
+    ///
+    /// No request is retried by default.
+    pub retry: RetryConfig,
+    /// Where to find the status of the response.
+    ///
+    /// Default value is [`StatusSource::Both`].
+    pub status_source: StatusSource,
+    /// The keys of the trailers carrying the status, which are checked unless
+    /// the [`RpcConfig::status_source`] is [`StatusSource::Header`].
+    ///
+    /// Default value is [`StatusTrailerKeys::default`].
+    pub status_trailer_keys: StatusTrailerKeys,
+    /// Fail fast the requests to the endpoint which keeps failing to connect.
+    ///
+    /// It is disabled by default.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Recreate the connection to the endpoint unused for so long before the
+    /// next request, as it may have been closed silently by an intermediary,
+    /// and the request failing with the `Unavailable` status is retried once
+    /// on a recreated connection too.
+    ///
+    /// It only takes effect when `keep_alive_while_idle` is disabled, and it is
+    /// disabled by default.
+    pub idle_timeout: Option<Duration>,
+    /// Identify the application to the server, which is set as the user agent
+    /// of the connections, and prepended to the `x-client-version` metadata of
+    /// each rpc carrying the version of this crate.
+    ///
+    /// Default value is None, and only the version of this crate is sent.
+    pub user_agent: Option<String>,
+    /// The number of the connections to each endpoint, across which the
+    /// requests are distributed in round-robin, e.g. to get over the limit of
+    /// the concurrent streams of one HTTP/2 connection.
+    ///
+    /// The endpoint is connected if any of its connections is established.
+    /// Default value is 1, and 0 is taken as 1.
+    pub connections_per_endpoint: usize,
+    /// Limit the retries of all the requests of the client by a budget, so
+    /// that the retries stop once too many requests fail, e.g. in an outage.
+    ///
+    /// The retries are unlimited by default besides the `max_attempts` of the
+    /// `retry`.
+    pub retry_budget: Option<RetryBudgetConfig>,
+    /// The initial HTTP/2 flow-control window in bytes of each stream, which
+    /// may be enlarged for the links of the high bandwidth-delay product.
+    ///
+    /// The default of the underlying grpc transport is used if not set.
+    pub initial_stream_window_size: Option<u32>,
+    /// The initial HTTP/2 flow-control window in bytes of each connection.
+    ///
+    /// The default of the underlying grpc transport is used if not set.
+    pub initial_connection_window_size: Option<u32>,
+    /// Set `TCP_NODELAY` on the connections or not.
+    ///
+    /// It is enabled if not set, as the underlying grpc transport does.
+    pub tcp_nodelay: Option<bool>,
+    /// The interval of the TCP keepalive probes on the connections.
+    ///
+    /// The TCP keepalive is disabled if not set.
+    pub tcp_keepalive: Option<Duration>,
+    /// The availability zone of the client, and the requests are sent to the
+    /// healthy proxy endpoints in it in `Proxy` mode, see
+    /// [`Builder::endpoint_zone`](crate::Builder::endpoint_zone). The
+    /// endpoints in other zones are used only if none in it is healthy.
+    ///
+    /// No zone is preferred by default.
+    pub local_zone: Option<String>,
 }
 
+/// Config for connecting to the endpoints by TLS.
 #[derive(Debug, Clone)]
-pub struct Authorization {
+pub struct TlsConfig {
+    /// The pem file of the CA certificate to verify the server.
+    pub ca_certificate: PathBuf,
+    /// The certificate and the key of the client for the mutual TLS.
+    pub client_identity: Option<TlsIdentity>,
+    /// Override the domain name to verify the server certificate against,
+    /// which is the host of the endpoint by default.
+    pub domain_name: Option<String>,
+}
+
+/// The pem files of the certificate and the private key.
+#[derive(Debug, Clone)]
+pub struct TlsIdentity {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+}
+
+/// The compression of the grpc messages.
+///
+/// Note that only gzip is supported by the underlying grpc transport now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+}
+
+impl Compression {
+    pub(crate) fn encoding(&self) -> CompressionEncoding {
+        match self {
+            Compression::Gzip => CompressionEncoding::Gzip,
+        }
+    }
+}
+
+/// The source of the status of the response.
+///
+/// Some deployments report the error in the trailers rather than the header of
+/// the response, whose keys are set by [`RpcConfig::status_trailer_keys`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatusSource {
+    /// Only the header of the response is checked.
+    Header,
+    /// The trailers are authoritative, and the header is checked only if the
+    /// trailers carry no status.
+    Trailer,
+    /// Any failure reported by the header or the trailers is surfaced, and the
+    /// header is checked first.
+    #[default]
+    Both,
+}
+
+/// The keys of the trailers carrying the status of the response.
+///
+/// They are not a contract of the HoraeDB server, which reports the status only
+/// in the header of the response. Set them to the keys used by the proxy or the
+/// server in front of the client which reports the status in the trailers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusTrailerKeys {
+    /// The key of the trailer carrying the status code.
+    ///
+    /// Default value is `x-horaedb-code`.
+    pub code: String,
+    /// The key of the trailer carrying the error message.
+    ///
+    /// Default value is `x-horaedb-error`.
+    pub error: String,
+}
+
+impl Default for StatusTrailerKeys {
+    fn default() -> Self {
+        Self {
+            code: "x-horaedb-code".to_string(),
+            error: "x-horaedb-error".to_string(),
+        }
+    }
+}
+
+/// Config for the circuit breaker of each endpoint.
+///
+/// The connection failures are the [`Error::Connect`] and the rpc errors with
+/// `Unavailable` code, and any other response closes the circuit.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// The number of the consecutive connection failures to open the circuit.
+    ///
+    /// Default value is 5.
+    pub failure_threshold: usize,
+    /// The consecutive failures must happen within it since the first one,
+    /// otherwise the counting starts again.
+    ///
+    /// Default value is 10s.
+    pub failure_window: Duration,
+    /// The requests fail fast with [`Error::CircuitOpen`] for it after the
+    /// circuit is opened, and then one trial request is let through.
+    ///
+    /// Default value is 30s.
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            failure_window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Decide whether the error is retryable, see [`RetryConfig::retryable`].
+pub type RetryPredicate = Arc<dyn Fn(&Error) -> bool + Send + Sync>;
+
+/// Config for retrying the failed requests.
+///
+/// In `Direct` mode, only the tables of the write failed on some endpoints are
+/// reissued, so the tables written already are not duplicated.
+#[derive(Clone)]
+pub struct RetryConfig {
+    /// The max attempts of a request, including the first one.
+    ///
+    /// Default value is 1, that is, no retry.
+    pub max_attempts: usize,
+    /// The interval before the first retry.
+    ///
+    /// Default value is 100ms.
+    pub backoff: Duration,
+    /// The interval is multiplied by it after each retry.
+    ///
+    /// Default value is 2.0.
+    pub backoff_multiplier: f64,
+    /// The max interval between two attempts.
+    ///
+    /// Default value is 5s.
+    pub max_backoff: Duration,
+    /// The ratio of the interval randomized to spread the retries of the
+    /// concurrent requests, that is, the interval is picked randomly within
+    /// `[interval * (1 - jitter), interval]`, and it is clamped into `[0, 1]`.
+    ///
+    /// Default value is 0, that is, no jitter.
+    pub jitter: f64,
+    /// The server codes to retry, e.g. the one telling the schema is updating.
+    ///
+    /// The transport errors, that is, the rpc errors with `Unavailable` or
+    /// `DeadlineExceeded` code and the connection errors, are always retried,
+    /// while the server errors are retried only if their codes are listed here.
+    /// It is empty by default.
+    pub retryable_server_codes: Vec<u32>,
+    /// Decide whether the error is retryable instead of the rules above if set.
+    ///
+    /// The errors wrapping others, e.g. the failed parts of the write in
+    /// `Direct` mode, are unwrapped before being passed to it.
+    pub retryable: Option<RetryPredicate>,
+}
+
+impl fmt::Debug for RetryConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryConfig")
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff", &self.backoff)
+            .field("backoff_multiplier", &self.backoff_multiplier)
+            .field("max_backoff", &self.max_backoff)
+            .field("jitter", &self.jitter)
+            .field("retryable_server_codes", &self.retryable_server_codes)
+            .field("retryable", &self.retryable.is_some())
+            .finish()
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Duration::from_millis(100),
+            backoff_multiplier: 2.0,
+            max_backoff: Duration::from_secs(5),
+            jitter: 0.0,
+            retryable_server_codes: Vec::new(),
+            retryable: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub(crate) fn is_retryable(&self, err: &Error) -> bool {
+        match err {
+            Error::Shared(err) => self.is_retryable(err),
+            Error::PinnedEndpoint { source, .. } | Error::WithMeta { source, .. } => {
+                self.is_retryable(source)
+            }
+            // Retry the write only if all the failed parts are retryable, and
+            // only they are reissued.
+            Error::RouteBasedWriteError(err) => {
+                !err.errors.is_empty() && err.errors.iter().all(|(_, e)| self.is_retryable(e))
+            }
+            Error::NoAvailableEndpoint(errors) => {
+                !errors.is_empty() && errors.iter().all(|(_, e)| self.is_retryable(e))
+            }
+            err => match &self.retryable {
+                Some(retryable) => retryable(err),
+                None => match err {
+                    Error::Server(server_error) => {
+                        self.retryable_server_codes.contains(&server_error.code)
+                    }
+                    err => is_transport_error(err),
+                },
+            },
+        }
+    }
+
+    /// The interval before the `retry`th retry, starting from 1.
+    pub(crate) fn backoff(&self, retry: usize) -> Duration {
+        let exp = i32::try_from(retry.saturating_sub(1)).unwrap_or(i32::MAX);
+        let backoff = self.backoff.as_secs_f64() * self.backoff_multiplier.powi(exp);
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    /// The [`backoff`](Self::backoff) randomized by the `jitter`.
+    pub(crate) fn jittered_backoff(&self, retry: usize) -> Duration {
+        let backoff = self.backoff(retry);
+        let jitter = if self.jitter.is_nan() {
+            0.0
+        } else {
+            self.jitter.clamp(0.0, 1.0)
+        };
+        backoff.mul_f64(1.0 - jitter * random_ratio())
+    }
+}
+
+/// Config for the budget of the retries, see [`RpcConfig::retry_budget`].
+///
+/// Each retry takes one token from the budget and each succeeded request puts
+/// `ratio` tokens back, so that about `ratio` retries are allowed per request
+/// in the long run. The failed requests are not retried if no token is left.
+#[derive(Debug, Clone)]
+pub struct RetryBudgetConfig {
+    /// The tokens earned by each succeeded request.
+    ///
+    /// Default value is 0.1.
+    pub ratio: f64,
+    /// The capacity of the budget, which is full at the start.
+    ///
+    /// Default value is 10.
+    pub max_tokens: f64,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            ratio: 0.1,
+            max_tokens: 10.0,
+        }
+    }
+}
+
+/// Override the keep-alive settings in [`RpcConfig`] for one endpoint, e.g.
+/// the one behind a load balancer with a shorter idle timeout.
+///
+/// The unset ones fall back to the global settings.
+#[derive(Debug, Clone, Default)]
+pub struct KeepAliveOverride {
+    pub keep_alive_interval: Option<Duration>,
+    pub keep_alive_timeout: Option<Duration>,
+    pub keep_alive_while_idle: Option<bool>,
+}
+
+/// The keep-alive settings resolved for an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KeepAlive {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub while_idle: bool,
+}
+
+impl KeepAlive {
+    pub(crate) fn resolve(
+        rpc_config: &RpcConfig,
+        keep_alive_override: Option<&KeepAliveOverride>,
+    ) -> Self {
+        let keep_alive_override = keep_alive_override.cloned().unwrap_or_default();
+        Self {
+            interval: keep_alive_override
+                .keep_alive_interval
+                .unwrap_or(rpc_config.keep_alive_interval),
+            timeout: keep_alive_override
+                .keep_alive_timeout
+                .unwrap_or(rpc_config.keep_alive_timeout),
+            while_idle: keep_alive_override
+                .keep_alive_while_idle
+                .unwrap_or(rpc_config.keep_alive_while_idle),
+        }
+    }
+}
+
+/// Scale the timeout by the number of the expected rows.
+///
+/// The scaled timeout is `min(base + per_row * expected_rows, max_timeout)`,
+/// where `base` is the default timeout. And the timeout set in the
+/// [`RpcContext`](crate::RpcContext) takes precedence over the scaled one.
+#[derive(Debug, Clone)]
+pub struct TimeoutScaling {
+    pub per_row: Duration,
+    pub max_timeout: Duration,
+}
+
+impl TimeoutScaling {
+    pub(crate) fn scale(&self, base: Duration, expected_rows: usize) -> Duration {
+        let budget = self
+            .per_row
+            .checked_mul(u32::try_from(expected_rows).unwrap_or(u32::MAX))
+            .unwrap_or(Duration::MAX);
+        base.saturating_add(budget).min(self.max_timeout)
+    }
+}
+
+/// The fully resolved configuration of a [`DbClient`](crate::DbClient).
+///
+/// It is read-only and just for diagnosis, e.g. logging at startup.
+#[derive(Debug, Clone)]
+pub struct EffectiveConfig {
+    pub mode: Mode,
+    /// All the configured endpoints in `Proxy` mode, or the one for routing in
+    /// `Direct` mode.
+    pub endpoints: Vec<String>,
+    pub default_database: Option<String>,
+    pub rpc_config: RpcConfig,
+}
+
+/// Provider of the bearer token, which is called on each connecting to renew
+/// the short-lived token.
+pub type TokenProvider = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// The credentials sent by the `authorization` metadata of each rpc.
+#[derive(Clone)]
+pub enum Authorization {
+    /// Sent as `Basic <base64 of username:password>`.
+    Basic { username: String, password: String },
+    /// Sent as `Bearer <token>`.
+    Bearer { token: String },
+    /// Sent as `Bearer <token>`, and the token is provided on each connecting.
+    ///
+    /// The token is also refreshed once the rpc is rejected with the
+    /// `Unauthenticated` status, and the rpc is retried once with the new
+    /// token.
+    BearerProvider(TokenProvider),
+}
+
+impl Authorization {
+    pub fn basic(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self::Basic {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    pub fn bearer(token: impl Into<String>) -> Self {
+        Self::Bearer {
+            token: token.into(),
+        }
+    }
+
+    pub fn bearer_provider(provider: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        Self::BearerProvider(Arc::new(provider))
+    }
+}
+
+/// The secrets are not printed.
+impl fmt::Debug for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::Bearer { .. } => f.debug_struct("Bearer").finish_non_exhaustive(),
+            Self::BearerProvider(_) => f.debug_tuple("BearerProvider").finish(),
+        }
+    }
+}
+
+/// The username and the password, i.e. the former struct of the
+/// [`Authorization`], converted into [`Authorization::Basic`].
+#[derive(Debug, Clone)]
+pub struct BasicAuthorization {
     pub username: String,
     pub password: String,
 }
 
+impl From<BasicAuthorization> for Authorization {
+    fn from(auth: BasicAuthorization) -> Self {
+        Self::Basic {
+            username: auth.username,
+            password: auth.password,
+        }
+    }
+}
+
 impl Default for RpcConfig {
     fn default() -> Self {
         Self {
@@ -78,7 +600,132 @@ impl Default for RpcConfig {
             keep_alive_while_idle: true,
             default_write_timeout: Duration::from_secs(5),
             default_sql_query_timeout: Duration::from_secs(60),
+            sql_query_timeout_scaling: None,
             connect_timeout: Duration::from_secs(3),
+            coalesce_sql_query: false,
+            strip_sql_comments: false,
+            max_sql_length: None,
+            max_write_batch_rows: None,
+            max_write_batch_concurrency: 4,
+            compression: None,
+            compression_min_size: None,
+            tls: None,
+            route_cache_ttl: None,
+            retry: RetryConfig::default(),
+            status_source: StatusSource::default(),
+            status_trailer_keys: StatusTrailerKeys::default(),
+            circuit_breaker: None,
+            idle_timeout: None,
+            user_agent: None,
+            connections_per_endpoint: 1,
+            retry_budget: None,
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            tcp_nodelay: None,
+            tcp_keepalive: None,
+            local_zone: None,
         }
     }
 }
+
+impl RpcConfig {
+    /// The `idle_timeout` in effect, i.e. only if the idle connections are not
+    /// kept alive.
+    pub(crate) fn effective_idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout.filter(|_| !self.keep_alive_while_idle)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{KeepAlive, KeepAliveOverride, RetryConfig, RpcConfig, TimeoutScaling};
+    use crate::Error;
+
+    #[test]
+    fn test_scale_timeout() {
+        let scaling = TimeoutScaling {
+            per_row: Duration::from_millis(1),
+            max_timeout: Duration::from_secs(60),
+        };
+        let base = Duration::from_secs(5);
+        assert_eq!(scaling.scale(base, 0), base);
+        assert_eq!(scaling.scale(base, 10_000), Duration::from_secs(15));
+        assert_eq!(scaling.scale(base, 1_000_000), Duration::from_secs(60));
+        assert_eq!(scaling.scale(base, usize::MAX), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let config = RetryConfig {
+            backoff: Duration::from_millis(100),
+            backoff_multiplier: 2.0,
+            max_backoff: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let backoffs: Vec<_> = (1..=4).map(|retry| config.backoff(retry)).collect();
+        assert_eq!(
+            backoffs,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(400),
+                Duration::from_millis(500),
+            ]
+        );
+        assert_eq!(config.backoff(usize::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_retry_jitter() {
+        let config = RetryConfig {
+            backoff: Duration::from_millis(100),
+            jitter: 0.5,
+            ..Default::default()
+        };
+        let backoffs: Vec<_> = (0..100).map(|_| config.jittered_backoff(1)).collect();
+        assert!(backoffs.iter().all(|backoff| (Duration::from_millis(50)
+            ..=Duration::from_millis(100))
+            .contains(backoff)));
+        assert!(backoffs.iter().any(|backoff| *backoff != backoffs[0]));
+
+        // No jitter by default.
+        let config = RetryConfig::default();
+        assert_eq!(config.jittered_backoff(1), config.backoff(1));
+    }
+
+    #[test]
+    fn test_retryable_predicate() {
+        let config = RetryConfig {
+            retryable: Some(Arc::new(|err| matches!(err, Error::Client(_)))),
+            ..Default::default()
+        };
+        assert!(config.is_retryable(&Error::Client("retry me".to_string())));
+        assert!(config.is_retryable(&Error::Shared(Arc::new(Error::Client(String::new())))));
+        // The default rules are replaced.
+        assert!(!config.is_retryable(&Error::Rpc(tonic::Status::unavailable(""))));
+    }
+
+    #[test]
+    fn test_resolve_keep_alive() {
+        let rpc_config = RpcConfig::default();
+        let global = KeepAlive::resolve(&rpc_config, None);
+        assert_eq!(global.interval, rpc_config.keep_alive_interval);
+        assert_eq!(global.timeout, rpc_config.keep_alive_timeout);
+        assert_eq!(global.while_idle, rpc_config.keep_alive_while_idle);
+
+        let keep_alive_override = KeepAliveOverride {
+            keep_alive_interval: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let overridden = KeepAlive::resolve(&rpc_config, Some(&keep_alive_override));
+        assert_eq!(
+            overridden,
+            KeepAlive {
+                interval: Duration::from_secs(30),
+                ..global
+            }
+        );
+    }
+}
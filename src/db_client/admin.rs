@@ -0,0 +1,224 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The typed surface for the administrative operations.
+
+use crate::{
+    db_client::DbClient,
+    model::{
+        sql_query::{
+            escape::quote_identifier, Request as SqlQueryRequest, Response as SqlQueryResponse,
+        },
+        table_schema::{ColumnSchema, TableSchema},
+        value::DataType,
+    },
+    rpc_client::RpcContext,
+    util::is_table_not_found,
+    Error, Result,
+};
+
+/// Client for the DDL operations, got by [`DbClient::admin`].
+///
+/// The cached routes of the table are evicted after the operation finishes,
+/// no matter it succeeds or not.
+pub struct AdminClient<'a> {
+    client: &'a dyn DbClient,
+}
+
+impl<'a> AdminClient<'a> {
+    /// Create the client issuing the DDL operations through the `client`,
+    /// which serves [`DbClient::admin`] of the custom implementations.
+    pub fn new(client: &'a dyn DbClient) -> Self {
+        Self { client }
+    }
+
+    /// Create the table if it doesn't exist.
+    pub async fn create_table(
+        &self,
+        ctx: &RpcContext,
+        schema: &TableSchema,
+    ) -> Result<SqlQueryResponse> {
+        self.execute(ctx, &schema.table, create_table_sql(schema))
+            .await
+    }
+
+    pub async fn drop_table(&self, ctx: &RpcContext, table: &str) -> Result<SqlQueryResponse> {
+        let sql = format!("DROP TABLE IF EXISTS {}", quote_identifier(table));
+        self.execute(ctx, table, sql).await
+    }
+
+    /// Remove all the rows in the table.
+    ///
+    /// It fails if the table doesn't exist unless `if_exists` is set.
+    pub async fn truncate_table(
+        &self,
+        ctx: &RpcContext,
+        table: &str,
+        if_exists: bool,
+    ) -> Result<SqlQueryResponse> {
+        let sql = format!("TRUNCATE TABLE {}", quote_identifier(table));
+        match self.execute(ctx, table, sql).await {
+            Err(Error::Server(e)) if if_exists && is_table_not_found(e.code, &e.msg) => {
+                Ok(SqlQueryResponse::default())
+            }
+            res => res,
+        }
+    }
+
+    /// Add a tag or field column into the table.
+    pub async fn alter_table_add_column(
+        &self,
+        ctx: &RpcContext,
+        table: &str,
+        column: &ColumnSchema,
+    ) -> Result<SqlQueryResponse> {
+        let sql = format!(
+            "ALTER TABLE {} ADD COLUMN ({})",
+            quote_identifier(table),
+            column_definition(column)
+        );
+        self.execute(ctx, table, sql).await
+    }
+
+    async fn execute(
+        &self,
+        ctx: &RpcContext,
+        table: &str,
+        sql: String,
+    ) -> Result<SqlQueryResponse> {
+        let tables = vec![table.to_string()];
+        let req = SqlQueryRequest {
+            tables: tables.clone(),
+            sql,
+        };
+        let resp = self.client.sql_query(ctx, &req).await;
+        self.client.evict_routes(&tables);
+
+        resp
+    }
+}
+
+fn data_type_name(data_type: DataType) -> &'static str {
+    match data_type {
+        DataType::Null => "null",
+        DataType::Timestamp => "timestamp",
+        DataType::Double => "double",
+        DataType::Float => "float",
+        DataType::Varbinary => "varbinary",
+        DataType::String => "string",
+        DataType::UInt64 => "uint64",
+        DataType::UInt32 => "uint32",
+        DataType::UInt16 => "uint16",
+        DataType::UInt8 => "uint8",
+        DataType::Int64 => "int64",
+        DataType::Int32 => "int32",
+        DataType::Int16 => "int16",
+        DataType::Int8 => "int8",
+        DataType::Boolean => "boolean",
+    }
+}
+
+fn column_definition(column: &ColumnSchema) -> String {
+    let mut definition = format!(
+        "{} {}",
+        quote_identifier(&column.name),
+        data_type_name(column.data_type)
+    );
+    if column.is_tag {
+        definition.push_str(" TAG");
+    }
+
+    definition
+}
+
+fn create_table_sql(schema: &TableSchema) -> String {
+    let timestamp_column = quote_identifier(&schema.timestamp_column);
+    let mut definitions = Vec::with_capacity(schema.columns.len() + 2);
+    definitions.push(format!("{timestamp_column} timestamp NOT NULL"));
+    definitions.extend(schema.columns.iter().map(column_definition));
+    definitions.push(format!("TIMESTAMP KEY({timestamp_column})"));
+
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({}) ENGINE=Analytic",
+        quote_identifier(&schema.table),
+        definitions.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        db_client::route_based::RouteBasedImpl,
+        model::{route::Endpoint, table_schema::TableSchemaBuilder},
+        rpc_client::MockRpcClientFactory,
+        RpcConfig,
+    };
+
+    #[test]
+    fn test_create_table_sql() {
+        let schema = TableSchemaBuilder::new("demo")
+            .tag("host", DataType::String)
+            .field("value", DataType::Double)
+            .build()
+            .unwrap();
+        assert_eq!(
+            create_table_sql(&schema),
+            "CREATE TABLE IF NOT EXISTS `demo` (`timestamp` timestamp NOT NULL, `host` string \
+             TAG, `value` double, TIMESTAMP KEY(`timestamp`)) ENGINE=Analytic"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evict_routes_after_ddl() {
+        let endpoint1 = Endpoint::new("192.168.0.1".to_string(), 11);
+        let endpoint2 = Endpoint::new("192.168.0.2".to_string(), 12);
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .route_table
+            .insert("demo".to_string(), endpoint1.clone());
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        let ctx = RpcContext::default();
+
+        client.admin().drop_table(&ctx, "demo").await.unwrap();
+        assert_eq!(
+            *factory.request_counts.get(&endpoint1.to_string()).unwrap(),
+            1
+        );
+
+        // The table is routed again after the ddl.
+        factory
+            .route_table
+            .insert("demo".to_string(), endpoint2.clone());
+        client
+            .admin()
+            .truncate_table(&ctx, "demo", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            *factory.request_counts.get(&endpoint2.to_string()).unwrap(),
+            1
+        );
+    }
+}
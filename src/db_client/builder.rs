@@ -15,14 +15,81 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use futures::{future::BoxFuture, FutureExt};
+use horaedbproto::storage::{RequestContext, RouteRequest};
+
+#[cfg(feature = "blocking")]
+use crate::blocking::BlockingDbClient;
+#[cfg(feature = "tracing")]
+use crate::rpc_client::TraceContextInjector;
 use crate::{
-    db_client::{raw::RawImpl, route_based::RouteBasedImpl, DbClient},
-    rpc_client::RpcClientImplFactory,
-    Authorization, RpcConfig,
+    db_client::{raw::RawImpl, route_based::RouteBasedImpl, DbClient, EndpointSelector, RetryHook},
+    rpc_client::{
+        MessageSizeRecorder, ObservedRpcClientFactory, Observer, OperationAuditor, Resolver,
+        RpcClientFactory, RpcClientImplFactory, RpcContext,
+    },
+    Authorization, Error, KeepAliveOverride, Result, RpcConfig,
 };
 
+/// The database used by the probe if no default database is set.
+const PROBE_DATABASE: &str = "public";
+
+const HTTPS_SCHEME: &str = "https://";
+
+/// Normalize the endpoint into the form: `{host}:{port}`, where the host may
+/// be a bracketed IPv6 address.
+///
+/// The surrounding whitespaces, an accidental `http://` or `https://` prefix
+/// and the trailing slashes are stripped.
+fn normalize_endpoint(endpoint: &str) -> Result<String> {
+    let invalid = |reason: &str| Error::InvalidEndpoint {
+        endpoint: endpoint.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let mut normalized = endpoint.trim();
+    for scheme in ["http://", "https://"] {
+        if normalized.len() >= scheme.len()
+            && normalized[..scheme.len()].eq_ignore_ascii_case(scheme)
+        {
+            normalized = &normalized[scheme.len()..];
+            break;
+        }
+    }
+    let normalized = normalized.trim_end_matches('/');
+    if normalized.is_empty() {
+        return Err(invalid("empty endpoint"));
+    }
+    if normalized.contains("://") {
+        return Err(invalid("only http and https schemes are allowed"));
+    }
+    if normalized.contains('/') {
+        return Err(invalid("path is not allowed"));
+    }
+    if normalized.contains(char::is_whitespace) {
+        return Err(invalid("whitespace is not allowed"));
+    }
+
+    let (host, port) = normalized
+        .rsplit_once(':')
+        .ok_or_else(|| invalid("missing port"))?;
+    if host.is_empty() {
+        return Err(invalid("missing host"));
+    }
+    let is_bracketed = host.starts_with('[') && host.ends_with(']');
+    if host.contains(':') && !is_bracketed {
+        return Err(invalid("IPv6 address must be bracketed"));
+    }
+    match port.parse::<u16>() {
+        Ok(0) => Err(invalid("port must not be 0")),
+        Ok(_) => Ok(normalized.to_string()),
+        Err(_) if port.is_empty() => Err(invalid("missing port")),
+        Err(_) => Err(invalid("port must be a number in 1..=65535")),
+    }
+}
+
 /// Access mode to HoraeDB server(s).
 #[derive(Debug, Clone)]
 pub enum Mode {
@@ -40,21 +107,76 @@ pub enum Mode {
 #[derive(Debug, Clone)]
 pub struct Builder {
     mode: Mode,
-    endpoint: String,
+    endpoints: Vec<String>,
     default_database: Option<String>,
     rpc_config: RpcConfig,
     authorization: Option<Authorization>,
+    message_size_recorder: Option<Arc<dyn MessageSizeRecorder>>,
+    operation_auditor: Option<Arc<dyn OperationAuditor>>,
+    observer: Option<Arc<dyn Observer>>,
+    resolver: Option<Arc<dyn Resolver>>,
+    #[cfg(feature = "tracing")]
+    trace_context_injector: Option<Arc<dyn TraceContextInjector>>,
+    keep_alive_overrides: HashMap<String, KeepAliveOverride>,
+    eager_connect: bool,
+    prefetch_tables: Vec<String>,
+    on_retry: Option<RetryHook>,
+    proxy_fallback: bool,
+    endpoint_selector: Option<Arc<dyn EndpointSelector>>,
+    endpoint_recovery_wait: Option<Duration>,
+    endpoint_zones: HashMap<String, String>,
 }
 
+/// Connecting the endpoints of the built client eagerly.
+type Warmup = BoxFuture<'static, Result<()>>;
+
 impl Builder {
     // We hide this detail new method for the convenience of users.
     pub fn new(endpoint: String, mode: Mode) -> Self {
+        Self::with_endpoints(vec![endpoint], mode)
+    }
+
+    /// Build the client accessing multiple endpoints.
+    ///
+    /// In `Proxy` mode, the requests are distributed across the endpoints in
+    /// round-robin, or by the [`Builder::endpoint_selector`], and the endpoint
+    /// failing to connect is skipped for a while. If none of them can be
+    /// reached, [`Error::NoAvailableEndpoint`] is returned, or the error of the
+    /// only endpoint, see [`Builder::endpoint_recovery_wait`]. In `Direct`
+    /// mode, only the first endpoint is used for routing.
+    ///
+    /// The endpoints should be in the form: `{host}:{port}`, and an accidental
+    /// `http://` prefix or trailing slash is stripped. The `https://` prefix is
+    /// stripped only if the [`RpcConfig::tls`] is set. The malformed ones, as
+    /// well as the `https://` ones without the tls config, are kept as is and
+    /// fail the requests, use [`Builder::try_build`] to reject them up front.
+    ///
+    /// # Panics
+    ///
+    /// The client built from the empty `endpoints` panics on building, use
+    /// [`Builder::try_build`] to get the error instead.
+    pub fn with_endpoints(endpoints: Vec<String>, mode: Mode) -> Self {
+        // The endpoints are checked on building, when the tls config is known.
         Self {
             mode,
-            endpoint,
+            endpoints,
             rpc_config: RpcConfig::default(),
             default_database: None,
             authorization: None,
+            message_size_recorder: None,
+            operation_auditor: None,
+            observer: None,
+            resolver: None,
+            #[cfg(feature = "tracing")]
+            trace_context_injector: None,
+            keep_alive_overrides: HashMap::new(),
+            eager_connect: false,
+            prefetch_tables: Vec::new(),
+            on_retry: None,
+            proxy_fallback: false,
+            endpoint_selector: None,
+            endpoint_recovery_wait: None,
+            endpoint_zones: HashMap::new(),
         }
     }
 
@@ -71,28 +193,848 @@ impl Builder {
     }
 
     #[inline]
-    pub fn authorization(mut self, authorization: Authorization) -> Self {
-        self.authorization = Some(authorization);
+    pub fn authorization(mut self, authorization: impl Into<Authorization>) -> Self {
+        self.authorization = Some(authorization.into());
+        self
+    }
+
+    /// Register the recorder of the encoded sizes of the rpc messages.
+    #[inline]
+    pub fn message_size_recorder(mut self, recorder: Arc<dyn MessageSizeRecorder>) -> Self {
+        self.message_size_recorder = Some(recorder);
         self
     }
 
+    /// Register the auditor notified after each rpc completes.
+    #[inline]
+    pub fn operation_auditor(mut self, auditor: Arc<dyn OperationAuditor>) -> Self {
+        self.operation_auditor = Some(auditor);
+        self
+    }
+
+    /// Register the resolver of the hostnames of the endpoints, and the
+    /// [`SystemResolver`](crate::SystemResolver) is used by default.
+    #[inline]
+    pub fn resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Register the observer notified at the start and the end of each rpc to
+    /// each endpoint.
+    #[inline]
+    pub fn observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Register the provider of the trace context propagated to the server by
+    /// the `traceparent` and `tracestate` metadata of each rpc.
+    #[cfg(feature = "tracing")]
+    #[inline]
+    pub fn trace_context_injector(mut self, injector: Arc<dyn TraceContextInjector>) -> Self {
+        self.trace_context_injector = Some(injector);
+        self
+    }
+
+    /// Register the callback invoked before each retry of the requests, e.g. to
+    /// log or meter the retries.
+    ///
+    /// It is called with the number of the failed attempt starting from 1, the
+    /// error failing it and the backoff before the next attempt. It is not
+    /// called for the error finally returned.
+    #[inline]
+    pub fn on_retry(
+        mut self,
+        on_retry: impl Fn(u32, &Error, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_retry = Some(RetryHook::new(on_retry));
+        self
+    }
+
+    /// Reissue the request on the router endpoint in `Direct` mode, which
+    /// proxies it as in `Proxy` mode, if the route service or the endpoint
+    /// routed to can't be reached, and it is ignored in `Proxy` mode.
+    ///
+    /// Only the connecting failures, the open circuits and the `Unavailable`
+    /// status fall back, while the errors from the server are returned as is.
+    ///
+    /// Each attempt of the request is reissued at most once, and the reissued
+    /// ones are counted in the [`ClientStats`](crate::ClientStats). The
+    /// handles got by
+    /// [`with_endpoint_affinity`](DbClient::with_endpoint_affinity) never fall
+    /// back. It is disabled by default.
+    #[inline]
+    pub fn proxy_fallback(mut self, proxy_fallback: bool) -> Self {
+        self.proxy_fallback = proxy_fallback;
+        self
+    }
+
+    /// Select the endpoint to issue each request in `Proxy` mode by the
+    /// `selector`, e.g. [`LeastInFlight`](crate::LeastInFlight), and it is
+    /// ignored in `Direct` mode.
+    ///
+    /// The endpoints are selected in [`RoundRobin`](crate::RoundRobin) by
+    /// default.
+    #[inline]
+    pub fn endpoint_selector(mut self, selector: Arc<dyn EndpointSelector>) -> Self {
+        self.endpoint_selector = Some(selector);
+        self
+    }
+
+    /// Wait at most `wait` for any proxy endpoint to recover if none of them
+    /// can be reached in `Proxy` mode, by trying them again every 100ms, and it
+    /// is ignored in `Direct` mode.
+    ///
+    /// The request fails with [`Error::NoAvailableEndpoint`] after the wait,
+    /// which is bounded by the `total_timeout` of the
+    /// [`RpcContext`](crate::RpcContext) too. The request fails at once by
+    /// default.
+    #[inline]
+    pub fn endpoint_recovery_wait(mut self, wait: Duration) -> Self {
+        self.endpoint_recovery_wait = Some(wait);
+        self
+    }
+
+    /// Tag the proxy endpoint with the availability zone it is in, and the ones
+    /// in the [`RpcConfig::local_zone`] are preferred in `Proxy` mode.
+    ///
+    /// The `endpoint` is matched after being normalized as the ones to access,
+    /// and the endpoints not tagged are in no zone.
+    #[inline]
+    pub fn endpoint_zone(mut self, endpoint: impl Into<String>, zone: impl Into<String>) -> Self {
+        self.endpoint_zones.insert(endpoint.into(), zone.into());
+        self
+    }
+
+    /// Override the keep-alive settings in the [`RpcConfig`] for the endpoint
+    /// in the form: `{ip_addr}:{port}`.
+    #[inline]
+    pub fn keep_alive_override(
+        mut self,
+        endpoint: impl Into<String>,
+        keep_alive_override: KeepAliveOverride,
+    ) -> Self {
+        self.keep_alive_overrides
+            .insert(endpoint.into(), keep_alive_override);
+        self
+    }
+
+    /// Connect the endpoints in the background on building, i.e. the proxy
+    /// endpoints in `Proxy` mode or the router endpoint in `Direct` mode, to
+    /// save the handshake of the first request.
+    ///
+    /// It only takes effect when the client is built in a tokio runtime, and
+    /// the failures are left to the requests. Use [`Builder::build_connected`]
+    /// to wait for the connections instead.
+    #[inline]
+    pub fn eager_connect(mut self, eager_connect: bool) -> Self {
+        self.eager_connect = eager_connect;
+        self
+    }
+
+    /// Prefetch the routes of the `tables` in the default database on
+    /// connecting eagerly in `Direct` mode, and it is ignored in `Proxy` mode.
+    #[inline]
+    pub fn prefetch_routes(mut self, tables: Vec<String>) -> Self {
+        self.prefetch_tables = tables;
+        self
+    }
+
+    /// Build the client.
+    ///
+    /// The endpoints are not validated, and the malformed ones fail the
+    /// requests, use [`Builder::try_build`] to reject them up front instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no endpoint is provided.
     pub fn build(self) -> Arc<dyn DbClient> {
-        let rpc_client_factory = Arc::new(RpcClientImplFactory::new(
-            self.rpc_config,
-            self.authorization,
-        ));
+        let rpc_client_factory = Arc::new(self.rpc_client_factory());
+        self.build_with_factory(rpc_client_factory)
+    }
 
-        match self.mode {
-            Mode::Direct => Arc::new(RouteBasedImpl::new(
-                rpc_client_factory,
-                self.endpoint,
-                self.default_database,
-            )),
-            Mode::Proxy => Arc::new(RawImpl::new(
+    /// Build the client after checking the endpoints are in the form:
+    /// `{host}:{port}`, or fail with [`Error::InvalidEndpoint`]. It fails with
+    /// [`Error::Client`] if no endpoint is provided.
+    ///
+    /// Nothing is connected, as [`Builder::build`] does.
+    pub fn try_build(self) -> Result<Arc<dyn DbClient>> {
+        self.check_endpoints()?;
+        Ok(self.build())
+    }
+
+    fn check_endpoints(&self) -> Result<()> {
+        self.normalized_endpoints().map(|_| ())
+    }
+
+    /// Normalize the endpoints, see [`Builder::normalized_endpoint`].
+    fn normalized_endpoints(&self) -> Result<Vec<String>> {
+        if self.endpoints.is_empty() {
+            return Err(Error::Client("No endpoint is provided".to_string()));
+        }
+
+        self.endpoints
+            .iter()
+            .map(|endpoint| self.normalized_endpoint(endpoint))
+            .collect()
+    }
+
+    /// Normalize the endpoint, and reject the `https://` one without the tls
+    /// config, which would be connected in plaintext otherwise.
+    fn normalized_endpoint(&self, endpoint: &str) -> Result<String> {
+        let normalized = normalize_endpoint(endpoint)?;
+        let is_https = endpoint
+            .trim_start()
+            .get(..HTTPS_SCHEME.len())
+            .map(|scheme| scheme.eq_ignore_ascii_case(HTTPS_SCHEME))
+            .unwrap_or(false);
+        if is_https && self.rpc_config.tls.is_none() {
+            return Err(Error::InvalidEndpoint {
+                endpoint: endpoint.to_string(),
+                reason: "https scheme requires the tls config".to_string(),
+            });
+        }
+
+        Ok(normalized)
+    }
+
+    /// Build the client, and return once the endpoints are connected, which
+    /// fails fast if they are unreachable rather than on the first request.
+    ///
+    /// In `Proxy` mode, it fails only if none of the endpoints is connected.
+    /// The routes set by [`Builder::prefetch_routes`] are fetched too.
+    pub async fn build_connected(self) -> Result<Arc<dyn DbClient>> {
+        self.check_endpoints()?;
+        let rpc_client_factory = Arc::new(self.rpc_client_factory());
+        self.build_connected_with_factory(rpc_client_factory).await
+    }
+
+    /// Build the [`BlockingDbClient`] with an owned runtime.
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(self) -> Result<BlockingDbClient> {
+        BlockingDbClient::new(self.try_build()?)
+    }
+
+    /// Build the client, and check the authorization by a lightweight probe to
+    /// the endpoint.
+    ///
+    /// It costs an extra round trip than [`Builder::build`], but fails fast
+    /// with [`Error::Unauthenticated`] or [`Error::PermissionDenied`] if the
+    /// credentials are rejected, or [`Error::Auth`] if the token refreshed by
+    /// the [`Authorization::BearerProvider`] is rejected too.
+    pub async fn build_and_check_auth(self) -> Result<Arc<dyn DbClient>> {
+        let endpoint = self.normalized_endpoints()?.swap_remove(0);
+        let rpc_client_factory = Arc::new(self.rpc_client_factory());
+        let rpc_client = rpc_client_factory.build(endpoint).await?;
+
+        let database = self
+            .default_database
+            .clone()
+            .unwrap_or_else(|| PROBE_DATABASE.to_string());
+        let probe_req = RouteRequest {
+            context: Some(RequestContext {
+                database: database.clone(),
+            }),
+            tables: vec![],
+        };
+        let ctx = RpcContext::default().database(database);
+        // The server errors mean the credentials are accepted.
+        if let Err(
+            e @ (Error::Unauthenticated(_)
+            | Error::PermissionDenied(_)
+            | Error::Auth(_)
+            | Error::Rpc(_)
+            | Error::Connect { .. }),
+        ) = rpc_client.route(&ctx, probe_req).await
+        {
+            return Err(e);
+        }
+
+        Ok(self.build_with_factory(rpc_client_factory))
+    }
+
+    fn rpc_client_factory(&self) -> RpcClientImplFactory {
+        let mut rpc_client_factory =
+            RpcClientImplFactory::new(self.rpc_config.clone(), self.authorization.clone())
+                .with_keep_alive_overrides(self.keep_alive_overrides.clone());
+        if let Some(recorder) = &self.message_size_recorder {
+            rpc_client_factory = rpc_client_factory.with_message_size_recorder(recorder.clone());
+        }
+        if let Some(auditor) = &self.operation_auditor {
+            rpc_client_factory = rpc_client_factory.with_operation_auditor(auditor.clone());
+        }
+        if let Some(resolver) = &self.resolver {
+            rpc_client_factory = rpc_client_factory.with_resolver(resolver.clone());
+        }
+        #[cfg(feature = "tracing")]
+        if let Some(injector) = &self.trace_context_injector {
+            rpc_client_factory = rpc_client_factory.with_trace_context_injector(injector.clone());
+        }
+
+        rpc_client_factory
+    }
+
+    /// Build the client on the provided [`RpcClientFactory`] rather than the
+    /// grpc one, e.g. a mock for testing without a server.
+    ///
+    /// The settings consumed by the grpc factory, i.e. the authorization, the
+    /// recorder, the auditor, the trace context injector and the keep-alive
+    /// overrides, are ignored, while the observer still applies.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no endpoint is provided, as [`Builder::build`] does.
+    pub fn build_with_factory<F: RpcClientFactory + ?Sized + 'static>(
+        self,
+        rpc_client_factory: Arc<F>,
+    ) -> Arc<dyn DbClient> {
+        let eager_connect = self.eager_connect;
+        let (client, warmup) = self.build_observed(rpc_client_factory);
+        if eager_connect {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(warmup);
+            }
+        }
+
+        client
+    }
+
+    async fn build_connected_with_factory<F: RpcClientFactory + ?Sized + 'static>(
+        self,
+        rpc_client_factory: Arc<F>,
+    ) -> Result<Arc<dyn DbClient>> {
+        let (client, warmup) = self.build_observed(rpc_client_factory);
+        warmup.await?;
+
+        Ok(client)
+    }
+
+    fn build_observed<F: RpcClientFactory + ?Sized + 'static>(
+        mut self,
+        rpc_client_factory: Arc<F>,
+    ) -> (Arc<dyn DbClient>, Warmup) {
+        // The rejected endpoints are kept as is, and reported by the requests.
+        self.endpoints = std::mem::take(&mut self.endpoints)
+            .into_iter()
+            .map(|endpoint| self.normalized_endpoint(&endpoint).unwrap_or(endpoint))
+            .collect();
+        self.endpoint_zones = std::mem::take(&mut self.endpoint_zones)
+            .into_iter()
+            .map(|(endpoint, zone)| (normalize_endpoint(&endpoint).unwrap_or(endpoint), zone))
+            .collect();
+        match self.observer.take() {
+            Some(observer) => self.build_on_factory(Arc::new(ObservedRpcClientFactory::new(
                 rpc_client_factory,
-                self.endpoint,
-                self.default_database,
-            )),
+                observer,
+            ))),
+            None => self.build_on_factory(rpc_client_factory),
         }
     }
+
+    /// Build the client, along with the warmup connecting it, which is not
+    /// started until polled.
+    fn build_on_factory<F: RpcClientFactory + ?Sized + 'static>(
+        self,
+        rpc_client_factory: Arc<F>,
+    ) -> (Arc<dyn DbClient>, Warmup) {
+        match self.mode {
+            Mode::Direct => {
+                let client = Arc::new(
+                    RouteBasedImpl::new(
+                        rpc_client_factory,
+                        self.endpoints.into_iter().next().unwrap(),
+                        self.default_database,
+                        &self.rpc_config,
+                    )
+                    .with_on_retry(self.on_retry)
+                    .with_proxy_fallback(self.proxy_fallback),
+                );
+                let tables = self.prefetch_tables;
+                let warmup = {
+                    let client = client.clone();
+                    async move { client.warmup(&tables).await }.boxed()
+                };
+                (client, warmup)
+            }
+            Mode::Proxy => {
+                let client = Arc::new(
+                    RawImpl::new(
+                        rpc_client_factory,
+                        self.endpoints,
+                        self.default_database,
+                        &self.rpc_config,
+                    )
+                    .expect("The endpoints are checked on building")
+                    .with_on_retry(self.on_retry)
+                    .with_endpoint_selector(self.endpoint_selector)
+                    .with_recovery_wait(self.endpoint_recovery_wait)
+                    .with_endpoint_zones(&self.endpoint_zones),
+                );
+                let warmup = {
+                    let client = client.clone();
+                    async move { client.warmup().await }.boxed()
+                };
+                (client, warmup)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+    use futures::stream::BoxStream;
+    use horaedbproto::storage::{
+        sql_query_response::Output as OutputPb, RouteRequest as RouteRequestPb,
+        RouteResponse as RouteResponsePb, SqlQueryRequest as QueryRequestPb,
+        SqlQueryResponse as QueryResponsePb, WriteRequest as WriteRequestPb,
+        WriteResponse as WriteResponsePb,
+    };
+    use tonic::Status;
+
+    use super::{normalize_endpoint, Builder, Mode};
+    use crate::{
+        model::route::Endpoint,
+        rpc_client::{MockRpcClientFactory, MockStorageService},
+        Authorization, BasicAuthorization, Error, Result, RetryConfig, RpcClient, RpcClientFactory,
+        RpcConfig, RpcContext, ServerCapabilities, SqlQueryRequest, TlsConfig,
+    };
+
+    /// Rpc client answering every sql query with the stubbed affected rows,
+    /// and the databases of the queries are recorded.
+    struct StubRpcClient {
+        affected_rows: u32,
+        databases: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl RpcClient for StubRpcClient {
+        async fn sql_query(
+            &self,
+            _ctx: &RpcContext,
+            req: QueryRequestPb,
+        ) -> Result<QueryResponsePb> {
+            self.databases
+                .lock()
+                .unwrap()
+                .push(req.context.unwrap().database);
+            Ok(QueryResponsePb {
+                header: None,
+                output: Some(OutputPb::AffectedRows(self.affected_rows)),
+            })
+        }
+
+        async fn sql_query_stream(
+            &self,
+            _ctx: &RpcContext,
+            _req: QueryRequestPb,
+        ) -> Result<BoxStream<'static, Result<QueryResponsePb>>> {
+            Err(Error::Client("not stubbed".to_string()))
+        }
+
+        async fn write(&self, _ctx: &RpcContext, _req: WriteRequestPb) -> Result<WriteResponsePb> {
+            Err(Error::Client("not stubbed".to_string()))
+        }
+
+        async fn route(&self, _ctx: &RpcContext, _req: RouteRequestPb) -> Result<RouteResponsePb> {
+            Err(Error::Client("not stubbed".to_string()))
+        }
+
+        async fn capabilities(&self, _ctx: &RpcContext) -> Result<ServerCapabilities> {
+            Err(Error::Client("not stubbed".to_string()))
+        }
+    }
+
+    #[derive(Default)]
+    struct StubRpcClientFactory {
+        databases: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl RpcClientFactory for StubRpcClientFactory {
+        async fn build(&self, _endpoint: String) -> Result<Arc<dyn RpcClient>> {
+            Ok(Arc::new(StubRpcClient {
+                affected_rows: 42,
+                databases: self.databases.clone(),
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_with_factory() {
+        let factory: Arc<dyn RpcClientFactory> = Arc::new(StubRpcClientFactory::default());
+        let client = Builder::new("127.0.0.1:8831".to_string(), Mode::Proxy)
+            .default_database("public")
+            .build_with_factory(factory);
+
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+        let resp = client
+            .sql_query(&RpcContext::default(), &req)
+            .await
+            .unwrap();
+        assert_eq!(resp.affected_rows, 42);
+    }
+
+    #[tokio::test]
+    async fn test_build_connected() {
+        // No server is listening on the released port.
+        let addr = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        for mode in [Mode::Proxy, Mode::Direct] {
+            let start = Instant::now();
+            let err = Builder::new(addr.to_string(), mode)
+                .build_connected()
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(err, Error::Connect { .. }));
+            assert!(start.elapsed() < Duration::from_secs(1));
+        }
+
+        // It fails only if none of the proxy endpoints is connected.
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .unreachable_endpoints
+            .insert("2.2.2.2:2".to_string());
+        let client = Builder::with_endpoints(
+            vec!["1.1.1.1:1".to_string(), "2.2.2.2:2".to_string()],
+            Mode::Proxy,
+        )
+        .build_connected_with_factory(factory.clone())
+        .await
+        .unwrap();
+        assert_eq!(client.endpoints(), vec!["1.1.1.1:1".to_string()]);
+        // The unreachable endpoint is still configured.
+        assert_eq!(
+            client.effective_config().endpoints,
+            ["1.1.1.1:1", "2.2.2.2:2"]
+        );
+        let err = Builder::new("2.2.2.2:2".to_string(), Mode::Proxy)
+            .build_connected_with_factory(factory.clone())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Connect { .. }));
+
+        // The routes are prefetched in the default database.
+        factory
+            .route_table
+            .insert("t1".to_string(), Endpoint::new("1.1.1.1".to_string(), 1));
+        Builder::new("1.1.1.1:1".to_string(), Mode::Direct)
+            .default_database("public")
+            .prefetch_routes(vec!["t1".to_string()])
+            .build_connected_with_factory(factory.clone())
+            .await
+            .unwrap();
+        let err = Builder::new("1.1.1.1:1".to_string(), Mode::Direct)
+            .prefetch_routes(vec!["t1".to_string()])
+            .build_connected_with_factory(factory)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::NoDatabase));
+    }
+
+    #[tokio::test]
+    async fn test_on_retry() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .unreachable_endpoints
+            .insert("1.1.1.1:1".to_string());
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let rpc_config = RpcConfig {
+            retry: RetryConfig {
+                max_attempts: 3,
+                backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let client = {
+            let attempts = attempts.clone();
+            Builder::new("1.1.1.1:1".to_string(), Mode::Proxy)
+                .default_database("public")
+                .rpc_config(rpc_config)
+                .on_retry(move |attempt, err, _| {
+                    assert!(matches!(err, Error::Connect { .. }));
+                    attempts.lock().unwrap().push(attempt);
+                })
+                .build_with_factory(factory)
+        };
+
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+        let err = client
+            .sql_query(&RpcContext::default(), &req)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Connect { .. }));
+        assert_eq!(*attempts.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_build_and_check_auth() {
+        let (addr, service) =
+            MockStorageService::serve(|metadata, _| match metadata.get("authorization") {
+                Some(token) if token == "Bearer valid" => Ok(None),
+                _ => Err(Status::unauthenticated("token is expired")),
+            })
+            .await;
+
+        // The token is rejected even after it is refreshed.
+        let err = Builder::new(addr.clone(), Mode::Proxy)
+            .authorization(Authorization::bearer_provider(|| "expired".to_string()))
+            .build_and_check_auth()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Auth(_)), "err:{err}");
+        assert_eq!(service.requests.lock().unwrap().len(), 2);
+
+        let err = Builder::new(addr.clone(), Mode::Proxy)
+            .authorization(Authorization::bearer("expired"))
+            .build_and_check_auth()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Unauthenticated(_)), "err:{err}");
+
+        assert!(Builder::new(addr, Mode::Proxy)
+            .authorization(Authorization::bearer_provider(|| "valid".to_string()))
+            .build_and_check_auth()
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_capabilities_not_cached_on_rejection() {
+        // Only the first probe is rejected.
+        let (addr, _service) = MockStorageService::serve(|_, handled| match handled {
+            0 => Err(Status::unauthenticated("token is expired")),
+            _ => Ok(None),
+        })
+        .await;
+        let client = Builder::new(addr, Mode::Proxy).build();
+        let ctx = RpcContext::default().database("public".to_string());
+
+        let err = client.capabilities(&ctx).await.unwrap_err();
+        assert!(matches!(err, Error::Unauthenticated(_)), "err:{err}");
+        assert_eq!(
+            client.capabilities(&ctx).await.unwrap(),
+            ServerCapabilities {
+                stream_sql_query: true,
+                stream_write: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_with_basic_authorization() {
+        let (addr, service) =
+            MockStorageService::serve(|metadata, _| match metadata.get("authorization") {
+                // base64 of "user:pass".
+                Some(token) if token == "Basic dXNlcjpwYXNz" => Ok(None),
+                _ => Err(Status::unauthenticated("invalid username or password")),
+            })
+            .await;
+
+        // The struct used before `Authorization` became an enum still works.
+        let client = Builder::new(addr, Mode::Proxy)
+            .authorization(BasicAuthorization {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            })
+            .build_and_check_auth()
+            .await
+            .unwrap();
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+        let ctx = RpcContext::default().database("public".to_string());
+        let resp = client.sql_query(&ctx, &req).await.unwrap();
+        assert_eq!(resp.affected_rows, 1);
+        assert!(!service.requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_normalize_endpoint() {
+        for (endpoint, normalized) in [
+            ("127.0.0.1:8831", "127.0.0.1:8831"),
+            ("http://127.0.0.1:8831", "127.0.0.1:8831"),
+            ("HTTPS://horaedb:8831/", "horaedb:8831"),
+            (" horaedb:8831// ", "horaedb:8831"),
+            ("[::1]:8831", "[::1]:8831"),
+        ] {
+            assert_eq!(normalize_endpoint(endpoint).unwrap(), normalized);
+        }
+
+        for (endpoint, reason) in [
+            ("", "empty endpoint"),
+            ("http://", "empty endpoint"),
+            (
+                "grpc://horaedb:8831",
+                "only http and https schemes are allowed",
+            ),
+            ("horaedb:8831/api", "path is not allowed"),
+            ("horae db:8831", "whitespace is not allowed"),
+            ("horaedb", "missing port"),
+            ("horaedb:", "missing port"),
+            (":8831", "missing host"),
+            ("::1:8831", "IPv6 address must be bracketed"),
+            ("horaedb:0", "port must not be 0"),
+            ("horaedb:port", "port must be a number in 1..=65535"),
+            ("horaedb:65536", "port must be a number in 1..=65535"),
+        ] {
+            let err = normalize_endpoint(endpoint).unwrap_err();
+            assert!(
+                matches!(&err, Error::InvalidEndpoint { endpoint: e, reason: r } if e == endpoint && r == reason),
+                "endpoint:{endpoint:?}, err:{err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_endpoints() {
+        let client = Builder::new("http://127.0.0.1:8831/".to_string(), Mode::Proxy)
+            .try_build()
+            .unwrap();
+        assert_eq!(client.effective_config().endpoints, ["127.0.0.1:8831"]);
+
+        let endpoints = vec!["127.0.0.1:8831".to_string(), "127.0.0.1".to_string()];
+        let err = Builder::with_endpoints(endpoints.clone(), Mode::Proxy)
+            .try_build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidEndpoint { endpoint, .. } if endpoint == "127.0.0.1"));
+        let err = Builder::with_endpoints(endpoints, Mode::Direct)
+            .build_connected()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidEndpoint { .. }));
+
+        // No endpoint is provided.
+        let err = Builder::with_endpoints(vec![], Mode::Proxy)
+            .try_build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Client(_)), "err:{err}");
+
+        // The https endpoint is not connected in plaintext.
+        let err = Builder::new("https://127.0.0.1:8831".to_string(), Mode::Proxy)
+            .try_build()
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, Error::InvalidEndpoint { reason, .. } if reason == "https scheme requires the tls config"),
+            "err:{err}"
+        );
+        let rpc_config = RpcConfig {
+            tls: Some(TlsConfig {
+                ca_certificate: PathBuf::from("ca.pem"),
+                client_identity: None,
+                domain_name: None,
+            }),
+            ..Default::default()
+        };
+        let client = Builder::new("https://127.0.0.1:8831".to_string(), Mode::Proxy)
+            .rpc_config(rpc_config)
+            .try_build()
+            .unwrap();
+        assert_eq!(client.effective_config().endpoints, ["127.0.0.1:8831"]);
+    }
+
+    #[tokio::test]
+    async fn test_build_with_invalid_endpoint() {
+        let (addr, service) = MockStorageService::serve(|_, _| Ok(None)).await;
+        let ctx = RpcContext::default().database("public".to_string());
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+
+        // The rejected endpoints are kept as is rather than panicking, and
+        // reported by the requests.
+        for endpoint in ["127.0.0.1".to_string(), format!("https://{addr}")] {
+            let client = Builder::new(endpoint.clone(), Mode::Proxy).build();
+            assert_eq!(client.effective_config().endpoints, [endpoint]);
+            assert!(client.sql_query(&ctx, &req).await.is_err());
+        }
+        // The https endpoint is not connected in plaintext.
+        assert!(service.requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_effective_config() {
+        let rpc_config = RpcConfig {
+            default_write_timeout: Duration::from_secs(10),
+            coalesce_sql_query: true,
+            ..Default::default()
+        };
+        let client = Builder::new("127.0.0.1:8831".to_string(), Mode::Proxy)
+            .default_database("public")
+            .rpc_config(rpc_config)
+            .build();
+
+        let config = client.effective_config();
+        assert!(matches!(config.mode, Mode::Proxy));
+        assert_eq!(config.endpoints, ["127.0.0.1:8831"]);
+        assert_eq!(config.default_database.as_deref(), Some("public"));
+        assert_eq!(
+            config.rpc_config.default_write_timeout,
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            config.rpc_config.default_sql_query_timeout,
+            RpcConfig::default().default_sql_query_timeout
+        );
+        assert!(config.rpc_config.coalesce_sql_query);
+    }
+
+    #[tokio::test]
+    async fn test_default_database() {
+        let factory = Arc::new(StubRpcClientFactory::default());
+        let client = Builder::new("127.0.0.1:8831".to_string(), Mode::Proxy)
+            .default_database("public")
+            .build_with_factory(factory.clone());
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+
+        // The database in the context overrides the default one.
+        client
+            .sql_query(&RpcContext::default(), &req)
+            .await
+            .unwrap();
+        let ctx = RpcContext::default().database("other".to_string());
+        client.sql_query(&ctx, &req).await.unwrap();
+        let resp = client.sql_query_simple("select 1").await.unwrap();
+        assert_eq!(resp.affected_rows, 42);
+        assert_eq!(
+            *factory.databases.lock().unwrap(),
+            vec!["public", "other", "public"]
+        );
+
+        // No database is found.
+        let client = Builder::new("127.0.0.1:8831".to_string(), Mode::Proxy)
+            .build_with_factory(Arc::new(StubRpcClientFactory::default()));
+        let err = client.sql_query_simple("select 1").await.unwrap_err();
+        assert!(matches!(err, Error::NoDatabase));
+    }
 }
@@ -0,0 +1,151 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::{
+    db_client::DbClient,
+    model::write::{
+        ingest::{IngestOptions, IngestProgress, LineParser},
+        point::Point,
+        Request as WriteRequest, Response as WriteResponse,
+    },
+    rpc_client::RpcContext,
+    Error, Result,
+};
+
+/// Read the records line by line, and write them in batches with bounded
+/// concurrency.
+pub(crate) async fn write_from_reader<C: DbClient + ?Sized>(
+    client: &C,
+    ctx: &RpcContext,
+    table: &str,
+    reader: &mut (dyn AsyncRead + Send + Unpin),
+    options: &IngestOptions,
+) -> Result<WriteResponse> {
+    let write_batch = |points: Vec<Point>| async move {
+        let num_points = points.len();
+        let mut req = WriteRequest::default();
+        req.add_points(points);
+        client.write(ctx, &req).await.map(|resp| (num_points, resp))
+    };
+
+    let mut parser = LineParser::new(table, options);
+    let mut lines = BufReader::new(reader).lines();
+    let mut in_flight = FuturesUnordered::new();
+    let mut batch = Vec::with_capacity(options.batch_size);
+    let mut progress = IngestProgress::default();
+    let mut merged = WriteResponse::new(0, 0);
+    let mut on_written = |res: Result<(usize, WriteResponse)>, lines: usize| -> Result<()> {
+        let (num_points, resp) = res?;
+        merged.merge(resp);
+        progress.lines = lines;
+        progress.points += num_points;
+        if let Some(callback) = &options.progress {
+            callback(&progress);
+        }
+        Ok(())
+    };
+
+    let mut line_no = 0;
+    loop {
+        let line = lines
+            .next_line()
+            .await
+            .map_err(|e| Error::Client(format!("Failed to read line:{}, err:{e}", line_no + 1)))?;
+        let reached_end = line.is_none();
+        if let Some(line) = line {
+            line_no += 1;
+            let point = parser
+                .parse(&line)
+                .map_err(|e| Error::Client(format!("Failed to parse line:{line_no}, err:{e}")))?;
+            batch.extend(point);
+        }
+
+        if batch.len() >= options.batch_size.max(1) || (reached_end && !batch.is_empty()) {
+            if in_flight.len() >= options.max_concurrency.max(1) {
+                if let Some(res) = in_flight.next().await {
+                    on_written(res, line_no)?;
+                }
+            }
+            let points = std::mem::replace(&mut batch, Vec::with_capacity(options.batch_size));
+            in_flight.push(write_batch(points));
+        }
+
+        if reached_end {
+            break;
+        }
+    }
+
+    while let Some(res) = in_flight.next().await {
+        on_written(res, line_no)?;
+    }
+
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::write_from_reader;
+    use crate::{
+        db_client::raw::RawImpl,
+        model::write::ingest::{IngestFormat, IngestOptions},
+        rpc_client::{MockRpcClientFactory, RpcContext},
+        Error, RpcConfig,
+    };
+
+    #[tokio::test]
+    async fn test_write_from_reader() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = RawImpl::new(
+            factory.clone(),
+            vec!["192.168.0.1:11".to_string()],
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        )
+        .unwrap();
+        let ctx = RpcContext::default();
+
+        let progresses = Arc::new(Mutex::new(Vec::new()));
+        let mut options = IngestOptions::new(IngestFormat::Csv);
+        options.tag_columns = vec!["host".to_string()];
+        options.batch_size = 2;
+        let progresses_clone = progresses.clone();
+        options.progress = Some(Arc::new(move |progress| {
+            progresses_clone.lock().unwrap().push(progress.points)
+        }));
+
+        let mut data = "timestamp,host,value\n1,a,1\n2,a,2\n3,b,3\n".as_bytes();
+        let resp = write_from_reader(&client, &ctx, "demo", &mut data, &options)
+            .await
+            .unwrap();
+        assert_eq!(resp.success, 3);
+        let mut progresses = progresses.lock().unwrap().clone();
+        progresses.sort();
+        assert_eq!(progresses, vec![2, 3]);
+        assert_eq!(*factory.request_counts.get("192.168.0.1:11").unwrap(), 2);
+
+        let mut data = "timestamp,host,value\n1,a,1\nx,a,2\n".as_bytes();
+        let err = write_from_reader(&client, &ctx, "demo", &mut data, &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Client(msg) if msg.contains("line:3")));
+    }
+}
@@ -15,41 +15,300 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::sync::Arc;
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
+use futures::{stream::BoxStream, StreamExt};
 use horaedbproto::storage;
 use tokio::sync::OnceCell;
+use tonic::{Code, Status};
 
 use crate::{
+    circuit_breaker::CircuitBreaker,
+    config::CircuitBreakerConfig,
+    db_client::{selector::RecentLatency, stats::RequestCounter},
+    errors::Error,
     model::{
-        sql_query::{Request as SqlQueryRequest, Response as SqlQueryResponse},
-        write::{Request as WriteRequest, Response as WriteResponse, WriteTableRequestPbsBuilder},
+        capabilities::ServerCapabilities,
+        route::RouteInfo,
+        sql_query::{
+            comment::strip_comments, Request as SqlQueryRequest, Response as SqlQueryResponse,
+        },
+        write::{
+            columnar::ColumnarBatch, Request as WriteRequest, Response as WriteResponse,
+            WriteTableRequestPbsBuilder,
+        },
     },
-    rpc_client::{RpcClient, RpcClientFactory, RpcContext},
+    rpc_client::{ResponseMeta, RpcClient, RpcClientFactory, RpcContext},
+    single_flight::SingleFlight,
     Result,
 };
 
+/// Key to find the identical sql queries.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct SqlQueryKey {
+    database: String,
+    tables: Vec<String>,
+    sql: String,
+    accepted_codes: Vec<u32>,
+    /// The sorted custom headers, e.g. the queries of different tenants or
+    /// with different credentials can't be coalesced.
+    headers: Vec<(String, String)>,
+    /// The limits of the query, e.g. the one with a shorter timeout can't
+    /// share the result of a longer one.
+    timeout: Option<Duration>,
+    first_response_timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    expected_rows: Option<usize>,
+}
+
+type SqlQueryCoalescer =
+    SingleFlight<SqlQueryKey, std::result::Result<SqlQueryResponse, Arc<Error>>>;
+
 /// Inner client for both standalone and route based modes.
 ///
-/// Now, [`InnerClient`] just wraps [`RpcClient`] simply.
-pub(crate) struct InnerClient<F: RpcClientFactory> {
+/// Now, [`InnerClient`] just wraps [`RpcClient`] simply, which is recreated
+/// after being idle for the `idle_timeout`.
+pub(crate) struct InnerClient<F: RpcClientFactory + ?Sized> {
     factory: Arc<F>,
     endpoint: String,
-    inner_client: OnceCell<Arc<dyn RpcClient>>,
+    inner_client: tokio::sync::Mutex<Option<Arc<dyn RpcClient>>>,
+    /// Whether the `inner_client` is built, which is readable without locking
+    /// it.
+    connected: AtomicBool,
+    last_used: Mutex<Instant>,
+    idle_timeout: Option<Duration>,
+    sql_query_coalescer: Option<SqlQueryCoalescer>,
+    strip_sql_comments: bool,
+    capabilities: OnceCell<ServerCapabilities>,
+    circuit_breaker: Option<CircuitBreaker>,
+    counter: RequestCounter,
+    in_flight: AtomicUsize,
+    latency: RecentLatency,
+}
+
+/// Decrease the requests in flight on drop, even if the request is cancelled.
+struct InFlightRequest<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
-impl<F: RpcClientFactory> InnerClient<F> {
-    pub fn new(factory: Arc<F>, endpoint: String) -> Self {
+impl<F: RpcClientFactory + ?Sized> InnerClient<F> {
+    pub fn new(
+        factory: Arc<F>,
+        endpoint: String,
+        coalesce_sql_query: bool,
+        strip_sql_comments: bool,
+        circuit_breaker: Option<CircuitBreakerConfig>,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
         InnerClient {
             factory,
             endpoint,
-            inner_client: OnceCell::new(),
+            inner_client: tokio::sync::Mutex::new(None),
+            connected: AtomicBool::new(false),
+            last_used: Mutex::new(Instant::now()),
+            idle_timeout,
+            sql_query_coalescer: coalesce_sql_query.then(SingleFlight::new),
+            strip_sql_comments,
+            capabilities: OnceCell::new(),
+            circuit_breaker: circuit_breaker.map(CircuitBreaker::new),
+            counter: RequestCounter::default(),
+            in_flight: AtomicUsize::new(0),
+            latency: RecentLatency::default(),
+        }
+    }
+
+    /// Tell whether the requests to the endpoint fail fast now.
+    pub fn is_circuit_open(&self) -> bool {
+        self.circuit_breaker
+            .as_ref()
+            .map(CircuitBreaker::is_open)
+            .unwrap_or(false)
+    }
+
+    /// Issue the request, including the connecting, through the circuit
+    /// breaker if any.
+    async fn guarded<T>(&self, request: impl Future<Output = Result<T>>) -> Result<T> {
+        match &self.circuit_breaker {
+            Some(breaker) => breaker.call(&self.endpoint, request).await,
+            None => request.await,
+        }
+    }
+
+    /// Issue the request by [`guarded`](Self::guarded), and count it and
+    /// record its latency if it succeeds.
+    async fn counted<T>(&self, request: impl Future<Output = Result<T>>) -> Result<T> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let _in_flight = InFlightRequest(&self.in_flight);
+        let started = Instant::now();
+        let result = self.guarded(request).await;
+        if result.is_ok() {
+            self.latency.record(started.elapsed());
+        }
+        self.counter.record(&result);
+        result
+    }
+
+    pub fn counter(&self) -> &RequestCounter {
+        &self.counter
+    }
+
+    /// The number of the requests in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The moving average of the latencies of the succeeded requests.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency.get()
+    }
+
+    /// Tell whether the endpoint is connected and the connection is not
+    /// idle for the `idle_timeout`, so the next request saves the handshake.
+    pub fn is_warm(&self) -> bool {
+        if !self.connected.load(Ordering::Relaxed) {
+            return false;
+        }
+        match self.idle_timeout {
+            Some(timeout) => self.last_used.lock().unwrap().elapsed() < timeout,
+            None => true,
         }
     }
 
-    #[inline]
-    async fn init(&self) -> Result<Arc<dyn RpcClient>> {
-        self.factory.build(self.endpoint.clone()).await
+    async fn init(&self, ctx: &RpcContext) -> Result<Arc<dyn RpcClient>> {
+        let build = self.factory.build(self.endpoint.clone());
+        let built = match ctx.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, build)
+                .await
+                .map_err(|e| Error::connect(self.endpoint.clone(), e))
+                .and_then(|built| built),
+            None => build.await,
+        };
+        self.connected.store(built.is_ok(), Ordering::Relaxed);
+        built
+    }
+
+    /// Connect the endpoint if not connected yet, e.g. before the request
+    /// which can't be reissued on another endpoint.
+    pub async fn connect(&self, ctx: &RpcContext) -> Result<()> {
+        self.guarded(async { self.client_handle(ctx).await.map(|_| ()) })
+            .await
+    }
+
+    /// Get the client, which is built on the first use or after being idle
+    /// for the `idle_timeout`.
+    async fn client_handle(&self, ctx: &RpcContext) -> Result<Arc<dyn RpcClient>> {
+        let mut client = self.inner_client.lock().await;
+        let now = Instant::now();
+        let idle = {
+            let mut last_used = self.last_used.lock().unwrap();
+            let idle = self
+                .idle_timeout
+                .map(|timeout| now.duration_since(*last_used) >= timeout)
+                .unwrap_or(false);
+            *last_used = now;
+            idle
+        };
+
+        match &*client {
+            Some(client) if !idle => Ok(client.clone()),
+            _ => {
+                let built = self.init(ctx).await?;
+                *client = Some(built.clone());
+                Ok(built)
+            }
+        }
+    }
+
+    /// Rebuild the `stale` client unless it has been rebuilt by others.
+    async fn rebuild(
+        &self,
+        ctx: &RpcContext,
+        stale: &Arc<dyn RpcClient>,
+    ) -> Result<Arc<dyn RpcClient>> {
+        let mut client = self.inner_client.lock().await;
+        if let Some(client) = &*client {
+            if Arc::as_ptr(client) as *const () != Arc::as_ptr(stale) as *const () {
+                return Ok(client.clone());
+            }
+        }
+
+        let built = self.init(ctx).await?;
+        *client = Some(built.clone());
+        Ok(built)
+    }
+
+    /// Issue the request on the client, and retry it once on the rebuilt client
+    /// if the connection is broken, which is enabled along with the
+    /// `idle_timeout`.
+    async fn call<T, Fut>(
+        &self,
+        ctx: &RpcContext,
+        request: impl Fn(Arc<dyn RpcClient>) -> Fut,
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let client = self.client_handle(ctx).await?;
+        match request(client.clone()).await {
+            Err(Error::Rpc(status))
+                if self.idle_timeout.is_some() && status.code() == Code::Unavailable =>
+            {
+                let client = self.rebuild(ctx, &client).await?;
+                request(client).await
+            }
+            result => result,
+        }
+    }
+
+    fn make_sql_query_request(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> storage::SqlQueryRequest {
+        let sql = if self.strip_sql_comments {
+            strip_comments(&req.sql)
+        } else {
+            req.sql.clone()
+        };
+
+        storage::SqlQueryRequest {
+            context: Some(storage::RequestContext {
+                database: ctx.database.clone().unwrap(),
+            }),
+            tables: req.tables.clone(),
+            sql,
+        }
+    }
+
+    pub async fn sql_query_stream_internal(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<BoxStream<'static, Result<SqlQueryResponse>>> {
+        assert!(ctx.database.is_some());
+
+        let stream = self
+            .counted(self.call(ctx, |client_handle| async move {
+                let req_pb = self.make_sql_query_request(ctx, req);
+                client_handle.sql_query_stream(ctx, req_pb).await
+            }))
+            .await?;
+
+        Ok(stream
+            .map(|resp| resp.and_then(|resp_pb| SqlQueryResponse::decode(resp_pb, None)))
+            .boxed())
     }
 
     pub async fn sql_query_internal(
@@ -59,21 +318,88 @@ impl<F: RpcClientFactory> InnerClient<F> {
     ) -> Result<SqlQueryResponse> {
         assert!(ctx.database.is_some());
 
-        let client_handle = self.inner_client.get_or_try_init(|| self.init()).await?;
-        let req_ctx = storage::RequestContext {
-            database: ctx.database.clone().unwrap(),
+        self.counted(self.call(ctx, |client_handle| {
+            self.sql_query_once(client_handle, ctx, req)
+        }))
+        .await
+    }
+
+    async fn sql_query_once(
+        &self,
+        client_handle: Arc<dyn RpcClient>,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<SqlQueryResponse> {
+        let req_pb = self.make_sql_query_request(ctx, req);
+
+        let coalescer = match &self.sql_query_coalescer {
+            Some(coalescer) => coalescer,
+            None => return Self::sql_query_with_stats(&client_handle, ctx, req_pb).await,
         };
-        let req_pb = storage::SqlQueryRequest {
-            context: Some(req_ctx),
+
+        let mut headers: Vec<_> = ctx
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        headers.sort();
+        let key = SqlQueryKey {
+            database: ctx.database.clone().unwrap(),
             tables: req.tables.clone(),
-            sql: req.sql.clone(),
+            sql: req_pb.sql.clone(),
+            accepted_codes: ctx.accepted_codes.clone(),
+            headers,
+            timeout: ctx.timeout,
+            first_response_timeout: ctx.first_response_timeout,
+            total_timeout: ctx.total_timeout,
+            deadline: ctx.deadline,
+            expected_rows: ctx.expected_rows,
         };
+        // The shared rpc must not be cancelled by the first caller, and each
+        // caller is cancelled by its own token outside instead.
+        let mut ctx = ctx.clone();
+        ctx.cancellation_token = None;
+        coalescer
+            .run(key, move || async move {
+                Self::sql_query_with_stats(&client_handle, &ctx, req_pb)
+                    .await
+                    .map_err(Arc::new)
+            })
+            .await
+            .map_err(Error::unshare)
+    }
 
-        client_handle
-            .as_ref()
-            .sql_query(ctx, req_pb)
+    /// Issue the sql query, and attach the statistics in the metadata of the
+    /// response.
+    async fn sql_query_with_stats(
+        client_handle: &Arc<dyn RpcClient>,
+        ctx: &RpcContext,
+        req_pb: storage::SqlQueryRequest,
+    ) -> Result<SqlQueryResponse> {
+        let (resp_pb, meta) = client_handle
+            .sql_query_with_meta(ctx, req_pb)
             .await
-            .and_then(SqlQueryResponse::try_from)
+            .map_err(Error::without_meta)?;
+        let resp = SqlQueryResponse::decode(resp_pb, ctx.expected_rows)?;
+        Ok(resp.with_stats(&meta))
+    }
+
+    /// Issue the sql query without coalescing, because the metadata belongs to
+    /// the response of each query.
+    pub async fn sql_query_with_meta_internal(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<(SqlQueryResponse, ResponseMeta)> {
+        assert!(ctx.database.is_some());
+
+        self.counted(self.call(ctx, |client_handle| async move {
+            let req_pb = self.make_sql_query_request(ctx, req);
+            let (resp_pb, meta) = client_handle.sql_query_with_meta(ctx, req_pb).await?;
+            let resp = SqlQueryResponse::decode(resp_pb, ctx.expected_rows)?.with_stats(&meta);
+            Ok((resp, meta))
+        }))
+        .await
     }
 
     pub async fn write_internal(
@@ -83,19 +409,159 @@ impl<F: RpcClientFactory> InnerClient<F> {
     ) -> Result<WriteResponse> {
         assert!(ctx.database.is_some());
 
-        let client_handle = self.inner_client.get_or_try_init(|| self.init()).await?;
-        let req_ctx = storage::RequestContext {
-            database: ctx.database.clone().unwrap(),
-        };
-        let write_table_request_pbs = WriteTableRequestPbsBuilder(req.clone()).build();
-        let req_pb = storage::WriteRequest {
-            context: Some(req_ctx),
-            table_requests: write_table_request_pbs,
+        self.counted(self.call(ctx, |client_handle| async move {
+            let req_ctx = storage::RequestContext {
+                database: ctx.database.clone().unwrap(),
+            };
+            let write_table_request_pbs = WriteTableRequestPbsBuilder(req.clone()).build();
+            let req_pb = storage::WriteRequest {
+                context: Some(req_ctx),
+                table_requests: write_table_request_pbs,
+            };
+
+            client_handle
+                .write(ctx, req_pb)
+                .await
+                .map(|resp_pb| resp_pb.into())
+        }))
+        .await
+    }
+
+    pub async fn write_columnar_internal(
+        &self,
+        ctx: &RpcContext,
+        batch: &ColumnarBatch,
+    ) -> Result<WriteResponse> {
+        assert!(ctx.database.is_some());
+
+        self.counted(self.call(ctx, |client_handle| async move {
+            let req_ctx = storage::RequestContext {
+                database: ctx.database.clone().unwrap(),
+            };
+            let req_pb = storage::WriteRequest {
+                context: Some(req_ctx),
+                table_requests: vec![batch.to_pb()],
+            };
+
+            client_handle
+                .write(ctx, req_pb)
+                .await
+                .map(|resp_pb| resp_pb.into())
+        }))
+        .await
+    }
+
+    pub async fn write_stream_internal(
+        &self,
+        ctx: &RpcContext,
+        reqs: BoxStream<'static, WriteRequest>,
+    ) -> Result<WriteResponse> {
+        assert!(ctx.database.is_some());
+
+        self.counted(async {
+            let client_handle = self.client_handle(ctx).await?;
+            let database = ctx.database.clone().unwrap();
+            let req_pbs = reqs
+                .map(move |req| storage::WriteRequest {
+                    context: Some(storage::RequestContext {
+                        database: database.clone(),
+                    }),
+                    table_requests: WriteTableRequestPbsBuilder(req).build(),
+                })
+                .boxed();
+
+            client_handle
+                .write_stream(ctx, req_pbs)
+                .await
+                .map(|resp_pb| resp_pb.into())
+        })
+        .await
+    }
+
+    /// Route the tables, and the tables without a route are skipped.
+    pub async fn route_internal(
+        &self,
+        ctx: &RpcContext,
+        tables: &[String],
+    ) -> Result<Vec<RouteInfo>> {
+        assert!(ctx.database.is_some());
+
+        let resp_pb = self
+            .counted(self.call(ctx, |client_handle| async move {
+                let req_pb = storage::RouteRequest {
+                    context: Some(storage::RequestContext {
+                        database: ctx.database.clone().unwrap(),
+                    }),
+                    tables: tables.to_vec(),
+                };
+                client_handle.route(ctx, req_pb).await
+            }))
+            .await?;
+
+        Ok(resp_pb
+            .routes
+            .into_iter()
+            .filter_map(|route_pb| {
+                route_pb.endpoint.map(|endpoint_pb| RouteInfo {
+                    table: route_pb.table,
+                    endpoint: endpoint_pb.into(),
+                })
+            })
+            .collect())
+    }
+
+    /// Probe the server by routing no table, bounded by the `timeout` in the
+    /// `ctx` including the connecting.
+    ///
+    /// The error returned by the server is ignored, which means the server is
+    /// reachable.
+    pub async fn health_check_internal(&self, ctx: &RpcContext) -> Result<()> {
+        self.probe(ctx, "health check").await.map(|_| ())
+    }
+
+    /// Measure the round trip to the server by the same probe as
+    /// [`health_check_internal`](Self::health_check_internal).
+    pub async fn ping_internal(&self, ctx: &RpcContext) -> Result<Duration> {
+        self.probe(ctx, "ping").await
+    }
+
+    /// Probe by a route request of no table, and return the round trip of it
+    /// excluding the time to connect.
+    async fn probe(&self, ctx: &RpcContext, name: &str) -> Result<Duration> {
+        assert!(ctx.database.is_some());
+
+        let probe = async {
+            let client_handle = self.client_handle(ctx).await?;
+            let req_pb = storage::RouteRequest {
+                context: Some(storage::RequestContext {
+                    database: ctx.database.clone().unwrap(),
+                }),
+                tables: vec![],
+            };
+            let started = Instant::now();
+            match client_handle.route(ctx, req_pb).await {
+                Ok(_) | Err(Error::Server(_)) => Ok(started.elapsed()),
+                Err(e) => Err(e),
+            }
         };
+        match ctx.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, probe).await.map_err(|_| {
+                Error::Rpc(Status::deadline_exceeded(format!(
+                    "{name} timeout:{timeout:?} is exceeded"
+                )))
+            })?,
+            None => probe.await,
+        }
+    }
+
+    /// Probe the capabilities of the server, and the result is cached.
+    pub async fn capabilities_internal(&self, ctx: &RpcContext) -> Result<ServerCapabilities> {
+        assert!(ctx.database.is_some());
 
-        client_handle
-            .write(ctx, req_pb)
+        let client_handle = self.client_handle(ctx).await?;
+        self.capabilities
+            .get_or_try_init(|| client_handle.capabilities(ctx))
             .await
-            .map(|resp_pb| resp_pb.into())
+            .cloned()
     }
 }
@@ -17,27 +17,486 @@
 
 //! This module provides the definition and implementations of the `DbClient`.
 
+mod admin;
 mod builder;
+mod ingest;
 mod inner;
-mod raw;
+mod pinned;
+pub(crate) mod raw;
 mod route_based;
+mod selector;
+mod shutdown;
+mod stats;
 
+use std::{borrow::Cow, collections::HashMap, fmt, future::Future, sync::Arc, time::Duration};
+
+pub use admin::AdminClient;
+#[cfg(feature = "arrow")]
+use arrow::record_batch::RecordBatch;
 use async_trait::async_trait;
 pub use builder::{Builder, Mode};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+pub use selector::{EndpointSelector, EndpointState, LeastInFlight, RoundRobin};
+pub use stats::{ClientStats, EndpointStats, RouteCacheStats};
+use tokio::io::AsyncRead;
 
 use crate::{
+    config::{EffectiveConfig, RetryBudgetConfig, RetryConfig},
     model::{
-        sql_query::{Request as SqlQueryRequest, Response as SqlQueryResponse},
-        write::{Request as WriteRequest, Response as WriteResponse},
+        capabilities::ServerCapabilities,
+        route::RouteInfo,
+        sql_query::{
+            batch::BatchOptions,
+            in_list::InListQuery,
+            paged::{contains_limit, page_sql},
+            Request as SqlQueryRequest, Response as SqlQueryResponse,
+        },
+        write::{
+            columnar::ColumnarBatch, ingest::IngestOptions, point::IntoPoint,
+            Request as WriteRequest, Response as WriteResponse,
+        },
     },
-    rpc_client::RpcContext,
-    Result,
+    retry_budget::RetryBudget,
+    rpc_client::{ConnectionStats, ResponseMeta, RpcContext},
+    Error, Result,
 };
 
+/// The client of HoraeDB, built by the [`Builder`].
+///
+/// Besides `sql_query` and `write`, the implementations outside this crate
+/// must provide `with_endpoint_affinity`, `effective_config` and `admin`, and
+/// the other methods have the default implementations:
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use horaedb_client::{
+///     db_client::{DbClient, Mode},
+///     AdminClient, EffectiveConfig, Result, RpcConfig, RpcContext, SqlQueryRequest,
+///     SqlQueryResponse, WriteRequest, WriteResponse,
+/// };
+///
+/// struct NoopClient;
+///
+/// #[async_trait]
+/// impl DbClient for NoopClient {
+///     async fn sql_query(&self, _: &RpcContext, _: &SqlQueryRequest) -> Result<SqlQueryResponse> {
+///         Ok(SqlQueryResponse::default())
+///     }
+///
+///     async fn write(&self, _: &RpcContext, _: &WriteRequest) -> Result<WriteResponse> {
+///         Ok(WriteResponse::new(0, 0))
+///     }
+///
+///     fn with_endpoint_affinity(&self) -> Box<dyn DbClient + '_> {
+///         Box::new(NoopClient)
+///     }
+///
+///     fn effective_config(&self) -> EffectiveConfig {
+///         EffectiveConfig {
+///             mode: Mode::Proxy,
+///             endpoints: vec!["127.0.0.1:8831".to_string()],
+///             default_database: None,
+///             rpc_config: RpcConfig::default(),
+///         }
+///     }
+///
+///     fn admin(&self) -> AdminClient<'_> {
+///         AdminClient::new(self)
+///     }
+/// }
+/// ```
 #[async_trait]
 pub trait DbClient: Send + Sync {
     async fn sql_query(&self, ctx: &RpcContext, req: &SqlQueryRequest) -> Result<SqlQueryResponse>;
+
+    /// Issue the sql query whose result is streamed in multiple responses
+    /// rather than buffered in one, which suits the large result set.
+    ///
+    /// The error of any response is yielded in the stream, and the retry and
+    /// the `total_timeout` only apply to establishing the stream.
+    ///
+    /// By default, the result is buffered by [`DbClient::sql_query`] and
+    /// streamed in one response.
+    async fn sql_query_stream(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<BoxStream<'static, Result<SqlQueryResponse>>> {
+        let resp = self.sql_query(ctx, req).await?;
+        Ok(futures::stream::once(async move { Ok(resp) }).boxed())
+    }
+
+    /// Issue the sql query, and return the metadata of the response too, e.g.
+    /// the `x-request-id` for correlating with the server logs.
+    ///
+    /// The query is never coalesced. The metadata of the failed rpc can be
+    /// found by [`Error::as_tonic_status`], and the one of the response failed
+    /// by its status is attached by [`Error::WithMeta`].
+    ///
+    /// By default, the query is issued by [`DbClient::sql_query`] and the
+    /// metadata is empty.
+    async fn sql_query_with_meta(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<(SqlQueryResponse, ResponseMeta)> {
+        let resp = self.sql_query(ctx, req).await?;
+        Ok((resp, ResponseMeta::default()))
+    }
+
     async fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse>;
+
+    /// Write the streamed requests over one client-streaming rpc, and return
+    /// the merged response, which suits the continuous ingestion.
+    ///
+    /// The stream can't be replayed, so it's never retried. In the route based
+    /// mode, the requests are written one by one by [`DbClient::write`]
+    /// instead, because the tables may be routed to different endpoints.
+    ///
+    /// By default, the requests are written one by one by [`DbClient::write`]
+    /// too, and [`Error::PartialWrite`] is returned if some of them are
+    /// written before the failure.
+    async fn write_stream(
+        &self,
+        ctx: &RpcContext,
+        mut reqs: BoxStream<'static, WriteRequest>,
+    ) -> Result<WriteResponse> {
+        let mut written = WriteResponse::new(0, 0);
+        let mut any_ok = false;
+        while let Some(req) = reqs.next().await {
+            match self.write(ctx, &req).await {
+                Ok(resp) => {
+                    any_ok = true;
+                    written.merge(resp);
+                }
+                Err(e) if !any_ok => return Err(e),
+                Err(e) => {
+                    return Err(Error::PartialWrite {
+                        written,
+                        source: Box::new(e),
+                    })
+                }
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Write the rows in the [`ColumnarBatch`], which is the faster but less
+    /// ergonomic alternative to [`DbClient::write`].
+    ///
+    /// It fails with [`Error::Unsupported`] by default.
+    async fn write_columnar(
+        &self,
+        _ctx: &RpcContext,
+        _batch: &ColumnarBatch,
+    ) -> Result<WriteResponse> {
+        Err(Error::Unsupported("write_columnar"))
+    }
+
+    /// Issue the `sql` with the default context, i.e. in the default database
+    /// of the client.
+    ///
+    /// No table is provided for routing, so it fails in `Direct` mode, where
+    /// [`DbClient::sql_query`] with the tables is required.
+    async fn sql_query_simple(&self, sql: &str) -> Result<SqlQueryResponse> {
+        let req = SqlQueryRequest {
+            tables: vec![],
+            sql: sql.to_string(),
+        };
+        self.sql_query(&RpcContext::default(), &req).await
+    }
+
+    /// Issue the queries split by the [`InListQuery`], and merge their
+    /// responses in order.
+    async fn sql_query_in_list(
+        &self,
+        ctx: &RpcContext,
+        query: &InListQuery,
+    ) -> Result<SqlQueryResponse> {
+        let mut merged = SqlQueryResponse::default();
+        for req in query.build()? {
+            let resp = self.sql_query(ctx, &req).await?;
+            merged.affected_rows += resp.affected_rows;
+            merged.rows.extend(resp.rows);
+            merged.warnings.extend(resp.warnings);
+        }
+
+        Ok(merged)
+    }
+
+    /// Issue the sql queries with at most `max_concurrency` in flight, and the
+    /// results are in the order of the `requests`.
+    ///
+    /// Each sql query is a separate rpc, as there is no batch rpc in the
+    /// protocol. The failure of a sql query is returned in its place, unless
+    /// `fail_fast` is set, where the first failure in the order of the
+    /// `requests` is returned, and the following sql queries are not issued or
+    /// dropped in flight.
+    async fn sql_query_batch(
+        &self,
+        ctx: &RpcContext,
+        requests: &[SqlQueryRequest],
+        options: &BatchOptions,
+    ) -> Result<Vec<Result<SqlQueryResponse>>> {
+        // Collected in advance, otherwise the future can't be proved `Send`.
+        let calls: Vec<_> = requests
+            .iter()
+            .map(|req| self.sql_query(ctx, req))
+            .collect();
+        let results = futures::stream::iter(calls).buffered(options.max_concurrency.max(1));
+        if options.fail_fast {
+            results.map_ok(Ok).try_collect().await
+        } else {
+            Ok(results.collect().await)
+        }
+    }
+
+    /// Page through the result of the sql query by issuing it with the
+    /// successive `LIMIT {page_size} OFFSET {n}` clauses, and a page is
+    /// streamed for each of them.
+    ///
+    /// It stops after the page of fewer rows than the `page_size`, and the
+    /// empty page is streamed only if it is the first one. The sql should
+    /// be ordered by `ORDER BY` to make the pages stable, and the one with
+    /// the `LIMIT` or `OFFSET` clause is rejected. The stream ends after
+    /// the first error.
+    fn sql_query_paged<'a>(
+        &'a self,
+        ctx: &'a RpcContext,
+        req: &'a SqlQueryRequest,
+        page_size: usize,
+    ) -> BoxStream<'a, Result<SqlQueryResponse>> {
+        let check = if page_size == 0 {
+            Err(Error::Client("Page size should be positive".to_string()))
+        } else if contains_limit(&req.sql) {
+            Err(Error::Client(format!(
+                "Sql to page through should not contain LIMIT or OFFSET, sql:{}",
+                req.sql
+            )))
+        } else {
+            Ok(())
+        };
+
+        futures::stream::unfold(Some((check, 0)), move |state| async move {
+            let (check, offset) = state?;
+            if let Err(e) = check {
+                return Some((Err(e), None));
+            }
+
+            let page_req = SqlQueryRequest {
+                tables: req.tables.clone(),
+                sql: page_sql(&req.sql, page_size, offset),
+            };
+            match self.sql_query(ctx, &page_req).await {
+                Ok(resp) if resp.rows.len() < page_size => {
+                    if offset > 0 && resp.rows.is_empty() {
+                        None
+                    } else {
+                        Some((Ok(resp), None))
+                    }
+                }
+                Ok(resp) => Some((Ok(resp), Some((Ok(()), offset + page_size)))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+        .boxed()
+    }
+
+    /// Issue the sql query by [`DbClient::sql_query_stream`], and stream the
+    /// rows of each response as the arrow [`RecordBatch`]es, so the large
+    /// result set can be processed incrementally.
+    ///
+    /// Each response is converted by
+    /// [`to_record_batches`](SqlQueryResponse::to_record_batches), and the
+    /// responses without the rows yield nothing.
+    #[cfg(feature = "arrow")]
+    async fn sql_query_record_batch_stream(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<BoxStream<'static, Result<RecordBatch>>> {
+        let stream = self.sql_query_stream(ctx, req).await?;
+        Ok(stream
+            .and_then(|resp| async move { resp.to_record_batches() })
+            .map_ok(|batches| futures::stream::iter(batches.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed())
+    }
+
+    /// Stream the records of the `table` from the `reader`, and write them in
+    /// batches.
+    ///
+    /// The error of parsing tells the offending line number.
+    async fn write_from_reader(
+        &self,
+        ctx: &RpcContext,
+        table: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+        options: &IngestOptions,
+    ) -> Result<WriteResponse> {
+        ingest::write_from_reader(self, ctx, table, reader, options).await
+    }
+
+    /// Detect the optional features supported by the server.
+    ///
+    /// The capabilities are detected by issuing the optional rpcs with the
+    /// empty requests, which are traced and audited as the sql query and the
+    /// write, but nothing is queried or written. The detected capabilities are
+    /// cached per endpoint, while the failures, e.g. the rejected credentials,
+    /// are not. It fails with [`Error::Unsupported`] by default.
+    async fn capabilities(&self, _ctx: &RpcContext) -> Result<ServerCapabilities> {
+        Err(Error::Unsupported("capabilities"))
+    }
+
+    /// Check whether the server can be reached by a lightweight probe, e.g. for
+    /// the readiness probe.
+    ///
+    /// In `Proxy` mode, all the proxy endpoints are probed. In `Direct` mode,
+    /// the endpoint for routing and the endpoints routed to are probed. The
+    /// `timeout` in the `ctx` bounds the probe of each endpoint.
+    ///
+    /// By default, the server is probed by [`DbClient::ping`].
+    async fn health_check(&self, ctx: &RpcContext) -> Result<()> {
+        self.ping(ctx).await.map(|_| ())
+    }
+
+    /// Measure the round trip of the cheapest request to the server, which
+    /// excludes the time to connect.
+    ///
+    /// In `Proxy` mode, the endpoint chosen next is pinged. In `Direct` mode,
+    /// it is the endpoint for routing. The `timeout` in the `ctx` bounds the
+    /// ping.
+    ///
+    /// It fails with [`Error::Unsupported`] by default.
+    async fn ping(&self, _ctx: &RpcContext) -> Result<Duration> {
+        Err(Error::Unsupported("ping"))
+    }
+
+    /// Measure the round trip to each endpoint, which are the ones probed by
+    /// [`DbClient::health_check`].
+    ///
+    /// By default, only the first endpoint in the
+    /// [`DbClient::effective_config`] is pinged by [`DbClient::ping`].
+    async fn ping_all(&self, ctx: &RpcContext) -> Vec<(String, Result<Duration>)> {
+        let endpoint = self
+            .effective_config()
+            .endpoints
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        vec![(endpoint, self.ping(ctx).await)]
+    }
+
+    /// Get a handle pinning all the requests issued by it to one endpoint.
+    ///
+    /// In `Direct` mode, the endpoint is resolved by routing the first request
+    /// issued by the handle. If the pinned endpoint fails, the handle returns
+    /// [`Error::PinnedEndpoint`] rather than re-routing to another endpoint.
+    fn with_endpoint_affinity(&self) -> Box<dyn DbClient + '_>;
+
+    /// Get the configuration resolved from the defaults and the options set on
+    /// the [`Builder`].
+    fn effective_config(&self) -> EffectiveConfig;
+
+    /// Get the healthy endpoints the requests are sent to, starting from the
+    /// one to be chosen next.
+    ///
+    /// In `Proxy` mode, the requests are distributed across these endpoints in
+    /// round-robin. In `Direct` mode, it is the endpoint for routing.
+    fn endpoints(&self) -> Vec<String> {
+        self.effective_config().endpoints
+    }
+
+    /// Get the statistics of the connections to each endpoint, by which the
+    /// flapping connections can be found.
+    ///
+    /// It is empty by default, i.e. the statistics are not collected.
+    fn connection_stats(&self) -> HashMap<String, ConnectionStats> {
+        HashMap::new()
+    }
+
+    /// Get the snapshot of the statistics of the client, including the
+    /// requests in flight, the counters of the requests and the statistics of
+    /// each endpoint.
+    ///
+    /// The handles got by
+    /// [`with_endpoint_affinity`](DbClient::with_endpoint_affinity) share the
+    /// statistics with the client.
+    ///
+    /// By default, the [`ClientStats::default`] is returned, whose all-zero
+    /// counters mean the statistics are not collected rather than no request
+    /// is issued, so don't take it as an idle client.
+    fn stats(&self) -> ClientStats {
+        ClientStats::default()
+    }
+
+    /// Get the client for the DDL operations.
+    fn admin(&self) -> AdminClient<'_>;
+
+    /// Remove all the rows in the table, see [`AdminClient::truncate_table`].
+    async fn truncate_table(
+        &self,
+        ctx: &RpcContext,
+        table: &str,
+        if_exists: bool,
+    ) -> Result<SqlQueryResponse> {
+        self.admin().truncate_table(ctx, table, if_exists).await
+    }
+
+    /// Find the endpoints which the tables are routed to.
+    ///
+    /// In `Direct` mode, the cached routes are returned unless the
+    /// `bypass_route_cache` of the `ctx` is set, and the tables without a route
+    /// fall back to the endpoint for routing, as the requests do. In `Proxy`
+    /// mode, the routes are fetched from the proxy endpoint every time,
+    /// and the tables without a route are skipped.
+    ///
+    /// It fails with [`Error::Unsupported`] by default.
+    async fn route(&self, _ctx: &RpcContext, _tables: &[String]) -> Result<Vec<RouteInfo>> {
+        Err(Error::Unsupported("route"))
+    }
+
+    /// Evict the cached routes of the tables, so that they will be routed
+    /// again by the following requests.
+    ///
+    /// It is a no-op in `Proxy` mode and by default.
+    fn evict_routes(&self, _tables: &[String]) {}
+
+    /// Shut down the client gracefully, the new sql queries and writes are
+    /// rejected by [`Error::Closed`] and the ones in flight are waited for at
+    /// most `timeout`.
+    ///
+    /// An error is returned if some requests are still in flight after the
+    /// `timeout`. For the streaming requests, only opening the stream is
+    /// waited for. The handles got by
+    /// [`with_endpoint_affinity`](DbClient::with_endpoint_affinity) share the
+    /// state with the client.
+    ///
+    /// By default, it returns at once and the later requests are not
+    /// rejected, i.e. [`Error::Closed`] is not enforced, so the implementors
+    /// tracking the requests in flight should override it.
+    async fn shutdown(&self, _timeout: Duration) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl dyn DbClient + '_ {
+    /// Write the `rows` converted into the points by [`IntoPoint`].
+    ///
+    /// It is provided on the trait object, since a generic method would make
+    /// [`DbClient`] not object safe.
+    pub async fn write_points<T: IntoPoint>(
+        &self,
+        ctx: &RpcContext,
+        rows: impl IntoIterator<Item = T>,
+    ) -> Result<WriteResponse> {
+        let mut req = WriteRequest::default();
+        for row in rows {
+            req.add_point(row.into_point());
+        }
+        self.write(ctx, &req).await
+    }
 }
 
 pub(crate) fn resolve_database(
@@ -53,3 +512,662 @@ pub(crate) fn resolve_database(
         (None, None) => Err(crate::Error::NoDatabase),
     }
 }
+
+/// Reject the sql longer than the `max_sql_length` if any.
+pub(crate) fn check_sql_length(max_sql_length: Option<usize>, sql: &str) -> Result<()> {
+    match max_sql_length {
+        Some(max_sql_length) if sql.len() > max_sql_length => Err(Error::Client(format!(
+            "Sql is too long, length:{}, max_sql_length:{max_sql_length}",
+            sql.len()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Split the write request into the ones of at most `max_rows` points, and
+/// issue them by `write` with at most `max_concurrency` in flight.
+///
+/// The request is written as is if no split is needed. Otherwise, the succeeded
+/// points are merged, and [`Error::PartialWrite`] is returned if some of the
+/// split requests fail.
+pub(crate) async fn write_in_batches<'a, F, Fut>(
+    max_rows: Option<usize>,
+    max_concurrency: usize,
+    req: &'a WriteRequest,
+    write: F,
+) -> Result<WriteResponse>
+where
+    F: Fn(Cow<'a, WriteRequest>) -> Fut,
+    Fut: Future<Output = Result<WriteResponse>>,
+{
+    let batches = match max_rows {
+        Some(max_rows) => split_write_request(req, max_rows),
+        None => Vec::new(),
+    };
+    if batches.len() <= 1 {
+        return write(Cow::Borrowed(req)).await;
+    }
+
+    let mut results =
+        futures::stream::iter(batches.into_iter().map(|batch| write(Cow::Owned(batch))))
+            .buffer_unordered(max_concurrency.max(1));
+    let mut written = WriteResponse::new(0, 0);
+    let mut first_error = None;
+    let mut any_ok = false;
+    while let Some(result) = results.next().await {
+        match result {
+            Ok(resp) => {
+                any_ok = true;
+                written.merge(resp);
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_error {
+        None => Ok(written),
+        Some(e) if !any_ok => Err(e),
+        Some(e) => Err(Error::PartialWrite {
+            written,
+            source: Box::new(e),
+        }),
+    }
+}
+
+/// Split the points in the request into the batches of at most `max_rows`
+/// points, and no split is made if the request is small enough.
+fn split_write_request(req: &WriteRequest, max_rows: usize) -> Vec<WriteRequest> {
+    let max_rows = max_rows.max(1);
+    let num_points: usize = req.point_groups.values().map(Vec::len).sum();
+    if num_points <= max_rows {
+        return Vec::new();
+    }
+
+    // Split the tables in a fixed order.
+    let mut tables: Vec<_> = req.point_groups.keys().collect();
+    tables.sort();
+    let mut batches = Vec::with_capacity((num_points + max_rows - 1) / max_rows);
+    let mut batch = WriteRequest::default();
+    let mut batch_rows = 0;
+    for table in tables {
+        for point in &req.point_groups[table] {
+            batch.add_point(point.clone());
+            batch_rows += 1;
+            if batch_rows == max_rows {
+                batches.push(std::mem::take(&mut batch));
+                batch_rows = 0;
+            }
+        }
+    }
+    if batch_rows > 0 {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+/// Bound the whole request by the `total_timeout` in the `ctx` if any, and
+/// abort it once the `cancellation_token` in the `ctx` is cancelled.
+pub(crate) async fn with_total_timeout<T>(
+    ctx: &RpcContext,
+    request: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    ctx.check_deadline()?;
+    let request = ctx.cancellable(request);
+    match (ctx.total_timeout, ctx.remaining()) {
+        // Bound the whole request by the deadline too, e.g. the backoff of the
+        // retries.
+        (total_timeout, Some(remaining))
+            if total_timeout.map_or(true, |timeout| remaining < timeout) =>
+        {
+            tokio::time::timeout(remaining, request)
+                .await
+                .map_err(|_| Error::DeadlineExceeded)?
+        }
+        (Some(timeout), _) => tokio::time::timeout(timeout, request)
+            .await
+            .map_err(|_| Error::DeadlineExceeded)?,
+        (None, _) => request.await,
+    }
+}
+
+type OnRetry = dyn Fn(u32, &Error, Duration) + Send + Sync;
+
+/// The callback invoked before each retry, see [`Builder::on_retry`].
+#[derive(Clone)]
+pub(crate) struct RetryHook(Arc<OnRetry>);
+
+impl RetryHook {
+    pub fn new(on_retry: impl Fn(u32, &Error, Duration) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(on_retry))
+    }
+}
+
+impl fmt::Debug for RetryHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryHook").finish_non_exhaustive()
+    }
+}
+
+/// The [`RetryConfig`] along with the retry budget shared by all the requests
+/// of the client, and its clones share the budget too.
+#[derive(Clone)]
+pub(crate) struct RetryPolicy {
+    config: RetryConfig,
+    budget: Option<Arc<RetryBudget>>,
+    on_retry: Option<RetryHook>,
+}
+
+impl RetryPolicy {
+    pub fn new(config: RetryConfig, budget: Option<RetryBudgetConfig>) -> Self {
+        Self {
+            config,
+            budget: budget.map(|budget| Arc::new(RetryBudget::new(budget))),
+            on_retry: None,
+        }
+    }
+
+    pub fn with_on_retry(mut self, on_retry: Option<RetryHook>) -> Self {
+        self.on_retry = on_retry;
+        self
+    }
+
+    fn try_withdraw(&self) -> bool {
+        self.budget
+            .as_ref()
+            .map(|budget| budget.try_withdraw())
+            .unwrap_or(true)
+    }
+
+    fn deposit(&self) {
+        if let Some(budget) = &self.budget {
+            budget.deposit();
+        }
+    }
+}
+
+/// Retry the request by the [`RetryPolicy`].
+pub(crate) async fn with_retry<T, F, Fut>(policy: &RetryPolicy, mut request: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let config = &policy.config;
+    let mut attempts = 1;
+    loop {
+        match request().await {
+            Err(e)
+                if attempts < config.max_attempts
+                    && config.is_retryable(&e)
+                    && policy.try_withdraw() =>
+            {
+                let backoff = config.jittered_backoff(attempts);
+                if let Some(RetryHook(on_retry)) = &policy.on_retry {
+                    on_retry(u32::try_from(attempts).unwrap_or(u32::MAX), &e, backoff);
+                }
+                tokio::time::sleep(backoff).await;
+                attempts += 1;
+            }
+            res => {
+                if res.is_ok() {
+                    policy.deposit();
+                }
+                return res;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+    use futures::StreamExt;
+    use tonic::Code;
+
+    use super::{
+        check_sql_length, split_write_request, with_retry, with_total_timeout, write_in_batches,
+        AdminClient, ClientStats, DbClient, Mode, RetryHook, RetryPolicy,
+    };
+    use crate::{
+        config::EffectiveConfig,
+        errors::ServerError,
+        model::{
+            sql_query::{Request as SqlQueryRequest, Response as SqlQueryResponse},
+            value::Value,
+            write::{point::PointBuilder, Request as WriteRequest, Response as WriteResponse},
+        },
+        Error, RetryBudgetConfig, RetryConfig, RpcConfig, RpcContext,
+    };
+
+    fn make_write_request(points: &[(&str, i64)]) -> WriteRequest {
+        let mut req = WriteRequest::default();
+        for (table, timestamp) in points {
+            let point = PointBuilder::new(*table)
+                .timestamp(*timestamp)
+                .field("value", Value::Int64(*timestamp))
+                .build()
+                .unwrap();
+            req.add_point(point);
+        }
+        req
+    }
+
+    #[test]
+    fn test_check_sql_length() {
+        assert!(check_sql_length(None, "SELECT 1").is_ok());
+        assert!(check_sql_length(Some(8), "SELECT 1").is_ok());
+        match check_sql_length(Some(7), "SELECT 1") {
+            Err(Error::Client(msg)) => assert!(msg.contains("length:8"), "msg:{msg}"),
+            res => panic!("unexpected result:{res:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_total_timeout() {
+        let slow_request = || async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(())
+        };
+
+        let ctx = RpcContext::default();
+        assert!(with_total_timeout(&ctx, slow_request()).await.is_ok());
+
+        let ctx = RpcContext::default().total_timeout(Duration::from_millis(10));
+        let res = with_total_timeout(&ctx, slow_request()).await;
+        assert!(matches!(res, Err(Error::DeadlineExceeded)));
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let slow_request = || async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(())
+        };
+
+        // The deadline earlier than the total timeout bounds the request.
+        let ctx = RpcContext::default()
+            .total_timeout(Duration::from_secs(10))
+            .deadline(Instant::now() + Duration::from_millis(10));
+        let res = with_total_timeout(&ctx, slow_request()).await;
+        assert!(matches!(res, Err(Error::DeadlineExceeded)));
+
+        let ctx = RpcContext::default()
+            .total_timeout(Duration::from_millis(10))
+            .deadline(Instant::now() + Duration::from_secs(10));
+        let res = with_total_timeout(&ctx, slow_request()).await;
+        // The total timeout fails the same as the deadline.
+        assert!(matches!(res, Err(Error::DeadlineExceeded)));
+
+        // The request is not issued after the deadline.
+        let ctx = RpcContext::default().deadline(Instant::now() - Duration::from_millis(1));
+        let res: Result<(), _> = with_total_timeout(&ctx, async { panic!("issued") }).await;
+        assert!(matches!(res, Err(Error::DeadlineExceeded)));
+    }
+
+    #[tokio::test]
+    async fn test_retry_server_codes() {
+        let policy = RetryPolicy::new(
+            RetryConfig {
+                max_attempts: 3,
+                backoff: Duration::from_millis(1),
+                retryable_server_codes: vec![503],
+                ..Default::default()
+            },
+            None,
+        );
+        let server_error = |code| {
+            Error::Server(ServerError {
+                code,
+                msg: String::new(),
+            })
+        };
+
+        let mut attempts = 0;
+        let res = with_retry(&policy, || {
+            attempts += 1;
+            let res = if attempts < 3 {
+                Err(server_error(503))
+            } else {
+                Ok(())
+            };
+            async move { res }
+        })
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let res: crate::Result<()> = with_retry(&policy, || {
+            attempts += 1;
+            async { Err(server_error(400)) }
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_transport_errors() {
+        let policy = RetryPolicy::new(
+            RetryConfig {
+                max_attempts: 3,
+                backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            None,
+        );
+
+        let mut attempts = 0;
+        let res = with_retry(&policy, || {
+            attempts += 1;
+            let res = if attempts < 2 {
+                Err(Error::Rpc(tonic::Status::unavailable("restarting")))
+            } else {
+                Ok(())
+            };
+            async move { res }
+        })
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(attempts, 2);
+
+        // The server errors with business codes are never retried by default.
+        let mut attempts = 0;
+        let res: crate::Result<()> = with_retry(&policy, || {
+            attempts += 1;
+            async {
+                Err(Error::Server(ServerError {
+                    code: 500,
+                    msg: String::new(),
+                }))
+            }
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let res: crate::Result<()> = with_retry(&policy, || {
+            attempts += 1;
+            async { Err(Error::Rpc(tonic::Status::invalid_argument("bad sql"))) }
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_on_retry() {
+        let retries = Arc::new(Mutex::new(Vec::new()));
+        let on_retry = {
+            let retries = retries.clone();
+            RetryHook::new(move |attempt, err, backoff| {
+                let code = err.as_tonic_status().unwrap().code();
+                retries.lock().unwrap().push((attempt, code, backoff));
+            })
+        };
+        let policy = RetryPolicy::new(
+            RetryConfig {
+                max_attempts: 4,
+                backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            None,
+        )
+        .with_on_retry(Some(on_retry));
+
+        let mut attempts = 0;
+        let res = with_retry(&policy, || {
+            attempts += 1;
+            let res = if attempts < 3 {
+                Err(Error::Rpc(tonic::Status::unavailable("restarting")))
+            } else {
+                Ok(())
+            };
+            async move { res }
+        })
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(
+            *retries.lock().unwrap(),
+            vec![
+                (1, Code::Unavailable, Duration::from_millis(1)),
+                (2, Code::Unavailable, Duration::from_millis(2)),
+            ]
+        );
+
+        // Not called for the error not retried.
+        retries.lock().unwrap().clear();
+        let res: crate::Result<()> = with_retry(&policy, || async {
+            Err(Error::Rpc(tonic::Status::invalid_argument("bad request")))
+        })
+        .await;
+        assert!(res.is_err());
+        assert!(retries.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget() {
+        let policy = RetryPolicy::new(
+            RetryConfig {
+                max_attempts: 3,
+                backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            Some(RetryBudgetConfig {
+                ratio: 0.5,
+                max_tokens: 2.0,
+            }),
+        );
+        let attempts = |policy: RetryPolicy, succeed: bool| async move {
+            let mut attempts = 0;
+            let _ = with_retry(&policy, || {
+                attempts += 1;
+                let res = if succeed {
+                    Ok(())
+                } else {
+                    Err(Error::Rpc(tonic::Status::unavailable("outage")))
+                };
+                async move { res }
+            })
+            .await;
+            attempts
+        };
+
+        // The budget is exhausted by the retries of the first failed request.
+        assert_eq!(attempts(policy.clone(), false).await, 3);
+        assert_eq!(attempts(policy.clone(), false).await, 1);
+        assert_eq!(attempts(policy.clone(), false).await, 1);
+
+        // Two succeeded requests earn one retry again, and the clones share the
+        // budget.
+        assert_eq!(attempts(policy.clone(), true).await, 1);
+        assert_eq!(attempts(policy.clone(), true).await, 1);
+        assert_eq!(attempts(policy.clone(), false).await, 2);
+        assert_eq!(attempts(policy, false).await, 1);
+    }
+
+    #[test]
+    fn test_split_write_request() {
+        let req = make_write_request(&[("a", 1), ("a", 2), ("b", 3), ("b", 4), ("b", 5)]);
+        assert!(split_write_request(&req, 5).is_empty());
+
+        let batches = split_write_request(&req, 2);
+        let batch_sizes: Vec<Vec<_>> = batches
+            .iter()
+            .map(|batch| {
+                let mut sizes: Vec<_> = batch
+                    .point_groups
+                    .iter()
+                    .map(|(table, points)| (table.clone(), points.len()))
+                    .collect();
+                sizes.sort();
+                sizes
+            })
+            .collect();
+        assert_eq!(
+            batch_sizes,
+            vec![
+                vec![("a".to_string(), 2)],
+                vec![("b".to_string(), 2)],
+                vec![("b".to_string(), 1)],
+            ]
+        );
+
+        let batches = split_write_request(&req, 3);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].point_groups["a"].len(), 2);
+        assert_eq!(batches[0].point_groups["b"].len(), 1);
+        assert_eq!(batches[1].point_groups["b"].len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_write_in_batches() {
+        let write = |req: std::borrow::Cow<'_, WriteRequest>| {
+            let num_points: usize = req.point_groups.values().map(Vec::len).sum();
+            let fail = req.point_groups.contains_key("bad");
+            async move {
+                if fail {
+                    Err(Error::Server(ServerError {
+                        code: 500,
+                        msg: "bad table".to_string(),
+                    }))
+                } else {
+                    Ok(WriteResponse::new(num_points as u32, 0))
+                }
+            }
+        };
+
+        let req = make_write_request(&[("a", 1), ("a", 2), ("a", 3), ("b", 4), ("b", 5)]);
+        let resp = write_in_batches(None, 2, &req, write).await.unwrap();
+        assert_eq!(resp.success, 5);
+        let resp = write_in_batches(Some(2), 2, &req, write).await.unwrap();
+        assert_eq!(resp.success, 5);
+
+        let req = make_write_request(&[("a", 1), ("a", 2), ("bad", 3), ("bad", 4), ("c", 5)]);
+        match write_in_batches(Some(2), 2, &req, write).await {
+            Err(Error::PartialWrite { written, source }) => {
+                assert_eq!(written.success, 3);
+                assert!(matches!(*source, Error::Server(e) if e.msg == "bad table"));
+            }
+            res => panic!("unexpected result:{res:?}"),
+        }
+
+        // No batch succeeds.
+        let req = make_write_request(&[("bad", 1), ("bad", 2), ("bad", 3)]);
+        assert!(matches!(
+            write_in_batches(Some(2), 2, &req, write).await,
+            Err(Error::Server(_))
+        ));
+    }
+
+    /// The client implementing only the required methods.
+    struct MinimalClient;
+
+    #[async_trait]
+    impl DbClient for MinimalClient {
+        async fn sql_query(
+            &self,
+            _ctx: &RpcContext,
+            req: &SqlQueryRequest,
+        ) -> crate::Result<SqlQueryResponse> {
+            Ok(SqlQueryResponse {
+                affected_rows: req.sql.len() as u32,
+                ..Default::default()
+            })
+        }
+
+        async fn write(
+            &self,
+            _ctx: &RpcContext,
+            req: &WriteRequest,
+        ) -> crate::Result<WriteResponse> {
+            if req.point_groups.contains_key("bad") {
+                return Err(Error::Client("bad table".to_string()));
+            }
+            let num_points: usize = req.point_groups.values().map(Vec::len).sum();
+            Ok(WriteResponse::new(num_points as u32, 0))
+        }
+
+        fn with_endpoint_affinity(&self) -> Box<dyn DbClient + '_> {
+            Box::new(MinimalClient)
+        }
+
+        fn effective_config(&self) -> EffectiveConfig {
+            EffectiveConfig {
+                mode: Mode::Proxy,
+                endpoints: vec!["127.0.0.1:8831".to_string()],
+                default_database: None,
+                rpc_config: RpcConfig::default(),
+            }
+        }
+
+        fn admin(&self) -> AdminClient<'_> {
+            AdminClient::new(self)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_default_methods() {
+        let client = MinimalClient;
+        let ctx = RpcContext::default();
+        let req = SqlQueryRequest {
+            tables: vec![],
+            sql: "select 1".to_string(),
+        };
+
+        let resps: Vec<_> = client
+            .sql_query_stream(&ctx, &req)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(resps.len(), 1);
+        assert_eq!(resps[0].as_ref().unwrap().affected_rows, 8);
+        let (resp, meta) = client.sql_query_with_meta(&ctx, &req).await.unwrap();
+        assert_eq!(resp.affected_rows, 8);
+        assert!(meta.entries.is_empty());
+
+        let reqs = vec![
+            make_write_request(&[("a", 1), ("a", 2)]),
+            make_write_request(&[("bad", 3)]),
+        ];
+        match client
+            .write_stream(&ctx, futures::stream::iter(reqs).boxed())
+            .await
+        {
+            Err(Error::PartialWrite { written, .. }) => assert_eq!(written.success, 2),
+            res => panic!("unexpected result:{res:?}"),
+        }
+
+        // The ones without a sensible default are unsupported.
+        assert!(matches!(
+            client.capabilities(&ctx).await,
+            Err(Error::Unsupported("capabilities"))
+        ));
+        assert!(matches!(
+            client.health_check(&ctx).await,
+            Err(Error::Unsupported("ping"))
+        ));
+        let rtts = client.ping_all(&ctx).await;
+        assert_eq!(rtts.len(), 1);
+        assert_eq!(rtts[0].0, "127.0.0.1:8831");
+        assert!(matches!(
+            client.route(&ctx, &["demo".to_string()]).await,
+            Err(Error::Unsupported("route"))
+        ));
+        assert!(client.connection_stats().is_empty());
+        assert_eq!(client.stats(), ClientStats::default());
+
+        // The shutdown doesn't reject the later requests.
+        client.shutdown(Duration::from_secs(1)).await.unwrap();
+        client.sql_query(&ctx, &req).await.unwrap();
+    }
+}
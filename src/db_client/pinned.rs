@@ -0,0 +1,441 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::{collections::HashMap, pin::Pin, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+use tokio::sync::OnceCell;
+
+use crate::{
+    config::EffectiveConfig,
+    db_client::{
+        admin::AdminClient, check_sql_length, inner::InnerClient, route_based::RouteBasedImpl,
+        shutdown::InFlight, with_retry, with_total_timeout, write_in_batches, ClientStats,
+        DbClient, RetryPolicy,
+    },
+    model::{
+        capabilities::ServerCapabilities,
+        route::RouteInfo,
+        sql_query::{Request as SqlQueryRequest, Response as SqlQueryResponse},
+        write::{columnar::ColumnarBatch, Request as WriteRequest, Response as WriteResponse},
+    },
+    rpc_client::{ConnectionStats, ResponseMeta, RpcClientFactory, RpcContext},
+    Error, Result,
+};
+
+type PinnedClient<F> = (String, Arc<InnerClient<F>>);
+
+/// Client pinning all the requests to one endpoint.
+///
+/// The endpoint is pinned on creation, or resolved by routing the first request
+/// when created from [`RouteBasedImpl`].
+pub(crate) struct PinnedImpl<'a, F: RpcClientFactory + ?Sized> {
+    parent: &'a dyn DbClient,
+    route_based: Option<&'a RouteBasedImpl<F>>,
+    /// The requests in flight of the parent.
+    in_flight: &'a InFlight,
+    default_database: Option<String>,
+    /// The retry policy of the parent, sharing its retry budget.
+    retry: RetryPolicy,
+    max_sql_length: Option<usize>,
+    max_write_batch_rows: Option<usize>,
+    max_write_batch_concurrency: usize,
+    pinned: OnceCell<PinnedClient<F>>,
+}
+
+impl<'a, F: RpcClientFactory + ?Sized> PinnedImpl<'a, F> {
+    pub fn with_pinned(
+        parent: &'a dyn DbClient,
+        in_flight: &'a InFlight,
+        retry: RetryPolicy,
+        default_database: Option<String>,
+        endpoint: String,
+        client: Arc<InnerClient<F>>,
+    ) -> Self {
+        let rpc_config = parent.effective_config().rpc_config;
+        Self {
+            parent,
+            route_based: None,
+            in_flight,
+            default_database,
+            retry,
+            max_sql_length: rpc_config.max_sql_length,
+            max_write_batch_rows: rpc_config.max_write_batch_rows,
+            max_write_batch_concurrency: rpc_config.max_write_batch_concurrency,
+            pinned: OnceCell::new_with(Some((endpoint, client))),
+        }
+    }
+
+    pub fn with_route_based(
+        route_based: &'a RouteBasedImpl<F>,
+        in_flight: &'a InFlight,
+        retry: RetryPolicy,
+        default_database: Option<String>,
+    ) -> Self {
+        let rpc_config = route_based.effective_config().rpc_config;
+        Self {
+            parent: route_based,
+            route_based: Some(route_based),
+            in_flight,
+            default_database,
+            retry,
+            max_sql_length: rpc_config.max_sql_length,
+            max_write_batch_rows: rpc_config.max_write_batch_rows,
+            max_write_batch_concurrency: rpc_config.max_write_batch_concurrency,
+            pinned: OnceCell::new(),
+        }
+    }
+
+    async fn pinned_client(&self, ctx: &RpcContext, tables: &[String]) -> Result<&PinnedClient<F>> {
+        self.pinned
+            .get_or_try_init(|| async {
+                match self.route_based {
+                    Some(route_based) => {
+                        let (endpoint, client) = route_based.route_client(ctx, tables).await?;
+                        Ok((endpoint.to_string(), client))
+                    }
+                    None => Err(Error::Client("no endpoint to pin".to_string())),
+                }
+            })
+            .await
+    }
+}
+
+/// The failures of connecting the pinned endpoint will just be surfaced
+/// instead of re-routing.
+fn pinned_endpoint_error(endpoint: &str, e: Error) -> Error {
+    match e {
+        Error::Connect { .. } | Error::Rpc(_) | Error::CircuitOpen { .. } => {
+            Error::PinnedEndpoint {
+                endpoint: endpoint.to_string(),
+                source: Box::new(e),
+            }
+        }
+        e => e,
+    }
+}
+
+#[async_trait]
+impl<'a, F: RpcClientFactory + ?Sized> DbClient for PinnedImpl<'a, F> {
+    async fn sql_query(&self, ctx: &RpcContext, req: &SqlQueryRequest) -> Result<SqlQueryResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        check_sql_length(self.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || async {
+                    let (endpoint, client) = self.pinned_client(&ctx, &req.tables).await?;
+                    client
+                        .sql_query_internal(&ctx, req)
+                        .await
+                        .map_err(|e| pinned_endpoint_error(endpoint, e))
+                }),
+            ))
+            .await
+    }
+
+    async fn sql_query_with_meta(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<(SqlQueryResponse, ResponseMeta)> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        check_sql_length(self.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || async {
+                    let (endpoint, client) = self.pinned_client(&ctx, &req.tables).await?;
+                    client
+                        .sql_query_with_meta_internal(&ctx, req)
+                        .await
+                        .map_err(|e| pinned_endpoint_error(endpoint, e))
+                }),
+            ))
+            .await
+    }
+
+    async fn sql_query_stream(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<BoxStream<'static, Result<SqlQueryResponse>>> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        check_sql_length(self.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || async {
+                    let (endpoint, client) = self.pinned_client(&ctx, &req.tables).await?;
+                    client
+                        .sql_query_stream_internal(&ctx, req)
+                        .await
+                        .map_err(|e| pinned_endpoint_error(endpoint, e))
+                }),
+            ))
+            .await
+    }
+
+    async fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        let ctx = &ctx;
+        self.in_flight
+            .track(with_total_timeout(
+                ctx,
+                write_in_batches(
+                    self.max_write_batch_rows,
+                    self.max_write_batch_concurrency,
+                    req,
+                    |req| async move {
+                        let tables: Vec<_> = req.point_groups.keys().cloned().collect();
+                        with_retry(&self.retry, || async {
+                            let (endpoint, client) = self.pinned_client(ctx, &tables).await?;
+                            client
+                                .write_internal(ctx, &req)
+                                .await
+                                .map_err(|e| pinned_endpoint_error(endpoint, e))
+                        })
+                        .await
+                    },
+                ),
+            ))
+            .await
+    }
+
+    async fn write_stream(
+        &self,
+        ctx: &RpcContext,
+        reqs: BoxStream<'static, WriteRequest>,
+    ) -> Result<WriteResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        self.in_flight
+            .track(with_total_timeout(&ctx, async {
+                // The endpoint is resolved by the tables of the first request if
+                // not pinned yet.
+                let mut reqs = reqs.peekable();
+                let tables: Vec<_> = match Pin::new(&mut reqs).peek().await {
+                    Some(req) => req.point_groups.keys().cloned().collect(),
+                    None => return Ok(WriteResponse::new(0, 0)),
+                };
+                let (endpoint, client) = self.pinned_client(&ctx, &tables).await?;
+                client
+                    .write_stream_internal(&ctx, reqs.boxed())
+                    .await
+                    .map_err(|e| pinned_endpoint_error(endpoint, e))
+            }))
+            .await
+    }
+
+    async fn write_columnar(
+        &self,
+        ctx: &RpcContext,
+        batch: &ColumnarBatch,
+    ) -> Result<WriteResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        let tables = vec![batch.table().to_string()];
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || async {
+                    let (endpoint, client) = self.pinned_client(&ctx, &tables).await?;
+                    client
+                        .write_columnar_internal(&ctx, batch)
+                        .await
+                        .map_err(|e| pinned_endpoint_error(endpoint, e))
+                }),
+            ))
+            .await
+    }
+
+    async fn capabilities(&self, ctx: &RpcContext) -> Result<ServerCapabilities> {
+        match self.pinned.get() {
+            Some((endpoint, client)) => {
+                let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+                client
+                    .capabilities_internal(&ctx)
+                    .await
+                    .map_err(|e| pinned_endpoint_error(endpoint, e))
+            }
+            None => self.parent.capabilities(ctx).await,
+        }
+    }
+
+    async fn route(&self, ctx: &RpcContext, tables: &[String]) -> Result<Vec<RouteInfo>> {
+        self.parent.route(ctx, tables).await
+    }
+
+    async fn health_check(&self, ctx: &RpcContext) -> Result<()> {
+        match self.pinned.get() {
+            Some((endpoint, client)) => {
+                let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+                client
+                    .health_check_internal(&ctx)
+                    .await
+                    .map_err(|e| pinned_endpoint_error(endpoint, e))
+            }
+            None => self.parent.health_check(ctx).await,
+        }
+    }
+
+    async fn ping(&self, ctx: &RpcContext) -> Result<Duration> {
+        match self.pinned.get() {
+            Some((endpoint, client)) => {
+                let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+                client
+                    .ping_internal(&ctx)
+                    .await
+                    .map_err(|e| pinned_endpoint_error(endpoint, e))
+            }
+            None => self.parent.ping(ctx).await,
+        }
+    }
+
+    async fn ping_all(&self, ctx: &RpcContext) -> Vec<(String, Result<Duration>)> {
+        match self.pinned.get() {
+            Some((endpoint, _)) => vec![(endpoint.clone(), self.ping(ctx).await)],
+            None => self.parent.ping_all(ctx).await,
+        }
+    }
+
+    fn with_endpoint_affinity(&self) -> Box<dyn DbClient + '_> {
+        let pinned = self
+            .pinned
+            .get()
+            .map(|(endpoint, client)| (endpoint.clone(), client.clone()));
+
+        Box::new(PinnedImpl {
+            parent: self.parent,
+            route_based: self.route_based,
+            in_flight: self.in_flight,
+            default_database: self.default_database.clone(),
+            retry: self.retry.clone(),
+            max_sql_length: self.max_sql_length,
+            max_write_batch_rows: self.max_write_batch_rows,
+            max_write_batch_concurrency: self.max_write_batch_concurrency,
+            pinned: OnceCell::new_with(pinned),
+        })
+    }
+
+    fn effective_config(&self) -> EffectiveConfig {
+        self.parent.effective_config()
+    }
+
+    fn endpoints(&self) -> Vec<String> {
+        match self.pinned.get() {
+            Some((endpoint, _)) => vec![endpoint.clone()],
+            None => self.parent.endpoints(),
+        }
+    }
+
+    fn connection_stats(&self) -> HashMap<String, ConnectionStats> {
+        self.parent.connection_stats()
+    }
+
+    fn stats(&self) -> ClientStats {
+        self.parent.stats()
+    }
+
+    fn admin(&self) -> AdminClient<'_> {
+        AdminClient::new(self)
+    }
+
+    fn evict_routes(&self, tables: &[String]) {
+        self.parent.evict_routes(tables)
+    }
+
+    async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        self.parent.shutdown(timeout).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, sync::Arc};
+
+    use crate::{
+        db_client::{route_based::RouteBasedImpl, DbClient},
+        model::{
+            route::Endpoint,
+            sql_query::Request as SqlQueryRequest,
+            value::Value,
+            write::{point::PointBuilder, Request as WriteRequest},
+        },
+        rpc_client::{MockRpcClientFactory, RpcContext},
+        RpcConfig,
+    };
+
+    fn make_write_request(table: &str) -> WriteRequest {
+        let point = PointBuilder::new(table)
+            .timestamp(42)
+            .field("value", Value::Int32(42))
+            .build()
+            .unwrap();
+        let mut req = WriteRequest::default();
+        req.add_point(point);
+        req
+    }
+
+    #[tokio::test]
+    async fn test_pin_routed_endpoint() {
+        let endpoint1 = Endpoint::new("192.168.0.1".to_string(), 11);
+        let endpoint2 = Endpoint::new("192.168.0.2".to_string(), 12);
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .route_table
+            .insert("table1".to_string(), endpoint1.clone());
+        factory
+            .route_table
+            .insert("table2".to_string(), endpoint2.clone());
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        let ctx = RpcContext::default();
+
+        // The endpoint of table1 is pinned by the first request, and the write to
+        // table2 is also sent to it.
+        let pinned = client.with_endpoint_affinity();
+        let query_req = SqlQueryRequest {
+            tables: vec!["table1".to_string()],
+            sql: "select * from table1".to_string(),
+        };
+        pinned.sql_query(&ctx, &query_req).await.unwrap();
+        let resp = pinned
+            .write(&ctx, &make_write_request("table2"))
+            .await
+            .unwrap();
+        assert_eq!(resp.success, 1);
+
+        // The unpinned client still routes the write of table2 to its own endpoint.
+        client
+            .write(&ctx, &make_write_request("table2"))
+            .await
+            .unwrap();
+
+        let request_counts: HashMap<_, _> = factory
+            .request_counts
+            .iter()
+            .map(|pair| (pair.key().clone(), *pair.value()))
+            .collect();
+        let expected_counts =
+            HashMap::from([(endpoint1.to_string(), 2), (endpoint2.to_string(), 1)]);
+        assert_eq!(request_counts, expected_counts);
+    }
+}
@@ -15,46 +15,1490 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
+use futures::{future::join_all, stream::BoxStream};
 
 use crate::{
-    db_client::{inner::InnerClient, DbClient},
+    config::EffectiveConfig,
+    db_client::{
+        admin::AdminClient,
+        check_sql_length,
+        inner::InnerClient,
+        pinned::PinnedImpl,
+        selector::{EndpointSelector, EndpointState, RoundRobin},
+        shutdown::InFlight,
+        stats::merge_endpoint_stats,
+        with_retry, with_total_timeout, write_in_batches, ClientStats, DbClient, Mode, RetryHook,
+        RetryPolicy,
+    },
     model::{
+        capabilities::ServerCapabilities,
+        route::RouteInfo,
         sql_query::{Request as SqlQueryRequest, Response as SqlQueryResponse},
-        write::{Request as WriteRequest, Response as WriteResponse},
+        write::{columnar::ColumnarBatch, Request as WriteRequest, Response as WriteResponse},
     },
-    rpc_client::{RpcClientFactory, RpcContext},
-    Result,
+    rpc_client::{ConnectionStats, ResponseMeta, RpcClientFactory, RpcContext},
+    Error, Result, RpcConfig,
 };
 
+/// How long the endpoint failing to connect is skipped.
+const UNHEALTHY_DURATION: Duration = Duration::from_secs(30);
+
+/// The interval of polling the endpoints again while waiting for any of them to
+/// recover.
+const RECOVERY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// One of the proxy endpoints.
+struct ProxyEndpoint<F: RpcClientFactory + ?Sized> {
+    endpoint: String,
+    zone: Option<String>,
+    client: Arc<InnerClient<F>>,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl<F: RpcClientFactory + ?Sized> ProxyEndpoint<F> {
+    fn is_healthy(&self, now: Instant) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(until) => until <= now,
+            None => true,
+        }
+    }
+
+    fn mark_unhealthy(&self) {
+        *self.unhealthy_until.lock().unwrap() = Some(Instant::now() + UNHEALTHY_DURATION);
+    }
+}
+
 /// Client for horaedb of standalone mode.
 ///
-/// Now, [`RawImpl`] just wraps [`InnerClient`] simply.
-pub struct RawImpl<F: RpcClientFactory> {
-    inner_client: InnerClient<F>,
+/// The requests are distributed across the proxy endpoints by the
+/// [`EndpointSelector`], in round-robin by default, and the endpoint failing to
+/// connect is skipped for a while.
+pub struct RawImpl<F: RpcClientFactory + ?Sized> {
+    factory: Arc<F>,
+    endpoints: Vec<ProxyEndpoint<F>>,
+    selector: Arc<dyn EndpointSelector>,
     default_database: Option<String>,
+    rpc_config: RpcConfig,
+    retry: RetryPolicy,
+    in_flight: InFlight,
+    recovery_wait: Option<Duration>,
 }
 
-impl<F: RpcClientFactory> RawImpl<F> {
-    pub fn new(factory: Arc<F>, endpoint: String, default_database: Option<String>) -> Self {
-        Self {
-            inner_client: InnerClient::new(factory, endpoint),
+impl<F: RpcClientFactory + ?Sized> RawImpl<F> {
+    /// Fails with [`Error::Client`] if the `endpoints` is empty.
+    pub fn new(
+        factory: Arc<F>,
+        endpoints: Vec<String>,
+        default_database: Option<String>,
+        rpc_config: &RpcConfig,
+    ) -> Result<Self> {
+        if endpoints.is_empty() {
+            return Err(Error::Client("No proxy endpoint is provided".to_string()));
+        }
+
+        let endpoints = endpoints
+            .into_iter()
+            .map(|endpoint| ProxyEndpoint {
+                client: Arc::new(InnerClient::new(
+                    factory.clone(),
+                    endpoint.clone(),
+                    rpc_config.coalesce_sql_query,
+                    rpc_config.strip_sql_comments,
+                    rpc_config.circuit_breaker.clone(),
+                    rpc_config.effective_idle_timeout(),
+                )),
+                endpoint,
+                zone: None,
+                unhealthy_until: Mutex::new(None),
+            })
+            .collect();
+        Ok(Self {
+            factory,
+            endpoints,
+            selector: Arc::new(RoundRobin::default()),
             default_database,
+            rpc_config: rpc_config.clone(),
+            retry: RetryPolicy::new(rpc_config.retry.clone(), rpc_config.retry_budget.clone()),
+            in_flight: InFlight::default(),
+            recovery_wait: None,
+        })
+    }
+
+    /// Invoke the `on_retry` before each retry of the requests.
+    pub(crate) fn with_on_retry(mut self, on_retry: Option<RetryHook>) -> Self {
+        self.retry = self.retry.with_on_retry(on_retry);
+        self
+    }
+
+    /// Select the endpoints by the `selector` rather than in round-robin.
+    pub(crate) fn with_endpoint_selector(
+        mut self,
+        selector: Option<Arc<dyn EndpointSelector>>,
+    ) -> Self {
+        if let Some(selector) = selector {
+            self.selector = selector;
+        }
+        self
+    }
+
+    /// Wait at most `recovery_wait` for any endpoint to recover before failing
+    /// with [`Error::NoAvailableEndpoint`].
+    pub(crate) fn with_recovery_wait(mut self, recovery_wait: Option<Duration>) -> Self {
+        self.recovery_wait = recovery_wait;
+        self
+    }
+
+    /// Tag the endpoints with the zones they are in.
+    pub(crate) fn with_endpoint_zones(mut self, zones: &HashMap<String, String>) -> Self {
+        for endpoint in &mut self.endpoints {
+            endpoint.zone = zones.get(&endpoint.endpoint).cloned();
+        }
+        self
+    }
+
+    fn is_local(&self, endpoint: &ProxyEndpoint<F>) -> bool {
+        match &self.rpc_config.local_zone {
+            Some(local_zone) => endpoint.zone.as_ref() == Some(local_zone),
+            None => false,
+        }
+    }
+
+    fn endpoint_states(&self) -> Vec<EndpointState> {
+        let now = Instant::now();
+        self.endpoints
+            .iter()
+            .map(|endpoint| EndpointState {
+                endpoint: endpoint.endpoint.clone(),
+                latency: endpoint.client.latency(),
+                zone: endpoint.zone.clone(),
+                in_flight: endpoint.client.in_flight(),
+                healthy: endpoint.is_healthy(now),
+                warm: endpoint.client.is_warm(),
+            })
+            .collect()
+    }
+
+    /// Choose the endpoint by the `choose` of the selector, among the healthy
+    /// endpoints in the local zone if any, or all the endpoints otherwise.
+    fn choose(
+        &self,
+        choose: impl FnOnce(&dyn EndpointSelector, &[EndpointState]) -> usize,
+    ) -> usize {
+        let states = self.endpoint_states();
+        let local: Vec<_> = (0..self.endpoints.len())
+            .filter(|idx| states[*idx].healthy && self.is_local(&self.endpoints[*idx]))
+            .collect();
+        if local.is_empty() {
+            return choose(self.selector.as_ref(), &states) % self.endpoints.len();
+        }
+
+        let local_states: Vec<_> = local.iter().map(|idx| states[*idx].clone()).collect();
+        local[choose(self.selector.as_ref(), &local_states) % local.len()]
+    }
+
+    /// Select the endpoint for the next request.
+    fn select(&self) -> usize {
+        self.choose(|selector, states| selector.select(states))
+    }
+
+    /// The endpoints starting from `start` in order, and the healthy ones in
+    /// the local zone are moved to the front, while the unhealthy ones are
+    /// moved to the end as the last resort.
+    fn candidates(&self, start: usize) -> Vec<&ProxyEndpoint<F>> {
+        let now = Instant::now();
+        let mut candidates: Vec<_> = (0..self.endpoints.len())
+            .map(|offset| &self.endpoints[(start + offset) % self.endpoints.len()])
+            .collect();
+        candidates.sort_by_key(|endpoint| (!endpoint.is_healthy(now), !self.is_local(endpoint)));
+
+        candidates
+    }
+
+    /// Connect all the endpoints eagerly, and it fails only if none is
+    /// connected, while the failed ones are skipped for a while.
+    pub(crate) async fn warmup(&self) -> Result<()> {
+        let ctx = RpcContext::default();
+        let results = join_all(
+            self.endpoints
+                .iter()
+                .map(|endpoint| endpoint.client.connect(&ctx)),
+        )
+        .await;
+
+        let mut connected = false;
+        let mut errors = Vec::new();
+        for (endpoint, result) in self.endpoints.iter().zip(results) {
+            match result {
+                Ok(()) => connected = true,
+                Err(e) => {
+                    if matches!(e, Error::Connect { .. }) {
+                        endpoint.mark_unhealthy();
+                    }
+                    errors.push((endpoint.endpoint.clone(), e));
+                }
+            }
+        }
+
+        if connected {
+            Ok(())
+        } else {
+            Err(no_available_endpoint(errors))
+        }
+    }
+
+    /// Issue the request on the next endpoint, and fall back to the following
+    /// ones if it fails to connect.
+    ///
+    /// If all of them fail, they are polled again until the `recovery_wait`
+    /// passes if it is set.
+    async fn balanced<'a, T, Fut>(
+        &'a self,
+        request: impl Fn(&'a InnerClient<F>) -> Fut,
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>> + 'a,
+    {
+        let deadline = self.recovery_wait.map(|wait| Instant::now() + wait);
+        loop {
+            let start = self.select();
+            let mut errors = Vec::with_capacity(self.endpoints.len());
+            for endpoint in self.candidates(start) {
+                match request(&endpoint.client).await {
+                    Err(e @ Error::Connect { .. }) => {
+                        endpoint.mark_unhealthy();
+                        errors.push((endpoint.endpoint.clone(), e));
+                    }
+                    Err(e @ Error::CircuitOpen { .. }) => {
+                        errors.push((endpoint.endpoint.clone(), e))
+                    }
+                    res => return res,
+                }
+            }
+
+            let now = Instant::now();
+            match deadline {
+                Some(deadline) if now < deadline => {
+                    tokio::time::sleep(RECOVERY_POLL_INTERVAL.min(deadline - now)).await
+                }
+                _ => return Err(no_available_endpoint(errors)),
+            }
         }
     }
 }
 
+/// The error of the only endpoint is returned as is, otherwise the errors of
+/// all the endpoints are returned by [`Error::NoAvailableEndpoint`].
+fn no_available_endpoint(mut errors: Vec<(String, Error)>) -> Error {
+    if errors.len() == 1 {
+        errors.pop().unwrap().1
+    } else {
+        Error::NoAvailableEndpoint(errors)
+    }
+}
+
 #[async_trait]
-impl<F: RpcClientFactory> DbClient for RawImpl<F> {
+impl<F: RpcClientFactory + ?Sized> DbClient for RawImpl<F> {
     async fn sql_query(&self, ctx: &RpcContext, req: &SqlQueryRequest) -> Result<SqlQueryResponse> {
         let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
-        self.inner_client.sql_query_internal(&ctx, req).await
+        check_sql_length(self.rpc_config.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || {
+                    self.balanced(|client| client.sql_query_internal(&ctx, req))
+                }),
+            ))
+            .await
+    }
+
+    async fn sql_query_with_meta(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<(SqlQueryResponse, ResponseMeta)> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        check_sql_length(self.rpc_config.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || {
+                    self.balanced(|client| client.sql_query_with_meta_internal(&ctx, req))
+                }),
+            ))
+            .await
+    }
+
+    async fn sql_query_stream(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<BoxStream<'static, Result<SqlQueryResponse>>> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        check_sql_length(self.rpc_config.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || {
+                    self.balanced(|client| client.sql_query_stream_internal(&ctx, req))
+                }),
+            ))
+            .await
     }
 
     async fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
         let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
-        self.inner_client.write_internal(&ctx, req).await
+        let ctx = &ctx;
+        self.in_flight
+            .track(with_total_timeout(
+                ctx,
+                write_in_batches(
+                    self.rpc_config.max_write_batch_rows,
+                    self.rpc_config.max_write_batch_concurrency,
+                    req,
+                    |req| async move {
+                        with_retry(&self.retry, || {
+                            self.balanced(|client| client.write_internal(ctx, &req))
+                        })
+                        .await
+                    },
+                ),
+            ))
+            .await
+    }
+
+    async fn write_stream(
+        &self,
+        ctx: &RpcContext,
+        reqs: BoxStream<'static, WriteRequest>,
+    ) -> Result<WriteResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        let ctx = &ctx;
+        self.in_flight
+            .track(with_total_timeout(ctx, async {
+                // Pick the endpoint by connecting it before streaming, as the
+                // streamed requests can't be reissued on another endpoint.
+                let client = self
+                    .balanced(|client| async move { client.connect(ctx).await.map(|_| client) })
+                    .await?;
+                client.write_stream_internal(ctx, reqs).await
+            }))
+            .await
+    }
+
+    async fn write_columnar(
+        &self,
+        ctx: &RpcContext,
+        batch: &ColumnarBatch,
+    ) -> Result<WriteResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || {
+                    self.balanced(|client| client.write_columnar_internal(&ctx, batch))
+                }),
+            ))
+            .await
+    }
+
+    async fn capabilities(&self, ctx: &RpcContext) -> Result<ServerCapabilities> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        self.balanced(|client| client.capabilities_internal(&ctx))
+            .await
+    }
+
+    async fn route(&self, ctx: &RpcContext, tables: &[String]) -> Result<Vec<RouteInfo>> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        with_total_timeout(
+            &ctx,
+            with_retry(&self.retry, || {
+                self.balanced(|client| client.route_internal(&ctx, tables))
+            }),
+        )
+        .await
+    }
+
+    async fn health_check(&self, ctx: &RpcContext) -> Result<()> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        let results = join_all(
+            self.endpoints
+                .iter()
+                .map(|endpoint| endpoint.client.health_check_internal(&ctx)),
+        )
+        .await;
+
+        results.into_iter().collect()
+    }
+
+    async fn ping(&self, ctx: &RpcContext) -> Result<Duration> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        self.balanced(|client| client.ping_internal(&ctx)).await
+    }
+
+    async fn ping_all(&self, ctx: &RpcContext) -> Vec<(String, Result<Duration>)> {
+        join_all(self.endpoints.iter().map(|endpoint| async {
+            let rtt = match crate::db_client::resolve_database(ctx, &self.default_database) {
+                Ok(ctx) => endpoint.client.ping_internal(&ctx).await,
+                Err(e) => Err(e),
+            };
+            (endpoint.endpoint.clone(), rtt)
+        }))
+        .await
+    }
+
+    fn with_endpoint_affinity(&self) -> Box<dyn DbClient + '_> {
+        let start = self.select();
+        let endpoint = self.candidates(start)[0];
+        Box::new(PinnedImpl::with_pinned(
+            self,
+            &self.in_flight,
+            self.retry.clone(),
+            self.default_database.clone(),
+            endpoint.endpoint.clone(),
+            endpoint.client.clone(),
+        ))
+    }
+
+    fn effective_config(&self) -> EffectiveConfig {
+        EffectiveConfig {
+            mode: Mode::Proxy,
+            endpoints: self
+                .endpoints
+                .iter()
+                .map(|endpoint| endpoint.endpoint.clone())
+                .collect(),
+            default_database: self.default_database.clone(),
+            rpc_config: self.rpc_config.clone(),
+        }
+    }
+
+    fn endpoints(&self) -> Vec<String> {
+        let start = self.choose(|selector, states| selector.peek(states));
+        let now = Instant::now();
+        self.candidates(start)
+            .into_iter()
+            .filter(|endpoint| endpoint.is_healthy(now))
+            .map(|endpoint| endpoint.endpoint.clone())
+            .collect()
+    }
+
+    fn connection_stats(&self) -> HashMap<String, ConnectionStats> {
+        self.factory.connection_stats()
+    }
+
+    fn stats(&self) -> ClientStats {
+        let counters = self.endpoints.iter().map(|endpoint| {
+            let counter = endpoint.client.counter();
+            (
+                endpoint.endpoint.clone(),
+                counter.requests(),
+                counter.errors(),
+            )
+        });
+        ClientStats {
+            in_flight: self.in_flight.count(),
+            requests: self.in_flight.counter().requests(),
+            errors: self.in_flight.counter().errors(),
+            endpoints: merge_endpoint_stats(counters, self.factory.connection_stats()),
+            route_cache: None,
+            proxy_fallbacks: 0,
+        }
+    }
+
+    fn admin(&self) -> AdminClient<'_> {
+        AdminClient::new(self)
+    }
+
+    fn evict_routes(&self, _tables: &[String]) {}
+
+    async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        self.in_flight.shutdown(timeout).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        time::{Duration, Instant},
+    };
+
+    use futures::StreamExt;
+    use tokio_util::sync::CancellationToken;
+    use tonic::Code;
+
+    use super::RawImpl;
+    use crate::{
+        config::CircuitBreakerConfig,
+        db_client::{DbClient, LeastInFlight},
+        model::{
+            capabilities::ServerCapabilities,
+            route::{Endpoint, RouteInfo},
+            sql_query::batch::BatchOptions,
+            value::Value,
+            write::point::{IntoPoint, Point, PointBuilder},
+        },
+        rpc_client::{MockRpcClientFactory, RpcContext},
+        Error, RpcConfig, SqlQueryRequest, WriteRequest,
+    };
+
+    fn make_client(
+        factory: Arc<MockRpcClientFactory>,
+        endpoints: &[&str],
+    ) -> RawImpl<MockRpcClientFactory> {
+        RawImpl::new(
+            factory,
+            endpoints
+                .iter()
+                .map(|endpoint| endpoint.to_string())
+                .collect(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_no_endpoint() {
+        let res = RawImpl::new(
+            Arc::new(MockRpcClientFactory::default()),
+            vec![],
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        assert!(matches!(res, Err(Error::Client(_))));
+    }
+
+    #[tokio::test]
+    async fn test_sql_query_stream() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = RawImpl::new(
+            factory.clone(),
+            vec!["192.168.0.1:11".to_string()],
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        )
+        .unwrap();
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+
+        let resps: Vec<_> = client
+            .sql_query_stream(&RpcContext::default(), &req)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(resps.len(), 4);
+        for resp in &resps[..3] {
+            assert_eq!(resp.as_ref().unwrap().affected_rows, 1);
+        }
+        assert!(matches!(&resps[3], Err(Error::Server(e)) if e.code == 500));
+        assert_eq!(*factory.request_counts.get("192.168.0.1:11").unwrap(), 1);
+    }
+
+    #[cfg(feature = "arrow")]
+    #[tokio::test]
+    async fn test_sql_query_record_batch_stream() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory.rows.insert("select value from demo".to_string(), 3);
+        let client = make_client(factory, &["1.1.1.1:1"]);
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select value from demo".to_string(),
+        };
+        let batches = client
+            .sql_query_record_batch_stream(&RpcContext::default(), &req)
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(batches.len(), 2);
+        for batch in batches {
+            assert_eq!(batch.num_rows(), 3);
+            assert_eq!(batch.schema().field(0).name(), "value");
+        }
+
+        // The error in the stream is yielded, and the responses without the
+        // rows yield nothing.
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "insert into demo".to_string(),
+        };
+        let results: Vec<_> = client
+            .sql_query_record_batch_stream(&RpcContext::default(), &req)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0], Err(Error::Server(_))));
+    }
+
+    #[tokio::test]
+    async fn test_capabilities() {
+        let capabilities = ServerCapabilities {
+            stream_sql_query: true,
+            stream_write: false,
+        };
+        let factory = Arc::new(MockRpcClientFactory {
+            capabilities: capabilities.clone(),
+            ..Default::default()
+        });
+        let client = make_client(factory.clone(), &["192.168.0.1:11"]);
+        let ctx = RpcContext::default();
+        assert_eq!(client.capabilities(&ctx).await.unwrap(), capabilities);
+        // The detected capabilities are cached.
+        assert_eq!(client.capabilities(&ctx).await.unwrap(), capabilities);
+        assert_eq!(*factory.build_counts.get("192.168.0.1:11").unwrap(), 1);
+
+        factory
+            .broken_endpoints
+            .insert("192.168.0.2:11".to_string());
+        let client = make_client(factory, &["192.168.0.2:11"]);
+        assert!(matches!(
+            client.capabilities(&ctx).await,
+            Err(Error::Rpc(status)) if status.code() == Code::Unavailable
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_sql_query_error() {
+        let factory = Arc::new(MockRpcClientFactory {
+            sql_query_delay: Some(Duration::from_millis(50)),
+            ..Default::default()
+        });
+        factory
+            .server_errors
+            .insert("select * from missing".to_string(), 404);
+        let rpc_config = RpcConfig {
+            coalesce_sql_query: true,
+            ..Default::default()
+        };
+        let client = RawImpl::new(
+            factory.clone(),
+            vec!["192.168.0.1:11".to_string()],
+            Some("db".to_string()),
+            &rpc_config,
+        )
+        .unwrap();
+        let req = SqlQueryRequest {
+            tables: vec!["missing".to_string()],
+            sql: "select * from missing".to_string(),
+        };
+
+        // Both callers get the server error of the shared query, instead of a
+        // wrapped one.
+        let ctx = RpcContext::default();
+        let (first, second) =
+            futures::join!(client.sql_query(&ctx, &req), client.sql_query(&ctx, &req));
+        assert!(matches!(first, Err(Error::Server(e)) if e.code == 404));
+        assert!(matches!(second, Err(Error::Server(e)) if e.code == 404));
+        assert_eq!(*factory.request_counts.get("192.168.0.1:11").unwrap(), 1);
+
+        // The queries with different timeouts are not coalesced.
+        let short_ctx = RpcContext::default().timeout(Duration::from_secs(1));
+        let (first, second) = futures::join!(
+            client.sql_query(&ctx, &req),
+            client.sql_query(&short_ctx, &req)
+        );
+        assert!(matches!(first, Err(Error::Server(_))));
+        assert!(matches!(second, Err(Error::Server(_))));
+        assert_eq!(*factory.request_counts.get("192.168.0.1:11").unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_sql_query_with_meta() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_client(factory, &["192.168.0.1:11"]);
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+
+        let (resp, meta) = client
+            .sql_query_with_meta(&RpcContext::default(), &req)
+            .await
+            .unwrap();
+        assert_eq!(resp.affected_rows, 0);
+        assert_eq!(meta.get("x-request-id"), Some("192.168.0.1:11"));
+        // The binary trailers are dropped.
+        assert_eq!(meta.entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_client(factory.clone(), &["1.1.1.1:1", "2.2.2.2:2", "3.3.3.3:3"]);
+        let ctx = RpcContext::default();
+
+        assert_eq!(
+            client.effective_config().endpoints,
+            ["1.1.1.1:1", "2.2.2.2:2", "3.3.3.3:3"]
+        );
+        assert_eq!(
+            client.endpoints(),
+            vec!["1.1.1.1:1", "2.2.2.2:2", "3.3.3.3:3"]
+        );
+        client.write(&ctx, &WriteRequest::default()).await.unwrap();
+        assert_eq!(
+            client.endpoints(),
+            vec!["2.2.2.2:2", "3.3.3.3:3", "1.1.1.1:1"]
+        );
+
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+        for _ in 0..5 {
+            client.sql_query(&ctx, &req).await.unwrap();
+        }
+        for endpoint in ["1.1.1.1:1", "2.2.2.2:2", "3.3.3.3:3"] {
+            assert_eq!(*factory.request_counts.get(endpoint).unwrap(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_unreachable_endpoint() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .unreachable_endpoints
+            .insert("2.2.2.2:2".to_string());
+        let client = make_client(factory.clone(), &["1.1.1.1:1", "2.2.2.2:2", "3.3.3.3:3"]);
+        let ctx = RpcContext::default();
+
+        for _ in 0..4 {
+            client.write(&ctx, &WriteRequest::default()).await.unwrap();
+        }
+        assert!(factory.request_counts.get("2.2.2.2:2").is_none());
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 2);
+        assert_eq!(*factory.request_counts.get("3.3.3.3:3").unwrap(), 2);
+        assert!(!client.endpoints().contains(&"2.2.2.2:2".to_string()));
+
+        // All the endpoints are unreachable.
+        factory
+            .unreachable_endpoints
+            .insert("1.1.1.1:1".to_string());
+        factory
+            .unreachable_endpoints
+            .insert("3.3.3.3:3".to_string());
+        let client = make_client(factory, &["1.1.1.1:1", "3.3.3.3:3"]);
+        let err = client
+            .write(&ctx, &WriteRequest::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NoAvailableEndpoint(_)));
+        assert!(client.endpoints().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_no_available_endpoint() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        for endpoint in ["1.1.1.1:1", "2.2.2.2:2"] {
+            factory.unreachable_endpoints.insert(endpoint.to_string());
+        }
+        let rpc_config = RpcConfig {
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let client = RawImpl::new(
+            factory.clone(),
+            vec!["1.1.1.1:1".to_string(), "2.2.2.2:2".to_string()],
+            Some("db".to_string()),
+            &rpc_config,
+        )
+        .unwrap();
+        let ctx = RpcContext::default();
+        let req = WriteRequest::default();
+
+        // The last failure of each endpoint is listed.
+        let err = client.write(&ctx, &req).await.unwrap_err();
+        match err {
+            Error::NoAvailableEndpoint(errors) => {
+                assert_eq!(errors.len(), 2);
+                for ((endpoint, err), expect) in errors.iter().zip(["1.1.1.1:1", "2.2.2.2:2"]) {
+                    assert_eq!(endpoint, expect);
+                    assert!(matches!(err, Error::Connect { addr, .. } if addr == expect));
+                }
+            }
+            err => panic!("unexpected err:{err}"),
+        }
+        let err = client.write(&ctx, &req).await.unwrap_err();
+        match err {
+            Error::NoAvailableEndpoint(errors) => {
+                assert_eq!(errors.len(), 2);
+                assert!(errors
+                    .iter()
+                    .all(|(endpoint, err)| matches!(err, Error::CircuitOpen { endpoint: e } if e == endpoint)));
+            }
+            err => panic!("unexpected err:{err}"),
+        }
+
+        // The request waits for the endpoint to recover.
+        let client = make_client(factory.clone(), &["1.1.1.1:1", "2.2.2.2:2"])
+            .with_recovery_wait(Some(Duration::from_secs(1)));
+        let start = Instant::now();
+        let (resp, _) = tokio::join!(client.write(&ctx, &req), async {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            factory.unreachable_endpoints.remove("2.2.2.2:2");
+        });
+        resp.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(*factory.request_counts.get("2.2.2.2:2").unwrap(), 1);
+
+        // It gives up after the wait.
+        let client = make_client(factory.clone(), &["1.1.1.1:1", "3.3.3.3:3"])
+            .with_recovery_wait(Some(Duration::from_millis(150)));
+        factory
+            .unreachable_endpoints
+            .insert("3.3.3.3:3".to_string());
+        let start = Instant::now();
+        let err = client.write(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::NoAvailableEndpoint(errors) if errors.len() == 2));
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn test_sql_query_batch() {
+        let factory = Arc::new(MockRpcClientFactory {
+            sql_query_delay: Some(Duration::from_millis(10)),
+            ..Default::default()
+        });
+        let rpc_config = RpcConfig {
+            max_sql_length: Some(10),
+            ..Default::default()
+        };
+        let client = RawImpl::new(
+            factory.clone(),
+            vec!["1.1.1.1:1".to_string()],
+            Some("db".to_string()),
+            &rpc_config,
+        )
+        .unwrap();
+        let ctx = RpcContext::default();
+        let mut reqs: Vec<_> = (1..=6)
+            .map(|i| {
+                let sql = format!("insert {i}");
+                factory.affected_rows.insert(sql.clone(), i);
+                SqlQueryRequest {
+                    tables: vec![],
+                    sql,
+                }
+            })
+            .collect();
+
+        // The results are in order, and the concurrency is bounded.
+        let options = BatchOptions {
+            max_concurrency: 2,
+            ..Default::default()
+        };
+        let results = client.sql_query_batch(&ctx, &reqs, &options).await.unwrap();
+        let affected_rows: Vec<_> = results
+            .into_iter()
+            .map(|result| result.unwrap().affected_rows)
+            .collect();
+        assert_eq!(affected_rows, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(factory.max_in_flight_sql_queries.load(Ordering::SeqCst), 2);
+
+        // The failure is returned in its place.
+        reqs[1].sql = "select * from too_long".to_string();
+        factory.request_counts.clear();
+        let results = client
+            .sql_query_batch(&ctx, &reqs, &BatchOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 6);
+        assert!(matches!(&results[1], Err(Error::Client(_))));
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 5);
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 5);
+
+        // The following sql queries are not issued after the failure.
+        factory.request_counts.clear();
+        let options = BatchOptions {
+            max_concurrency: 1,
+            fail_fast: true,
+        };
+        let err = client
+            .sql_query_batch(&ctx, &reqs, &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Client(_)));
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sql_query_paged() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_client(factory.clone(), &["1.1.1.1:1"]);
+        let ctx = RpcContext::default();
+        let page_rows = |req: &SqlQueryRequest, page_size| {
+            let client = &client;
+            let ctx = &ctx;
+            let req = req.clone();
+            async move {
+                client
+                    .sql_query_paged(ctx, &req, page_size)
+                    .map(|page| page.map(|page| page.rows.len()))
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+        let req = |sql: &str| SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: sql.to_string(),
+        };
+
+        // It stops after the page of fewer rows.
+        for (offset, rows) in [(0, 3), (3, 3), (6, 1)] {
+            let sql = format!("SELECT * FROM demo ORDER BY t LIMIT 3 OFFSET {offset}");
+            factory.rows.insert(sql, rows);
+        }
+        let pages = page_rows(&req("SELECT * FROM demo ORDER BY t;"), 3).await;
+        let pages: Vec<_> = pages.into_iter().map(Result::unwrap).collect();
+        assert_eq!(pages, vec![3, 3, 1]);
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 3);
+
+        // The empty page after the exact multiple of the page size is not
+        // streamed, while the empty first page is.
+        factory.request_counts.clear();
+        for offset in [0, 2] {
+            let sql = format!("SELECT * FROM other LIMIT 2 OFFSET {offset}");
+            factory.rows.insert(sql, 2);
+        }
+        let pages = page_rows(&req("SELECT * FROM other"), 2).await;
+        let pages: Vec<_> = pages.into_iter().map(Result::unwrap).collect();
+        assert_eq!(pages, vec![2, 2]);
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 3);
+        let pages = page_rows(&req("SELECT * FROM empty"), 2).await;
+        let pages: Vec<_> = pages.into_iter().map(Result::unwrap).collect();
+        assert_eq!(pages, vec![0]);
+
+        // The invalid ones are rejected without issuing any sql query.
+        factory.request_counts.clear();
+        for (sql, page_size) in [
+            ("SELECT * FROM demo LIMIT 10", 3),
+            ("SELECT * FROM demo", 0),
+        ] {
+            let pages = page_rows(&req(sql), page_size).await;
+            assert_eq!(pages.len(), 1);
+            assert!(matches!(pages[0], Err(Error::Client(_))));
+        }
+        assert!(factory.request_counts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_points() {
+        struct Row(i64);
+
+        impl IntoPoint for Row {
+            fn into_point(self) -> Point {
+                PointBuilder::new("t")
+                    .timestamp(self.0)
+                    .field("value", Value::Int64(self.0))
+                    .build()
+                    .unwrap()
+            }
+        }
+
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client: Box<dyn DbClient> = Box::new(make_client(factory.clone(), &["1.1.1.1:1"]));
+        let resp = client
+            .write_points(&RpcContext::default(), (0..3).map(Row))
+            .await
+            .unwrap();
+        assert_eq!(resp.success, 3);
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_stream() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_client(factory.clone(), &["1.1.1.1:1"]);
+        let reqs: Vec<_> = (1..=3)
+            .map(|rows| {
+                let mut req = WriteRequest::default();
+                for timestamp in 0..rows {
+                    let point = PointBuilder::new("t")
+                        .timestamp(timestamp)
+                        .field("value", Value::Int64(timestamp))
+                        .build()
+                        .unwrap();
+                    req.add_point(point);
+                }
+                req
+            })
+            .collect();
+
+        let resp = client
+            .write_stream(&RpcContext::default(), futures::stream::iter(reqs).boxed())
+            .await
+            .unwrap();
+        assert_eq!(resp.success, 6);
+        assert_eq!(resp.failed, 0);
+        // All the requests are streamed in one rpc.
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        for (table, port) in [("table1", 11), ("table2", 12)] {
+            factory.route_table.insert(
+                table.to_string(),
+                Endpoint::new("192.168.0.1".to_string(), port),
+            );
+        }
+        let client = make_client(factory, &["1.1.1.1:1"]);
+        let tables = vec![
+            "table2".to_string(),
+            "table3".to_string(),
+            "table1".to_string(),
+        ];
+
+        // The table without a route is skipped.
+        let routes = client.route(&RpcContext::default(), &tables).await.unwrap();
+        assert_eq!(
+            routes,
+            vec![
+                RouteInfo {
+                    table: "table2".to_string(),
+                    endpoint: Endpoint::new("192.168.0.1".to_string(), 12),
+                },
+                RouteInfo {
+                    table: "table1".to_string(),
+                    endpoint: Endpoint::new("192.168.0.1".to_string(), 11),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .unreachable_endpoints
+            .insert("1.1.1.1:1".to_string());
+        let rpc_config = RpcConfig {
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 2,
+                failure_window: Duration::from_secs(10),
+                cooldown: Duration::from_millis(100),
+            }),
+            ..Default::default()
+        };
+        let client = RawImpl::new(
+            factory.clone(),
+            vec!["1.1.1.1:1".to_string()],
+            Some("db".to_string()),
+            &rpc_config,
+        )
+        .unwrap();
+        let ctx = RpcContext::default();
+        let req = WriteRequest::default();
+
+        // Closed -> open.
+        for _ in 0..2 {
+            let err = client.write(&ctx, &req).await.unwrap_err();
+            assert!(matches!(err, Error::Connect { .. }));
+        }
+        factory.unreachable_endpoints.clear();
+        let err = client.write(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::CircuitOpen { endpoint } if endpoint == "1.1.1.1:1"));
+        assert!(factory.request_counts.is_empty());
+
+        // Open -> half-open, and the trial request closes the circuit.
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        for _ in 0..2 {
+            client.write(&ctx, &req).await.unwrap();
+        }
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_sql_query() {
+        let factory = Arc::new(MockRpcClientFactory {
+            sql_query_delay: Some(Duration::from_secs(10)),
+            ..Default::default()
+        });
+        let client = make_client(factory.clone(), &["1.1.1.1:1"]);
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+
+        let token = CancellationToken::new();
+        let ctx = RpcContext::default().cancellation_token(token.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            token.cancel();
+        });
+        let start = Instant::now();
+        let err = client.sql_query(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(factory.request_counts.is_empty());
+
+        // The total timeout still works without cancelling.
+        let ctx = RpcContext::default()
+            .cancellation_token(CancellationToken::new())
+            .total_timeout(Duration::from_millis(50));
+        let err = client.sql_query(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::DeadlineExceeded), "err:{err}");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let factory = Arc::new(MockRpcClientFactory {
+            sql_query_delay: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+        let client = make_client(factory.clone(), &["1.1.1.1:1"]);
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+        let ctx = RpcContext::default();
+
+        // The query in flight is allowed to finish, and the new ones are
+        // rejected, including the ones of the pinned handle.
+        let (resp, shutdown) = tokio::join!(client.sql_query(&ctx, &req), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            client.shutdown(Duration::from_secs(1)).await
+        });
+        resp.unwrap();
+        shutdown.unwrap();
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 1);
+        let err = client.sql_query(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::Closed));
+        let err = client
+            .write(&ctx, &WriteRequest::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Closed));
+        let pinned = client.with_endpoint_affinity();
+        let err = pinned.sql_query(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::Closed));
+
+        // The shutdown times out if the query in flight is too slow.
+        let client = make_client(factory, &["1.1.1.1:1"]);
+        let (resp, shutdown) = tokio::join!(client.sql_query(&ctx, &req), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            client.shutdown(Duration::from_millis(10)).await
+        });
+        resp.unwrap();
+        assert!(matches!(shutdown, Err(Error::Client(_))));
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_client(factory.clone(), &["1.1.1.1:1"]);
+        let ctx = RpcContext::default();
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+        factory
+            .unreachable_endpoints
+            .insert("1.1.1.1:1".to_string());
+        client.sql_query(&ctx, &req).await.unwrap_err();
+        factory.unreachable_endpoints.clear();
+        client.sql_query(&ctx, &req).await.unwrap();
+        client.write(&ctx, &WriteRequest::default()).await.unwrap();
+        // The request issued by the pinned handle is counted too.
+        let pinned = client.with_endpoint_affinity();
+        pinned.write(&ctx, &WriteRequest::default()).await.unwrap();
+
+        let stats = client.stats();
+        assert_eq!(stats.in_flight, 0);
+        assert_eq!((stats.requests, stats.errors), (4, 1));
+        assert_eq!(stats.endpoints.len(), 1);
+        let endpoint_stats = &stats.endpoints["1.1.1.1:1"];
+        assert_eq!((endpoint_stats.requests, endpoint_stats.errors), (4, 1));
+        assert!(stats.route_cache.is_none());
+        assert_eq!(pinned.stats(), stats);
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_client(factory.clone(), &["1.1.1.1:1", "2.2.2.2:2"]);
+        let ctx = RpcContext::default();
+        client.health_check(&ctx).await.unwrap();
+
+        factory
+            .unreachable_endpoints
+            .insert("2.2.2.2:2".to_string());
+        let client = make_client(factory, &["1.1.1.1:1", "2.2.2.2:2"]);
+        let err = client.health_check(&ctx).await.unwrap_err();
+        assert!(matches!(err, Error::Connect { addr, .. } if addr == "2.2.2.2:2"));
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_selector() {
+        let factory = Arc::new(MockRpcClientFactory {
+            sql_query_delay: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+        let client = make_client(factory.clone(), &["1.1.1.1:1", "2.2.2.2:2"])
+            .with_endpoint_selector(Some(Arc::new(LeastInFlight)));
+        let ctx = RpcContext::default();
+        let req = SqlQueryRequest {
+            tables: vec![],
+            sql: "select 1".to_string(),
+        };
+
+        // The first endpoint is selected among the idle ones.
+        client.sql_query(&ctx, &req).await.unwrap();
+        client.sql_query(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 2);
+        assert!(factory.request_counts.get("2.2.2.2:2").is_none());
+        assert!(client.endpoint_states()[0].latency.unwrap() >= Duration::from_millis(100));
+
+        // The endpoint busy with the slow request is avoided.
+        let (first, second) = tokio::join!(client.sql_query(&ctx, &req), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert_eq!(client.endpoint_states()[0].in_flight, 1);
+            client.sql_query(&ctx, &req).await
+        });
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 3);
+        assert_eq!(*factory.request_counts.get("2.2.2.2:2").unwrap(), 1);
+        assert!(client
+            .endpoint_states()
+            .iter()
+            .all(|state| state.in_flight == 0));
+    }
+
+    fn make_zoned_client(
+        factory: Arc<MockRpcClientFactory>,
+        zones: &[(&str, &str)],
+    ) -> RawImpl<MockRpcClientFactory> {
+        let rpc_config = RpcConfig {
+            local_zone: Some("az1".to_string()),
+            ..Default::default()
+        };
+        let zones = zones
+            .iter()
+            .map(|(endpoint, zone)| (endpoint.to_string(), zone.to_string()))
+            .collect();
+        RawImpl::new(
+            factory,
+            vec![
+                "1.1.1.1:1".to_string(),
+                "2.2.2.2:2".to_string(),
+                "3.3.3.3:3".to_string(),
+            ],
+            Some("db".to_string()),
+            &rpc_config,
+        )
+        .unwrap()
+        .with_endpoint_zones(&zones)
+    }
+
+    #[tokio::test]
+    async fn test_prefer_local_zone() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_zoned_client(
+            factory.clone(),
+            &[
+                ("1.1.1.1:1", "az2"),
+                ("2.2.2.2:2", "az1"),
+                ("3.3.3.3:3", "az1"),
+            ],
+        );
+        let ctx = RpcContext::default();
+
+        // The requests are distributed across the endpoints in the local zone.
+        assert_eq!(
+            client.endpoints(),
+            vec!["2.2.2.2:2", "3.3.3.3:3", "1.1.1.1:1"]
+        );
+        for _ in 0..4 {
+            client.write(&ctx, &WriteRequest::default()).await.unwrap();
+        }
+        assert!(factory.request_counts.get("1.1.1.1:1").is_none());
+        assert_eq!(*factory.request_counts.get("2.2.2.2:2").unwrap(), 2);
+        assert_eq!(*factory.request_counts.get("3.3.3.3:3").unwrap(), 2);
+
+        // No zone is preferred without the local zone.
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_client(factory.clone(), &["1.1.1.1:1", "2.2.2.2:2"])
+            .with_endpoint_zones(&[("2.2.2.2:2".to_string(), "az1".to_string())].into());
+        for _ in 0..2 {
+            client.write(&ctx, &WriteRequest::default()).await.unwrap();
+        }
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 1);
+        assert_eq!(*factory.request_counts.get("2.2.2.2:2").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fall_back_to_other_zones() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .unreachable_endpoints
+            .insert("2.2.2.2:2".to_string());
+        let client = make_zoned_client(
+            factory.clone(),
+            &[
+                ("1.1.1.1:1", "az2"),
+                ("2.2.2.2:2", "az1"),
+                ("3.3.3.3:3", "az2"),
+            ],
+        );
+        let ctx = RpcContext::default();
+
+        // The local endpoint is tried first, and the requests fall back to the
+        // other zones once it is unhealthy.
+        for _ in 0..4 {
+            client.write(&ctx, &WriteRequest::default()).await.unwrap();
+        }
+        assert!(factory.request_counts.get("2.2.2.2:2").is_none());
+        assert_eq!(
+            *factory.request_counts.get("1.1.1.1:1").unwrap()
+                + *factory.request_counts.get("3.3.3.3:3").unwrap(),
+            4
+        );
+        assert_eq!(client.endpoints(), vec!["3.3.3.3:3", "1.1.1.1:1"]);
+
+        // The local endpoint is preferred again once it recovers.
+        factory.unreachable_endpoints.clear();
+        *client.endpoints[1].unhealthy_until.lock().unwrap() = None;
+        factory.request_counts.clear();
+        for _ in 0..2 {
+            client.write(&ctx, &WriteRequest::default()).await.unwrap();
+        }
+        assert_eq!(*factory.request_counts.get("2.2.2.2:2").unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_prefer_warm_endpoint() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_client(factory.clone(), &["1.1.1.1:1", "2.2.2.2:2", "3.3.3.3:3"])
+            .with_endpoint_selector(Some(Arc::new(LeastInFlight)));
+        let ctx = RpcContext::default();
+        let req = WriteRequest::default();
+        client.endpoints[1].client.connect(&ctx).await.unwrap();
+        assert_eq!(
+            client
+                .endpoint_states()
+                .iter()
+                .map(|state| state.warm)
+                .collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+
+        // The warm endpoint is chosen among the idle ones.
+        for _ in 0..2 {
+            client.write(&ctx, &req).await.unwrap();
+        }
+        assert_eq!(*factory.request_counts.get("2.2.2.2:2").unwrap(), 2);
+        assert!(factory.build_counts.get("1.1.1.1:1").is_none());
+
+        // The cold one is chosen only if no warm one is healthy.
+        client.endpoints[1].mark_unhealthy();
+        client.write(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let factory = Arc::new(MockRpcClientFactory {
+            route_delay: Some(Duration::from_millis(20)),
+            ..Default::default()
+        });
+        factory
+            .unreachable_endpoints
+            .insert("2.2.2.2:2".to_string());
+        let client = make_client(factory, &["1.1.1.1:1", "2.2.2.2:2"]);
+        let ctx = RpcContext::default();
+        assert!(client.ping(&ctx).await.unwrap() >= Duration::from_millis(20));
+
+        let rtts = client.ping_all(&ctx).await;
+        assert_eq!(rtts.len(), 2);
+        assert_eq!(rtts[0].0, "1.1.1.1:1");
+        assert!(*rtts[0].1.as_ref().unwrap() >= Duration::from_millis(20));
+        assert_eq!(rtts[1].0, "2.2.2.2:2");
+        assert!(matches!(rtts[1].1, Err(Error::Connect { .. })));
+    }
+
+    fn make_idle_client(
+        factory: Arc<MockRpcClientFactory>,
+        keep_alive_while_idle: bool,
+    ) -> RawImpl<MockRpcClientFactory> {
+        let rpc_config = RpcConfig {
+            keep_alive_while_idle,
+            idle_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        RawImpl::new(
+            factory,
+            vec!["1.1.1.1:1".to_string()],
+            Some("db".to_string()),
+            &rpc_config,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_recreate_idle_connection() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_idle_client(factory.clone(), false);
+        let ctx = RpcContext::default();
+        let req = WriteRequest::default();
+
+        client.write(&ctx, &req).await.unwrap();
+        client.write(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.build_counts.get("1.1.1.1:1").unwrap(), 1);
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        client.write(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.build_counts.get("1.1.1.1:1").unwrap(), 2);
+
+        // The idle connections are kept alive.
+        let factory = Arc::new(MockRpcClientFactory::default());
+        let client = make_idle_client(factory.clone(), true);
+        client.write(&ctx, &req).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        client.write(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.build_counts.get("1.1.1.1:1").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_broken_connection() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory.broken_endpoints.insert("1.1.1.1:1".to_string());
+        let client = make_idle_client(factory.clone(), false);
+        let ctx = RpcContext::default();
+        let req = SqlQueryRequest {
+            tables: vec!["demo".to_string()],
+            sql: "select * from demo".to_string(),
+        };
+
+        // The rebuilt connection is still broken, and it is retried only once.
+        let err = client.sql_query(&ctx, &req).await.unwrap_err();
+        assert_eq!(err.as_tonic_status().unwrap().code(), Code::Unavailable);
+        assert_eq!(*factory.build_counts.get("1.1.1.1:1").unwrap(), 2);
+
+        // The stale connection is recreated, and the request succeeds.
+        factory.broken_endpoints.clear();
+        client.sql_query(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.build_counts.get("1.1.1.1:1").unwrap(), 3);
+        assert_eq!(*factory.request_counts.get("1.1.1.1:1").unwrap(), 1);
     }
 }
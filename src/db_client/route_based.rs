@@ -15,101 +15,233 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use dashmap::DashMap;
-use futures::future::join_all;
+use futures::{future::join_all, stream::BoxStream, StreamExt};
 use tokio::sync::OnceCell;
+use tonic::Code;
 
 use crate::{
-    db_client::{inner::InnerClient, DbClient},
+    config::{CircuitBreakerConfig, EffectiveConfig},
+    db_client::{
+        admin::AdminClient, check_sql_length, inner::InnerClient, pinned::PinnedImpl,
+        shutdown::InFlight, stats::merge_endpoint_stats, with_retry, with_total_timeout,
+        write_in_batches, ClientStats, DbClient, Mode, RetryHook, RetryPolicy,
+    },
     errors::RouteBasedWriteError,
     model::{
-        route::Endpoint,
+        capabilities::ServerCapabilities,
+        route::{Endpoint, RouteInfo},
         sql_query::{Request as SqlQueryRequest, Response as SqlQueryResponse},
-        write::{Request as WriteRequest, Response as WriteResponse},
+        write::{columnar::ColumnarBatch, Request as WriteRequest, Response as WriteResponse},
     },
     router::{Router, RouterImpl},
-    rpc_client::{RpcClientFactory, RpcContext},
-    util::should_refresh,
-    Error, Result,
+    rpc_client::{ConnectionStats, ResponseMeta, RpcClientFactory, RpcContext},
+    util::{is_transport_error, should_refresh},
+    Error, Result, RpcConfig,
 };
 
 /// Client implementation for horaedb while using route based mode.
-pub struct RouteBasedImpl<F: RpcClientFactory> {
+pub struct RouteBasedImpl<F: RpcClientFactory + ?Sized> {
     factory: Arc<F>,
     router_endpoint: String,
     router: OnceCell<Box<dyn Router>>,
     standalone_pool: DirectClientPool<F>,
     default_database: Option<String>,
+    rpc_config: RpcConfig,
+    retry: RetryPolicy,
+    in_flight: InFlight,
+    proxy_fallback: bool,
+    proxy_fallbacks: AtomicU64,
 }
 
-impl<F: RpcClientFactory> RouteBasedImpl<F> {
-    pub fn new(factory: Arc<F>, router_endpoint: String, default_database: Option<String>) -> Self {
+impl<F: RpcClientFactory + ?Sized> RouteBasedImpl<F> {
+    pub fn new(
+        factory: Arc<F>,
+        router_endpoint: String,
+        default_database: Option<String>,
+        rpc_config: &RpcConfig,
+    ) -> Self {
         Self {
             factory: factory.clone(),
             router_endpoint,
             router: OnceCell::new(),
-            standalone_pool: DirectClientPool::new(factory),
+            standalone_pool: DirectClientPool::new(factory, rpc_config),
             default_database,
+            rpc_config: rpc_config.clone(),
+            retry: RetryPolicy::new(rpc_config.retry.clone(), rpc_config.retry_budget.clone()),
+            in_flight: InFlight::default(),
+            proxy_fallback: false,
+            proxy_fallbacks: AtomicU64::new(0),
         }
     }
 
-    async fn init_router(&self) -> Result<Box<dyn Router>> {
-        let router_client = self.factory.build(self.router_endpoint.clone()).await?;
-        let default_endpoint: Endpoint = self.router_endpoint.parse().map_err(|e| {
+    /// Reissue the requests on the router endpoint, which proxies them, if the
+    /// routing fails or the endpoint routed to can't be reached.
+    pub(crate) fn with_proxy_fallback(mut self, proxy_fallback: bool) -> Self {
+        self.proxy_fallback = proxy_fallback;
+        self
+    }
+
+    /// Invoke the `on_retry` before each retry of the requests.
+    pub(crate) fn with_on_retry(mut self, on_retry: Option<RetryHook>) -> Self {
+        self.retry = self.retry.with_on_retry(on_retry);
+        self
+    }
+
+    fn default_endpoint(&self) -> Result<Endpoint> {
+        self.router_endpoint.parse().map_err(|e| {
             Error::Client(format!(
                 "Failed to parse default endpoint:{}, err:{}",
                 self.router_endpoint, e
             ))
-        })?;
-        Ok(Box::new(RouterImpl::new(default_endpoint, router_client)))
+        })
     }
-}
 
-#[async_trait]
-impl<F: RpcClientFactory> DbClient for RouteBasedImpl<F> {
-    async fn sql_query(&self, ctx: &RpcContext, req: &SqlQueryRequest) -> Result<SqlQueryResponse> {
-        if req.tables.is_empty() {
+    async fn init_router(&self) -> Result<Box<dyn Router>> {
+        let router_client = self.factory.build(self.router_endpoint.clone()).await?;
+        let default_endpoint = self.default_endpoint()?;
+        Ok(Box::new(
+            RouterImpl::new(default_endpoint, router_client)
+                .with_cache_ttl(self.rpc_config.route_cache_ttl),
+        ))
+    }
+
+    /// Connect the router eagerly, and prefetch the routes of the `tables` in
+    /// the default database if any.
+    pub(crate) async fn warmup(&self, tables: &[String]) -> Result<()> {
+        let router_handle = self.router.get_or_try_init(|| self.init_router()).await?;
+        if tables.is_empty() {
+            return Ok(());
+        }
+
+        let ctx =
+            crate::db_client::resolve_database(&RpcContext::default(), &self.default_database)?;
+        router_handle.route(tables, &ctx).await.map(|_| ())
+    }
+
+    /// Find the client of the endpoint which the first table is routed to.
+    pub(crate) async fn route_client(
+        &self,
+        ctx: &RpcContext,
+        tables: &[String],
+    ) -> Result<(Endpoint, Arc<InnerClient<F>>)> {
+        if tables.is_empty() {
             return Err(Error::Unknown(
                 "tables in query request can't be empty in route based mode".to_string(),
             ));
         }
-        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
 
         let router_handle = self.router.get_or_try_init(|| self.init_router()).await?;
 
-        let endpoint = match router_handle.route(&req.tables, &ctx).await {
-            Ok(mut eps) => {
-                if let Some(ep) = eps[0].take() {
-                    ep
-                } else {
-                    return Err(Error::Unknown(
-                        "table doesn't have corresponding endpoint".to_string(),
-                    ));
-                }
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        };
+        let endpoint = Self::route_first(router_handle.as_ref(), ctx, tables).await?;
+        let client = self.standalone_pool.get_or_create(&endpoint);
+        if !client.is_circuit_open() {
+            return Ok((endpoint, client));
+        }
 
-        let client = self.standalone_pool.get_or_create(&endpoint).clone();
+        // Refresh the route to find an alternative endpoint, and the request
+        // fails fast if it is routed to the same one.
+        router_handle.evict(tables);
+        let endpoint = Self::route_first(router_handle.as_ref(), ctx, tables).await?;
+        let client = self.standalone_pool.get_or_create(&endpoint);
+        Ok((endpoint, client))
+    }
 
-        client.sql_query_internal(&ctx, req).await.map_err(|e| {
-            router_handle.evict(&req.tables);
-            e
-        })
+    async fn route_first(
+        router_handle: &dyn Router,
+        ctx: &RpcContext,
+        tables: &[String],
+    ) -> Result<Endpoint> {
+        match router_handle.route(tables, ctx).await {
+            Ok(mut eps) => eps[0].take().ok_or_else(|| {
+                Error::Unknown("table doesn't have corresponding endpoint".to_string())
+            }),
+            Err(e) => Err(e),
+        }
     }
 
-    async fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
-        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+    /// Reissue the request failed by `err` on the router endpoint if the proxy
+    /// fallback is enabled, and the routing or the `routed` endpoint can't be
+    /// reached, see [`is_unreachable`].
+    ///
+    /// The `routed` endpoint is `None` if the routing fails. The request is
+    /// reissued at most once, and not if the router endpoint is the one
+    /// failed.
+    async fn proxy_fallback<T, Fut>(
+        &self,
+        tables: &[String],
+        routed: Option<&Endpoint>,
+        err: Error,
+        request: impl FnOnce(Arc<InnerClient<F>>) -> Fut,
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        // The requests without the tables are never routed.
+        if !self.proxy_fallback || tables.is_empty() || !is_unreachable(&err) {
+            return Err(err);
+        }
+        let proxy = match self.default_endpoint() {
+            Ok(proxy) if routed != Some(&proxy) => proxy,
+            _ => return Err(err),
+        };
+
+        // Route the tables again after the endpoint routed to is back.
+        if routed.is_some() {
+            self.evict_routes(tables);
+        }
+        self.proxy_fallbacks.fetch_add(1, Ordering::Relaxed);
+        request(self.standalone_pool.get_or_create(&proxy)).await
+    }
+
+    async fn sql_query_internal(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<SqlQueryResponse> {
+        let proxied =
+            |client: Arc<InnerClient<F>>| async move { client.sql_query_internal(ctx, req).await };
+        let (endpoint, client) = match self.route_client(ctx, &req.tables).await {
+            Ok(routed) => routed,
+            Err(e) => return self.proxy_fallback(&req.tables, None, e, proxied).await,
+        };
+
+        match client.sql_query_internal(ctx, req).await {
+            Err(e) => {
+                if let Some(router_handle) = self.router.get() {
+                    router_handle.evict(&req.tables);
+                }
+                self.proxy_fallback(&req.tables, Some(&endpoint), e, proxied)
+                    .await
+            }
+            resp => resp,
+        }
+    }
 
+    async fn write_internal(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
         // Get tables' related endpoints(some may not exist).
         let should_routes: Vec<_> = req.point_groups.keys().cloned().collect();
-        let router_handle = self.router.get_or_try_init(|| self.init_router()).await?;
-        let endpoints = router_handle.route(&should_routes, &ctx).await?;
+        let proxied =
+            |client: Arc<InnerClient<F>>| async move { client.write_internal(ctx, req).await };
+        let router_handle = match self.router.get_or_try_init(|| self.init_router()).await {
+            Ok(router_handle) => router_handle,
+            Err(e) => return self.proxy_fallback(&should_routes, None, e, proxied).await,
+        };
+        let endpoints = match router_handle.route(&should_routes, ctx).await {
+            Ok(endpoints) => endpoints,
+            Err(e) => return self.proxy_fallback(&should_routes, None, e, proxied).await,
+        };
 
         // Partition write entries in request according to related endpoints.
         let mut no_corresponding_endpoints = Vec::new();
@@ -140,13 +272,24 @@ impl<F: RpcClientFactory> DbClient for RouteBasedImpl<F> {
             .map(|(idx, (ep, req))| {
                 assert!(idx < write_tables.len());
                 write_tables[idx].extend(req.point_groups.keys().cloned());
-                (self.standalone_pool.get_or_create(&ep), req)
+                (ep, req)
             })
             .collect();
         let mut futures = Vec::with_capacity(client_req_paris.len());
-        for (client, req) in client_req_paris {
-            let ctx_clone = ctx.clone();
-            futures.push(async move { client.write_internal(&ctx_clone, &req).await })
+        for ((ep, req), tables) in client_req_paris.into_iter().zip(&write_tables) {
+            let client = self.standalone_pool.get_or_create(&ep);
+            futures.push(async move {
+                let req = &req;
+                match client.write_internal(ctx, req).await {
+                    Err(e) => {
+                        let proxied = |client: Arc<InnerClient<F>>| async move {
+                            client.write_internal(ctx, req).await
+                        };
+                        self.proxy_fallback(tables, Some(&ep), e, proxied).await
+                    }
+                    resp => resp,
+                }
+            })
         }
 
         // Await rpc results and collect results.
@@ -171,16 +314,9 @@ impl<F: RpcClientFactory> DbClient for RouteBasedImpl<F> {
         //  + Merge results and return.
         let evicts: Vec<_> = tables_result_pairs
             .iter()
-            .filter_map(|(tables, result)| {
-                if let Err(Error::Server(server_error)) = &result {
-                    if should_refresh(server_error.code, &server_error.msg) {
-                        Some(tables.clone())
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
+            .filter_map(|(tables, result)| match result {
+                Err(e) if should_evict(e) => Some(tables.clone()),
+                _ => None,
             })
             .flatten()
             .collect();
@@ -193,19 +329,404 @@ impl<F: RpcClientFactory> DbClient for RouteBasedImpl<F> {
             Err(Error::RouteBasedWriteError(route_based_error))
         }
     }
+
+    async fn write_batched(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        let ctx = &ctx;
+        with_total_timeout(
+            ctx,
+            write_in_batches(
+                self.rpc_config.max_write_batch_rows,
+                self.rpc_config.max_write_batch_concurrency,
+                req,
+                |req| async move { self.write_with_retry(ctx, &req).await },
+            ),
+        )
+        .await
+    }
+
+    /// Write the request by [`with_retry`], and only the tables of the failed
+    /// partitions are reissued by the retries, so that the written ones are not
+    /// duplicated.
+    ///
+    /// The tables written by all the attempts are merged into the response, or
+    /// into the [`RouteBasedWriteError`] if some partitions still fail.
+    async fn write_with_retry(
+        &self,
+        ctx: &RpcContext,
+        req: &WriteRequest,
+    ) -> Result<WriteResponse> {
+        // The request of the failed tables, and `None` for the first attempt.
+        let pending: Mutex<Option<WriteRequest>> = Mutex::new(None);
+        let written = Mutex::new((Vec::new(), WriteResponse::new(0, 0)));
+        let result = with_retry(&self.retry, || async {
+            let pending_req = pending.lock().unwrap().clone();
+            let req = pending_req.as_ref().unwrap_or(req);
+            match self.write_internal(ctx, req).await {
+                Err(Error::RouteBasedWriteError(mut e)) => {
+                    let mut written = written.lock().unwrap();
+                    written.0.append(&mut e.ok.0);
+                    written.1.merge(e.ok.1);
+                    e.ok = written.clone();
+
+                    let mut failed = WriteRequest::default();
+                    for table in e.errors.iter().flat_map(|(tables, _)| tables) {
+                        if let Some(points) = req.point_groups.get(table) {
+                            failed.point_groups.insert(table.clone(), points.clone());
+                        }
+                    }
+                    *pending.lock().unwrap() = Some(failed);
+                    Err(Error::RouteBasedWriteError(e))
+                }
+                result => result,
+            }
+        })
+        .await;
+
+        result.map(|resp| {
+            let (_, mut merged) = written.into_inner().unwrap();
+            merged.merge(resp);
+            merged
+        })
+    }
+
+    /// Write the streamed requests one by one, since they may be routed to
+    /// different endpoints.
+    async fn write_stream_batched(
+        &self,
+        ctx: &RpcContext,
+        mut reqs: BoxStream<'static, WriteRequest>,
+    ) -> Result<WriteResponse> {
+        let mut written = WriteResponse::new(0, 0);
+        let mut any_ok = false;
+        while let Some(req) = reqs.next().await {
+            match self.write_batched(ctx, &req).await {
+                Ok(resp) => {
+                    any_ok = true;
+                    written.merge(resp);
+                }
+                Err(e) if !any_ok => return Err(e),
+                Err(e) => {
+                    return Err(Error::PartialWrite {
+                        written,
+                        source: Box::new(e),
+                    })
+                }
+            }
+        }
+
+        Ok(written)
+    }
+
+    async fn write_columnar_internal(
+        &self,
+        ctx: &RpcContext,
+        batch: &ColumnarBatch,
+    ) -> Result<WriteResponse> {
+        let tables = vec![batch.table().to_string()];
+        let proxied = |client: Arc<InnerClient<F>>| async move {
+            client.write_columnar_internal(ctx, batch).await
+        };
+        let (endpoint, client) = match self.route_client(ctx, &tables).await {
+            Ok(routed) => routed,
+            Err(e) => return self.proxy_fallback(&tables, None, e, proxied).await,
+        };
+
+        match client.write_columnar_internal(ctx, batch).await {
+            Err(e) => {
+                if should_evict(&e) {
+                    self.evict_routes(&tables);
+                }
+                self.proxy_fallback(&tables, Some(&endpoint), e, proxied)
+                    .await
+            }
+            resp => resp,
+        }
+    }
+}
+
+#[async_trait]
+impl<F: RpcClientFactory + ?Sized> DbClient for RouteBasedImpl<F> {
+    async fn sql_query(&self, ctx: &RpcContext, req: &SqlQueryRequest) -> Result<SqlQueryResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        check_sql_length(self.rpc_config.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || self.sql_query_internal(&ctx, req)),
+            ))
+            .await
+    }
+
+    async fn sql_query_with_meta(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<(SqlQueryResponse, ResponseMeta)> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        check_sql_length(self.rpc_config.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || async {
+                    let ctx = &ctx;
+                    let proxied = |client: Arc<InnerClient<F>>| async move {
+                        client.sql_query_with_meta_internal(ctx, req).await
+                    };
+                    let (endpoint, client) = match self.route_client(ctx, &req.tables).await {
+                        Ok(routed) => routed,
+                        Err(e) => return self.proxy_fallback(&req.tables, None, e, proxied).await,
+                    };
+                    match client.sql_query_with_meta_internal(ctx, req).await {
+                        Err(e) => {
+                            if should_evict(&e) {
+                                self.evict_routes(&req.tables);
+                            }
+                            self.proxy_fallback(&req.tables, Some(&endpoint), e, proxied)
+                                .await
+                        }
+                        resp => resp,
+                    }
+                }),
+            ))
+            .await
+    }
+
+    async fn sql_query_stream(
+        &self,
+        ctx: &RpcContext,
+        req: &SqlQueryRequest,
+    ) -> Result<BoxStream<'static, Result<SqlQueryResponse>>> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        check_sql_length(self.rpc_config.max_sql_length, &req.sql)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || async {
+                    let ctx = &ctx;
+                    let proxied = |client: Arc<InnerClient<F>>| async move {
+                        client.sql_query_stream_internal(ctx, req).await
+                    };
+                    let (endpoint, client) = match self.route_client(ctx, &req.tables).await {
+                        Ok(routed) => routed,
+                        Err(e) => return self.proxy_fallback(&req.tables, None, e, proxied).await,
+                    };
+                    match client.sql_query_stream_internal(ctx, req).await {
+                        Err(e) => {
+                            if should_evict(&e) {
+                                self.evict_routes(&req.tables);
+                            }
+                            self.proxy_fallback(&req.tables, Some(&endpoint), e, proxied)
+                                .await
+                        }
+                        stream => stream,
+                    }
+                }),
+            ))
+            .await
+    }
+
+    async fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
+        self.in_flight.track(self.write_batched(ctx, req)).await
+    }
+
+    async fn write_stream(
+        &self,
+        ctx: &RpcContext,
+        reqs: BoxStream<'static, WriteRequest>,
+    ) -> Result<WriteResponse> {
+        self.in_flight
+            .track(self.write_stream_batched(ctx, reqs))
+            .await
+    }
+
+    async fn write_columnar(
+        &self,
+        ctx: &RpcContext,
+        batch: &ColumnarBatch,
+    ) -> Result<WriteResponse> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        self.in_flight
+            .track(with_total_timeout(
+                &ctx,
+                with_retry(&self.retry, || self.write_columnar_internal(&ctx, batch)),
+            ))
+            .await
+    }
+
+    async fn capabilities(&self, ctx: &RpcContext) -> Result<ServerCapabilities> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        let endpoint = self.default_endpoint()?;
+
+        self.standalone_pool
+            .get_or_create(&endpoint)
+            .capabilities_internal(&ctx)
+            .await
+    }
+
+    async fn route(&self, ctx: &RpcContext, tables: &[String]) -> Result<Vec<RouteInfo>> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        let router_handle = self.router.get_or_try_init(|| self.init_router()).await?;
+        let endpoints = with_total_timeout(&ctx, router_handle.route(tables, &ctx)).await?;
+
+        Ok(tables
+            .iter()
+            .zip(endpoints)
+            .filter_map(|(table, endpoint)| {
+                endpoint.map(|endpoint| RouteInfo {
+                    table: table.clone(),
+                    endpoint,
+                })
+            })
+            .collect())
+    }
+
+    async fn health_check(&self, ctx: &RpcContext) -> Result<()> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        // The endpoint for routing is probed even if no table is routed yet.
+        self.standalone_pool
+            .get_or_create(&self.default_endpoint()?);
+        let clients: Vec<_> = self
+            .standalone_pool
+            .pool
+            .iter()
+            .map(|pair| pair.value().clone())
+            .collect();
+        let results = join_all(
+            clients
+                .iter()
+                .map(|client| client.health_check_internal(&ctx)),
+        )
+        .await;
+
+        results.into_iter().collect()
+    }
+
+    async fn ping(&self, ctx: &RpcContext) -> Result<Duration> {
+        let ctx = crate::db_client::resolve_database(ctx, &self.default_database)?;
+        self.standalone_pool
+            .get_or_create(&self.default_endpoint()?)
+            .ping_internal(&ctx)
+            .await
+    }
+
+    async fn ping_all(&self, ctx: &RpcContext) -> Vec<(String, Result<Duration>)> {
+        // The endpoint for routing is pinged even if no table is routed yet.
+        if let Ok(endpoint) = self.default_endpoint() {
+            self.standalone_pool.get_or_create(&endpoint);
+        }
+        let clients: Vec<_> = self
+            .standalone_pool
+            .pool
+            .iter()
+            .map(|pair| (pair.key().to_string(), pair.value().clone()))
+            .collect();
+        join_all(clients.into_iter().map(|(endpoint, client)| async move {
+            let rtt = match crate::db_client::resolve_database(ctx, &self.default_database) {
+                Ok(ctx) => client.ping_internal(&ctx).await,
+                Err(e) => Err(e),
+            };
+            (endpoint, rtt)
+        }))
+        .await
+    }
+
+    fn with_endpoint_affinity(&self) -> Box<dyn DbClient + '_> {
+        Box::new(PinnedImpl::with_route_based(
+            self,
+            &self.in_flight,
+            self.retry.clone(),
+            self.default_database.clone(),
+        ))
+    }
+
+    fn effective_config(&self) -> EffectiveConfig {
+        EffectiveConfig {
+            mode: Mode::Direct,
+            endpoints: vec![self.router_endpoint.clone()],
+            default_database: self.default_database.clone(),
+            rpc_config: self.rpc_config.clone(),
+        }
+    }
+
+    fn connection_stats(&self) -> HashMap<String, ConnectionStats> {
+        self.factory.connection_stats()
+    }
+
+    fn stats(&self) -> ClientStats {
+        let counters = self.standalone_pool.pool.iter().map(|pair| {
+            let counter = pair.value().counter();
+            (pair.key().to_string(), counter.requests(), counter.errors())
+        });
+        ClientStats {
+            in_flight: self.in_flight.count(),
+            requests: self.in_flight.counter().requests(),
+            errors: self.in_flight.counter().errors(),
+            endpoints: merge_endpoint_stats(counters, self.factory.connection_stats()),
+            route_cache: self
+                .router
+                .get()
+                .map(|router_handle| router_handle.cache_stats()),
+            proxy_fallbacks: self.proxy_fallbacks.load(Ordering::Relaxed),
+        }
+    }
+
+    fn admin(&self) -> AdminClient<'_> {
+        AdminClient::new(self)
+    }
+
+    fn evict_routes(&self, tables: &[String]) {
+        if let Some(router_handle) = self.router.get() {
+            router_handle.evict(tables);
+        }
+    }
+
+    async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        self.in_flight.shutdown(timeout).await
+    }
+}
+
+/// Tell whether the routes should be evicted after the error, e.g. the table
+/// is moved to another endpoint or the endpoint is down, so that the retry can
+/// pick up the new routes.
+fn should_evict(err: &Error) -> bool {
+    match err {
+        Error::Server(server_error) => should_refresh(server_error.code, &server_error.msg),
+        Error::WithMeta { source, .. } => should_evict(source),
+        err => is_transport_error(err),
+    }
+}
+
+/// Tell whether the request fails because the endpoint can't be reached, so
+/// it can be reissued on another one. The errors from the server, e.g. the
+/// rejected credentials, will fail on any endpoint the same way.
+fn is_unreachable(err: &Error) -> bool {
+    match err {
+        Error::Connect { .. } | Error::CircuitOpen { .. } => true,
+        Error::Rpc(status) => status.code() == Code::Unavailable,
+        _ => false,
+    }
 }
 
 /// DirectClientPool is the pool actually holding connections to data nodes.
-struct DirectClientPool<F: RpcClientFactory> {
+struct DirectClientPool<F: RpcClientFactory + ?Sized> {
     pool: DashMap<Endpoint, Arc<InnerClient<F>>>,
     factory: Arc<F>,
+    coalesce_sql_query: bool,
+    strip_sql_comments: bool,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    idle_timeout: Option<Duration>,
 }
 
-impl<F: RpcClientFactory> DirectClientPool<F> {
-    fn new(factory: Arc<F>) -> Self {
+impl<F: RpcClientFactory + ?Sized> DirectClientPool<F> {
+    fn new(factory: Arc<F>, rpc_config: &RpcConfig) -> Self {
         Self {
             pool: DashMap::new(),
             factory,
+            coalesce_sql_query: rpc_config.coalesce_sql_query,
+            strip_sql_comments: rpc_config.strip_sql_comments,
+            circuit_breaker: rpc_config.circuit_breaker.clone(),
+            idle_timeout: rpc_config.effective_idle_timeout(),
         }
     }
 
@@ -220,8 +741,439 @@ impl<F: RpcClientFactory> DirectClientPool<F> {
                 .or_insert(Arc::new(InnerClient::new(
                     self.factory.clone(),
                     endpoint.to_string(),
+                    self.coalesce_sql_query,
+                    self.strip_sql_comments,
+                    self.circuit_breaker.clone(),
+                    self.idle_timeout,
                 )))
                 .clone()
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        time::Duration,
+    };
+
+    use tonic::Status;
+
+    use super::{should_evict, RouteBasedImpl};
+    use crate::{
+        config::CircuitBreakerConfig,
+        db_client::{DbClient, RetryHook},
+        errors::ServerError,
+        model::{
+            route::{Endpoint, RouteInfo},
+            sql_query::Request as SqlQueryRequest,
+            value::Value,
+            write::point::PointBuilder,
+        },
+        rpc_client::{MockRpcClientFactory, RpcContext},
+        Error, RetryConfig, RpcConfig, WriteRequest,
+    };
+
+    #[test]
+    fn test_should_evict() {
+        assert!(should_evict(&Error::Rpc(Status::unavailable(
+            "instance not found"
+        ))));
+        assert!(should_evict(&Error::Server(ServerError {
+            code: 400,
+            msg: "Table not found, table:demo".to_string(),
+        })));
+        assert!(!should_evict(&Error::Server(ServerError {
+            code: 500,
+            msg: "internal error".to_string(),
+        })));
+        assert!(!should_evict(&Error::Rpc(Status::invalid_argument(
+            "bad sql"
+        ))));
+    }
+
+    #[tokio::test]
+    async fn test_retry_failed_partitions() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        for (table, port) in [("table1", 11), ("table2", 12)] {
+            factory.route_table.insert(
+                table.to_string(),
+                Endpoint::new("192.168.0.1".to_string(), port),
+            );
+        }
+        factory
+            .unreachable_endpoints
+            .insert("192.168.0.1:12".to_string());
+        let rpc_config = RpcConfig {
+            retry: RetryConfig {
+                max_attempts: 3,
+                backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let unreachable_endpoints = factory.unreachable_endpoints.clone();
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &rpc_config,
+        )
+        .with_on_retry(Some(RetryHook::new(move |attempt, _, _| {
+            // The endpoint recovers before the last attempt.
+            if attempt == 2 {
+                unreachable_endpoints.clear();
+            }
+        })));
+        let mut req = WriteRequest::default();
+        for (table, timestamp) in [("table1", 1), ("table1", 2), ("table2", 3)] {
+            let point = PointBuilder::new(table)
+                .timestamp(timestamp)
+                .field("value", Value::Int64(timestamp))
+                .build()
+                .unwrap();
+            req.add_point(point);
+        }
+
+        // The written partition is not reissued by the retries.
+        let resp = client.write(&RpcContext::default(), &req).await.unwrap();
+        assert_eq!(resp.success, 3);
+        assert_eq!(*factory.request_counts.get("192.168.0.1:11").unwrap(), 1);
+        assert_eq!(*factory.request_counts.get("192.168.0.1:12").unwrap(), 1);
+
+        // The written tables are kept in the error of the last attempt.
+        factory
+            .unreachable_endpoints
+            .insert("192.168.0.1:12".to_string());
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &rpc_config,
+        );
+        match client.write(&RpcContext::default(), &req).await {
+            Err(Error::RouteBasedWriteError(e)) => {
+                assert_eq!(e.ok.0, vec!["table1".to_string()]);
+                assert_eq!(e.ok.1.success, 2);
+                assert_eq!(e.errors.len(), 1);
+                assert_eq!(e.errors[0].0, vec!["table2".to_string()]);
+            }
+            res => panic!("unexpected result:{res:?}"),
+        }
+        assert_eq!(*factory.request_counts.get("192.168.0.1:11").unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory.route_table.insert(
+            "table1".to_string(),
+            Endpoint::new("192.168.0.1".to_string(), 11),
+        );
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        let ctx = RpcContext::default();
+        client.health_check(&ctx).await.unwrap();
+
+        // The endpoint routed to is probed too.
+        let req = SqlQueryRequest {
+            tables: vec!["table1".to_string()],
+            sql: "select * from table1".to_string(),
+        };
+        client.sql_query(&ctx, &req).await.unwrap();
+        client.health_check(&ctx).await.unwrap();
+
+        factory
+            .unreachable_endpoints
+            .insert("192.168.0.3:13".to_string());
+        let client = RouteBasedImpl::new(
+            factory,
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        let err = client.health_check(&ctx).await.unwrap_err();
+        assert!(matches!(err, Error::Connect { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let factory = Arc::new(MockRpcClientFactory {
+            route_delay: Some(Duration::from_millis(20)),
+            ..Default::default()
+        });
+        factory.route_table.insert(
+            "table1".to_string(),
+            Endpoint::new("192.168.0.1".to_string(), 11),
+        );
+        let client = RouteBasedImpl::new(
+            factory,
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        let ctx = RpcContext::default();
+        assert!(client.ping(&ctx).await.unwrap() >= Duration::from_millis(20));
+        let rtts = client.ping_all(&ctx).await;
+        assert_eq!(rtts.len(), 1);
+
+        // The endpoint routed to is pinged too.
+        let req = SqlQueryRequest {
+            tables: vec!["table1".to_string()],
+            sql: "select * from table1".to_string(),
+        };
+        client.sql_query(&ctx, &req).await.unwrap();
+        let mut rtts = client.ping_all(&ctx).await;
+        rtts.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            rtts.iter()
+                .map(|(endpoint, _)| endpoint.as_str())
+                .collect::<Vec<_>>(),
+            ["192.168.0.1:11", "192.168.0.3:13"]
+        );
+        for (_, rtt) in rtts {
+            assert!(rtt.unwrap() >= Duration::from_millis(20));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let endpoint1 = Endpoint::new("192.168.0.1".to_string(), 11);
+        let endpoint2 = Endpoint::new("192.168.0.2".to_string(), 12);
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .route_table
+            .insert("table1".to_string(), endpoint1.clone());
+        factory
+            .route_table
+            .insert("table2".to_string(), endpoint2.clone());
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        let ctx = RpcContext::default();
+        let tables = vec!["table2".to_string(), "table1".to_string()];
+
+        let routes = client.route(&ctx, &tables).await.unwrap();
+        assert_eq!(
+            routes,
+            vec![
+                RouteInfo {
+                    table: "table2".to_string(),
+                    endpoint: endpoint2.clone(),
+                },
+                RouteInfo {
+                    table: "table1".to_string(),
+                    endpoint: endpoint1.clone(),
+                },
+            ]
+        );
+
+        // The cached route is returned unless the cache is bypassed.
+        factory
+            .route_table
+            .insert("table1".to_string(), endpoint2.clone());
+        let routes = client.route(&ctx, &tables[1..]).await.unwrap();
+        assert_eq!(routes[0].endpoint, endpoint1);
+        let routes = client
+            .route(&ctx.clone().bypass_route_cache(true), &tables[1..])
+            .await
+            .unwrap();
+        assert_eq!(routes[0].endpoint, endpoint2);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory.route_table.insert(
+            "table1".to_string(),
+            Endpoint::new("192.168.0.1".to_string(), 11),
+        );
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        assert!(client.stats().route_cache.is_none());
+
+        let ctx = RpcContext::default();
+        let req = SqlQueryRequest {
+            tables: vec!["table1".to_string()],
+            sql: "select * from table1".to_string(),
+        };
+        for _ in 0..3 {
+            client.sql_query(&ctx, &req).await.unwrap();
+        }
+
+        let stats = client.stats();
+        assert_eq!((stats.requests, stats.errors), (3, 0));
+        assert_eq!(stats.endpoints["192.168.0.1:11"].requests, 3);
+        let route_cache = stats.route_cache.unwrap();
+        assert_eq!(
+            (route_cache.size, route_cache.hits, route_cache.misses),
+            (1, 2, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_fallback() {
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory.route_table.insert(
+            "table1".to_string(),
+            Endpoint::new("192.168.0.1".to_string(), 11),
+        );
+        factory
+            .unreachable_endpoints
+            .insert("192.168.0.1:11".to_string());
+        let ctx = RpcContext::default();
+        let req = SqlQueryRequest {
+            tables: vec!["table1".to_string()],
+            sql: "select * from table1".to_string(),
+        };
+
+        // Fail without the fallback.
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        );
+        let err = client.sql_query(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::Connect { .. }));
+        assert_eq!(client.stats().proxy_fallbacks, 0);
+
+        // The endpoint routed to is unreachable.
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        )
+        .with_proxy_fallback(true);
+        client.sql_query(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.request_counts.get("192.168.0.3:13").unwrap(), 1);
+        assert_eq!(client.stats().proxy_fallbacks, 1);
+
+        // The route service is unavailable.
+        factory.unreachable_endpoints.clear();
+        factory.route_unavailable.store(true, Ordering::Relaxed);
+        client.sql_query(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.request_counts.get("192.168.0.3:13").unwrap(), 2);
+        assert_eq!(client.stats().proxy_fallbacks, 2);
+
+        // Routed normally after the route service is back.
+        factory.route_unavailable.store(false, Ordering::Relaxed);
+        client.sql_query(&ctx, &req).await.unwrap();
+        assert_eq!(*factory.request_counts.get("192.168.0.1:11").unwrap(), 1);
+        assert_eq!(client.stats().proxy_fallbacks, 2);
+
+        // The proxy is tried only once.
+        factory.route_unavailable.store(true, Ordering::Relaxed);
+        factory
+            .broken_endpoints
+            .insert("192.168.0.3:13".to_string());
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        )
+        .with_proxy_fallback(true);
+        let err = client.sql_query(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::Rpc(_)));
+        assert_eq!(client.stats().proxy_fallbacks, 1);
+
+        // The routing rejected by the server is not reissued on the proxy.
+        factory.route_unavailable.store(false, Ordering::Relaxed);
+        factory.route_rejected.store(true, Ordering::Relaxed);
+        factory.broken_endpoints.clear();
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &RpcConfig::default(),
+        )
+        .with_proxy_fallback(true);
+        let proxied = *factory.request_counts.get("192.168.0.3:13").unwrap();
+        let err = client.sql_query(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::Server(e) if e.code == 403));
+
+        // Nor is the query failed by the endpoint routed to.
+        factory.route_rejected.store(false, Ordering::Relaxed);
+        factory.server_errors.insert(req.sql.clone(), 500);
+        let err = client.sql_query(&ctx, &req).await.unwrap_err();
+        assert!(matches!(err, Error::Server(e) if e.code == 500));
+        assert_eq!(client.stats().proxy_fallbacks, 0);
+        assert_eq!(
+            *factory.request_counts.get("192.168.0.3:13").unwrap(),
+            proxied
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh_route_of_open_circuit() {
+        let endpoint1 = Endpoint::new("192.168.0.1".to_string(), 11);
+        let endpoint2 = Endpoint::new("192.168.0.2".to_string(), 12);
+        let factory = Arc::new(MockRpcClientFactory::default());
+        factory
+            .route_table
+            .insert("table1".to_string(), endpoint1.clone());
+        factory
+            .route_table
+            .insert("table2".to_string(), endpoint1.clone());
+        let rpc_config = RpcConfig {
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let client = RouteBasedImpl::new(
+            factory.clone(),
+            "192.168.0.3:13".to_string(),
+            Some("db".to_string()),
+            &rpc_config,
+        );
+        let ctx = RpcContext::default();
+        let make_req = |table: &str| SqlQueryRequest {
+            tables: vec![table.to_string()],
+            sql: format!("select * from {table}"),
+        };
+        // Cache the route of table2 without connecting.
+        client
+            .route_client(
+                &ctx.clone().database("db".to_string()),
+                &["table2".to_string()],
+            )
+            .await
+            .unwrap();
+
+        // The circuit of endpoint1 is opened by the failure of table1.
+        factory.unreachable_endpoints.insert(endpoint1.to_string());
+        let err = client.sql_query(&ctx, &make_req("table1")).await;
+        assert!(matches!(err, Err(Error::Connect { .. })));
+
+        // The cached route of table2 is refreshed, and the query is sent to the
+        // alternative endpoint.
+        factory
+            .route_table
+            .insert("table2".to_string(), endpoint2.clone());
+        client.sql_query(&ctx, &make_req("table2")).await.unwrap();
+        assert_eq!(
+            *factory.request_counts.get(&endpoint2.to_string()).unwrap(),
+            1
+        );
+
+        // Fail fast if there is no alternative endpoint.
+        let err = client.sql_query(&ctx, &make_req("table1")).await;
+        assert!(matches!(err, Err(Error::CircuitOpen { .. })));
+    }
+}
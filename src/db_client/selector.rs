@@ -0,0 +1,206 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Select the proxy endpoint to issue each request in `Proxy` mode.
+
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// The state of a proxy endpoint to select from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EndpointState {
+    pub endpoint: String,
+    /// The moving average of the latencies of the succeeded requests, and it
+    /// is `None` if no request succeeds yet.
+    pub latency: Option<Duration>,
+    /// The availability zone of the endpoint, see
+    /// [`Builder::endpoint_zone`](crate::Builder::endpoint_zone).
+    pub zone: Option<String>,
+    /// The number of the requests in flight on the endpoint.
+    pub in_flight: usize,
+    /// Whether the endpoint is healthy, i.e. not skipped after failing to
+    /// connect.
+    pub healthy: bool,
+    /// Whether the endpoint is connected, so the request to it saves the
+    /// handshake, while the cold one is connected on demand.
+    pub warm: bool,
+}
+
+/// Selector of the proxy endpoint to issue each request in `Proxy` mode,
+/// which can be registered by
+/// [`Builder::endpoint_selector`](crate::Builder::endpoint_selector).
+///
+/// If the selected endpoint fails to connect, the request falls back to the
+/// following endpoints in order, where the healthy ones in the
+/// [`RpcConfig::local_zone`](crate::RpcConfig::local_zone) are tried first and
+/// the unhealthy ones last.
+///
+/// If the local zone is set and any endpoint in it is healthy, only these
+/// endpoints are passed to the selector.
+pub trait EndpointSelector: Debug + Send + Sync {
+    /// Select the endpoint for the next request by its index in the
+    /// `endpoints`, which is never empty, and the index out of range wraps
+    /// around.
+    fn select(&self, endpoints: &[EndpointState]) -> usize;
+
+    /// The endpoint the next [`select`](EndpointSelector::select) would choose,
+    /// without advancing the state of the selector if any.
+    ///
+    /// It calls `select` by default, so override it if `select` has side
+    /// effects.
+    fn peek(&self, endpoints: &[EndpointState]) -> usize {
+        self.select(endpoints)
+    }
+}
+
+/// Select the endpoints in round-robin, which is used by default.
+///
+/// The warmth of the endpoints is not considered, so the requests are spread
+/// evenly and all the endpoints are connected in turn.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl EndpointSelector for RoundRobin {
+    fn select(&self, endpoints: &[EndpointState]) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % endpoints.len()
+    }
+
+    fn peek(&self, endpoints: &[EndpointState]) -> usize {
+        self.next.load(Ordering::Relaxed) % endpoints.len()
+    }
+}
+
+/// Select the healthy endpoint of the fewest requests in flight, and the warm
+/// ones are preferred among the ties, then the first one.
+#[derive(Debug, Default)]
+pub struct LeastInFlight;
+
+impl EndpointSelector for LeastInFlight {
+    fn select(&self, endpoints: &[EndpointState]) -> usize {
+        endpoints
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, state)| (!state.healthy, state.in_flight, !state.warm))
+            .map(|(idx, _)| idx)
+            .unwrap_or_default()
+    }
+}
+
+/// The weight of the latest latency in the moving average.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// The exponential moving average of the latencies.
+#[derive(Debug, Default)]
+pub(crate) struct RecentLatency {
+    /// The average in nanoseconds, and zero if nothing is recorded.
+    nanos: AtomicU64,
+}
+
+impl RecentLatency {
+    pub fn record(&self, latency: Duration) {
+        let latest = latency.as_nanos().clamp(1, u64::MAX as u128) as u64;
+        let _ = self
+            .nanos
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                if average == 0 {
+                    return Some(latest);
+                }
+                let average =
+                    average as f64 * (1.0 - LATENCY_SMOOTHING) + latest as f64 * LATENCY_SMOOTHING;
+                Some((average as u64).max(1))
+            });
+    }
+
+    pub fn get(&self) -> Option<Duration> {
+        match self.nanos.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{EndpointSelector, EndpointState, LeastInFlight, RecentLatency, RoundRobin};
+
+    fn make_states(states: &[(usize, bool)]) -> Vec<EndpointState> {
+        states
+            .iter()
+            .enumerate()
+            .map(|(idx, (in_flight, healthy))| EndpointState {
+                endpoint: format!("{idx}.{idx}.{idx}.{idx}:{idx}"),
+                latency: None,
+                zone: None,
+                in_flight: *in_flight,
+                healthy: *healthy,
+                warm: true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let selector = RoundRobin::default();
+        let states = make_states(&[(0, true), (0, true), (0, true)]);
+        assert_eq!(selector.peek(&states), 0);
+        let selected: Vec<_> = (0..4).map(|_| selector.select(&states)).collect();
+        assert_eq!(selected, [0, 1, 2, 0]);
+        assert_eq!(selector.peek(&states), 1);
+    }
+
+    #[test]
+    fn test_least_in_flight() {
+        let selector = LeastInFlight;
+        let states = make_states(&[(3, true), (1, true), (2, true)]);
+        assert_eq!(selector.select(&states), 1);
+
+        // The first one is selected among the ties.
+        let states = make_states(&[(2, true), (1, true), (1, true)]);
+        assert_eq!(selector.select(&states), 1);
+
+        // The unhealthy ones are selected only if all are unhealthy.
+        let states = make_states(&[(3, true), (0, false), (2, true)]);
+        assert_eq!(selector.select(&states), 2);
+        let states = make_states(&[(3, false), (1, false)]);
+        assert_eq!(selector.select(&states), 1);
+
+        // The cold ones are selected among the ties only if no warm one is
+        // healthy.
+        let mut states = make_states(&[(0, true), (0, true), (1, true)]);
+        states[0].warm = false;
+        assert_eq!(selector.select(&states), 1);
+        states[1].healthy = false;
+        assert_eq!(selector.select(&states), 0);
+    }
+
+    #[test]
+    fn test_recent_latency() {
+        let latency = RecentLatency::default();
+        assert_eq!(latency.get(), None);
+        latency.record(Duration::from_millis(100));
+        assert_eq!(latency.get(), Some(Duration::from_millis(100)));
+        latency.record(Duration::from_millis(200));
+        assert_eq!(latency.get(), Some(Duration::from_millis(120)));
+    }
+}
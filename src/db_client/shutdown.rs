@@ -0,0 +1,135 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+use crate::{db_client::stats::RequestCounter, Error, Result};
+
+/// Track the requests in flight, so that the client can be shut down after
+/// they are drained.
+///
+/// All the requests tracked are counted too, including the rejected ones.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    closing: AtomicBool,
+    count: AtomicUsize,
+    drained: Notify,
+    counter: RequestCounter,
+}
+
+/// Mark one request in flight until dropped.
+struct InFlightGuard<'a>(&'a InFlight);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.drained.notify_waiters();
+        }
+    }
+}
+
+impl InFlight {
+    fn enter(&self) -> Result<InFlightGuard<'_>> {
+        // Count the request before checking the flag, so that the shutdown
+        // either waits for it or it is rejected.
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(self);
+        if self.closing.load(Ordering::SeqCst) {
+            return Err(Error::Closed);
+        }
+        Ok(guard)
+    }
+
+    /// Run the request as in flight, or reject it by [`Error::Closed`] if the
+    /// client is shut down.
+    pub async fn track<T>(&self, request: impl Future<Output = Result<T>>) -> Result<T> {
+        let result = match self.enter() {
+            Ok(_guard) => request.await,
+            Err(e) => Err(e),
+        };
+        self.counter.record(&result);
+        result
+    }
+
+    /// The number of the requests in flight.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub fn counter(&self) -> &RequestCounter {
+        &self.counter
+    }
+
+    /// Reject the new requests, and wait for the ones in flight to finish for
+    /// at most `timeout`.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        self.closing.store(true, Ordering::SeqCst);
+        let drain = async {
+            loop {
+                let mut notified = pin!(self.drained.notified());
+                notified.as_mut().enable();
+                if self.count.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+
+        tokio::time::timeout(timeout, drain).await.map_err(|_| {
+            Error::Client(format!(
+                "Requests are still in flight after shutdown timeout, count:{}, timeout:{timeout:?}",
+                self.count.load(Ordering::SeqCst)
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::InFlight;
+    use crate::Error;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let in_flight = InFlight::default();
+        // Nothing to wait for.
+        in_flight.shutdown(Duration::ZERO).await.unwrap();
+        let res = in_flight.track(async { Ok(()) }).await;
+        assert!(matches!(res, Err(Error::Closed)));
+
+        let in_flight = InFlight::default();
+        let request = in_flight.track(async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(1)
+        });
+        let (res, shutdown) = tokio::join!(request, async {
+            tokio::task::yield_now().await;
+            in_flight.shutdown(Duration::from_secs(1)).await
+        });
+        assert_eq!(res.unwrap(), 1);
+        shutdown.unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Statistics of the client for observing its internals.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{rpc_client::ConnectionStats, Result};
+
+/// Snapshot of the statistics got by
+/// [`DbClient::stats`](crate::DbClient::stats).
+///
+/// The all-zero one is also returned by the implementations of the
+/// [`DbClient`](crate::DbClient) collecting no statistics.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientStats {
+    /// The number of the sql queries and writes in flight.
+    pub in_flight: usize,
+    /// The number of the sql queries and writes issued, including the failed
+    /// ones.
+    pub requests: u64,
+    /// The number of the failed sql queries and writes.
+    pub errors: u64,
+    /// The statistics of each endpoint connected.
+    pub endpoints: HashMap<String, EndpointStats>,
+    /// The statistics of the route cache, which is only used in `Direct` mode.
+    pub route_cache: Option<RouteCacheStats>,
+    /// The number of the requests proxied by the router endpoint after failing
+    /// to route or to reach the endpoint routed to, see
+    /// [`Builder::proxy_fallback`](crate::Builder::proxy_fallback).
+    pub proxy_fallbacks: u64,
+}
+
+/// Statistics of an endpoint.
+///
+/// Different from [`ClientStats`], each attempt of the retried requests and
+/// the route requests are counted too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EndpointStats {
+    /// The number of the rpcs issued to the endpoint.
+    pub requests: u64,
+    /// The number of the failed rpcs.
+    pub errors: u64,
+    /// The statistics of the connections to the endpoint.
+    pub connections: ConnectionStats,
+}
+
+/// Statistics of the route cache.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteCacheStats {
+    /// The number of the tables cached.
+    pub size: usize,
+    /// The number of the tables found in the cache.
+    pub hits: u64,
+    /// The number of the tables not found in the cache, or expired.
+    pub misses: u64,
+}
+
+impl RouteCacheStats {
+    /// The ratio of the hits to all the lookups, and it is zero if nothing is
+    /// looked up.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+/// Count the requests and the failed ones.
+#[derive(Debug, Default)]
+pub(crate) struct RequestCounter {
+    requests: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl RequestCounter {
+    pub fn record<T>(&self, result: &Result<T>) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+}
+
+/// Merge the statistics of the endpoints with the ones of their connections.
+pub(crate) fn merge_endpoint_stats(
+    counters: impl IntoIterator<Item = (String, u64, u64)>,
+    connections: HashMap<String, ConnectionStats>,
+) -> HashMap<String, EndpointStats> {
+    let mut endpoints: HashMap<_, _> = connections
+        .into_iter()
+        .map(|(endpoint, connections)| {
+            let stats = EndpointStats {
+                connections,
+                ..Default::default()
+            };
+            (endpoint, stats)
+        })
+        .collect();
+    for (endpoint, requests, errors) in counters {
+        let stats = endpoints.entry(endpoint).or_default();
+        stats.requests += requests;
+        stats.errors += errors;
+    }
+    endpoints
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{merge_endpoint_stats, RequestCounter, RouteCacheStats};
+    use crate::{rpc_client::ConnectionStats, Error};
+
+    #[test]
+    fn test_request_counter() {
+        let counter = RequestCounter::default();
+        counter.record(&Ok(()));
+        counter.record::<()>(&Err(Error::Closed));
+        assert_eq!((counter.requests(), counter.errors()), (2, 1));
+    }
+
+    #[test]
+    fn test_merge_endpoint_stats() {
+        let connections = HashMap::from([
+            (
+                "a:8831".to_string(),
+                ConnectionStats {
+                    connects: 2,
+                    failed_connects: 1,
+                },
+            ),
+            ("b:8831".to_string(), ConnectionStats::default()),
+        ]);
+        let counters = vec![("a:8831".to_string(), 3, 1), ("c:8831".to_string(), 1, 1)];
+        let endpoints = merge_endpoint_stats(counters, connections);
+        assert_eq!(endpoints.len(), 3);
+        assert_eq!(endpoints["a:8831"].requests, 3);
+        assert_eq!(endpoints["a:8831"].errors, 1);
+        assert_eq!(endpoints["a:8831"].connections.connects, 2);
+        assert_eq!(endpoints["b:8831"].requests, 0);
+        assert_eq!(endpoints["c:8831"].connections, ConnectionStats::default());
+    }
+
+    #[test]
+    fn test_hit_rate() {
+        assert_eq!(RouteCacheStats::default().hit_rate(), 0.0);
+        let stats = RouteCacheStats {
+            size: 1,
+            hits: 3,
+            misses: 1,
+        };
+        assert_eq!(stats.hit_rate(), 0.75);
+    }
+}
@@ -15,11 +15,14 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::fmt::Display;
+use std::{fmt::Display, io, sync::Arc};
 
 use thiserror::Error as ThisError;
 
-use crate::model::write::Response;
+use crate::{
+    model::{value::DataType, write::Response},
+    rpc_client::ResponseMeta,
+};
 
 /// An error generated by the client.
 #[derive(Debug, ThisError)]
@@ -40,6 +43,7 @@ pub enum Error {
     #[error("failed to connect, addr:{addr:?}, err:{source:?}")]
     Connect {
         addr: String,
+        kind: ConnectErrorKind,
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
@@ -52,11 +56,37 @@ pub enum Error {
     #[error("failed to check auth, err:{0}")]
     AuthFail(AuthFailStatus),
 
+    /// The credentials are rejected by the server, by either the grpc status
+    /// or the code in the response header.
+    #[error("unauthenticated, msg:{0}")]
+    Unauthenticated(String),
+
+    /// The credentials are accepted but not permitted to issue the request,
+    /// by either the grpc status or the code in the response header.
+    #[error("permission denied, msg:{0}")]
+    PermissionDenied(String),
+
+    /// The credentials are still rejected by the server after the token is
+    /// refreshed by the
+    /// [`Authorization::BearerProvider`](crate::Authorization::BearerProvider).
+    #[error("failed to authenticate with the refreshed token, msg:{0}")]
+    Auth(String),
+
     /// Error from write in route based mode, some of rows may be written
     /// successfully, and others may fail.
     #[error("failed to write with route based client, err:{0}")]
     RouteBasedWriteError(RouteBasedWriteError),
 
+    /// Error from the write split by
+    /// [`RpcConfig::max_write_batch_rows`](crate::RpcConfig::max_write_batch_rows),
+    /// some of the split requests are written successfully, and `source` is
+    /// the first error of the failed ones.
+    #[error("failed to write partially, written:{written:?}, err:{source}")]
+    PartialWrite {
+        written: Response,
+        source: Box<Error>,
+    },
+
     /// Error unknown
     #[error("unknown error, msg:{0}")]
     Unknown(String),
@@ -64,12 +94,88 @@ pub enum Error {
     #[error("failed to decode, msg:{0}")]
     BuildRows(String),
 
+    /// The response from the server can't be understood.
+    #[error("malformed response, reason:{reason}")]
+    MalformedResponse { reason: String },
+
     #[error("failed to decode arrow payload, msg:{0}")]
     DecodeArrowPayload(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("failed to find a database")]
     NoDatabase,
 
+    /// The endpoint provided to the [`Builder`](crate::Builder) is not in the
+    /// form: `{host}:{port}`.
+    #[error("invalid endpoint:{endpoint:?}, reason:{reason}")]
+    InvalidEndpoint { endpoint: String, reason: String },
+
+    /// The value of the column can't be converted into the requested type, see
+    /// [`Row::get`](crate::model::sql_query::row::Row::get).
+    #[error("mismatched type of column:{column}, expect:{expect}, found:{found:?}")]
+    MismatchedColumnType {
+        column: String,
+        expect: &'static str,
+        found: DataType,
+    },
+
+    /// Error from the endpoint pinned by
+    /// [`DbClient::with_endpoint_affinity`](crate::DbClient::with_endpoint_affinity).
+    #[error("failed to request pinned endpoint, endpoint:{endpoint}, err:{source}")]
+    PinnedEndpoint {
+        endpoint: String,
+        source: Box<Error>,
+    },
+
+    /// Error from the response failed by its status, along with the metadata of
+    /// it, returned by
+    /// [`DbClient::sql_query_with_meta`](crate::DbClient::sql_query_with_meta).
+    #[error("failed with response meta:{meta:?}, err:{source}")]
+    WithMeta {
+        meta: ResponseMeta,
+        source: Box<Error>,
+    },
+
+    /// The request fails fast because the circuit of the endpoint is open, see
+    /// [`RpcConfig::circuit_breaker`](crate::RpcConfig::circuit_breaker).
+    #[error("circuit is open, endpoint:{endpoint}")]
+    CircuitOpen { endpoint: String },
+
+    /// None of the proxy endpoints can be reached in `Proxy` mode, along with
+    /// the last failure of each endpoint, i.e. [`Error::Connect`] or
+    /// [`Error::CircuitOpen`], see
+    /// [`Builder::endpoint_recovery_wait`](crate::Builder::endpoint_recovery_wait).
+    #[error("no available endpoint, errors:{0:?}")]
+    NoAvailableEndpoint(Vec<(String, Error)>),
+
+    /// The request is cancelled by the
+    /// [`RpcContext::cancellation_token`](crate::RpcContext::cancellation_token).
+    #[error("request is cancelled")]
+    Cancelled,
+
+    /// The request is not issued or aborted because the
+    /// [`RpcContext::deadline`](crate::RpcContext::deadline) has passed or the
+    /// [`RpcContext::total_timeout`](crate::RpcContext::total_timeout) is
+    /// exceeded.
+    #[error("deadline is exceeded")]
+    DeadlineExceeded,
+
+    /// The request is rejected because the client is shut down by
+    /// [`DbClient::shutdown`](crate::DbClient::shutdown).
+    #[error("client is closed")]
+    Closed,
+
+    /// The operation is not supported by the implementation of the
+    /// [`DbClient`](crate::DbClient), e.g. the one relying on the default
+    /// methods of the trait.
+    #[error("unsupported operation:{0}")]
+    Unsupported(&'static str),
+
+    /// Error from a request shared by the concurrent callers. Note the
+    /// coalesced sql queries return the errors of their original variants
+    /// instead.
+    #[error("failed in shared request, err:{0}")]
+    Shared(Arc<Error>),
+
     #[error(transparent)]
     Other {
         #[from]
@@ -77,6 +183,197 @@ pub enum Error {
     },
 }
 
+impl Error {
+    /// Get the underlying [`tonic::Status`] if the error is caused by the
+    /// grpc, even if it is wrapped in [`Error::PinnedEndpoint`],
+    /// [`Error::WithMeta`] or [`Error::Shared`].
+    pub fn as_tonic_status(&self) -> Option<&tonic::Status> {
+        match self {
+            Error::Rpc(status) => Some(status),
+            Error::PinnedEndpoint { source, .. } | Error::WithMeta { source, .. } => {
+                source.as_tonic_status()
+            }
+            Error::Shared(source) => source.as_tonic_status(),
+            _ => None,
+        }
+    }
+
+    /// Get the kind of the connecting failure, even if it is wrapped in
+    /// [`Error::PinnedEndpoint`] or [`Error::Shared`].
+    pub fn connect_error_kind(&self) -> Option<ConnectErrorKind> {
+        match self {
+            Error::Connect { kind, .. } => Some(*kind),
+            Error::PinnedEndpoint { source, .. } => source.connect_error_kind(),
+            Error::Shared(source) => source.connect_error_kind(),
+            _ => None,
+        }
+    }
+
+    /// Get the metadata of the failed response, even if it is wrapped in
+    /// [`Error::PinnedEndpoint`].
+    pub fn response_meta(&self) -> Option<&ResponseMeta> {
+        match self {
+            Error::WithMeta { meta, .. } => Some(meta),
+            Error::PinnedEndpoint { source, .. } => source.response_meta(),
+            _ => None,
+        }
+    }
+
+    /// Drop the metadata attached by [`Error::WithMeta`], for the callers
+    /// which don't ask for it.
+    pub(crate) fn without_meta(self) -> Self {
+        match self {
+            Error::WithMeta { source, .. } => *source,
+            err => err,
+        }
+    }
+
+    /// Build the [`Error::Connect`] of the kind classified from the `source`.
+    pub(crate) fn connect(
+        addr: impl Into<String>,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        let source = source.into();
+        Error::Connect {
+            addr: addr.into(),
+            kind: ConnectErrorKind::classify(source.as_ref()),
+            source,
+        }
+    }
+
+    /// Take the error shared by the concurrent callers, e.g. the output of the
+    /// [`SingleFlight`](crate::single_flight::SingleFlight), which is the
+    /// original one for the last caller and a copy of it for the others.
+    pub(crate) fn unshare(shared: Arc<Error>) -> Self {
+        Arc::try_unwrap(shared).unwrap_or_else(|shared| shared.duplicate())
+    }
+
+    /// Copy the error of the same variant, so the callers can still match on
+    /// it. The boxed sources which can't be cloned are kept by their messages.
+    fn duplicate(&self) -> Self {
+        let message = |source: &dyn Display| -> Box<dyn std::error::Error + Send + Sync> {
+            source.to_string().into()
+        };
+        match self {
+            Error::Server(e) => Error::Server(e.clone()),
+            Error::Rpc(status) => Error::Rpc(status.clone()),
+            Error::Connect { addr, kind, source } => Error::Connect {
+                addr: addr.clone(),
+                kind: *kind,
+                source: message(source),
+            },
+            Error::Client(msg) => Error::Client(msg.clone()),
+            Error::AuthFail(status) => Error::AuthFail(status.clone()),
+            Error::Unauthenticated(msg) => Error::Unauthenticated(msg.clone()),
+            Error::PermissionDenied(msg) => Error::PermissionDenied(msg.clone()),
+            Error::Auth(msg) => Error::Auth(msg.clone()),
+            Error::RouteBasedWriteError(e) => Error::RouteBasedWriteError(RouteBasedWriteError {
+                ok: e.ok.clone(),
+                errors: e
+                    .errors
+                    .iter()
+                    .map(|(tables, e)| (tables.clone(), e.duplicate()))
+                    .collect(),
+            }),
+            Error::PartialWrite { written, source } => Error::PartialWrite {
+                written: written.clone(),
+                source: Box::new(source.duplicate()),
+            },
+            Error::Unknown(msg) => Error::Unknown(msg.clone()),
+            Error::BuildRows(msg) => Error::BuildRows(msg.clone()),
+            Error::MalformedResponse { reason } => Error::MalformedResponse {
+                reason: reason.clone(),
+            },
+            Error::DecodeArrowPayload(source) => Error::DecodeArrowPayload(message(source)),
+            Error::NoDatabase => Error::NoDatabase,
+            Error::InvalidEndpoint { endpoint, reason } => Error::InvalidEndpoint {
+                endpoint: endpoint.clone(),
+                reason: reason.clone(),
+            },
+            Error::MismatchedColumnType {
+                column,
+                expect,
+                found,
+            } => Error::MismatchedColumnType {
+                column: column.clone(),
+                expect,
+                found: *found,
+            },
+            Error::PinnedEndpoint { endpoint, source } => Error::PinnedEndpoint {
+                endpoint: endpoint.clone(),
+                source: Box::new(source.duplicate()),
+            },
+            Error::WithMeta { meta, source } => Error::WithMeta {
+                meta: meta.clone(),
+                source: Box::new(source.duplicate()),
+            },
+            Error::CircuitOpen { endpoint } => Error::CircuitOpen {
+                endpoint: endpoint.clone(),
+            },
+            Error::NoAvailableEndpoint(errors) => Error::NoAvailableEndpoint(
+                errors
+                    .iter()
+                    .map(|(endpoint, e)| (endpoint.clone(), e.duplicate()))
+                    .collect(),
+            ),
+            Error::Cancelled => Error::Cancelled,
+            Error::DeadlineExceeded => Error::DeadlineExceeded,
+            Error::Closed => Error::Closed,
+            Error::Unsupported(operation) => Error::Unsupported(operation),
+            Error::Shared(source) => Error::Shared(source.clone()),
+            Error::Other { source } => Error::Other {
+                source: anyhow::anyhow!("{source:#}"),
+            },
+        }
+    }
+}
+
+/// The kind of the failure of [`Error::Connect`], e.g. to tell the
+/// infrastructure faults from the transient ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectErrorKind {
+    /// The hostname of the endpoint can't be resolved.
+    Dns,
+    /// The connection is refused by the endpoint.
+    Refused,
+    /// The connecting times out.
+    Timeout,
+    /// The TLS is misconfigured or its handshake fails.
+    Tls,
+    Other,
+}
+
+impl ConnectErrorKind {
+    /// Classify the error by walking through its sources, e.g. the
+    /// [`io::Error`] from connecting the socket.
+    pub fn classify(err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut current = Some(err);
+        while let Some(err) = current {
+            let msg = err.to_string().to_lowercase();
+            if msg.contains("dns error") {
+                return ConnectErrorKind::Dns;
+            }
+            if let Some(io_err) = err.downcast_ref::<io::Error>() {
+                match io_err.kind() {
+                    io::ErrorKind::ConnectionRefused => return ConnectErrorKind::Refused,
+                    io::ErrorKind::TimedOut => return ConnectErrorKind::Timeout,
+                    _ => {}
+                }
+            }
+            if err.is::<tokio::time::error::Elapsed>() || err.is::<tower::timeout::error::Elapsed>()
+            {
+                return ConnectErrorKind::Timeout;
+            }
+            if msg.contains("tls") || msg.contains("certificate") || msg.contains("handshake") {
+                return ConnectErrorKind::Tls;
+            }
+            current = err.source();
+        }
+
+        ConnectErrorKind::Other
+    }
+}
+
 #[derive(Debug)]
 pub struct RouteBasedWriteError {
     pub ok: (Vec<String>, Response),       // (tables, write_response)
@@ -85,15 +382,13 @@ pub struct RouteBasedWriteError {
 
 impl From<Vec<(Vec<String>, Result<Response>)>> for RouteBasedWriteError {
     fn from(write_results: Vec<(Vec<String>, Result<Response>)>) -> Self {
-        let mut success_total = 0;
-        let mut failed_total = 0;
+        let mut merged = Response::new(0, 0);
         let mut ok_tables = Vec::new();
         let mut errors = Vec::new();
         for (tables, write_result) in write_results {
             match write_result {
                 Ok(write_resp) => {
-                    success_total += write_resp.success;
-                    failed_total += write_resp.failed;
+                    merged.merge(write_resp);
                     ok_tables.extend(tables);
                 }
                 Err(e) => {
@@ -103,7 +398,7 @@ impl From<Vec<(Vec<String>, Result<Response>)>> for RouteBasedWriteError {
         }
 
         Self {
-            ok: (ok_tables, Response::new(success_total, failed_total)),
+            ok: (ok_tables, merged),
             errors,
         }
     }
@@ -170,11 +465,121 @@ pub type Result<T> = std::result::Result<T, Error>;
 mod test {
     use super::*;
 
+    #[test]
+    fn test_as_tonic_status() {
+        let rpc_error = Error::Rpc(tonic::Status::unavailable("server is down"));
+        assert_eq!(
+            rpc_error.as_tonic_status().unwrap().code(),
+            tonic::Code::Unavailable
+        );
+
+        let pinned_error = Error::PinnedEndpoint {
+            endpoint: "1.1.1.1:1111".to_string(),
+            source: Box::new(Error::Shared(Arc::new(rpc_error))),
+        };
+        assert_eq!(
+            pinned_error.as_tonic_status().unwrap().message(),
+            "server is down"
+        );
+
+        assert!(Error::NoDatabase.as_tonic_status().is_none());
+    }
+
+    /// Error wrapping its source, as the errors of hyper and tonic.
+    #[derive(Debug, ThisError)]
+    #[error("{msg}")]
+    struct Wrapped {
+        msg: &'static str,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    }
+
+    #[tokio::test]
+    async fn test_classify_connect_error() {
+        let wrap = |msg,
+                    source: Box<dyn std::error::Error + Send + Sync>|
+         -> Box<dyn std::error::Error + Send + Sync> {
+            Box::new(Wrapped { msg, source })
+        };
+        let io_error = |kind| Box::new(io::Error::new(kind, "io error"));
+        let elapsed =
+            tokio::time::timeout(std::time::Duration::ZERO, futures::future::pending::<()>())
+                .await
+                .unwrap_err();
+
+        let cases: Vec<(Box<dyn std::error::Error + Send + Sync>, _)> = vec![
+            (
+                wrap(
+                    "transport error",
+                    wrap("dns error", io_error(io::ErrorKind::Other)),
+                ),
+                ConnectErrorKind::Dns,
+            ),
+            (
+                wrap(
+                    "transport error",
+                    wrap(
+                        "tcp connect error",
+                        io_error(io::ErrorKind::ConnectionRefused),
+                    ),
+                ),
+                ConnectErrorKind::Refused,
+            ),
+            (io_error(io::ErrorKind::TimedOut), ConnectErrorKind::Timeout),
+            (Box::new(elapsed), ConnectErrorKind::Timeout),
+            (
+                wrap(
+                    "transport error",
+                    Box::new(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid peer certificate: UnknownIssuer",
+                    )),
+                ),
+                ConnectErrorKind::Tls,
+            ),
+            (
+                wrap("transport error", io_error(io::ErrorKind::Other)),
+                ConnectErrorKind::Other,
+            ),
+        ];
+        for (source, expected) in cases {
+            let err = Error::connect("1.1.1.1:1111", source);
+            assert_eq!(err.connect_error_kind(), Some(expected), "err:{err}");
+        }
+
+        let shared = Error::Shared(Arc::new(Error::connect(
+            "1.1.1.1:1111",
+            io::Error::from(io::ErrorKind::ConnectionRefused),
+        )));
+        assert_eq!(shared.connect_error_kind(), Some(ConnectErrorKind::Refused));
+        assert!(Error::NoDatabase.connect_error_kind().is_none());
+    }
+
+    #[test]
+    fn test_unshare() {
+        let shared = Arc::new(Error::Server(ServerError {
+            code: 404,
+            msg: "table not found".to_string(),
+        }));
+        let copied = Error::unshare(shared.clone());
+        assert!(matches!(&copied, Error::Server(e) if e.code == 404));
+        let original = Error::unshare(shared);
+        assert!(matches!(original, Error::Server(e) if e.msg == "table not found"));
+
+        let connect_error = Arc::new(Error::connect(
+            "1.1.1.1:1111",
+            io::Error::from(io::ErrorKind::ConnectionRefused),
+        ));
+        let _guard = connect_error.clone();
+        let copied = Error::unshare(connect_error);
+        assert_eq!(copied.connect_error_kind(), Some(ConnectErrorKind::Refused));
+    }
+
     #[test]
     fn test_error_standardizing() {
         let source_error = Box::new(Error::Unknown("unknown error".to_string()));
         let connect_error = Error::Connect {
             addr: "1.1.1.1:1111".to_string(),
+            kind: ConnectErrorKind::Other,
             source: source_error as _,
         };
         assert_eq!(
@@ -76,24 +76,57 @@
 //! # }
 //! ```
 
+// Make the code generated by the derive macros resolve inside this crate too.
+extern crate self as horaedb_client;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod circuit_breaker;
 mod config;
 #[doc(hidden)]
 pub mod db_client;
 mod errors;
 #[doc(hidden)]
 pub mod model;
+mod retry_budget;
 mod router;
 mod rpc_client;
+mod single_flight;
 mod util;
 
+#[cfg(feature = "derive")]
+#[doc(inline)]
+pub use horaedb_client_derive::IntoPoint;
+#[doc(inline)]
+pub use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "tracing")]
+#[doc(inline)]
+pub use crate::rpc_client::{TraceContext, TraceContextInjector};
 #[doc(inline)]
 pub use crate::{
-    config::{Authorization, RpcConfig},
-    db_client::{Builder, DbClient, Mode},
-    errors::{Error, Result},
+    config::{
+        Authorization, BasicAuthorization, CircuitBreakerConfig, Compression, EffectiveConfig,
+        KeepAliveOverride, RetryBudgetConfig, RetryConfig, RetryPredicate, RpcConfig, StatusSource,
+        StatusTrailerKeys, TimeoutScaling, TlsConfig, TlsIdentity, TokenProvider,
+    },
+    db_client::{
+        AdminClient, Builder, ClientStats, DbClient, EndpointSelector, EndpointState,
+        EndpointStats, LeastInFlight, Mode, RoundRobin, RouteCacheStats,
+    },
+    errors::{ConnectErrorKind, Error, Result},
     model::{
-        sql_query::{Request as SqlQueryRequest, Response as SqlQueryResponse},
-        write::{Request as WriteRequest, Response as WriteResponse},
+        capabilities::ServerCapabilities,
+        route::RouteInfo,
+        sql_query::{stats::QueryStats, Request as SqlQueryRequest, Response as SqlQueryResponse},
+        write::{
+            point::IntoPoint, FailureDetail as WriteFailureDetail, Request as WriteRequest,
+            Response as WriteResponse,
+        },
+    },
+    rpc_client::{
+        ConnectionStats, MessageSize, MessageSizeRecorder, ObservedRequest, Observer,
+        OperationAuditor, OperationOutcome, Resolver, ResponseMeta, RpcClient, RpcClientFactory,
+        RpcContext, RpcOperation, SystemResolver,
     },
-    rpc_client::RpcContext,
 };
@@ -15,7 +15,9 @@
 // specific language governing permissions and limitations
 // under the License.
 
+pub mod capabilities;
 pub mod route;
 pub mod sql_query;
+pub mod table_schema;
 pub mod value;
 pub mod write;
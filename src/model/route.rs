@@ -70,6 +70,15 @@ impl FromStr for Endpoint {
     }
 }
 
+/// The endpoint which a table is routed to.
+///
+/// The shard of the table is not provided by the route response yet.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RouteInfo {
+    pub table: String,
+    pub endpoint: Endpoint,
+}
+
 impl From<EndPointPb> for Endpoint {
     fn from(endpoint_pb: EndPointPb) -> Self {
         Self {
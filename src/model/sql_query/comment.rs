@@ -0,0 +1,121 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Strip the comments from the sql.
+
+/// Strip the `--` line comments and the `/* */` block comments from the sql.
+///
+/// The quoted strings and identifiers are kept as they are, even if they
+/// contain the comment-like sequences. A block comment is replaced by a space
+/// to avoid joining the tokens around it, and the unclosed one is kept to let
+/// the server report the error.
+///
+/// Example:
+/// ```rust
+/// # use horaedb_client::model::sql_query::comment::strip_comments;
+/// let sql = "SELECT /* secret */ '-- not a comment' FROM demo -- secret";
+/// assert_eq!(
+///     strip_comments(sql),
+///     "SELECT   '-- not a comment' FROM demo "
+/// );
+/// ```
+pub fn strip_comments(sql: &str) -> String {
+    let mut stripped = String::with_capacity(sql.len());
+    let mut chars = sql.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                stripped.push(c);
+                while let Some((_, quoted)) = chars.next() {
+                    stripped.push(quoted);
+                    if quoted == c {
+                        break;
+                    }
+                    // The backslash escapes the next char in the string literals.
+                    if quoted == '\\' && c != '`' {
+                        if let Some((_, escaped)) = chars.next() {
+                            stripped.push(escaped);
+                        }
+                    }
+                }
+            }
+            '-' if matches!(chars.peek(), Some((_, '-'))) => {
+                // Keep the line break ending the comment.
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => match sql[idx + 2..].find("*/") {
+                Some(len) => {
+                    let end = idx + 2 + len + 2;
+                    while chars.next_if(|(i, _)| *i < end).is_some() {}
+                    stripped.push(' ');
+                }
+                None => {
+                    stripped.push_str(&sql[idx..]);
+                    break;
+                }
+            },
+            c => stripped.push(c),
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod test {
+    use super::strip_comments;
+
+    #[test]
+    fn test_strip_comments() {
+        let cases = [
+            ("SELECT 1", "SELECT 1"),
+            ("SELECT 1 -- comment", "SELECT 1 "),
+            ("SELECT 1 -- comment\nFROM demo", "SELECT 1 \nFROM demo"),
+            ("SELECT a/* comment */FROM demo", "SELECT a FROM demo"),
+            ("SELECT /* multi\nline -- comment */ 1", "SELECT   1"),
+            ("SELECT 10--1", "SELECT 10"),
+        ];
+        for (sql, expect) in cases {
+            assert_eq!(strip_comments(sql), expect, "sql:{sql}");
+        }
+    }
+
+    #[test]
+    fn test_keep_literals() {
+        let sqls = [
+            "SELECT '-- not a comment'",
+            "SELECT '/* not a comment */'",
+            "SELECT 'it''s -- not a comment'",
+            r"SELECT 'it\'s -- not a comment'",
+            r"SELECT 'ends with backslash\\' -- x",
+            "SELECT `col--name`, \"/*ident*/\" FROM demo",
+            "SELECT 1 /* unclosed",
+        ];
+        let expects = [
+            "SELECT '-- not a comment'",
+            "SELECT '/* not a comment */'",
+            "SELECT 'it''s -- not a comment'",
+            r"SELECT 'it\'s -- not a comment'",
+            r"SELECT 'ends with backslash\\' ",
+            "SELECT `col--name`, \"/*ident*/\" FROM demo",
+            "SELECT 1 /* unclosed",
+        ];
+        for (sql, expect) in sqls.into_iter().zip(expects) {
+            assert_eq!(strip_comments(sql), expect, "sql:{sql}");
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Deserialize the rows of the sql query into the user types by serde.
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value as JsonValue};
+
+use crate::{
+    model::{
+        sql_query::{response::Response, row::Row},
+        value::Value,
+    },
+    Error, Result,
+};
+
+impl Row {
+    /// Deserialize the row into `T`, e.g. a struct deriving
+    /// `serde::Deserialize`, whose fields are mapped to the columns by name.
+    ///
+    /// The null values are deserialized as `None` of the `Option` fields,
+    /// and so are the `NaN` and infinite floats which can't be represented.
+    /// The timestamp is deserialized as the milliseconds in `i64`, and the
+    /// varbinary as `Vec<u8>`.
+    pub fn to_struct<T: DeserializeOwned>(&self) -> Result<T> {
+        let object = self
+            .columns()
+            .iter()
+            .map(|column| (column.name().to_string(), to_json_value(column.value())))
+            .collect::<Map<_, _>>();
+        serde_json::from_value(JsonValue::Object(object))
+            .map_err(|e| Error::Client(format!("Failed to deserialize row, err:{e}")))
+    }
+}
+
+impl Response {
+    /// Deserialize all the rows into `T`, see [`Row::to_struct`].
+    ///
+    /// Error will be thrown if any row fails, telling the index of it.
+    pub fn rows_as<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(idx, row)| {
+                row.to_struct()
+                    .map_err(|e| Error::Client(format!("Failed to deserialize row:{idx}, err:{e}")))
+            })
+            .collect()
+    }
+}
+
+fn to_json_value(value: &Value) -> JsonValue {
+    match value {
+        Value::Null => JsonValue::Null,
+        Value::Timestamp(v) | Value::Int64(v) => JsonValue::from(*v),
+        Value::Double(v) => float_to_json_value(*v),
+        Value::Float(v) => float_to_json_value(*v as f64),
+        Value::Varbinary(v) => JsonValue::from(v.as_slice()),
+        Value::String(v) => JsonValue::from(v.as_str()),
+        Value::UInt64(v) => JsonValue::from(*v),
+        Value::UInt32(v) => JsonValue::from(*v),
+        Value::UInt16(v) => JsonValue::from(*v),
+        Value::UInt8(v) => JsonValue::from(*v),
+        Value::Int32(v) => JsonValue::from(*v),
+        Value::Int16(v) => JsonValue::from(*v),
+        Value::Int8(v) => JsonValue::from(*v),
+        Value::Boolean(v) => JsonValue::from(*v),
+    }
+}
+
+fn float_to_json_value(v: f64) -> JsonValue {
+    Number::from_f64(v)
+        .map(JsonValue::Number)
+        .unwrap_or(JsonValue::Null)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::{
+        model::{
+            sql_query::{
+                response::Response,
+                row::{Column, Row},
+            },
+            value::Value,
+        },
+        Error,
+    };
+
+    fn make_row(values: Vec<(&str, Value)>) -> Row {
+        Row::new(
+            values
+                .into_iter()
+                .map(|(name, value)| Column::new(name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_to_struct() {
+        let row = make_row(vec![
+            ("t", Value::Timestamp(1000)),
+            ("name", Value::String("a".to_string())),
+            ("value", Value::Double(1.5)),
+            ("payload", Value::Varbinary(vec![1, 2])),
+            ("missing", Value::Null),
+        ]);
+        let map: HashMap<String, serde_json::Value> = row.to_struct().unwrap();
+        assert_eq!(map["t"], 1000);
+        assert_eq!(map["name"], "a");
+        assert_eq!(map["value"], 1.5);
+        assert_eq!(map["payload"], serde_json::json!([1, 2]));
+        assert!(map["missing"].is_null());
+
+        // The nulls are deserialized as `None`.
+        let row = make_row(vec![("a", Value::Int32(1)), ("b", Value::Null)]);
+        let map: HashMap<String, Option<i64>> = row.to_struct().unwrap();
+        assert_eq!(
+            map,
+            HashMap::from([("a".to_string(), Some(1)), ("b".to_string(), None)])
+        );
+        let err = row.to_struct::<HashMap<String, i64>>().unwrap_err();
+        assert!(matches!(err, Error::Client(_)));
+    }
+
+    #[test]
+    fn test_rows_as() {
+        let resp = Response {
+            rows: vec![
+                make_row(vec![("a", Value::Int32(1))]),
+                make_row(vec![("a", Value::String("x".to_string()))]),
+            ],
+            ..Default::default()
+        };
+        let err = resp.rows_as::<HashMap<String, i32>>().unwrap_err();
+        assert!(err.to_string().contains("row:1"));
+
+        let rows = resp
+            .rows_as::<HashMap<String, serde_json::Value>>()
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["a"], 1);
+    }
+}
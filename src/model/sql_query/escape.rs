@@ -0,0 +1,107 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Helpers to render the identifiers and literals into sql safely.
+//!
+//! The rendered sql follows the MySQL dialect used by HoraeDB, that is, the
+//! identifiers are quoted by backticks and the string literals are quoted by
+//! single quotes with the backslash escapes.
+
+use crate::{model::value::Value, Error, Result};
+
+/// Quote the identifier, e.g. table name or column name, with backticks.
+pub fn quote_identifier(ident: &str) -> String {
+    format!("`{}`", ident.replace('`', "``"))
+}
+
+/// Quote the string literal with single quotes.
+pub fn quote_string(literal: &str) -> String {
+    let mut quoted = String::with_capacity(literal.len() + 2);
+    quoted.push('\'');
+    for c in literal.chars() {
+        match c {
+            '\'' => quoted.push_str("''"),
+            '\\' => quoted.push_str("\\\\"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+
+    quoted
+}
+
+/// Render the [`Value`] as a sql literal.
+///
+/// The timestamp is rendered as the milliseconds, and the varbinary and the
+/// non-finite float values are not supported.
+pub fn literal(value: &Value) -> Result<String> {
+    let literal = match value {
+        Value::Null => "NULL".to_string(),
+        Value::Timestamp(v) => v.to_string(),
+        Value::Double(v) if v.is_finite() => v.to_string(),
+        Value::Float(v) if v.is_finite() => v.to_string(),
+        Value::String(v) => quote_string(v),
+        Value::UInt64(v) => v.to_string(),
+        Value::UInt32(v) => v.to_string(),
+        Value::UInt16(v) => v.to_string(),
+        Value::UInt8(v) => v.to_string(),
+        Value::Int64(v) => v.to_string(),
+        Value::Int32(v) => v.to_string(),
+        Value::Int16(v) => v.to_string(),
+        Value::Int8(v) => v.to_string(),
+        Value::Boolean(v) => v.to_string(),
+        Value::Double(_) | Value::Float(_) | Value::Varbinary(_) => {
+            return Err(Error::Client(format!(
+                "Unsupported value to render as sql literal, value:{value:?}"
+            )))
+        }
+    };
+
+    Ok(literal)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote_identifier("host"), "`host`");
+        assert_eq!(quote_identifier("ho`st"), "`ho``st`");
+        assert_eq!(quote_string("it's"), "'it''s'");
+        assert_eq!(quote_string(r"a\'b"), r"'a\\''b'");
+    }
+
+    #[test]
+    fn test_literal() {
+        let cases = vec![
+            (Value::Null, "NULL"),
+            (Value::Timestamp(1000), "1000"),
+            (Value::Double(0.5), "0.5"),
+            (Value::Int8(-1), "-1"),
+            (Value::UInt64(u64::MAX), "18446744073709551615"),
+            (Value::Boolean(true), "true"),
+            (Value::String("a'b".to_string()), "'a''b'"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(literal(&value).unwrap(), expected);
+        }
+
+        assert!(literal(&Value::Double(f64::NAN)).is_err());
+        assert!(literal(&Value::Varbinary(b"bin".to_vec())).is_err());
+    }
+}
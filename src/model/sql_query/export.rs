@@ -0,0 +1,225 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Export the [`SqlQueryResponse`](Response) into the files of other formats.
+
+use std::io::Write;
+
+use anyhow::Context;
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+
+use crate::{
+    model::sql_query::{record_batch::rows_to_record_batch, response::Response},
+    Error, Result,
+};
+
+/// The compression codec of the exported parquet file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParquetCompression {
+    Uncompressed,
+    Snappy,
+    Zstd,
+}
+
+/// Options for exporting parquet file.
+#[derive(Clone, Debug)]
+pub struct ParquetOptions {
+    /// Default value is `Snappy`.
+    pub compression: ParquetCompression,
+    /// The max number of rows in a row group.
+    ///
+    /// Default value is 1048576.
+    pub max_row_group_size: usize,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            compression: ParquetCompression::Snappy,
+            max_row_group_size: 1024 * 1024,
+        }
+    }
+}
+
+impl Response {
+    /// Write the rows into `writer` in parquet format.
+    ///
+    /// The arrow schema is derived from the rows, or taken from the
+    /// [`schema`](Response::schema) of the response if there is no rows, in
+    /// which case a file without any row is written. It will fail if there is
+    /// neither, e.g. for the response of the affected rows.
+    pub fn write_parquet<W: Write + Send>(
+        &self,
+        writer: W,
+        options: &ParquetOptions,
+    ) -> Result<()> {
+        let record_batch = if self.rows.is_empty() {
+            self.to_record_batches()?.pop().ok_or_else(|| {
+                Error::Client("Failed to export the response without schema".to_string())
+            })?
+        } else {
+            rows_to_record_batch(&self.rows)?
+        };
+
+        let compression = match options.compression {
+            ParquetCompression::Uncompressed => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        };
+        let props = WriterProperties::builder()
+            .set_compression(compression)
+            .set_max_row_group_size(options.max_row_group_size)
+            .build();
+
+        let mut arrow_writer = ArrowWriter::try_new(writer, record_batch.schema(), Some(props))
+            .context("failed to create parquet writer")?;
+        arrow_writer
+            .write(&record_batch)
+            .context("failed to write parquet")?;
+        arrow_writer
+            .close()
+            .context("failed to close parquet writer")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use arrow::datatypes::{DataType, TimeUnit};
+    use parquet::{
+        arrow::arrow_reader::ParquetRecordBatchReaderBuilder,
+        basic::Compression,
+        file::{reader::FileReader, serialized_reader::SerializedFileReader},
+    };
+
+    use super::{ParquetCompression, ParquetOptions};
+    use crate::{
+        model::{
+            sql_query::{
+                row::{Column, Row},
+                schema::{ColumnKind, ColumnSchema},
+                Response,
+            },
+            value::Value,
+        },
+        Error,
+    };
+
+    #[test]
+    fn test_write_parquet() {
+        let rows = (0..5)
+            .map(|i| {
+                Row::new(vec![
+                    Column::new("t".to_string(), Value::Timestamp(i)),
+                    Column::new("host".to_string(), Value::String(format!("host{i}"))),
+                    Column::new("value".to_string(), Value::Int64(i)),
+                ])
+            })
+            .collect();
+        let resp = Response {
+            rows,
+            ..Default::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rows.parquet");
+        let options = ParquetOptions {
+            compression: ParquetCompression::Zstd,
+            max_row_group_size: 2,
+        };
+        resp.write_parquet(File::create(&path).unwrap(), &options)
+            .unwrap();
+
+        let file_reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = file_reader.metadata();
+        assert_eq!(metadata.num_row_groups(), 3);
+        assert!(matches!(
+            metadata.row_group(0).column(0).compression(),
+            Compression::ZSTD(_)
+        ));
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let num_rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(num_rows, 5);
+    }
+
+    #[test]
+    fn test_write_empty_parquet() {
+        let resp = Response {
+            schema: vec![
+                ColumnSchema {
+                    name: "t".to_string(),
+                    data_type: DataType::Timestamp(TimeUnit::Millisecond, None),
+                    nullable: false,
+                    kind: ColumnKind::Timestamp,
+                },
+                ColumnSchema {
+                    name: "value".to_string(),
+                    data_type: DataType::Int64,
+                    nullable: true,
+                    kind: ColumnKind::Field,
+                },
+            ],
+            ..Default::default()
+        };
+        let read_back = |resp: &Response| {
+            let mut file = tempfile::tempfile().unwrap();
+            resp.write_parquet(&mut file, &ParquetOptions::default())
+                .unwrap();
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+            let schema = reader.schema().clone();
+            let num_rows: usize = reader
+                .build()
+                .unwrap()
+                .map(|batch| batch.unwrap().num_rows())
+                .sum();
+            (schema, num_rows)
+        };
+
+        // The schema of the response is kept.
+        let (schema, num_rows) = read_back(&resp);
+        assert_eq!(num_rows, 0);
+        let fields: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| (field.name().as_str(), field.data_type().clone()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("t", DataType::Timestamp(TimeUnit::Millisecond, None)),
+                ("value", DataType::Int64)
+            ]
+        );
+
+        // Neither rows nor schema, e.g. the response of the DDL.
+        let err = Response::default()
+            .write_parquet(tempfile::tempfile().unwrap(), &ParquetOptions::default())
+            .unwrap_err();
+        assert!(matches!(err, Error::Client(_)));
+    }
+}
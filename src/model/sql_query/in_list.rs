@@ -0,0 +1,190 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::{
+    model::{
+        sql_query::{
+            escape::{literal, quote_identifier},
+            Request,
+        },
+        value::Value,
+    },
+    Error, Result,
+};
+
+/// Build the queries filtering a column by a large set of values.
+///
+/// The placeholder [`IN_LIST_PLACEHOLDER`](Self::IN_LIST_PLACEHOLDER) in the
+/// sql template will be replaced by the `IN` predicate with the properly
+/// escaped values, e.g. `` `host` IN ('a', 'b') ``. The values will be split
+/// into multiple queries if there are more than `max_values_per_query` ones.
+///
+/// Example:
+/// ```rust
+/// # use horaedb_client::model::{sql_query::in_list::InListQuery, value::Value};
+/// let query = InListQuery::new(
+///     "demo",
+///     "SELECT * FROM demo WHERE {in_list} ORDER BY t",
+///     "host",
+///     vec![
+///         Value::String("a".to_string()),
+///         Value::String("b".to_string()),
+///     ],
+/// );
+/// let reqs = query.build().unwrap();
+/// assert_eq!(
+///     reqs[0].sql,
+///     "SELECT * FROM demo WHERE `host` IN ('a', 'b') ORDER BY t"
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct InListQuery {
+    tables: Vec<String>,
+    sql_template: String,
+    column: String,
+    values: Vec<Value>,
+    max_values_per_query: usize,
+}
+
+impl InListQuery {
+    pub const DEFAULT_MAX_VALUES_PER_QUERY: usize = 1000;
+    pub const IN_LIST_PLACEHOLDER: &'static str = "{in_list}";
+
+    pub fn new(
+        table: impl Into<String>,
+        sql_template: impl Into<String>,
+        column: impl Into<String>,
+        values: Vec<Value>,
+    ) -> Self {
+        Self {
+            tables: vec![table.into()],
+            sql_template: sql_template.into(),
+            column: column.into(),
+            values,
+            max_values_per_query: Self::DEFAULT_MAX_VALUES_PER_QUERY,
+        }
+    }
+
+    /// Set the max number of values in the `IN` predicate of one query.
+    pub fn max_values_per_query(mut self, max_values_per_query: usize) -> Self {
+        self.max_values_per_query = max_values_per_query;
+        self
+    }
+
+    /// Build the split queries.
+    pub fn build(&self) -> Result<Vec<Request>> {
+        if !self.sql_template.contains(Self::IN_LIST_PLACEHOLDER) {
+            return Err(Error::Client(format!(
+                "Placeholder:{} not found in sql template:{}",
+                Self::IN_LIST_PLACEHOLDER,
+                self.sql_template
+            )));
+        }
+        if self.values.is_empty() {
+            return Err(Error::Client(
+                "Values of in list should not be empty".to_string(),
+            ));
+        }
+        if self.max_values_per_query == 0 {
+            return Err(Error::Client(
+                "Max values per query should be positive".to_string(),
+            ));
+        }
+
+        let column = quote_identifier(&self.column);
+        self.values
+            .chunks(self.max_values_per_query)
+            .map(|values| {
+                let literals = values.iter().map(literal).collect::<Result<Vec<_>>>()?;
+                let predicate = format!("{column} IN ({})", literals.join(", "));
+                Ok(Request {
+                    tables: self.tables.clone(),
+                    sql: self
+                        .sql_template
+                        .replace(Self::IN_LIST_PLACEHOLDER, &predicate),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InListQuery;
+    use crate::model::value::Value;
+
+    #[test]
+    fn test_escape_values() {
+        let values = vec![
+            Value::String("it's".to_string()),
+            Value::String("'); DROP TABLE demo; --".to_string()),
+            Value::Int32(42),
+        ];
+        let query = InListQuery::new(
+            "demo",
+            "SELECT * FROM demo WHERE {in_list}",
+            "ho`st",
+            values,
+        );
+
+        let reqs = query.build().unwrap();
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].tables, vec!["demo".to_string()]);
+        assert_eq!(
+            reqs[0].sql,
+            "SELECT * FROM demo WHERE `ho``st` IN ('it''s', '''); DROP TABLE demo; --', 42)"
+        );
+    }
+
+    #[test]
+    fn test_split_values() {
+        let values = (0..5).map(Value::Int64).collect();
+        let query = InListQuery::new("demo", "SELECT * FROM demo WHERE {in_list}", "id", values)
+            .max_values_per_query(2);
+
+        let sqls: Vec<_> = query
+            .build()
+            .unwrap()
+            .into_iter()
+            .map(|req| req.sql)
+            .collect();
+        assert_eq!(
+            sqls,
+            vec![
+                "SELECT * FROM demo WHERE `id` IN (0, 1)",
+                "SELECT * FROM demo WHERE `id` IN (2, 3)",
+                "SELECT * FROM demo WHERE `id` IN (4)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_query() {
+        let values = vec![Value::Int64(1)];
+        let no_placeholder = InListQuery::new("demo", "SELECT * FROM demo", "id", values.clone());
+        assert!(no_placeholder.build().is_err());
+
+        let no_values =
+            InListQuery::new("demo", "SELECT * FROM demo WHERE {in_list}", "id", vec![]);
+        assert!(no_values.build().is_err());
+
+        let zero_size =
+            InListQuery::new("demo", "SELECT * FROM demo WHERE {in_list}", "id", values)
+                .max_values_per_query(0);
+        assert!(zero_size.build().is_err());
+    }
+}
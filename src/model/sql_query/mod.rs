@@ -15,10 +15,25 @@
 // specific language governing permissions and limitations
 // under the License.
 
+pub mod batch;
+pub mod comment;
+#[cfg(feature = "serde")]
+mod de;
 pub mod display;
+pub mod escape;
+#[cfg(feature = "parquet")]
+pub mod export;
+pub mod in_list;
+pub mod paged;
+pub mod projection;
+#[cfg(feature = "arrow")]
+mod record_batch;
 pub(crate) mod request;
 pub(crate) mod response;
 pub mod row;
+pub mod schema;
+pub mod stats;
+pub mod time_range;
 
 pub use request::Request;
 pub use response::Response;
@@ -0,0 +1,101 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Helpers to page through the result of a sql query by `LIMIT` and `OFFSET`.
+
+use crate::model::sql_query::comment::strip_comments;
+
+/// Tell whether the sql has a `LIMIT` or `OFFSET` clause, which conflicts with
+/// the ones appended for paging.
+///
+/// The comments and the quoted strings and identifiers in the sql are skipped.
+pub fn contains_limit(sql: &str) -> bool {
+    let sql = strip_comments(sql);
+    let mut token = String::new();
+    let mut chars = sql.chars();
+    let is_limit =
+        |token: &str| token.eq_ignore_ascii_case("limit") || token.eq_ignore_ascii_case("offset");
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                for quoted in chars.by_ref() {
+                    if quoted == c {
+                        break;
+                    }
+                }
+                token.clear();
+            }
+            c if c.is_alphanumeric() || c == '_' => token.push(c),
+            _ => {
+                if is_limit(&token) {
+                    return true;
+                }
+                token.clear();
+            }
+        }
+    }
+
+    is_limit(&token)
+}
+
+/// Append the `LIMIT` and `OFFSET` clauses of the page to the sql, and the
+/// trailing semicolon of the sql is dropped.
+pub(crate) fn page_sql(sql: &str, page_size: usize, offset: usize) -> String {
+    let sql = sql.trim_end().trim_end_matches(';').trim_end();
+    format!("{sql} LIMIT {page_size} OFFSET {offset}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{contains_limit, page_sql};
+
+    #[test]
+    fn test_contains_limit() {
+        for sql in [
+            "SELECT * FROM demo LIMIT 10",
+            "select * from demo limit 10 offset 5",
+            "SELECT * FROM demo OFFSET 5",
+            "SELECT * FROM demo\nLIMIT\n10",
+            "SELECT * FROM demo LIMIT",
+        ] {
+            assert!(contains_limit(sql), "sql:{sql}");
+        }
+
+        for sql in [
+            "SELECT * FROM demo",
+            "SELECT * FROM demo WHERE name = 'limit 10'",
+            "SELECT `limit`, \"offset\" FROM demo",
+            "SELECT * FROM demo -- LIMIT 10",
+            "SELECT * FROM demo /* LIMIT 10 */",
+            "SELECT limited, offsets FROM demo",
+        ] {
+            assert!(!contains_limit(sql), "sql:{sql}");
+        }
+    }
+
+    #[test]
+    fn test_page_sql() {
+        assert_eq!(
+            page_sql("SELECT * FROM demo", 10, 20),
+            "SELECT * FROM demo LIMIT 10 OFFSET 20"
+        );
+        assert_eq!(
+            page_sql("SELECT * FROM demo ; \n", 10, 0),
+            "SELECT * FROM demo LIMIT 10 OFFSET 0"
+        );
+    }
+}
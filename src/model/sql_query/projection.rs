@@ -0,0 +1,156 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Helpers to build the projected queries instead of `SELECT *`.
+
+use crate::{
+    model::sql_query::{escape::quote_identifier, Request},
+    Error, Result,
+};
+
+/// Build the query reading only the `columns` of the `table`.
+///
+/// The `predicate` is appended as the `WHERE` clause as it is, so the values in
+/// it should be escaped by [`escape`](crate::model::sql_query::escape).
+///
+/// Example:
+/// ```rust
+/// # use horaedb_client::model::sql_query::projection::projected_select;
+/// let req = projected_select("demo", &["t", "value"], Some("t > 1000")).unwrap();
+/// assert_eq!(req.sql, "SELECT `t`, `value` FROM `demo` WHERE t > 1000");
+/// ```
+pub fn projected_select<S: AsRef<str>>(
+    table: &str,
+    columns: &[S],
+    predicate: Option<&str>,
+) -> Result<Request> {
+    if columns.is_empty() {
+        return Err(Error::Client(
+            "Columns to select should not be empty".to_string(),
+        ));
+    }
+
+    let columns: Vec<_> = columns
+        .iter()
+        .map(|column| quote_identifier(column.as_ref()))
+        .collect();
+    let mut sql = format!(
+        "SELECT {} FROM {}",
+        columns.join(", "),
+        quote_identifier(table)
+    );
+    if let Some(predicate) = predicate {
+        sql.push_str(" WHERE ");
+        sql.push_str(predicate);
+    }
+
+    Ok(Request {
+        tables: vec![table.to_string()],
+        sql,
+    })
+}
+
+/// Tell whether the sql selects all the columns by `*`, e.g. `SELECT *` or
+/// `SELECT t.*`, which can be used to enforce the projections.
+///
+/// The quoted strings and identifiers in the sql are skipped, and `*` used as
+/// the multiplication or in `count(*)` is not flagged.
+pub fn contains_select_star(sql: &str) -> bool {
+    let mut prev_token = String::new();
+    let mut token = String::new();
+    let mut chars = sql.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                // Skip the quoted content, and the doubled quote is just a escaped
+                // quote, which will be skipped as another quoted content.
+                for quoted in chars.by_ref() {
+                    if quoted == c {
+                        break;
+                    }
+                }
+                prev_token = c.to_string();
+                token.clear();
+            }
+            '*' => {
+                let prev = if token.is_empty() {
+                    &prev_token
+                } else {
+                    &token
+                };
+                let prev = prev.to_ascii_lowercase();
+                if matches!(prev.as_str(), "select" | "distinct" | "all" | "," | ".") {
+                    return true;
+                }
+                prev_token = "*".to_string();
+                token.clear();
+            }
+            c if c.is_alphanumeric() || c == '_' => token.push(c),
+            c if c.is_whitespace() => {
+                if !token.is_empty() {
+                    prev_token = std::mem::take(&mut token);
+                }
+            }
+            c => {
+                prev_token = c.to_string();
+                token.clear();
+            }
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_projected_select() {
+        let req = projected_select("demo", &["t", "a`b"], None).unwrap();
+        assert_eq!(req.sql, "SELECT `t`, `a``b` FROM `demo`");
+        assert_eq!(req.tables, vec!["demo".to_string()]);
+
+        let empty: &[&str] = &[];
+        assert!(projected_select("demo", empty, None).is_err());
+    }
+
+    #[test]
+    fn test_contains_select_star() {
+        let select_star_sqls = [
+            "SELECT * FROM demo",
+            "select distinct * from demo",
+            "SELECT t, * FROM demo",
+            "SELECT d.* FROM demo d",
+            "SELECT\n*\nFROM demo",
+        ];
+        for sql in select_star_sqls {
+            assert!(contains_select_star(sql), "sql:{sql}");
+        }
+
+        let projected_sqls = [
+            "SELECT t, value FROM demo",
+            "SELECT count(*) FROM demo",
+            "SELECT a * b FROM demo",
+            "SELECT 'select *' FROM demo",
+            "SELECT `*` FROM demo",
+        ];
+        for sql in projected_sqls {
+            assert!(!contains_select_star(sql), "sql:{sql}");
+        }
+    }
+}
@@ -0,0 +1,377 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::sync::Arc;
+
+use arrow::{
+    array::{
+        ArrayRef, BinaryArray, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array,
+        Int64Array, Int8Array, NullArray, StringArray, TimestampMillisecondArray, UInt16Array,
+        UInt32Array, UInt64Array, UInt8Array,
+    },
+    datatypes::{DataType as ArrowDataType, Field, Schema, TimeUnit},
+    record_batch::RecordBatch,
+};
+
+use crate::{
+    model::{
+        sql_query::{response::Response, row::Row},
+        value::{DataType, Value},
+    },
+    Error, Result,
+};
+
+macro_rules! build_array {
+    ($rows:expr, $col_idx:expr, $variant:ident, $array_type:ty) => {{
+        let values = $rows
+            .iter()
+            .map(|row| match row.columns()[$col_idx].value() {
+                Value::Null => Ok(None),
+                Value::$variant(v) => Ok(Some(v.clone())),
+                v => Err(Error::Client(format!(
+                    "Mismatched value in column:{}, expect:{}, value:{v:?}",
+                    row.columns()[$col_idx].name(),
+                    stringify!($variant),
+                ))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Arc::new(values.into_iter().collect::<$array_type>()) as ArrayRef
+    }};
+}
+
+/// Map the [`DataType`] of HoraeDB to the arrow one.
+pub(crate) fn to_arrow_data_type(data_type: DataType) -> ArrowDataType {
+    match data_type {
+        DataType::Null => ArrowDataType::Null,
+        DataType::Timestamp => ArrowDataType::Timestamp(TimeUnit::Millisecond, None),
+        DataType::Double => ArrowDataType::Float64,
+        DataType::Float => ArrowDataType::Float32,
+        DataType::Varbinary => ArrowDataType::Binary,
+        DataType::String => ArrowDataType::Utf8,
+        DataType::UInt64 => ArrowDataType::UInt64,
+        DataType::UInt32 => ArrowDataType::UInt32,
+        DataType::UInt16 => ArrowDataType::UInt16,
+        DataType::UInt8 => ArrowDataType::UInt8,
+        DataType::Int64 => ArrowDataType::Int64,
+        DataType::Int32 => ArrowDataType::Int32,
+        DataType::Int16 => ArrowDataType::Int16,
+        DataType::Int8 => ArrowDataType::Int8,
+        DataType::Boolean => ArrowDataType::Boolean,
+    }
+}
+
+/// Convert the rows into an arrow [`RecordBatch`].
+///
+/// The schema is derived from the rows: all the columns are nullable, and the
+/// data type of a column is decided by its first non-null value. The column
+/// consisting of nulls only is typed as `Null`.
+#[cfg(feature = "parquet")]
+pub(crate) fn rows_to_record_batch(rows: &[Row]) -> Result<RecordBatch> {
+    let first_row = rows
+        .first()
+        .ok_or_else(|| Error::Client("Failed to derive schema from empty rows".to_string()))?;
+    let col_names: Vec<_> = first_row.columns().iter().map(|col| col.name()).collect();
+    for row in rows {
+        let row_col_names = row.columns().iter().map(|col| col.name());
+        if !row_col_names.eq(col_names.iter().copied()) {
+            return Err(Error::Client(format!(
+                "Mismatched columns in rows, expect:{col_names:?}, row:{row:?}"
+            )));
+        }
+    }
+
+    let mut fields = Vec::with_capacity(col_names.len());
+    let mut arrays = Vec::with_capacity(col_names.len());
+    for (col_idx, col_name) in col_names.into_iter().enumerate() {
+        let data_type = rows
+            .iter()
+            .map(|row| row.columns()[col_idx].value())
+            .find(|value| !value.is_null())
+            .map(|value| value.data_type())
+            .unwrap_or(DataType::Null);
+
+        fields.push(Field::new(col_name, to_arrow_data_type(data_type), true));
+        arrays.push(build_column(rows, col_idx, data_type)?);
+    }
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)
+        .map_err(|e| Error::Client(format!("Failed to build record batch, err:{e}")))
+}
+
+/// Map the arrow [`DataType`](ArrowDataType) returned by the server to the one
+/// of HoraeDB, in the same way as the rows are decoded.
+fn from_arrow_data_type(data_type: &ArrowDataType) -> Option<DataType> {
+    let data_type = match data_type {
+        ArrowDataType::Null => DataType::Null,
+        ArrowDataType::Boolean => DataType::Boolean,
+        ArrowDataType::Int8 => DataType::Int8,
+        ArrowDataType::Int16 => DataType::Int16,
+        ArrowDataType::Int32 => DataType::Int32,
+        ArrowDataType::Int64 => DataType::Int64,
+        ArrowDataType::UInt8 => DataType::UInt8,
+        ArrowDataType::UInt16 => DataType::UInt16,
+        ArrowDataType::UInt32 => DataType::UInt32,
+        ArrowDataType::UInt64 => DataType::UInt64,
+        ArrowDataType::Float32 => DataType::Float,
+        ArrowDataType::Float64 => DataType::Double,
+        ArrowDataType::Utf8 | ArrowDataType::LargeUtf8 => DataType::String,
+        ArrowDataType::Binary | ArrowDataType::LargeBinary => DataType::Varbinary,
+        ArrowDataType::Timestamp(TimeUnit::Millisecond, _)
+        | ArrowDataType::Time32(TimeUnit::Millisecond) => DataType::Timestamp,
+        ArrowDataType::Dictionary(index_type, value_type)
+            if index_type.as_ref() == &ArrowDataType::Int32
+                && value_type.as_ref() == &ArrowDataType::Utf8 =>
+        {
+            DataType::String
+        }
+        _ => return None,
+    };
+
+    Some(data_type)
+}
+
+fn build_column(rows: &[Row], col_idx: usize, data_type: DataType) -> Result<ArrayRef> {
+    let array = match data_type {
+        DataType::Null => Arc::new(NullArray::new(rows.len())) as ArrayRef,
+        DataType::Timestamp => {
+            build_array!(rows, col_idx, Timestamp, TimestampMillisecondArray)
+        }
+        DataType::Double => build_array!(rows, col_idx, Double, Float64Array),
+        DataType::Float => build_array!(rows, col_idx, Float, Float32Array),
+        DataType::Varbinary => build_array!(rows, col_idx, Varbinary, BinaryArray),
+        DataType::String => build_array!(rows, col_idx, String, StringArray),
+        DataType::UInt64 => build_array!(rows, col_idx, UInt64, UInt64Array),
+        DataType::UInt32 => build_array!(rows, col_idx, UInt32, UInt32Array),
+        DataType::UInt16 => build_array!(rows, col_idx, UInt16, UInt16Array),
+        DataType::UInt8 => build_array!(rows, col_idx, UInt8, UInt8Array),
+        DataType::Int64 => build_array!(rows, col_idx, Int64, Int64Array),
+        DataType::Int32 => build_array!(rows, col_idx, Int32, Int32Array),
+        DataType::Int16 => build_array!(rows, col_idx, Int16, Int16Array),
+        DataType::Int8 => build_array!(rows, col_idx, Int8, Int8Array),
+        DataType::Boolean => build_array!(rows, col_idx, Boolean, BooleanArray),
+    };
+
+    Ok(array)
+}
+
+impl Response {
+    /// Convert the rows into the arrow [`RecordBatch`]es according to the
+    /// [`schema`](Response::schema) of the response.
+    ///
+    /// The column order and nullability are preserved, and the data types are
+    /// normalized as the rows are decoded, e.g. the timestamp is
+    /// `Timestamp(Millisecond)`, the string is `Utf8` and the varbinary is
+    /// `Binary`. The rows are merged on decoding, so at most one record batch
+    /// is returned, and none if the response carries no rows.
+    ///
+    /// Error will be thrown if any column has no equivalent in HoraeDB, or the
+    /// rows disagree with the schema.
+    pub fn to_record_batches(&self) -> Result<Vec<RecordBatch>> {
+        if self.schema.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut fields = Vec::with_capacity(self.schema.len());
+        let mut arrays = Vec::with_capacity(self.schema.len());
+        for (col_idx, column) in self.schema.iter().enumerate() {
+            let data_type = from_arrow_data_type(&column.data_type).ok_or_else(|| {
+                Error::Client(format!(
+                    "Unsupported data type:{} of column:{}",
+                    column.data_type, column.name
+                ))
+            })?;
+            for row in &self.rows {
+                if row.columns().get(col_idx).map(|col| col.name()) != Some(&column.name) {
+                    return Err(Error::Client(format!(
+                        "Mismatched column:{} in row:{row:?}",
+                        column.name
+                    )));
+                }
+            }
+
+            fields.push(Field::new(
+                &column.name,
+                to_arrow_data_type(data_type),
+                column.nullable,
+            ));
+            arrays.push(build_column(&self.rows, col_idx, data_type)?);
+        }
+
+        let record_batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)
+            .map_err(|e| Error::Client(format!("Failed to build record batch, err:{e}")))?;
+        Ok(vec![record_batch])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, sync::Arc};
+
+    use arrow::{
+        array::{BinaryArray, Int32Array, StringArray, TimestampMillisecondArray},
+        datatypes::{DataType as ArrowDataType, Field, Schema, TimeUnit},
+        ipc::writer::StreamWriter,
+        record_batch::RecordBatch,
+    };
+    use horaedbproto::storage::{
+        arrow_payload::Compression, sql_query_response::Output as OutputPb, ArrowPayload,
+        SqlQueryResponse,
+    };
+
+    #[cfg(feature = "parquet")]
+    use super::rows_to_record_batch;
+    use crate::{
+        model::{
+            sql_query::{
+                response::Response,
+                row::{Column, Row},
+                schema::{ColumnKind, ColumnSchema},
+            },
+            value::Value,
+        },
+        Error,
+    };
+
+    fn make_row(values: Vec<(&str, Value)>) -> Row {
+        let columns = values
+            .into_iter()
+            .map(|(name, value)| Column::new(name.to_string(), value))
+            .collect();
+        Row::new(columns)
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_rows_to_record_batch() {
+        let rows = vec![
+            make_row(vec![
+                ("t", Value::Timestamp(1000)),
+                ("host", Value::Null),
+                ("value", Value::Double(0.5)),
+                ("nothing", Value::Null),
+            ]),
+            make_row(vec![
+                ("t", Value::Timestamp(2000)),
+                ("host", Value::String("a".to_string())),
+                ("value", Value::Null),
+                ("nothing", Value::Null),
+            ]),
+        ];
+
+        let record_batch = rows_to_record_batch(&rows).unwrap();
+        assert_eq!(record_batch.num_rows(), 2);
+        let schema = record_batch.schema();
+        let data_types: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| (field.name().as_str(), field.data_type().clone()))
+            .collect();
+        assert_eq!(
+            data_types,
+            vec![
+                ("t", ArrowDataType::Timestamp(TimeUnit::Millisecond, None)),
+                ("host", ArrowDataType::Utf8),
+                ("value", ArrowDataType::Float64),
+                ("nothing", ArrowDataType::Null),
+            ]
+        );
+        assert_eq!(record_batch.column(1).null_count(), 1);
+        assert_eq!(record_batch.column(2).null_count(), 1);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_mismatched_rows() {
+        assert!(rows_to_record_batch(&[]).is_err());
+
+        let mismatched_types = vec![
+            make_row(vec![("value", Value::Double(0.5))]),
+            make_row(vec![("value", Value::String("0.5".to_string()))]),
+        ];
+        assert!(rows_to_record_batch(&mismatched_types).is_err());
+
+        let mismatched_columns = vec![
+            make_row(vec![("value", Value::Double(0.5))]),
+            make_row(vec![("other", Value::Double(0.5))]),
+        ];
+        assert!(rows_to_record_batch(&mismatched_columns).is_err());
+    }
+
+    #[test]
+    fn test_to_record_batches() {
+        let schema = Schema::new(vec![
+            Field::new(
+                "t",
+                ArrowDataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("host", ArrowDataType::Utf8, false),
+            Field::new("value", ArrowDataType::Int32, true),
+            Field::new("payload", ArrowDataType::Binary, true),
+        ]);
+        let record_batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![1000, 2000])),
+                Arc::new(StringArray::from(vec!["a", "b"])),
+                Arc::new(Int32Array::from(vec![Some(1), None])),
+                Arc::new(BinaryArray::from(vec![Some(b"x".as_ref()), None])),
+            ],
+        )
+        .unwrap();
+        let mut writer =
+            StreamWriter::try_new(Cursor::new(Vec::new()), &record_batch.schema()).unwrap();
+        writer.write(&record_batch).unwrap();
+        writer.finish().unwrap();
+        let resp_pb = SqlQueryResponse {
+            header: None,
+            output: Some(OutputPb::Arrow(ArrowPayload {
+                record_batches: vec![writer.into_inner().unwrap().into_inner()],
+                compression: Compression::None as i32,
+            })),
+        };
+
+        let resp = Response::try_from(resp_pb).unwrap();
+        let record_batches = resp.to_record_batches().unwrap();
+        assert_eq!(record_batches, vec![record_batch]);
+
+        let affected = Response {
+            affected_rows: 1,
+            ..Default::default()
+        };
+        assert!(affected.to_record_batches().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_to_record_batches_with_unsupported_type() {
+        let resp = Response {
+            rows: vec![make_row(vec![("price", Value::Null)])],
+            schema: vec![ColumnSchema {
+                name: "price".to_string(),
+                data_type: ArrowDataType::Decimal128(10, 2),
+                nullable: true,
+                kind: ColumnKind::Field,
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            resp.to_record_batches(),
+            Err(Error::Client(msg)) if msg.contains("Unsupported data type")
+        ));
+    }
+}
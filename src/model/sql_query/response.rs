@@ -15,9 +15,9 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::io::Cursor;
+use std::{collections::HashMap, io::Cursor};
 
-use arrow::{ipc::reader::StreamReader, record_batch::RecordBatch};
+use arrow::{datatypes::SchemaRef, ipc::reader::StreamReader, record_batch::RecordBatch};
 use horaedbproto::storage::{
     arrow_payload::Compression, sql_query_response::Output as OutputPb, ArrowPayload,
     SqlQueryResponse,
@@ -25,56 +25,150 @@ use horaedbproto::storage::{
 
 use crate::{
     errors::{Error, Result},
-    model::sql_query::row::{Row, RowBuilder},
+    model::{
+        sql_query::{
+            row::{Row, RowBuilder},
+            schema::{column_schemas, ColumnSchema},
+            stats::QueryStats,
+        },
+        value::Value,
+    },
+    rpc_client::ResponseMeta,
 };
 
 /// The response for [`SqlQueryRequest`](crate::model::sql_query::Request).
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct Response {
     /// The affected rows by the query sql.
     pub affected_rows: u32,
     /// The rows of the sql result.
     pub rows: Vec<Row>,
+    pub(crate) schema: Vec<ColumnSchema>,
+    pub(crate) stats: Option<QueryStats>,
+    pub(crate) warnings: Vec<String>,
 }
 
 #[derive(Debug)]
 enum Output {
     AffectedRows(u32),
-    Rows(Vec<Row>),
+    Rows {
+        schema: Vec<ColumnSchema>,
+        rows: Vec<Row>,
+    },
 }
 
 impl TryFrom<SqlQueryResponse> for Response {
     type Error = Error;
 
     fn try_from(sql_resp_pb: SqlQueryResponse) -> std::result::Result<Self, Self::Error> {
-        let output_pb = sql_resp_pb
-            .output
-            .ok_or_else(|| Error::Unknown("output is empty in sql query response".to_string()))?;
-        let output = Output::try_from(output_pb)?;
+        Self::decode(sql_resp_pb, None)
+    }
+}
+
+impl Response {
+    /// Decode the response with the hint of the number of rows in it, see
+    /// [`RpcContext::expected_rows`](crate::RpcContext::expected_rows).
+    pub(crate) fn decode(
+        sql_resp_pb: SqlQueryResponse,
+        expected_rows: Option<usize>,
+    ) -> Result<Self> {
+        let output_pb = sql_resp_pb.output.ok_or_else(|| Error::MalformedResponse {
+            reason: "output is empty in sql query response".to_string(),
+        })?;
+        let output = Output::decode(output_pb, expected_rows)?;
+        let warnings = sql_resp_pb
+            .header
+            .map(|header| header.error)
+            .filter(|error| !error.is_empty())
+            .into_iter()
+            .collect();
 
         let resp = match output {
             Output::AffectedRows(affected) => Response {
                 affected_rows: affected,
+                warnings,
                 ..Default::default()
             },
-            Output::Rows(rows) => Response {
+            Output::Rows { schema, rows } => Response {
                 rows,
+                schema,
+                warnings,
                 ..Default::default()
             },
         };
 
         Ok(resp)
     }
-}
 
-impl TryFrom<OutputPb> for Output {
-    type Error = Error;
+    /// Attach the statistics reported in the metadata of the response.
+    pub(crate) fn with_stats(mut self, meta: &ResponseMeta) -> Self {
+        self.stats = QueryStats::from_meta(meta, self.affected_rows);
+        self
+    }
 
-    fn try_from(output_pb: OutputPb) -> std::result::Result<Self, Self::Error> {
+    /// The execution statistics reported by the server, e.g. for the slow
+    /// query analysis, and it is `None` if the server reports none.
+    ///
+    /// The statistics are reported in the trailers, so they are absent in the
+    /// responses of
+    /// [`DbClient::sql_query_stream`](crate::DbClient::sql_query_stream).
+    pub fn stats(&self) -> Option<QueryStats> {
+        self.stats
+    }
+
+    /// The non-fatal messages of the succeeded response, e.g. the deprecation
+    /// or the truncation of the result, which are carried in the error message
+    /// of its header.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// The schema of the rows, including whether each column is the timestamp,
+    /// a tag or a field, and it is empty if the server returns no schema, e.g.
+    /// for the affected rows.
+    pub fn schema(&self) -> &[ColumnSchema] {
+        &self.schema
+    }
+
+    /// Convert the rows into the maps from the column name to the value, and
+    /// the null is kept as [`Value::Null`].
+    ///
+    /// Error will be thrown if the column names are duplicate, e.g. the
+    /// same column is selected twice without the alias.
+    pub fn rows_as_maps(&self) -> Result<Vec<HashMap<String, Value>>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut map = HashMap::with_capacity(row.columns().len());
+                for column in row.columns() {
+                    if map
+                        .insert(column.name().to_string(), column.value().clone())
+                        .is_some()
+                    {
+                        return Err(Error::Client(format!(
+                            "Duplicate column:{} in rows",
+                            column.name()
+                        )));
+                    }
+                }
+                Ok(map)
+            })
+            .collect()
+    }
+}
+
+impl Output {
+    fn decode(output_pb: OutputPb, expected_rows: Option<usize>) -> Result<Self> {
         let output = match output_pb {
             OutputPb::AffectedRows(affected) => Output::AffectedRows(affected),
             OutputPb::Arrow(arrow_payload) => {
-                let arrow_record_batches = decode_arrow_payload(arrow_payload)?;
+                let (arrow_schema, arrow_record_batches) = decode_arrow_payload(arrow_payload)?;
+                let schema = arrow_schema
+                    .map(|arrow_schema| column_schemas(&arrow_schema))
+                    .unwrap_or_default();
+                for record_batch in &arrow_record_batches {
+                    check_batch_schema(&schema, record_batch)?;
+                }
                 let rows_group = arrow_record_batches
                     .into_iter()
                     .map(|record_batch| {
@@ -85,9 +179,12 @@ impl TryFrom<OutputPb> for Output {
                         Ok(row_builder.build())
                     })
                     .collect::<Result<Vec<_>>>()?;
-                let rows = rows_group.into_iter().flatten().collect::<Vec<_>>();
+                let mut rows = Vec::with_capacity(expected_rows.unwrap_or_default());
+                for row_group in rows_group {
+                    rows.extend(row_group);
+                }
 
-                Output::Rows(rows)
+                Output::Rows { schema, rows }
             }
         };
 
@@ -95,7 +192,37 @@ impl TryFrom<OutputPb> for Output {
     }
 }
 
-pub fn decode_arrow_payload(arrow_payload: ArrowPayload) -> Result<Vec<RecordBatch>> {
+/// All the record batches in one response should share the same schema, or the
+/// rows can't be accessed consistently.
+fn check_batch_schema(schema: &[ColumnSchema], record_batch: &RecordBatch) -> Result<()> {
+    let batch_schema = record_batch.schema();
+    let fields = batch_schema.fields();
+    let matched = fields.len() == schema.len()
+        && fields.iter().zip(schema).all(|(field, column)| {
+            field.name() == &column.name && field.data_type() == &column.data_type
+        });
+    if matched {
+        return Ok(());
+    }
+
+    let expect = schema
+        .iter()
+        .map(|column| format!("{}:{}", column.name, column.data_type))
+        .collect::<Vec<_>>();
+    let found = fields
+        .iter()
+        .map(|field| format!("{}:{}", field.name(), field.data_type()))
+        .collect::<Vec<_>>();
+    Err(Error::MalformedResponse {
+        reason: format!("mismatched schema of record batches, expect:{expect:?}, found:{found:?}"),
+    })
+}
+
+/// Decode the record batches along with the schema of the arrow stream, which
+/// is kept even if the stream carries no record batch.
+fn decode_arrow_payload(
+    arrow_payload: ArrowPayload,
+) -> Result<(Option<SchemaRef>, Vec<RecordBatch>)> {
     let compression = arrow_payload.compression();
     let byte_batches = arrow_payload.record_batches;
 
@@ -111,6 +238,7 @@ pub fn decode_arrow_payload(arrow_payload: ArrowPayload) -> Result<Vec<RecordBat
 
     // Decode the byte batches to record batches, multiple record batches may be
     // included in one byte batch.
+    let mut schema = None;
     let record_batches_group = unzip_byte_batches
         .into_iter()
         .map(|byte_batch| {
@@ -121,6 +249,7 @@ pub fn decode_arrow_payload(arrow_payload: ArrowPayload) -> Result<Vec<RecordBat
                 Ok(reader) => reader,
                 Err(e) => return Err(e),
             };
+            schema.get_or_insert_with(|| stream_reader.schema());
 
             stream_reader
                 .into_iter()
@@ -136,5 +265,199 @@ pub fn decode_arrow_payload(arrow_payload: ArrowPayload) -> Result<Vec<RecordBat
         .flatten()
         .collect::<Vec<_>>();
 
-    Ok(record_batches)
+    Ok((schema, record_batches))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use arrow::{
+        array::{Int32Array, LargeStringArray},
+        datatypes::{DataType, Field, Schema},
+        ipc::writer::StreamWriter,
+        record_batch::RecordBatch,
+    };
+    use horaedbproto::storage::{
+        arrow_payload::Compression, sql_query_response::Output as OutputPb, ArrowPayload,
+        SqlQueryResponse,
+    };
+
+    use super::Response;
+    use crate::{
+        model::{
+            sql_query::{schema::ColumnKind, stats::QueryStats},
+            value::Value,
+        },
+        rpc_client::ResponseMeta,
+        Error,
+    };
+
+    fn make_arrow_response(record_batches: Vec<Vec<u8>>) -> SqlQueryResponse {
+        SqlQueryResponse {
+            header: None,
+            output: Some(OutputPb::Arrow(ArrowPayload {
+                record_batches,
+                compression: Compression::None as i32,
+            })),
+        }
+    }
+
+    fn encode_record_batch(record_batch: &RecordBatch) -> Vec<u8> {
+        let mut writer = StreamWriter::try_new(Vec::new(), &record_batch.schema()).unwrap();
+        writer.write(record_batch).unwrap();
+        writer.finish().unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let schema = Schema::new(vec![
+            Field::new("int", DataType::Int32, true),
+            Field::new("large_string", DataType::LargeUtf8, false),
+        ]);
+        let record_batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Int32Array::from(vec![Some(1), None])),
+                Arc::new(LargeStringArray::from(vec!["a", "b"])),
+            ],
+        )
+        .unwrap();
+
+        let resp_pb = make_arrow_response(vec![encode_record_batch(&record_batch)]);
+        let resp = Response::try_from(resp_pb).unwrap();
+        assert_eq!(resp.rows.len(), 2);
+        assert_eq!(
+            resp.rows[0].column("int").unwrap().value(),
+            &Value::Int32(1)
+        );
+        assert_eq!(resp.rows[1].column("int").unwrap().value(), &Value::Null);
+        assert_eq!(
+            resp.rows[1].column("large_string").unwrap().value(),
+            &Value::String("b".to_string())
+        );
+
+        let schema = resp.schema();
+        assert_eq!(schema.len(), 2);
+        assert_eq!(schema[1].name, "large_string");
+        assert_eq!(schema[1].kind, ColumnKind::Unknown);
+
+        assert_eq!(resp.rows[0].get_by_name("int"), Some(&Value::Int32(1)));
+        assert_eq!(resp.rows[1].get_by_name("int"), Some(&Value::Null));
+        assert_eq!(
+            resp.rows[1].get_by_index(1),
+            Some(&Value::String("b".to_string()))
+        );
+        assert!(resp.rows[0].get_by_name("not_exist").is_none());
+        assert!(resp.rows[0].get_by_index(2).is_none());
+
+        let maps = resp.rows_as_maps().unwrap();
+        assert_eq!(maps.len(), 2);
+        assert_eq!(maps[0]["int"], Value::Int32(1));
+        assert_eq!(maps[1]["int"], Value::Null);
+        assert_eq!(maps[1]["large_string"], Value::String("b".to_string()));
+    }
+
+    #[test]
+    fn test_decode_empty_response() {
+        // The stream carries the schema only.
+        let schema = Schema::new(vec![Field::new("int", DataType::Int32, true)]);
+        let mut writer = StreamWriter::try_new(Vec::new(), &schema).unwrap();
+        writer.finish().unwrap();
+        let resp_pb = make_arrow_response(vec![writer.into_inner().unwrap()]);
+        let resp = Response::try_from(resp_pb).unwrap();
+        assert!(resp.rows.is_empty());
+        assert_eq!(resp.schema().len(), 1);
+        assert_eq!(resp.schema()[0].name, "int");
+
+        let resp = Response::try_from(make_arrow_response(vec![])).unwrap();
+        assert!(resp.schema().is_empty());
+    }
+
+    #[test]
+    fn test_decode_malformed_response() {
+        let resp_pb = SqlQueryResponse {
+            header: None,
+            output: None,
+        };
+        assert!(matches!(
+            Response::try_from(resp_pb),
+            Err(Error::MalformedResponse { .. })
+        ));
+
+        let resp_pb = make_arrow_response(vec![b"corrupted bytes".to_vec()]);
+        assert!(matches!(
+            Response::try_from(resp_pb),
+            Err(Error::DecodeArrowPayload(_))
+        ));
+
+        // The truncated record batch.
+        let schema = Schema::new(vec![Field::new("int", DataType::Int32, false)]);
+        let record_batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )
+        .unwrap();
+        let mut bytes = encode_record_batch(&record_batch);
+        bytes.truncate(bytes.len() / 2);
+        let resp_pb = make_arrow_response(vec![bytes]);
+        assert!(Response::try_from(resp_pb).is_err());
+
+        // The record batches disagree on the schema.
+        let schema = Schema::new(vec![Field::new("int", DataType::LargeUtf8, false)]);
+        let another_record_batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(LargeStringArray::from(vec!["a"]))],
+        )
+        .unwrap();
+        let resp_pb = make_arrow_response(vec![
+            encode_record_batch(&record_batch),
+            encode_record_batch(&another_record_batch),
+        ]);
+        assert!(matches!(
+            Response::try_from(resp_pb),
+            Err(Error::MalformedResponse { reason }) if reason.contains("mismatched schema")
+        ));
+    }
+
+    #[test]
+    fn test_decode_stats() {
+        let resp_pb = SqlQueryResponse {
+            header: None,
+            output: Some(OutputPb::AffectedRows(3)),
+        };
+        let mut meta = ResponseMeta::default();
+        meta.entries
+            .insert("x-horaedb-scanned-rows".to_string(), "1024".to_string());
+        meta.entries
+            .insert("x-horaedb-elapsed-ms".to_string(), "15".to_string());
+        let resp = Response::try_from(resp_pb.clone())
+            .unwrap()
+            .with_stats(&meta);
+        assert_eq!(
+            resp.stats(),
+            Some(QueryStats {
+                affected_rows: 3,
+                scanned_rows: Some(1024),
+                elapsed: Some(Duration::from_millis(15)),
+            })
+        );
+
+        // Only the reported and well-formed statistics are kept.
+        meta.entries
+            .insert("x-horaedb-scanned-rows".to_string(), "many".to_string());
+        let resp = Response::try_from(resp_pb.clone())
+            .unwrap()
+            .with_stats(&meta);
+        let stats = resp.stats().unwrap();
+        assert_eq!(stats.scanned_rows, None);
+        assert_eq!(stats.elapsed, Some(Duration::from_millis(15)));
+
+        // No statistics are reported.
+        let resp = Response::try_from(resp_pb)
+            .unwrap()
+            .with_stats(&ResponseMeta::default());
+        assert_eq!(resp.stats(), None);
+    }
 }
@@ -17,16 +17,27 @@
 
 use arrow::{
     array::{
-        ArrayAccessor, ArrayRef, AsArray, BinaryArray, BooleanArray, Float32Array, Float64Array,
-        Int16Array, Int32Array, Int64Array, Int8Array, StringArray, Time32MillisecondArray,
-        TimestampMillisecondArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+        Array, ArrayAccessor, ArrayRef, AsArray, BinaryArray, BooleanArray, Float32Array,
+        Float64Array, Int16Array, Int32Array, Int64Array, Int8Array, LargeBinaryArray,
+        LargeStringArray, StringArray, Time32MillisecondArray, TimestampMillisecondArray,
+        UInt16Array, UInt32Array, UInt64Array, UInt8Array,
     },
     datatypes::{DataType, Int32Type, TimeUnit},
     record_batch::RecordBatch,
 };
 use paste::paste;
 
-use crate::{model::value::Value, Error, Result};
+use crate::{
+    model::{
+        sql_query::schema::{ColumnKind, ColumnSchema},
+        value::{FromValue, Value},
+        write::point::{Point, PointBuilder},
+    },
+    Error, Result,
+};
+
+/// The column generated by the server, which can't be written.
+const TSID_COLUMN: &str = "tsid";
 
 /// A row in the
 /// [`SqlQueryResponse`](crate::model::sql_query::Response).
@@ -37,6 +48,10 @@ pub struct Row {
 }
 
 impl Row {
+    pub(crate) fn new(columns: Vec<Column>) -> Self {
+        Self { columns }
+    }
+
     /// Find the [`Column`] by the column name.
     pub fn column(&self, name: &str) -> Option<&Column> {
         self.columns.iter().find(|column| column.name == name)
@@ -46,6 +61,105 @@ impl Row {
     pub fn columns(&self) -> &[Column] {
         &self.columns
     }
+
+    /// Get the [`Value`] of the column by the column name, and the first one
+    /// is returned if the name is duplicate.
+    pub fn get_by_name(&self, name: &str) -> Option<&Value> {
+        self.column(name).map(Column::value)
+    }
+
+    /// Get the [`Value`] of the column by its index in the
+    /// [`schema`](crate::SqlQueryResponse::schema).
+    pub fn get_by_index(&self, idx: usize) -> Option<&Value> {
+        self.columns.get(idx).map(Column::value)
+    }
+
+    /// Get the value of the column by the column name as the type `T`, and
+    /// `None` is returned if the value is null.
+    ///
+    /// Error will be thrown if the column is not found, or its value can't be
+    /// converted into `T`.
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<Option<T>> {
+        self.column(name)
+            .ok_or_else(|| Error::Client(format!("Column:{name} is not found in the row")))?
+            .get()
+    }
+
+    /// Get the value of the column by its index in the
+    /// [`schema`](crate::SqlQueryResponse::schema) as the type `T`, see
+    /// [`Row::get`].
+    pub fn get_at<T: FromValue>(&self, idx: usize) -> Result<Option<T>> {
+        self.columns
+            .get(idx)
+            .ok_or_else(|| {
+                Error::Client(format!(
+                    "Column index:{idx} is out of range, columns:{}",
+                    self.columns.len()
+                ))
+            })?
+            .get()
+    }
+
+    /// Convert the row into a [`Point`] of the `table`, according to the
+    /// [`schema`](crate::SqlQueryResponse::schema) of the response.
+    ///
+    /// The null values are skipped, and the server generated `tsid` column is
+    /// ignored. Error will be thrown if any column can't be classified as the
+    /// timestamp, a tag or a field.
+    pub fn to_point(
+        &self,
+        table: impl Into<String>,
+        schema: &[ColumnSchema],
+    ) -> std::result::Result<Point, String> {
+        if schema.len() != self.columns.len() {
+            return Err(format!(
+                "Mismatched number of columns, schema:{}, row:{}",
+                schema.len(),
+                self.columns.len()
+            ));
+        }
+
+        let mut builder = PointBuilder::new(table);
+        let mut has_timestamp = false;
+        for (column, column_schema) in self.columns.iter().zip(schema) {
+            if column.name != column_schema.name {
+                return Err(format!(
+                    "Mismatched column, schema:{}, row:{}",
+                    column_schema.name, column.name
+                ));
+            }
+            if column.name == TSID_COLUMN {
+                continue;
+            }
+
+            match (column_schema.kind, &column.value) {
+                (ColumnKind::Timestamp, Value::Timestamp(v) | Value::Int64(v)) => {
+                    builder = builder.timestamp(*v);
+                    has_timestamp = true;
+                }
+                (ColumnKind::Timestamp, v) => {
+                    return Err(format!(
+                        "Invalid timestamp of column:{}, value:{v:?}",
+                        column.name
+                    ))
+                }
+                (_, Value::Null) => continue,
+                (ColumnKind::Tag, v) => builder = builder.tag(column.name.clone(), v.clone()),
+                (ColumnKind::Field, v) => builder = builder.field(column.name.clone(), v.clone()),
+                (ColumnKind::Unknown, _) => {
+                    return Err(format!(
+                        "Column:{} is not classified as timestamp, tag or field",
+                        column.name
+                    ))
+                }
+            }
+        }
+        if !has_timestamp {
+            return Err("Timestamp column is missing".to_string());
+        }
+
+        builder.build()
+    }
 }
 
 /// A column in the [`Row`].
@@ -69,20 +183,36 @@ impl Column {
     pub fn value(&self) -> &Value {
         &self.value
     }
+
+    /// Return the value of the column as the type `T`, see [`Row::get`].
+    pub fn get<T: FromValue>(&self) -> Result<Option<T>> {
+        if matches!(self.value, Value::Null) {
+            return Ok(None);
+        }
+        T::from_value(&self.value)
+            .map(Some)
+            .ok_or_else(|| Error::MismatchedColumnType {
+                column: self.name.clone(),
+                expect: T::TYPE_NAME,
+                found: self.value.data_type(),
+            })
+    }
 }
 
 macro_rules! fill_column {
     ($arrow_column:expr, $arrow_array_type:ty, $value_type:ty, $rows:expr, $col_idx:expr) => {
         paste! {
-            let row_count = $rows.len();
             let cast_arrow_column = $arrow_column
                 .as_any()
-                .downcast_ref::<$arrow_array_type>().unwrap();
-            for row_idx in 0..row_count {
+                .downcast_ref::<$arrow_array_type>()
+                .ok_or_else(|| mismatched_array_error($arrow_column, stringify!($arrow_array_type)))?;
+            for (row_idx, row) in $rows.iter_mut().enumerate() {
+                // The null is kept as `Value::Null` filled on initialization.
+                if cast_arrow_column.is_null(row_idx) {
+                    continue;
+                }
                 let value = cast_arrow_column.value(row_idx).to_owned();
-                let row = $rows.get_mut(row_idx).unwrap();
-                let col = row.get_mut($col_idx).unwrap();
-                *col = $value_type(value)
+                row[$col_idx] = $value_type(value)
             }
         }
     };
@@ -110,7 +240,7 @@ impl RowBuilder {
                     })
                     .collect::<Vec<Column>>();
 
-                Row { columns }
+                Row::new(columns)
             })
             .collect::<Vec<_>>()
     }
@@ -147,7 +277,16 @@ impl RowBuilder {
         col_idx: usize,
         arrow_column: &ArrayRef,
     ) -> Result<()> {
-        let row_count = rows.len();
+        if arrow_column.len() != rows.len() {
+            return Err(Error::MalformedResponse {
+                reason: format!(
+                    "mismatched length of column:{col_idx}, expect:{}, found:{}",
+                    rows.len(),
+                    arrow_column.len()
+                ),
+            });
+        }
+
         let arrow_type = arrow_column.data_type();
         // TODO: may we can make it simpler with macro.
         match arrow_type {
@@ -187,12 +326,24 @@ impl RowBuilder {
             DataType::Float64 => {
                 fill_column!(arrow_column, Float64Array, Value::Double, rows, col_idx);
             }
-            DataType::Utf8 | DataType::LargeUtf8 => {
+            DataType::Utf8 => {
                 fill_column!(arrow_column, StringArray, Value::String, rows, col_idx);
             }
-            DataType::Binary | DataType::LargeBinary => {
+            DataType::LargeUtf8 => {
+                fill_column!(arrow_column, LargeStringArray, Value::String, rows, col_idx);
+            }
+            DataType::Binary => {
                 fill_column!(arrow_column, BinaryArray, Value::Varbinary, rows, col_idx);
             }
+            DataType::LargeBinary => {
+                fill_column!(
+                    arrow_column,
+                    LargeBinaryArray,
+                    Value::Varbinary,
+                    rows,
+                    col_idx
+                );
+            }
             DataType::Timestamp(TimeUnit::Millisecond, _) => {
                 fill_column!(
                     arrow_column,
@@ -206,28 +357,31 @@ impl RowBuilder {
                 let cast_arrow_column = arrow_column
                     .as_any()
                     .downcast_ref::<Time32MillisecondArray>()
-                    .unwrap();
-                for row_idx in 0..row_count {
+                    .ok_or_else(|| {
+                        mismatched_array_error(arrow_column, "Time32MillisecondArray")
+                    })?;
+                for (row_idx, row) in rows.iter_mut().enumerate() {
+                    if cast_arrow_column.is_null(row_idx) {
+                        continue;
+                    }
                     let value = cast_arrow_column.value(row_idx);
-                    let row = rows.get_mut(row_idx).unwrap();
-                    let col = row.get_mut(col_idx).unwrap();
-                    *col = Value::Timestamp(value as i64)
+                    row[col_idx] = Value::Timestamp(value as i64)
                 }
             }
             DataType::Dictionary(index_type, encode_type)
                 if index_type.as_ref() == &DataType::Int32
                     && encode_type.as_ref() == &DataType::Utf8 =>
             {
-                let row_count = rows.len();
                 let cast_arrow_column = arrow_column
-                    .as_dictionary::<Int32Type>()
-                    .downcast_dict::<StringArray>()
-                    .unwrap();
-                for row_idx in 0..row_count {
+                    .as_dictionary_opt::<Int32Type>()
+                    .and_then(|dict| dict.downcast_dict::<StringArray>())
+                    .ok_or_else(|| mismatched_array_error(arrow_column, "DictionaryArray"))?;
+                for (row_idx, row) in rows.iter_mut().enumerate() {
+                    if cast_arrow_column.is_null(row_idx) {
+                        continue;
+                    }
                     let value = cast_arrow_column.value(row_idx).to_owned();
-                    let row = rows.get_mut(row_idx).unwrap();
-                    let col = row.get_mut(col_idx).unwrap();
-                    *col = Value::String(value)
+                    row[col_idx] = Value::String(value)
                 }
             }
             // Encounter unsupported type.
@@ -241,6 +395,15 @@ impl RowBuilder {
     }
 }
 
+fn mismatched_array_error(arrow_column: &ArrayRef, expect: &str) -> Error {
+    Error::MalformedResponse {
+        reason: format!(
+            "mismatched arrow array, expect:{expect}, found data type:{}",
+            arrow_column.data_type()
+        ),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::Arc;
@@ -255,7 +418,93 @@ mod test {
     };
 
     use super::{Row, RowBuilder};
-    use crate::model::{sql_query::row::Column, value::Value};
+    use crate::{
+        model::{
+            sql_query::{
+                row::Column,
+                schema::{ColumnKind, ColumnSchema},
+            },
+            value::{DataType as ValueType, Value},
+        },
+        Error,
+    };
+
+    #[test]
+    fn test_get() {
+        let row = Row::new(vec![
+            Column::new("t".to_string(), Value::Timestamp(1000)),
+            Column::new("i".to_string(), Value::Int32(42)),
+            Column::new("f".to_string(), Value::Double(1.5)),
+            Column::new("s".to_string(), Value::String("a".to_string())),
+            Column::new("b".to_string(), Value::Varbinary(vec![1, 2])),
+            Column::new("n".to_string(), Value::Null),
+        ]);
+        assert_eq!(row.get::<i64>("t").unwrap(), Some(1000));
+        assert_eq!(row.get::<i32>("i").unwrap(), Some(42));
+        // The integers are widened.
+        assert_eq!(row.get::<i64>("i").unwrap(), Some(42));
+        assert_eq!(row.get::<f64>("f").unwrap(), Some(1.5));
+        assert_eq!(row.get::<String>("s").unwrap(), Some("a".to_string()));
+        assert_eq!(row.get::<Vec<u8>>("b").unwrap(), Some(vec![1, 2]));
+        assert_eq!(row.get::<String>("n").unwrap(), None);
+        assert_eq!(row.get_at::<i32>(1).unwrap(), Some(42));
+
+        let err = row.get::<i32>("s").unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MismatchedColumnType { column, expect: "i32", found: ValueType::String }
+                if column == "s"
+        ));
+        assert!(matches!(row.get::<i32>("x"), Err(Error::Client(_))));
+        assert!(matches!(row.get_at::<i32>(6), Err(Error::Client(_))));
+    }
+
+    #[test]
+    fn test_to_point() {
+        let column_schema = |name: &str, data_type, kind| ColumnSchema {
+            name: name.to_string(),
+            data_type,
+            nullable: true,
+            kind,
+        };
+        let schema = vec![
+            column_schema("tsid", DataType::UInt64, ColumnKind::Unknown),
+            column_schema(
+                "t",
+                DataType::Timestamp(arrow::datatypes::TimeUnit::Millisecond, None),
+                ColumnKind::Timestamp,
+            ),
+            column_schema("host", DataType::Utf8, ColumnKind::Tag),
+            column_schema("payload", DataType::Binary, ColumnKind::Field),
+            column_schema("value", DataType::Float64, ColumnKind::Field),
+        ];
+        let row = Row::new(vec![
+            Column::new("tsid".to_string(), Value::UInt64(42)),
+            Column::new("t".to_string(), Value::Timestamp(1000)),
+            Column::new("host".to_string(), Value::String("a".to_string())),
+            Column::new("payload".to_string(), Value::Varbinary(vec![0, 1])),
+            Column::new("value".to_string(), Value::Null),
+        ]);
+
+        let point = row.to_point("copy", &schema).unwrap();
+        assert_eq!(point.table, "copy");
+        assert_eq!(point.timestamp, 1000);
+        assert_eq!(point.tags["host"], Value::String("a".to_string()));
+        assert_eq!(point.fields.len(), 1);
+        assert_eq!(point.fields["payload"], Value::Varbinary(vec![0, 1]));
+
+        let mut unknown_schema = schema.clone();
+        unknown_schema[4].kind = ColumnKind::Unknown;
+        let row = Row::new(vec![
+            Column::new("tsid".to_string(), Value::UInt64(42)),
+            Column::new("t".to_string(), Value::Timestamp(1000)),
+            Column::new("host".to_string(), Value::String("a".to_string())),
+            Column::new("payload".to_string(), Value::Varbinary(vec![0, 1])),
+            Column::new("value".to_string(), Value::Double(0.5)),
+        ]);
+        assert!(row.to_point("copy", &unknown_schema).is_err());
+        assert!(row.to_point("copy", &schema[1..]).is_err());
+    }
 
     #[test]
     fn test_build_row() {
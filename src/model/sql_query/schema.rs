@@ -0,0 +1,116 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The schema of the rows returned by the sql query.
+
+use arrow::datatypes::{DataType, Schema};
+
+/// The key of the field metadata telling whether the column is a tag.
+const IS_TAG_KEY: &str = "field::is_tag";
+/// The key of the schema metadata telling the index of the timestamp column.
+const TIMESTAMP_INDEX_KEY: &str = "schema::timestamp_index";
+
+/// The role of a column in the table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnKind {
+    Timestamp,
+    Tag,
+    Field,
+    /// The server returns no classification of the column, e.g. the column
+    /// is computed by the query.
+    Unknown,
+}
+
+/// One column in the [`Response::schema`](crate::SqlQueryResponse::schema).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub data_type: DataType,
+    pub nullable: bool,
+    pub kind: ColumnKind,
+}
+
+/// Derive the column schemas from the metadata of the arrow schema returned
+/// by the server.
+pub(crate) fn column_schemas(schema: &Schema) -> Vec<ColumnSchema> {
+    let timestamp_index = schema
+        .metadata()
+        .get(TIMESTAMP_INDEX_KEY)
+        .and_then(|index| index.parse::<usize>().ok());
+
+    schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            let is_tag = field
+                .metadata()
+                .get(IS_TAG_KEY)
+                .and_then(|is_tag| is_tag.parse::<bool>().ok());
+            let kind = match (timestamp_index == Some(index), is_tag) {
+                (true, _) => ColumnKind::Timestamp,
+                (false, Some(true)) => ColumnKind::Tag,
+                (false, Some(false)) => ColumnKind::Field,
+                (false, None) => ColumnKind::Unknown,
+            };
+
+            ColumnSchema {
+                name: field.name().clone(),
+                data_type: field.data_type().clone(),
+                nullable: field.is_nullable(),
+                kind,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use arrow::datatypes::{DataType, Field, Schema};
+
+    use super::{column_schemas, ColumnKind, IS_TAG_KEY, TIMESTAMP_INDEX_KEY};
+
+    #[test]
+    fn test_column_kinds() {
+        let is_tag = |is_tag: bool| HashMap::from([(IS_TAG_KEY.to_string(), is_tag.to_string())]);
+        let schema = Schema::new_with_metadata(
+            vec![
+                Field::new("t", DataType::Int64, false).with_metadata(is_tag(false)),
+                Field::new("host", DataType::Utf8, false).with_metadata(is_tag(true)),
+                Field::new("value", DataType::Float64, true).with_metadata(is_tag(false)),
+                Field::new("count", DataType::Int64, true),
+            ],
+            HashMap::from([(TIMESTAMP_INDEX_KEY.to_string(), "0".to_string())]),
+        );
+
+        let kinds: Vec<_> = column_schemas(&schema)
+            .into_iter()
+            .map(|column| column.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ColumnKind::Timestamp,
+                ColumnKind::Tag,
+                ColumnKind::Field,
+                ColumnKind::Unknown
+            ]
+        );
+    }
+}
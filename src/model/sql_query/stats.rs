@@ -0,0 +1,64 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The execution statistics of the sql query reported by the server.
+
+use std::time::Duration;
+
+use crate::rpc_client::ResponseMeta;
+
+/// The key of the metadata carrying the number of the scanned rows.
+pub(crate) const SCANNED_ROWS_KEY: &str = "x-horaedb-scanned-rows";
+/// The key of the metadata carrying the execution time in milliseconds.
+pub(crate) const ELAPSED_MS_KEY: &str = "x-horaedb-elapsed-ms";
+
+/// The statistics of the sql query, see
+/// [`Response::stats`](crate::model::sql_query::Response::stats).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryStats {
+    /// The affected rows by the query sql.
+    pub affected_rows: u32,
+    /// The number of the rows scanned by the server if reported.
+    pub scanned_rows: Option<u64>,
+    /// The execution time in the server if reported.
+    pub elapsed: Option<Duration>,
+}
+
+impl QueryStats {
+    /// Parse the statistics in the metadata of the response, and it is `None`
+    /// if the server reports none of them.
+    ///
+    /// The malformed values are treated as not reported.
+    pub(crate) fn from_meta(meta: &ResponseMeta, affected_rows: u32) -> Option<Self> {
+        let scanned_rows = meta
+            .get(SCANNED_ROWS_KEY)
+            .and_then(|rows| rows.parse().ok());
+        let elapsed = meta
+            .get(ELAPSED_MS_KEY)
+            .and_then(|ms| ms.parse().ok())
+            .map(Duration::from_millis);
+        if scanned_rows.is_none() && elapsed.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            affected_rows,
+            scanned_rows,
+            elapsed,
+        })
+    }
+}
@@ -0,0 +1,144 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::{
+    model::sql_query::{escape::quote_identifier, Request},
+    Error, Result,
+};
+
+/// The half-open time range `[start, end)` in milliseconds, as the timestamps
+/// of HoraeDB.
+///
+/// Example:
+/// ```rust
+/// # use horaedb_client::model::sql_query::time_range::TimeRange;
+/// let range = TimeRange::new(1000, 2000).unwrap();
+/// let req = range
+///     .build_request("demo", "SELECT * FROM demo WHERE {time_range}", "t")
+///     .unwrap();
+/// assert_eq!(
+///     req.sql,
+///     "SELECT * FROM demo WHERE `t` >= 1000 AND `t` < 2000"
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeRange {
+    start: i64,
+    end: i64,
+}
+
+impl TimeRange {
+    pub const TIME_RANGE_PLACEHOLDER: &'static str = "{time_range}";
+
+    /// The `start` is inclusive and the `end` is exclusive, and both of them
+    /// must not be negative.
+    pub fn new(start: i64, end: i64) -> Result<Self> {
+        if start < 0 || end < 0 {
+            return Err(Error::Client(format!(
+                "Time range should not be negative, start:{start}, end:{end}"
+            )));
+        }
+        if start > end {
+            return Err(Error::Client(format!(
+                "Start of time range should not be after its end, start:{start}, end:{end}"
+            )));
+        }
+
+        Ok(Self { start, end })
+    }
+
+    pub fn start(&self) -> i64 {
+        self.start
+    }
+
+    pub fn end(&self) -> i64 {
+        self.end
+    }
+
+    /// Tell whether no timestamp is in the range, i.e. `start == end`.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Render the predicate of the timestamp `column`, e.g.
+    /// `` `t` >= 1000 AND `t` < 2000 ``.
+    pub fn predicate(&self, column: &str) -> String {
+        let column = quote_identifier(column);
+        format!("{column} >= {} AND {column} < {}", self.start, self.end)
+    }
+
+    /// Build the query by replacing the placeholder
+    /// [`TIME_RANGE_PLACEHOLDER`](Self::TIME_RANGE_PLACEHOLDER) in the sql
+    /// template with the predicate of the timestamp `column`.
+    pub fn build_request(
+        &self,
+        table: impl Into<String>,
+        sql_template: &str,
+        column: &str,
+    ) -> Result<Request> {
+        if !sql_template.contains(Self::TIME_RANGE_PLACEHOLDER) {
+            return Err(Error::Client(format!(
+                "Placeholder:{} not found in sql template:{sql_template}",
+                Self::TIME_RANGE_PLACEHOLDER,
+            )));
+        }
+
+        Ok(Request {
+            tables: vec![table.into()],
+            sql: sql_template.replace(Self::TIME_RANGE_PLACEHOLDER, &self.predicate(column)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TimeRange;
+
+    #[test]
+    fn test_predicate() {
+        let range = TimeRange::new(1000, 2000).unwrap();
+        assert_eq!(range.predicate("t"), "`t` >= 1000 AND `t` < 2000");
+        assert_eq!(range.predicate("t`s"), "`t``s` >= 1000 AND `t``s` < 2000");
+        assert!(!range.is_empty());
+
+        let range = TimeRange::new(0, 0).unwrap();
+        assert!(range.is_empty());
+        assert_eq!(range.predicate("t"), "`t` >= 0 AND `t` < 0");
+
+        let req = TimeRange::new(1000, 2000)
+            .unwrap()
+            .build_request("demo", "SELECT count(*) FROM demo WHERE {time_range}", "t")
+            .unwrap();
+        assert_eq!(req.tables, vec!["demo".to_string()]);
+        assert_eq!(
+            req.sql,
+            "SELECT count(*) FROM demo WHERE `t` >= 1000 AND `t` < 2000"
+        );
+    }
+
+    #[test]
+    fn test_invalid_range() {
+        assert!(TimeRange::new(2000, 1000).is_err());
+        assert!(TimeRange::new(-1, 1000).is_err());
+        assert!(TimeRange::new(0, -1).is_err());
+
+        let range = TimeRange::new(1000, 2000).unwrap();
+        assert!(range
+            .build_request("demo", "SELECT * FROM demo", "t")
+            .is_err());
+    }
+}
@@ -0,0 +1,149 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The schema of table used by the
+//! [`AdminClient`](crate::db_client::AdminClient).
+
+use crate::model::value::DataType;
+
+const DEFAULT_TIMESTAMP_COLUMN: &str = "timestamp";
+
+/// One column in the [`TableSchema`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub data_type: DataType,
+    pub is_tag: bool,
+}
+
+/// The schema of a table, built by [`TableSchemaBuilder`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableSchema {
+    pub table: String,
+    /// The name of the timestamp key column.
+    pub timestamp_column: String,
+    /// The tags and fields in the order of being added.
+    pub columns: Vec<ColumnSchema>,
+}
+
+/// Builder for building a table schema.
+#[derive(Debug)]
+pub struct TableSchemaBuilder {
+    table: String,
+    timestamp_column: String,
+    columns: Vec<ColumnSchema>,
+}
+
+impl TableSchemaBuilder {
+    pub fn new(table: impl Into<String>) -> Self {
+        Self {
+            table: table.into(),
+            timestamp_column: DEFAULT_TIMESTAMP_COLUMN.to_string(),
+            columns: Vec::new(),
+        }
+    }
+
+    /// Set the name of the timestamp key column, `timestamp` by default.
+    pub fn timestamp_column(mut self, name: impl Into<String>) -> Self {
+        self.timestamp_column = name.into();
+        self
+    }
+
+    /// Add a tag column.
+    pub fn tag(mut self, name: impl Into<String>, data_type: DataType) -> Self {
+        self.columns.push(ColumnSchema {
+            name: name.into(),
+            data_type,
+            is_tag: true,
+        });
+        self
+    }
+
+    /// Add a field column.
+    pub fn field(mut self, name: impl Into<String>, data_type: DataType) -> Self {
+        self.columns.push(ColumnSchema {
+            name: name.into(),
+            data_type,
+            is_tag: false,
+        });
+        self
+    }
+
+    /// Build the final table schema.
+    pub fn build(self) -> Result<TableSchema, String> {
+        if self.table.is_empty() {
+            return Err("Table name should not be empty".to_string());
+        }
+
+        if !self.columns.iter().any(|column| !column.is_tag) {
+            return Err("Fields should not be empty".to_string());
+        }
+
+        for (idx, column) in self.columns.iter().enumerate() {
+            if column.name == self.timestamp_column
+                || self.columns[..idx]
+                    .iter()
+                    .any(|prev| prev.name == column.name)
+            {
+                return Err(format!("Duplicate column name:{}", column.name));
+            }
+
+            if column.data_type == DataType::Null {
+                return Err(format!("Column:{} can't be null type", column.name));
+            }
+        }
+
+        Ok(TableSchema {
+            table: self.table,
+            timestamp_column: self.timestamp_column,
+            columns: self.columns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TableSchemaBuilder;
+    use crate::model::value::DataType;
+
+    #[test]
+    fn test_build_table_schema() {
+        let schema = TableSchemaBuilder::new("demo")
+            .tag("host", DataType::String)
+            .field("value", DataType::Double)
+            .build()
+            .unwrap();
+        assert_eq!(schema.timestamp_column, "timestamp");
+        assert_eq!(schema.columns.len(), 2);
+        assert!(schema.columns[0].is_tag);
+
+        assert!(TableSchemaBuilder::new("demo")
+            .tag("host", DataType::String)
+            .build()
+            .is_err());
+        assert!(TableSchemaBuilder::new("demo")
+            .tag("host", DataType::String)
+            .field("host", DataType::Double)
+            .build()
+            .is_err());
+        assert!(TableSchemaBuilder::new("demo")
+            .timestamp_column("t")
+            .field("t", DataType::Double)
+            .build()
+            .is_err());
+    }
+}
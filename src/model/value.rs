@@ -22,6 +22,12 @@ use horaedbproto::storage::{value, Value as ValuePb};
 pub type TimestampMs = i64;
 
 /// The value enum to express the data in HoraeDB.
+///
+/// Note that there is no decimal type in HoraeDB, so the high-precision
+/// numbers can't be written exactly as `Double`. Write them as the scaled
+/// integers (e.g. the cents in `Int64`) or as `String` instead. And the
+/// decimal columns returned by the sql query are rejected rather than
+/// converted to `Double`.
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Value {
     Null,
@@ -244,6 +250,66 @@ impl Default for Value {
     }
 }
 
+/// Conversion from the non-null [`Value`] into the Rust type, which is used by
+/// [`Row::get`](crate::model::sql_query::row::Row::get).
+///
+/// The integers are widened as the `as_*` methods of the [`Value`] do, and the
+/// timestamp can be got as the `i64` too.
+pub trait FromValue: Sized {
+    /// The name of the type, which is told in the error of the mismatched
+    /// type.
+    const TYPE_NAME: &'static str;
+
+    /// Convert the `value`, and `None` is returned if it can't be converted.
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+macro_rules! impl_from_value {
+    ($type:ty, $as_method:ident) => {
+        impl FromValue for $type {
+            const TYPE_NAME: &'static str = stringify!($type);
+
+            fn from_value(value: &Value) -> Option<Self> {
+                value.$as_method()
+            }
+        }
+    };
+}
+
+impl_from_value!(i8, as_i8);
+impl_from_value!(u8, as_u8);
+impl_from_value!(i16, as_i16);
+impl_from_value!(u16, as_u16);
+impl_from_value!(i32, as_i32);
+impl_from_value!(u32, as_u32);
+impl_from_value!(u64, as_u64);
+impl_from_value!(f32, as_f32);
+impl_from_value!(f64, as_f64);
+impl_from_value!(String, as_str);
+impl_from_value!(Vec<u8>, as_varbinary);
+
+impl FromValue for i64 {
+    const TYPE_NAME: &'static str = "i64";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Timestamp(v) => Some(*v),
+            v => v.as_i64(),
+        }
+    }
+}
+
+impl FromValue for bool {
+    const TYPE_NAME: &'static str = "bool";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Boolean(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
 impl From<Value> for ValuePb {
     fn from(val: Value) -> Self {
         let value = match val {
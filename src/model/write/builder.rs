@@ -0,0 +1,275 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The fluent builder of the [`WriteRequest`](Request).
+
+use std::collections::HashMap;
+
+use crate::model::{
+    value::{DataType, Value},
+    write::{point::PointBuilder, Request},
+};
+
+/// Builder for building a [`WriteRequest`](Request) table by table.
+///
+/// The points are validated on [`WriteRequestBuilder::build`]: each point must
+/// have a timestamp and at least one field, and the points of the same table
+/// must agree on whether a column is a tag or a field and on its data type.
+///
+/// ```rust
+/// # use horaedb_client::model::{value::Value, write::WriteRequestBuilder};
+/// let req = WriteRequestBuilder::new()
+///     .table("cpu")
+///     .point()
+///     .timestamp(1000)
+///     .tag("host", Value::String("a".to_string()))
+///     .field("value", Value::Double(1.0))
+///     .build_point()
+///     .build()
+///     .unwrap();
+/// assert_eq!(req.point_groups["cpu"].len(), 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct WriteRequestBuilder {
+    points: Vec<PointBuilder>,
+}
+
+impl WriteRequestBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start adding the points of the `table`.
+    pub fn table(self, table: impl Into<String>) -> TableBuilder {
+        TableBuilder {
+            request: self,
+            table: table.into(),
+        }
+    }
+
+    /// Validate the points and build the final request.
+    pub fn build(self) -> Result<Request, String> {
+        let mut request = Request::default();
+        let mut schemas: HashMap<String, TableSchema> = HashMap::new();
+        for (idx, point) in self.points.into_iter().enumerate() {
+            let point = point
+                .build()
+                .map_err(|e| format!("Invalid point:{idx}, err:{e}"))?;
+            let schema = schemas.entry(point.table.clone()).or_default();
+            for (name, value) in &point.tags {
+                schema.check(&point.table, name, true, value)?;
+            }
+            for (name, value) in &point.fields {
+                schema.check(&point.table, name, false, value)?;
+            }
+            request.add_point(point);
+        }
+
+        Ok(request)
+    }
+}
+
+/// Builder for adding the points of one table, see [`WriteRequestBuilder`].
+#[derive(Debug)]
+pub struct TableBuilder {
+    request: WriteRequestBuilder,
+    table: String,
+}
+
+impl TableBuilder {
+    /// Start building a point of the table.
+    pub fn point(self) -> TablePointBuilder {
+        let point = PointBuilder::new(self.table.clone());
+        TablePointBuilder { table: self, point }
+    }
+
+    /// Switch to adding the points of another table.
+    pub fn table(self, table: impl Into<String>) -> TableBuilder {
+        self.request.table(table)
+    }
+
+    /// Validate the points and build the final request, see
+    /// [`WriteRequestBuilder::build`].
+    pub fn build(self) -> Result<Request, String> {
+        self.request.build()
+    }
+}
+
+/// Builder for one point of the table, see [`WriteRequestBuilder`].
+#[derive(Debug)]
+pub struct TablePointBuilder {
+    table: TableBuilder,
+    point: PointBuilder,
+}
+
+impl TablePointBuilder {
+    /// Set the timestamp of the point.
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.point = self.point.timestamp(timestamp);
+        self
+    }
+
+    /// Set the tag of the point.
+    pub fn tag(mut self, name: impl Into<String>, value: Value) -> Self {
+        self.point = self.point.tag(name, value);
+        self
+    }
+
+    /// Set the field of the point.
+    pub fn field(mut self, name: impl Into<String>, value: Value) -> Self {
+        self.point = self.point.field(name, value);
+        self
+    }
+
+    /// Finish the point, and it is validated on building the request.
+    pub fn build_point(self) -> TableBuilder {
+        let mut table = self.table;
+        table.request.points.push(self.point);
+        table
+    }
+}
+
+/// The columns seen in the points of one table.
+#[derive(Default)]
+struct TableSchema {
+    /// Column name -> (is tag, data type of the first non-null value).
+    columns: HashMap<String, (bool, Option<DataType>)>,
+}
+
+impl TableSchema {
+    fn check(
+        &mut self,
+        table: &str,
+        name: &str,
+        is_tag: bool,
+        value: &Value,
+    ) -> Result<(), String> {
+        let data_type = (!value.is_null()).then(|| value.data_type());
+        let (seen_is_tag, seen_data_type) = self
+            .columns
+            .entry(name.to_string())
+            .or_insert((is_tag, data_type));
+        if *seen_is_tag != is_tag {
+            return Err(format!(
+                "Column:{name} of table:{table} is used as both a tag and a field"
+            ));
+        }
+        match (*seen_data_type, data_type) {
+            (Some(expect), Some(found)) if expect != found => Err(format!(
+                "Mismatched data type of column:{name} in table:{table}, \
+                 expect:{expect:?}, found:{found:?}"
+            )),
+            (None, Some(_)) => {
+                *seen_data_type = data_type;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WriteRequestBuilder;
+    use crate::model::value::Value;
+
+    #[test]
+    fn test_build_request() {
+        let req = WriteRequestBuilder::new()
+            .table("cpu")
+            .point()
+            .timestamp(1000)
+            .tag("host", Value::String("a".to_string()))
+            .field("value", Value::Null)
+            .build_point()
+            .point()
+            .timestamp(2000)
+            .tag("host", Value::String("b".to_string()))
+            .field("value", Value::Double(1.0))
+            .build_point()
+            .table("mem")
+            .point()
+            .timestamp(1000)
+            .field("used", Value::Int64(1))
+            .build_point()
+            .build()
+            .unwrap();
+
+        assert_eq!(req.point_groups["cpu"].len(), 2);
+        assert_eq!(req.point_groups["cpu"][1].timestamp, 2000);
+        assert_eq!(req.point_groups["mem"].len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        let missing_timestamp = WriteRequestBuilder::new()
+            .table("cpu")
+            .point()
+            .field("value", Value::Double(1.0))
+            .build_point()
+            .build();
+        assert!(missing_timestamp
+            .unwrap_err()
+            .contains("Timestamp must be set"));
+
+        let mismatched_type = WriteRequestBuilder::new()
+            .table("cpu")
+            .point()
+            .timestamp(1000)
+            .field("value", Value::Double(1.0))
+            .build_point()
+            .point()
+            .timestamp(2000)
+            .field("value", Value::Int64(1))
+            .build_point()
+            .build();
+        assert!(mismatched_type
+            .unwrap_err()
+            .contains("Mismatched data type of column:value"));
+
+        let mismatched_kind = WriteRequestBuilder::new()
+            .table("cpu")
+            .point()
+            .timestamp(1000)
+            .tag("host", Value::String("a".to_string()))
+            .field("value", Value::Double(1.0))
+            .build_point()
+            .point()
+            .timestamp(2000)
+            .field("host", Value::String("a".to_string()))
+            .build_point()
+            .build();
+        assert!(mismatched_kind
+            .unwrap_err()
+            .contains("used as both a tag and a field"));
+
+        // The same column in different tables can be of different types.
+        let req = WriteRequestBuilder::new()
+            .table("cpu")
+            .point()
+            .timestamp(1000)
+            .field("value", Value::Double(1.0))
+            .build_point()
+            .table("mem")
+            .point()
+            .timestamp(1000)
+            .field("value", Value::Int64(1))
+            .build_point()
+            .build();
+        assert!(req.is_ok());
+    }
+}
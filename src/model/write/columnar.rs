@@ -0,0 +1,330 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The columnar batch for writing, which is an advanced alternative to the
+//! [`PointBuilder`](crate::model::write::point::PointBuilder).
+//!
+//! It is less ergonomic, but much faster for the high-throughput producers: the
+//! column names are shared by all the rows, and the values are kept in typed
+//! vectors and converted to the protobuf messages directly, without building
+//! the intermediate points.
+
+use std::collections::HashMap;
+
+use horaedbproto::storage::{
+    value, Field, FieldGroup as FieldGroupPb, Tag as TagPb, Value as ValuePb,
+    WriteSeriesEntry as WriteSeriesEntryPb, WriteTableRequest as WriteTableRequestPb,
+};
+
+use crate::model::write::point::is_reserved_column_name;
+
+/// The values of a column in the [`ColumnarBatch`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColumnData {
+    Timestamp(Vec<i64>),
+    Double(Vec<f64>),
+    Float(Vec<f32>),
+    Varbinary(Vec<Vec<u8>>),
+    String(Vec<String>),
+    UInt64(Vec<u64>),
+    UInt32(Vec<u32>),
+    UInt16(Vec<u16>),
+    UInt8(Vec<u8>),
+    Int64(Vec<i64>),
+    Int32(Vec<i32>),
+    Int16(Vec<i16>),
+    Int8(Vec<i8>),
+    Boolean(Vec<bool>),
+}
+
+impl ColumnData {
+    pub fn len(&self) -> usize {
+        match self {
+            ColumnData::Timestamp(v) => v.len(),
+            ColumnData::Double(v) => v.len(),
+            ColumnData::Float(v) => v.len(),
+            ColumnData::Varbinary(v) => v.len(),
+            ColumnData::String(v) => v.len(),
+            ColumnData::UInt64(v) => v.len(),
+            ColumnData::UInt32(v) => v.len(),
+            ColumnData::UInt16(v) => v.len(),
+            ColumnData::UInt8(v) => v.len(),
+            ColumnData::Int64(v) => v.len(),
+            ColumnData::Int32(v) => v.len(),
+            ColumnData::Int16(v) => v.len(),
+            ColumnData::Int8(v) => v.len(),
+            ColumnData::Boolean(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn value_pb(&self, row_idx: usize) -> ValuePb {
+        let value = match self {
+            ColumnData::Timestamp(v) => value::Value::TimestampValue(v[row_idx]),
+            ColumnData::Double(v) => value::Value::Float64Value(v[row_idx]),
+            ColumnData::Float(v) => value::Value::Float32Value(v[row_idx]),
+            ColumnData::Varbinary(v) => value::Value::VarbinaryValue(v[row_idx].clone()),
+            ColumnData::String(v) => value::Value::StringValue(v[row_idx].clone()),
+            ColumnData::UInt64(v) => value::Value::Uint64Value(v[row_idx]),
+            ColumnData::UInt32(v) => value::Value::Uint32Value(v[row_idx]),
+            ColumnData::UInt16(v) => value::Value::Uint16Value(v[row_idx].into()),
+            ColumnData::UInt8(v) => value::Value::Uint8Value(v[row_idx].into()),
+            ColumnData::Int64(v) => value::Value::Int64Value(v[row_idx]),
+            ColumnData::Int32(v) => value::Value::Int32Value(v[row_idx]),
+            ColumnData::Int16(v) => value::Value::Int16Value(v[row_idx].into()),
+            ColumnData::Int8(v) => value::Value::Int8Value(v[row_idx].into()),
+            ColumnData::Boolean(v) => value::Value::BoolValue(v[row_idx]),
+        };
+
+        ValuePb { value: Some(value) }
+    }
+
+    /// Append the bytes of the value into the series key.
+    fn extend_series_key(&self, row_idx: usize, key: &mut Vec<u8>) {
+        match self {
+            ColumnData::Timestamp(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::Double(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::Float(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::Varbinary(v) => {
+                key.extend_from_slice(&v[row_idx].len().to_le_bytes());
+                key.extend_from_slice(&v[row_idx]);
+            }
+            ColumnData::String(v) => {
+                key.extend_from_slice(&v[row_idx].len().to_le_bytes());
+                key.extend_from_slice(v[row_idx].as_bytes());
+            }
+            ColumnData::UInt64(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::UInt32(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::UInt16(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::UInt8(v) => key.push(v[row_idx]),
+            ColumnData::Int64(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::Int32(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::Int16(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::Int8(v) => key.extend_from_slice(&v[row_idx].to_le_bytes()),
+            ColumnData::Boolean(v) => key.push(v[row_idx] as u8),
+        }
+    }
+}
+
+/// The rows of one table in columnar layout, built by
+/// [`ColumnarBatchBuilder`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnarBatch {
+    table: String,
+    timestamps: Vec<i64>,
+    tags: Vec<(String, ColumnData)>,
+    fields: Vec<(String, ColumnData)>,
+}
+
+impl ColumnarBatch {
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Build the [`WriteTableRequestPb`] directly from the columns.
+    ///
+    /// Different from the points, the rows with the same tags and timestamp
+    /// are not deduplicated.
+    pub(crate) fn to_pb(&self) -> WriteTableRequestPb {
+        let mut entries: Vec<WriteSeriesEntryPb> = Vec::new();
+        let mut entry_idx_by_key: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut key = Vec::new();
+        for (row_idx, timestamp) in self.timestamps.iter().enumerate() {
+            key.clear();
+            for (_, column) in &self.tags {
+                column.extend_series_key(row_idx, &mut key);
+            }
+
+            let entry_idx = match entry_idx_by_key.get(&key) {
+                Some(entry_idx) => *entry_idx,
+                None => {
+                    let tags = self
+                        .tags
+                        .iter()
+                        .enumerate()
+                        .map(|(name_index, (_, column))| TagPb {
+                            name_index: name_index as u32,
+                            value: Some(column.value_pb(row_idx)),
+                        })
+                        .collect();
+                    entries.push(WriteSeriesEntryPb {
+                        tags,
+                        field_groups: Vec::new(),
+                    });
+                    entry_idx_by_key.insert(key.clone(), entries.len() - 1);
+                    entries.len() - 1
+                }
+            };
+
+            let fields = self
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(name_index, (_, column))| Field {
+                    name_index: name_index as u32,
+                    value: Some(column.value_pb(row_idx)),
+                })
+                .collect();
+            entries[entry_idx].field_groups.push(FieldGroupPb {
+                timestamp: *timestamp,
+                fields,
+            });
+        }
+
+        WriteTableRequestPb {
+            table: self.table.clone(),
+            tag_names: self.tags.iter().map(|(name, _)| name.clone()).collect(),
+            field_names: self.fields.iter().map(|(name, _)| name.clone()).collect(),
+            entries,
+        }
+    }
+}
+
+/// Builder for building a columnar batch.
+#[derive(Debug)]
+pub struct ColumnarBatchBuilder {
+    table: String,
+    timestamps: Vec<i64>,
+    tags: Vec<(String, ColumnData)>,
+    fields: Vec<(String, ColumnData)>,
+}
+
+impl ColumnarBatchBuilder {
+    pub fn new(table: impl Into<String>, timestamps: Vec<i64>) -> Self {
+        Self {
+            table: table.into(),
+            timestamps,
+            tags: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Add a tag column, whose length must be the same as the timestamps.
+    pub fn tag(mut self, name: impl Into<String>, data: ColumnData) -> Self {
+        self.tags.push((name.into(), data));
+        self
+    }
+
+    /// Add a field column, whose length must be the same as the timestamps.
+    pub fn field(mut self, name: impl Into<String>, data: ColumnData) -> Self {
+        self.fields.push((name.into(), data));
+        self
+    }
+
+    /// Build the final columnar batch.
+    pub fn build(self) -> Result<ColumnarBatch, String> {
+        if self.timestamps.is_empty() {
+            return Err("Timestamps should not be empty".to_string());
+        }
+
+        if self.fields.is_empty() {
+            return Err("Fields should not be empty".to_string());
+        }
+
+        let columns: Vec<_> = self.tags.iter().chain(self.fields.iter()).collect();
+        for (idx, (name, data)) in columns.iter().enumerate() {
+            if is_reserved_column_name(name) {
+                return Err("Tag or field name reserved column name in horaedb".to_string());
+            }
+
+            if columns[..idx].iter().any(|(prev, _)| prev == name) {
+                return Err(format!("Duplicate column name:{name}"));
+            }
+
+            if data.len() != self.timestamps.len() {
+                return Err(format!(
+                    "Mismatched length of column:{name}, expect:{}, actual:{}",
+                    self.timestamps.len(),
+                    data.len()
+                ));
+            }
+        }
+
+        Ok(ColumnarBatch {
+            table: self.table,
+            timestamps: self.timestamps,
+            tags: self.tags,
+            fields: self.fields,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use horaedbproto::storage::{value, Value as ValuePb};
+
+    use super::{ColumnData, ColumnarBatchBuilder};
+
+    #[test]
+    fn test_columnar_batch_to_pb() {
+        let batch = ColumnarBatchBuilder::new("demo", vec![1, 2, 3])
+            .tag(
+                "host",
+                ColumnData::String(vec!["a".to_string(), "b".to_string(), "a".to_string()]),
+            )
+            .field("value", ColumnData::Double(vec![0.1, 0.2, 0.3]))
+            .build()
+            .unwrap();
+        assert_eq!(batch.num_rows(), 3);
+
+        let pb = batch.to_pb();
+        assert_eq!(pb.table, "demo");
+        assert_eq!(pb.tag_names, vec!["host".to_string()]);
+        assert_eq!(pb.field_names, vec!["value".to_string()]);
+        assert_eq!(pb.entries.len(), 2);
+
+        let entry = &pb.entries[0];
+        assert_eq!(
+            entry.tags[0].value,
+            Some(ValuePb {
+                value: Some(value::Value::StringValue("a".to_string()))
+            })
+        );
+        let timestamps: Vec<_> = entry.field_groups.iter().map(|g| g.timestamp).collect();
+        assert_eq!(timestamps, vec![1, 3]);
+        assert_eq!(
+            entry.field_groups[1].fields[0].value,
+            Some(ValuePb {
+                value: Some(value::Value::Float64Value(0.3))
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_columnar_batch() {
+        assert!(ColumnarBatchBuilder::new("demo", vec![1, 2])
+            .field("value", ColumnData::Double(vec![0.1]))
+            .build()
+            .is_err());
+        assert!(ColumnarBatchBuilder::new("demo", vec![1])
+            .tag("value", ColumnData::Int8(vec![1]))
+            .field("value", ColumnData::Double(vec![0.1]))
+            .build()
+            .is_err());
+        assert!(ColumnarBatchBuilder::new("demo", vec![1])
+            .field("timestamp", ColumnData::Double(vec![0.1]))
+            .build()
+            .is_err());
+    }
+}
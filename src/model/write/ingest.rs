@@ -0,0 +1,287 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Parse the records read from the file into points.
+
+use std::sync::Arc;
+
+use serde_json::Value as JsonValue;
+
+use crate::model::{
+    value::Value,
+    write::point::{DefaultTimestamp, Point, PointBuilder},
+};
+
+/// The format of the records, one record per line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IngestFormat {
+    /// Each line is a json object.
+    Ndjson,
+    /// The first line is the header consisting of the column names.
+    Csv,
+}
+
+/// The progress reported after each batch is written.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IngestProgress {
+    /// The number of lines read so far.
+    pub lines: usize,
+    /// The number of points written so far.
+    pub points: usize,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&IngestProgress) + Send + Sync>;
+
+/// Options for
+/// [`DbClient::write_from_reader`](crate::DbClient::write_from_reader).
+///
+/// The columns in `tag_columns` are written as tags, and the others except the
+/// timestamp column are written as fields. The type of the values is inferred
+/// from the records: integer as `Int64`, float as `Double`, `true`/`false` as
+/// `Boolean` and others as `String`. And the values of the tags in csv are
+/// always `String`.
+#[derive(Clone)]
+pub struct IngestOptions {
+    pub format: IngestFormat,
+    /// Default value is `timestamp`, whose values must be the milliseconds.
+    pub timestamp_column: String,
+    /// Used for the records without the timestamp column.
+    pub default_timestamp: Option<DefaultTimestamp>,
+    pub tag_columns: Vec<String>,
+    /// The max number of points in one write request.
+    ///
+    /// Default value is 1000.
+    pub batch_size: usize,
+    /// The max number of write requests in flight.
+    ///
+    /// Default value is 4.
+    pub max_concurrency: usize,
+    pub progress: Option<ProgressCallback>,
+}
+
+impl IngestOptions {
+    pub fn new(format: IngestFormat) -> Self {
+        Self {
+            format,
+            timestamp_column: "timestamp".to_string(),
+            default_timestamp: None,
+            tag_columns: Vec::new(),
+            batch_size: 1000,
+            max_concurrency: 4,
+            progress: None,
+        }
+    }
+}
+
+/// Parser converting the lines into points.
+pub(crate) struct LineParser<'a> {
+    table: &'a str,
+    options: &'a IngestOptions,
+    csv_header: Option<Vec<String>>,
+}
+
+impl<'a> LineParser<'a> {
+    pub fn new(table: &'a str, options: &'a IngestOptions) -> Self {
+        Self {
+            table,
+            options,
+            csv_header: None,
+        }
+    }
+
+    /// Parse one line, and `None` is returned for the blank line and the csv
+    /// header.
+    pub fn parse(&mut self, line: &str) -> Result<Option<Point>, String> {
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let columns = match self.options.format {
+            IngestFormat::Ndjson => parse_json_object(line)?,
+            IngestFormat::Csv => {
+                let values = split_csv_line(line)?;
+                match &self.csv_header {
+                    Some(header) => {
+                        if header.len() != values.len() {
+                            return Err(format!(
+                                "Mismatched number of values, expect:{}, actual:{}",
+                                header.len(),
+                                values.len()
+                            ));
+                        }
+                        header
+                            .iter()
+                            .cloned()
+                            .zip(values)
+                            .map(|(name, value)| {
+                                let value = if self.options.tag_columns.contains(&name) {
+                                    Value::String(value)
+                                } else {
+                                    infer_value(&value)
+                                };
+                                (name, value)
+                            })
+                            .collect()
+                    }
+                    None => {
+                        self.csv_header = Some(values);
+                        return Ok(None);
+                    }
+                }
+            }
+        };
+
+        self.build_point(columns).map(Some)
+    }
+
+    fn build_point(&self, columns: Vec<(String, Value)>) -> Result<Point, String> {
+        let mut builder = PointBuilder::new(self.table);
+        let mut timestamp = None;
+        for (name, value) in columns {
+            if name == self.options.timestamp_column {
+                timestamp = match value {
+                    Value::Int64(v) => Some(v),
+                    v => return Err(format!("Invalid timestamp:{v:?}")),
+                };
+            } else if value.is_null() {
+                continue;
+            } else if self.options.tag_columns.contains(&name) {
+                builder = builder.tag(name, value);
+            } else {
+                builder = builder.field(name, value);
+            }
+        }
+
+        match (timestamp, &self.options.default_timestamp) {
+            (Some(timestamp), _) => builder.timestamp(timestamp).build(),
+            (None, Some(default_timestamp)) => {
+                builder.default_timestamp(default_timestamp.clone()).build()
+            }
+            (None, None) => Err(format!(
+                "Timestamp column:{} is missing",
+                self.options.timestamp_column
+            )),
+        }
+    }
+}
+
+fn parse_json_object(line: &str) -> Result<Vec<(String, Value)>, String> {
+    let object = match serde_json::from_str(line).map_err(|e| e.to_string())? {
+        JsonValue::Object(object) => object,
+        v => return Err(format!("Expect json object, actual:{v}")),
+    };
+
+    object
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                JsonValue::Null => Value::Null,
+                JsonValue::Bool(v) => Value::Boolean(v),
+                JsonValue::Number(v) => match v.as_i64() {
+                    Some(v) => Value::Int64(v),
+                    None => Value::Double(v.as_f64().unwrap_or(f64::NAN)),
+                },
+                JsonValue::String(v) => Value::String(v),
+                v => return Err(format!("Unsupported value of column:{name}, value:{v}")),
+            };
+            Ok((name, value))
+        })
+        .collect()
+}
+
+fn infer_value(value: &str) -> Value {
+    if value.is_empty() {
+        Value::Null
+    } else if let Ok(v) = value.parse::<i64>() {
+        Value::Int64(v)
+    } else if let Ok(v) = value.parse::<f64>() {
+        Value::Double(v)
+    } else if let Ok(v) = value.parse::<bool>() {
+        Value::Boolean(v)
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+/// Split the csv line, and the value can be quoted by double quotes.
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.trim_end_matches(['\r', '\n']).chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => values.push(std::mem::take(&mut value)),
+            c => value.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("Unclosed quote".to_string());
+    }
+    values.push(value);
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let mut options = IngestOptions::new(IngestFormat::Csv);
+        options.tag_columns = vec!["host".to_string()];
+        let mut parser = LineParser::new("demo", &options);
+
+        assert!(parser.parse("timestamp,host,value,msg").unwrap().is_none());
+        let point = parser
+            .parse("1000,1,0.5,\"a, \"\"b\"\"\"")
+            .unwrap()
+            .unwrap();
+        assert_eq!(point.timestamp, 1000);
+        assert_eq!(point.tags["host"], Value::String("1".to_string()));
+        assert_eq!(point.fields["value"], Value::Double(0.5));
+        assert_eq!(point.fields["msg"], Value::String("a, \"b\"".to_string()));
+
+        assert!(parser.parse("1000,1,0.5").is_err());
+        assert!(parser.parse("abc,1,0.5,x").is_err());
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let options = IngestOptions::new(IngestFormat::Ndjson);
+        let mut parser = LineParser::new("demo", &options);
+
+        assert!(parser.parse("").unwrap().is_none());
+        let point = parser
+            .parse(r#"{"timestamp": 1000, "value": 1, "ok": true, "none": null}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(point.timestamp, 1000);
+        assert_eq!(point.fields.len(), 2);
+        assert_eq!(point.fields["value"], Value::Int64(1));
+        assert_eq!(point.fields["ok"], Value::Boolean(true));
+
+        assert!(parser.parse(r#"{"value": 1}"#).is_err());
+        assert!(parser.parse("[1]").is_err());
+    }
+}
@@ -15,9 +15,15 @@
 // specific language governing permissions and limitations
 // under the License.
 
+mod builder;
+pub mod columnar;
+pub mod ingest;
 pub mod point;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
 mod request;
 mod response;
 
+pub use builder::{TableBuilder, TablePointBuilder, WriteRequestBuilder};
 pub use request::{pb_builder::WriteTableRequestPbsBuilder, Request};
-pub use response::Response;
+pub use response::{FailureDetail, Response};
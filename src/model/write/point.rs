@@ -15,7 +15,12 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::model::value::Value;
 
@@ -36,11 +41,76 @@ pub struct Point {
     pub fields: BTreeMap<String, Value>,
 }
 
+/// Conversion into a [`Point`], which can be derived by `#[derive(IntoPoint)]`
+/// with the `derive` feature.
+///
+/// ```rust
+/// # #[cfg(feature = "derive")]
+/// # {
+/// use horaedb_client::IntoPoint;
+///
+/// #[derive(IntoPoint)]
+/// #[horaedb(table = "cpu")]
+/// struct Cpu {
+///     #[horaedb(timestamp)]
+///     ts: i64,
+///     #[horaedb(tag)]
+///     host: String,
+///     #[horaedb(rename = "usage_percent")]
+///     usage: f64,
+///     temperature: Option<f32>,
+/// }
+/// # }
+/// ```
+pub trait IntoPoint {
+    fn into_point(self) -> Point;
+}
+
+impl IntoPoint for Point {
+    fn into_point(self) -> Point {
+        self
+    }
+}
+
+/// The source of the timestamp for the point whose timestamp is not set.
+#[derive(Clone)]
+pub enum DefaultTimestamp {
+    /// The current time of the client.
+    Now,
+    Fixed(i64),
+    Func(Arc<dyn Fn() -> i64 + Send + Sync>),
+}
+
+impl DefaultTimestamp {
+    /// Get the timestamp in milliseconds.
+    pub fn timestamp(&self) -> i64 {
+        match self {
+            DefaultTimestamp::Now => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or_default(),
+            DefaultTimestamp::Fixed(v) => *v,
+            DefaultTimestamp::Func(f) => f(),
+        }
+    }
+}
+
+impl fmt::Debug for DefaultTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DefaultTimestamp::Now => f.write_str("Now"),
+            DefaultTimestamp::Fixed(v) => f.debug_tuple("Fixed").field(v).finish(),
+            DefaultTimestamp::Func(_) => f.write_str("Func"),
+        }
+    }
+}
+
 /// Builder for building a point.
 #[derive(Debug)]
 pub struct PointBuilder {
     table: String,
     timestamp: Option<i64>,
+    default_timestamp: Option<DefaultTimestamp>,
     // tags' traversing should have definite order
     tags: BTreeMap<String, Value>,
     fields: BTreeMap<String, Value>,
@@ -52,6 +122,7 @@ impl PointBuilder {
         Self {
             table: table.into(),
             timestamp: None,
+            default_timestamp: None,
             tags: BTreeMap::new(),
             fields: BTreeMap::new(),
             contains_reserved_column_name: false,
@@ -65,11 +136,21 @@ impl PointBuilder {
     }
 
     /// Set the timestamp for the point.
+    ///
+    /// The timestamp is required, because assigning timestamps on the server
+    /// side isn't supported by the write protocol of horaedb.
     pub fn timestamp(mut self, timestamp: i64) -> Self {
         self.timestamp = Some(timestamp);
         self
     }
 
+    /// Set the source of the timestamp used if the timestamp is not set, and
+    /// the timestamp set by [`PointBuilder::timestamp`] always wins.
+    pub fn default_timestamp(mut self, default_timestamp: DefaultTimestamp) -> Self {
+        self.default_timestamp = Some(default_timestamp);
+        self
+    }
+
     /// Set tag name and value of the write entry.
     ///
     /// You cannot set tag with name like 'timestamp' or 'tsid',
@@ -107,7 +188,10 @@ impl PointBuilder {
 
         let timestamp = self
             .timestamp
-            .ok_or_else(|| "Timestamp must be set".to_string())?;
+            .or_else(|| self.default_timestamp.as_ref().map(|d| d.timestamp()))
+            .ok_or_else(|| {
+                "Timestamp must be set, server-assigned timestamp is not supported".to_string()
+            })?;
 
         Ok(Point {
             table: self.table,
@@ -117,3 +201,132 @@ impl PointBuilder {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{DefaultTimestamp, PointBuilder};
+    use crate::model::value::Value;
+
+    #[test]
+    fn test_default_timestamp() {
+        let builder = || PointBuilder::new("demo").field("value", Value::Int32(1));
+        assert!(builder().build().is_err());
+
+        let point = builder()
+            .default_timestamp(DefaultTimestamp::Fixed(42))
+            .build()
+            .unwrap();
+        assert_eq!(point.timestamp, 42);
+
+        let point = builder()
+            .timestamp(1)
+            .default_timestamp(DefaultTimestamp::Func(Arc::new(|| 42)))
+            .build()
+            .unwrap();
+        assert_eq!(point.timestamp, 1);
+
+        let point = builder()
+            .default_timestamp(DefaultTimestamp::Now)
+            .build()
+            .unwrap();
+        assert!(point.timestamp > 0);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_into_point() {
+        use crate::{
+            model::write::request::pb_builder::WriteTableRequestPbsBuilder, IntoPoint, WriteRequest,
+        };
+
+        #[derive(IntoPoint)]
+        #[horaedb(table = "cpu")]
+        struct Cpu {
+            #[horaedb(timestamp)]
+            ts: i64,
+            #[horaedb(tag)]
+            host: String,
+            #[horaedb(tag)]
+            region: Option<String>,
+            #[horaedb(rename = "usage_percent")]
+            usage: f64,
+            cores: u32,
+            throttled: bool,
+            temperature: Option<f32>,
+            payload: Vec<u8>,
+        }
+
+        let cpu = Cpu {
+            ts: 42,
+            host: "host1".to_string(),
+            region: None,
+            usage: 0.5,
+            cores: 8,
+            throttled: false,
+            temperature: Some(60.0),
+            payload: vec![1, 2],
+        };
+        let expected = PointBuilder::new("cpu")
+            .timestamp(42)
+            .tag("host", Value::String("host1".to_string()))
+            .field("usage_percent", Value::Double(0.5))
+            .field("cores", Value::UInt32(8))
+            .field("throttled", Value::Boolean(false))
+            .field("temperature", Value::Float(60.0))
+            .field("payload", Value::Varbinary(vec![1, 2]))
+            .build()
+            .unwrap();
+        let mut req = WriteRequest::default();
+        req.add_point(cpu.into_point());
+        assert_eq!(req.point_groups["cpu"], vec![expected]);
+
+        let pbs = WriteTableRequestPbsBuilder(req).build();
+        assert_eq!(pbs.len(), 1);
+        assert_eq!(pbs[0].table, "cpu");
+        assert_eq!(pbs[0].tag_names, vec!["host"]);
+        assert_eq!(
+            pbs[0].field_names,
+            vec![
+                "cores",
+                "payload",
+                "temperature",
+                "throttled",
+                "usage_percent"
+            ]
+        );
+        assert_eq!(pbs[0].entries.len(), 1);
+        assert_eq!(pbs[0].entries[0].field_groups[0].timestamp, 42);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_into_point_field() {
+        use crate::IntoPoint;
+
+        #[derive(IntoPoint)]
+        #[horaedb(table = "disk")]
+        struct Disk {
+            #[horaedb(timestamp)]
+            ts: i64,
+            #[horaedb(field)]
+            used: u64,
+            #[horaedb(field = "free_bytes")]
+            free: u64,
+        }
+
+        let disk = Disk {
+            ts: 42,
+            used: 1,
+            free: 2,
+        };
+        let expected = PointBuilder::new("disk")
+            .timestamp(42)
+            .field("used", Value::UInt64(1))
+            .field("free_bytes", Value::UInt64(2))
+            .build()
+            .unwrap();
+        assert_eq!(disk.into_point(), expected);
+    }
+}
@@ -0,0 +1,266 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Convert the Prometheus remote-write requests into the [`WriteRequest`]s.
+//!
+//! The messages follow the `prometheus.WriteRequest` of the remote-write
+//! protocol, so the (snappy decompressed) body of a remote-write request can be
+//! decoded by [`prost::Message::decode`].
+
+use crate::{
+    model::{
+        value::Value,
+        write::{point::PointBuilder, Request as WriteRequest},
+    },
+    Error, Result,
+};
+
+/// The label whose value is the metric name.
+const METRIC_NAME_LABEL: &str = "__name__";
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PromWriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    /// The timestamp in milliseconds.
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+/// How to handle the samples which can't be written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidSamples {
+    /// Drop them silently.
+    #[default]
+    Skip,
+    /// Fail the conversion by [`Error::Client`].
+    Reject,
+}
+
+/// Options for converting [`PromWriteRequest`].
+#[derive(Clone, Debug)]
+pub struct ConvertOptions {
+    /// The name of the field holding the sample value.
+    ///
+    /// Default value is `value`.
+    pub value_field: String,
+    /// How to handle the series without the `__name__` label, including the
+    /// ones of empty label set.
+    ///
+    /// Default value is `Skip`.
+    pub unnamed_series: InvalidSamples,
+    /// How to handle the NaN samples, including the stale markers.
+    ///
+    /// Default value is `Skip`.
+    pub nan_samples: InvalidSamples,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            value_field: "value".to_string(),
+            unnamed_series: InvalidSamples::Skip,
+            nan_samples: InvalidSamples::Skip,
+        }
+    }
+}
+
+impl PromWriteRequest {
+    /// Convert into the [`WriteRequest`], the `__name__` label is mapped to
+    /// the table, the other labels are mapped to the tags, and each sample is
+    /// mapped to one point.
+    ///
+    /// The labels of empty value are dropped, as Prometheus does.
+    pub fn to_write_request(&self, options: &ConvertOptions) -> Result<WriteRequest> {
+        let mut req = WriteRequest::default();
+        for series in &self.timeseries {
+            let table = series
+                .labels
+                .iter()
+                .find(|label| label.name == METRIC_NAME_LABEL && !label.value.is_empty());
+            let table = match (table, options.unnamed_series) {
+                (Some(label), _) => &label.value,
+                (None, InvalidSamples::Skip) => continue,
+                (None, InvalidSamples::Reject) => {
+                    return Err(Error::Client(format!(
+                        "Series has no metric name, labels:{:?}",
+                        series.labels
+                    )))
+                }
+            };
+
+            for sample in &series.samples {
+                if sample.value.is_nan() {
+                    match options.nan_samples {
+                        InvalidSamples::Skip => continue,
+                        InvalidSamples::Reject => {
+                            return Err(Error::Client(format!(
+                                "Sample is NaN, table:{table}, timestamp:{}",
+                                sample.timestamp
+                            )))
+                        }
+                    }
+                }
+
+                let builder = series
+                    .labels
+                    .iter()
+                    .filter(|label| label.name != METRIC_NAME_LABEL && !label.value.is_empty())
+                    .fold(PointBuilder::new(table), |builder, label| {
+                        builder.tag(&label.name, Value::String(label.value.clone()))
+                    });
+                let point = builder
+                    .timestamp(sample.timestamp)
+                    .field(&options.value_field, Value::Double(sample.value))
+                    .build()
+                    .map_err(|e| {
+                        Error::Client(format!("Invalid series, table:{table}, err:{e}"))
+                    })?;
+                req.add_point(point);
+            }
+        }
+
+        Ok(req)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use prost::Message;
+
+    use super::{ConvertOptions, InvalidSamples, Label, PromWriteRequest, Sample, TimeSeries};
+    use crate::{model::value::Value, Error};
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn sample(value: f64, timestamp: i64) -> Sample {
+        Sample { value, timestamp }
+    }
+
+    #[test]
+    fn test_to_write_request() {
+        let prom_req = PromWriteRequest {
+            timeseries: vec![
+                TimeSeries {
+                    labels: vec![
+                        label("__name__", "cpu"),
+                        label("host", "a"),
+                        label("zone", ""),
+                    ],
+                    samples: vec![sample(0.5, 1000), sample(0.7, 2000)],
+                },
+                TimeSeries {
+                    labels: vec![label("__name__", "mem"), label("host", "b")],
+                    samples: vec![sample(1024.0, 1000)],
+                },
+            ],
+        };
+        // Round trip through the remote-write payload.
+        let prom_req = PromWriteRequest::decode(prom_req.encode_to_vec().as_slice()).unwrap();
+
+        let req = prom_req
+            .to_write_request(&ConvertOptions::default())
+            .unwrap();
+        let rows = |table: &str| -> Vec<_> {
+            req.point_groups[table]
+                .iter()
+                .map(|point| {
+                    (
+                        point.timestamp,
+                        point.tags.clone(),
+                        point.fields["value"].clone(),
+                    )
+                })
+                .collect()
+        };
+        let tags =
+            |host: &str| BTreeMap::from([("host".to_string(), Value::String(host.to_string()))]);
+        assert_eq!(
+            rows("cpu"),
+            vec![
+                (1000, tags("a"), Value::Double(0.5)),
+                (2000, tags("a"), Value::Double(0.7)),
+            ]
+        );
+        assert_eq!(rows("mem"), vec![(1000, tags("b"), Value::Double(1024.0))]);
+    }
+
+    #[test]
+    fn test_invalid_samples() {
+        let prom_req = PromWriteRequest {
+            timeseries: vec![
+                TimeSeries {
+                    labels: vec![label("__name__", "cpu")],
+                    samples: vec![sample(f64::NAN, 1000), sample(0.5, 2000)],
+                },
+                TimeSeries {
+                    labels: vec![],
+                    samples: vec![sample(0.5, 1000)],
+                },
+            ],
+        };
+
+        let req = prom_req
+            .to_write_request(&ConvertOptions::default())
+            .unwrap();
+        assert_eq!(req.point_groups.len(), 1);
+        assert_eq!(req.point_groups["cpu"][0].timestamp, 2000);
+
+        let options = ConvertOptions {
+            nan_samples: InvalidSamples::Reject,
+            ..Default::default()
+        };
+        let err = prom_req.to_write_request(&options).unwrap_err();
+        assert!(matches!(err, Error::Client(msg) if msg.contains("NaN")));
+
+        let options = ConvertOptions {
+            unnamed_series: InvalidSamples::Reject,
+            ..Default::default()
+        };
+        let err = prom_req.to_write_request(&options).unwrap_err();
+        assert!(matches!(err, Error::Client(msg) if msg.contains("no metric name")));
+    }
+}
@@ -24,19 +24,103 @@ pub struct Response {
     pub success: u32,
     /// The number of the rows which fail to write
     pub failed: u32,
+    /// The detail of the rows which fail to write
+    pub failure_detail: FailureDetail,
+}
+
+/// The detail of the rows failing to write reported by the server.
+///
+/// The protocol carries no index of the rejected rows, and only the error
+/// message in the header of the response is kept, which usually tells the
+/// rejected rows and the reasons.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum FailureDetail {
+    /// No detail is reported, e.g. no row fails to write.
+    #[default]
+    NotReported,
+    /// The error messages of the responses with failed rows.
+    Messages(Vec<String>),
 }
 
 impl Response {
     pub fn new(success: u32, failed: u32) -> Self {
-        Self { success, failed }
+        Self {
+            success,
+            failed,
+            failure_detail: FailureDetail::NotReported,
+        }
+    }
+
+    /// Merge the response of another part of the write.
+    pub fn merge(&mut self, other: Response) {
+        self.success += other.success;
+        self.failed += other.failed;
+        if let FailureDetail::Messages(messages) = other.failure_detail {
+            match &mut self.failure_detail {
+                FailureDetail::Messages(merged) => merged.extend(messages),
+                detail => *detail = FailureDetail::Messages(messages),
+            }
+        }
     }
 }
 
 impl From<WriteResponsePb> for Response {
     fn from(resp_pb: WriteResponsePb) -> Self {
+        let failure_detail = match resp_pb.header {
+            Some(header) if resp_pb.failed > 0 && !header.error.is_empty() => {
+                FailureDetail::Messages(vec![header.error])
+            }
+            _ => FailureDetail::NotReported,
+        };
+
         Response {
             success: resp_pb.success,
             failed: resp_pb.failed,
+            failure_detail,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use horaedbproto::storage::WriteResponse as WriteResponsePb;
+    use prost::Message;
+
+    use super::{FailureDetail, Response};
+
+    /// The encoded response of two rows written and one row rejected.
+    const PARTIAL_FAILURE_FIXTURE: &[u8] =
+        b"\x0a\x32\x08\xc8\x01\x12\x2drow:1 is rejected, err:timestamp out of range\x10\x02\x18\x01";
+
+    #[test]
+    fn test_decode_partial_failure() {
+        let resp_pb = WriteResponsePb::decode(PARTIAL_FAILURE_FIXTURE).unwrap();
+        let resp = Response::from(resp_pb);
+        assert_eq!(resp.success, 2);
+        assert_eq!(resp.failed, 1);
+        assert_eq!(
+            resp.failure_detail,
+            FailureDetail::Messages(vec![
+                "row:1 is rejected, err:timestamp out of range".to_string()
+            ])
+        );
+
+        // The detail is kept after merged.
+        let mut merged = Response::new(3, 0);
+        merged.merge(resp);
+        merged.merge(Response::new(1, 0));
+        assert_eq!((merged.success, merged.failed), (6, 1));
+        assert!(matches!(merged.failure_detail, FailureDetail::Messages(m) if m.len() == 1));
+    }
+
+    #[test]
+    fn test_no_failure_detail() {
+        let resp_pb = WriteResponsePb {
+            header: None,
+            success: 2,
+            failed: 1,
+        };
+        let resp = Response::from(resp_pb);
+        assert_eq!(resp.failure_detail, FailureDetail::NotReported);
+    }
+}
@@ -0,0 +1,86 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::sync::Mutex;
+
+use crate::config::RetryBudgetConfig;
+
+/// Token bucket limiting the retries of all the requests of a client, so that
+/// the retries can't multiply the load on a struggling server.
+///
+/// The bucket starts full, each retry takes one token and each succeeded
+/// request puts `ratio` tokens back, up to `max_tokens`. The failed requests
+/// are not retried while the bucket has less than one token.
+pub(crate) struct RetryBudget {
+    config: RetryBudgetConfig,
+    tokens: Mutex<f64>,
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryBudgetConfig) -> Self {
+        Self {
+            tokens: Mutex::new(config.max_tokens),
+            config,
+        }
+    }
+
+    /// Take one token for a retry if any.
+    pub fn try_withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Refill the bucket by a succeeded request.
+    pub fn deposit(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.config.ratio).min(self.config.max_tokens);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RetryBudget;
+    use crate::config::RetryBudgetConfig;
+
+    #[test]
+    fn test_retry_budget() {
+        let budget = RetryBudget::new(RetryBudgetConfig {
+            ratio: 0.5,
+            max_tokens: 2.0,
+        });
+        assert!(budget.try_withdraw());
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+
+        // Two succeeded requests earn one retry.
+        budget.deposit();
+        assert!(!budget.try_withdraw());
+        budget.deposit();
+        assert!(budget.try_withdraw());
+
+        // The tokens are capped.
+        (0..10).for_each(|_| budget.deposit());
+        assert!(budget.try_withdraw());
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+    }
+}
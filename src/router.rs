@@ -15,25 +15,39 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use dashmap::DashMap;
 use horaedbproto::storage::{self, RouteRequest};
 
 use crate::{
+    db_client::RouteCacheStats,
     errors::Result,
     model::route::Endpoint,
     rpc_client::{RpcClient, RpcContext},
+    single_flight::SingleFlight,
     Error,
 };
 
+/// The endpoints fetched by one route request, keyed by the table.
+type FetchedRoutes = std::result::Result<Arc<HashMap<String, Endpoint>>, Arc<Error>>;
+
 /// Used to route tables to endpoints.
 #[async_trait]
 pub trait Router: Send + Sync {
     async fn route(&self, tables: &[String], ctx: &RpcContext) -> Result<Vec<Option<Endpoint>>>;
 
     fn evict(&self, tables: &[String]);
+
+    fn cache_stats(&self) -> RouteCacheStats;
 }
 
 /// Implementation for [`Router`].
@@ -42,12 +56,24 @@ pub trait Router: Send + Sync {
 /// If returned endpoints is outdated, you should call [`evict`] to remove them.
 /// And [`RouterImpl`] will fetch new endpoints when you call ['route'] again.
 ///
+/// The concurrent lookups of the same uncached table share one route request
+/// in flight, instead of each issuing its own.
+///
 /// [`route`]: RouterImpl::route
 /// [`evict`]: RouterImpl::evict
 pub struct RouterImpl {
     default_endpoint: Endpoint,
-    cache: DashMap<String, Endpoint>,
+    cache: DashMap<String, CachedEndpoint>,
+    cache_ttl: Option<Duration>,
     rpc_client: Arc<dyn RpcClient>,
+    fetching: SingleFlight<String, FetchedRoutes>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct CachedEndpoint {
+    endpoint: Endpoint,
+    cached_at: Instant,
 }
 
 impl RouterImpl {
@@ -55,7 +81,62 @@ impl RouterImpl {
         Self {
             default_endpoint,
             cache: DashMap::new(),
+            cache_ttl: None,
             rpc_client,
+            fetching: SingleFlight::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Expire the cached endpoints after the `cache_ttl`, and they never expire
+    /// if it is `None`.
+    pub fn with_cache_ttl(mut self, cache_ttl: Option<Duration>) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+
+    async fn fetch(
+        rpc_client: &dyn RpcClient,
+        ctx: &RpcContext,
+        tables: Vec<String>,
+    ) -> Result<HashMap<String, Endpoint>> {
+        let req_ctx = storage::RequestContext {
+            database: ctx.database.clone().unwrap(),
+        };
+        let req = RouteRequest {
+            context: Some(req_ctx),
+            tables: tables.clone(),
+        };
+        let resp = rpc_client.route(ctx, req).await?;
+
+        let mut routes = HashMap::with_capacity(resp.routes.len());
+        for route in resp.routes {
+            // Impossible to get none.
+            if !tables.contains(&route.table) {
+                return Err(Error::Unknown(format!(
+                    "Unknown table:{} in response",
+                    route.table
+                )));
+            }
+            if let Some(endpoint) = route.endpoint {
+                routes.insert(route.table, endpoint.into());
+            }
+        }
+
+        Ok(routes)
+    }
+
+    fn get_cached(&self, table: &str) -> Option<Endpoint> {
+        let cached = self.cache.get(table)?;
+        match self.cache_ttl {
+            Some(ttl) if cached.cached_at.elapsed() >= ttl => {
+                drop(cached);
+                self.cache
+                    .remove_if(table, |_, cached| cached.cached_at.elapsed() >= ttl);
+                None
+            }
+            _ => Some(cached.endpoint.clone()),
         }
     }
 }
@@ -67,48 +148,71 @@ impl Router for RouterImpl {
 
         let mut target_endpoints = vec![Some(self.default_endpoint.clone()); tables.len()];
 
-        // Find from cache firstly and collect misses.
-        let misses = {
-            let mut misses = HashMap::new();
-            for (idx, table) in tables.iter().enumerate() {
-                match self.cache.get(table) {
-                    Some(pair) => {
-                        target_endpoints[idx] = Some(pair.value().clone());
-                    }
-
-                    None => {
-                        misses.insert(table.clone(), idx);
-                    }
+        // Find from cache firstly and collect misses, the indexes of the tables
+        // appearing several times are all collected.
+        let mut misses: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut miss_tables = Vec::new();
+        for (idx, table) in tables.iter().enumerate() {
+            if !ctx.bypass_route_cache {
+                if let Some(endpoint) = self.get_cached(table) {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    target_endpoints[idx] = Some(endpoint);
+                    continue;
                 }
+                self.misses.fetch_add(1, Ordering::Relaxed);
             }
+
             misses
-        };
+                .entry(table.clone())
+                .or_insert_with(|| {
+                    miss_tables.push(table.clone());
+                    Vec::new()
+                })
+                .push(idx);
+        }
+        if misses.is_empty() {
+            return Ok(target_endpoints);
+        }
 
-        // Get endpoints of misses from remote.
-        let req_ctx = storage::RequestContext {
-            database: ctx.database.clone().unwrap(),
-        };
-        let miss_tables = misses.keys().cloned().collect();
-        let req = RouteRequest {
-            context: Some(req_ctx),
-            tables: miss_tables,
-        };
-        let resp = self.rpc_client.route(ctx, req).await?;
+        // Get endpoints of all the misses from remote in one request, joining
+        // the requests in flight for some of them if any.
+        let rpc_client = self.rpc_client.clone();
+        // The shared rpc must not be cancelled by the first caller, and each
+        // caller is cancelled by its own token outside instead.
+        let mut rpc_ctx = ctx.clone();
+        rpc_ctx.cancellation_token = None;
+        let fetched = self
+            .fetching
+            .run_many(&miss_tables, move |tables| async move {
+                Self::fetch(rpc_client.as_ref(), &rpc_ctx, tables)
+                    .await
+                    .map(Arc::new)
+                    .map_err(Arc::new)
+            })
+            .await
+            .into_iter()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Error::unshare)?;
 
         // Fill miss endpoint and update cache.
-        for route in resp.routes {
+        for (table, routes) in miss_tables.into_iter().zip(fetched) {
             // Endpoint may be none, and not cache it when it is none.
-            if route.endpoint.is_none() {
-                continue;
+            let endpoint = match routes.get(&table) {
+                Some(endpoint) => endpoint.clone(),
+                None => continue,
+            };
+            for idx in &misses[&table] {
+                target_endpoints[*idx] = Some(endpoint.clone());
+            }
+            if !ctx.bypass_route_cache {
+                self.cache.insert(
+                    table,
+                    CachedEndpoint {
+                        endpoint,
+                        cached_at: Instant::now(),
+                    },
+                );
             }
-
-            // Impossible to get none.
-            let idx = misses.get(&route.table).ok_or_else(|| {
-                Error::Unknown(format!("Unknown table:{} in response", route.table))
-            })?;
-            let endpoint: Endpoint = route.endpoint.unwrap().into();
-            self.cache.insert(route.table, endpoint.clone());
-            target_endpoints[*idx] = Some(endpoint);
         }
 
         Ok(target_endpoints)
@@ -119,18 +223,31 @@ impl Router for RouterImpl {
             self.cache.remove(e.as_str());
         })
     }
+
+    fn cache_stats(&self) -> RouteCacheStats {
+        RouteCacheStats {
+            size: self.cache.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
+    use std::{
+        sync::{atomic::AtomicBool, Arc, Mutex},
+        time::Duration,
+    };
 
     use dashmap::DashMap;
+    use tonic::Code;
 
     use super::{Router, RouterImpl};
     use crate::{
         model::route::Endpoint,
         rpc_client::{MockRpcClient, RpcContext},
+        Error,
     };
 
     #[tokio::test]
@@ -150,6 +267,7 @@ mod test {
         let route_table = Arc::new(DashMap::default());
         let mock_rpc_client = MockRpcClient {
             route_table: route_table.clone(),
+            ..Default::default()
         };
         mock_rpc_client
             .route_table
@@ -162,7 +280,7 @@ mod test {
         // route --> change route_table --> route again.
         let ctx = RpcContext {
             database: Some("db".to_string()),
-            timeout: None,
+            ..Default::default()
         };
         let tables = vec![table1.clone(), table2.clone()];
         let route_client = RouterImpl::new(default_endpoint.clone(), Arc::new(mock_rpc_client));
@@ -193,4 +311,177 @@ mod test {
             route_res4.get(1).unwrap().as_ref().unwrap()
         );
     }
+
+    #[tokio::test]
+    async fn test_route_misses_only() {
+        let tables: Vec<_> = (1..=3).map(|i| format!("table{i}")).collect();
+        let default_endpoint = Endpoint::new("192.168.0.5".to_string(), 15);
+        let route_table = Arc::new(DashMap::default());
+        for (i, table) in tables.iter().enumerate() {
+            route_table.insert(table.clone(), Endpoint::new(format!("192.168.0.{i}"), 11));
+        }
+        let route_requests = Arc::new(Mutex::new(Vec::new()));
+        let mock_rpc_client = MockRpcClient {
+            route_table,
+            route_requests: route_requests.clone(),
+            ..Default::default()
+        };
+        let route_client = RouterImpl::new(default_endpoint, Arc::new(mock_rpc_client));
+        let ctx = RpcContext::default().database("db".to_string());
+
+        route_client.route(&tables[..2], &ctx).await.unwrap();
+        // The cached tables are excluded, and the duplicated one is requested
+        // once but filled for all its appearances.
+        let routes = route_client
+            .route(
+                &[
+                    tables[0].clone(),
+                    tables[2].clone(),
+                    tables[1].clone(),
+                    tables[2].clone(),
+                ],
+                &ctx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            *route_requests.lock().unwrap(),
+            vec![tables[..2].to_vec(), vec![tables[2].clone()]]
+        );
+        let addrs: Vec<_> = routes.into_iter().map(|e| e.unwrap().addr).collect();
+        assert_eq!(
+            addrs,
+            ["192.168.0.0", "192.168.0.2", "192.168.0.1", "192.168.0.2"]
+        );
+
+        // Nothing is requested when all the tables are cached.
+        route_client.route(&tables, &ctx).await.unwrap();
+        assert_eq!(route_requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_share_route_in_flight() {
+        let table = "table1".to_string();
+        let endpoint = Endpoint::new("192.168.0.1".to_string(), 11);
+        let route_table = Arc::new(DashMap::default());
+        route_table.insert(table.clone(), endpoint.clone());
+        let route_requests = Arc::new(Mutex::new(Vec::new()));
+        let mock_rpc_client = MockRpcClient {
+            route_table,
+            route_requests: route_requests.clone(),
+            route_delay: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let route_client = Arc::new(RouterImpl::new(
+            Endpoint::new("192.168.0.5".to_string(), 15),
+            Arc::new(mock_rpc_client),
+        ));
+        let ctx = RpcContext::default().database("db".to_string());
+
+        let lookups: Vec<_> = (0..10)
+            .map(|_| {
+                let route_client = route_client.clone();
+                let ctx = ctx.clone();
+                let tables = vec![table.clone()];
+                tokio::spawn(async move { route_client.route(&tables, &ctx).await })
+            })
+            .collect();
+        for lookup in lookups {
+            let routes = lookup.await.unwrap().unwrap();
+            assert_eq!(routes[0].as_ref().unwrap(), &endpoint);
+        }
+        assert_eq!(*route_requests.lock().unwrap(), vec![vec![table.clone()]]);
+
+        // The evicted route is fetched again.
+        route_client.evict(&[table.clone()]);
+        route_client.route(&[table], &ctx).await.unwrap();
+        assert_eq!(route_requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_share_route_error_in_flight() {
+        let mock_rpc_client = MockRpcClient {
+            route_delay: Some(Duration::from_millis(50)),
+            route_unavailable: Arc::new(AtomicBool::new(true)),
+            ..Default::default()
+        };
+        let route_client = RouterImpl::new(
+            Endpoint::new("192.168.0.5".to_string(), 15),
+            Arc::new(mock_rpc_client),
+        );
+        let ctx = RpcContext::default().database("db".to_string());
+
+        // Each waiter gets the error of the original variant.
+        let tables = vec!["table1".to_string(), "table2".to_string()];
+        let lookups = (0..3).map(|_| route_client.route(&tables, &ctx));
+        for result in futures::future::join_all(lookups).await {
+            assert!(
+                matches!(&result, Err(Error::Rpc(status)) if status.code() == Code::Unavailable),
+                "result:{result:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bypass_route_cache() {
+        let table = "table1".to_string();
+        let endpoint1 = Endpoint::new("192.168.0.1".to_string(), 11);
+        let endpoint2 = Endpoint::new("192.168.0.2".to_string(), 12);
+        let default_endpoint = Endpoint::new("192.168.0.5".to_string(), 15);
+        let route_table = Arc::new(DashMap::default());
+        route_table.insert(table.clone(), endpoint1.clone());
+        let mock_rpc_client = MockRpcClient {
+            route_table: route_table.clone(),
+            ..Default::default()
+        };
+        let route_client = RouterImpl::new(default_endpoint, Arc::new(mock_rpc_client));
+
+        let ctx = RpcContext::default().database("db".to_string());
+        let bypass_ctx = ctx.clone().bypass_route_cache(true);
+        let tables = vec![table.clone()];
+
+        // The route fetched with bypassing is not cached.
+        let routes = route_client.route(&tables, &bypass_ctx).await.unwrap();
+        assert_eq!(routes[0].as_ref().unwrap(), &endpoint1);
+        route_table.insert(table.clone(), endpoint2.clone());
+        let routes = route_client.route(&tables, &ctx).await.unwrap();
+        assert_eq!(routes[0].as_ref().unwrap(), &endpoint2);
+
+        // The cached route is bypassed.
+        route_table.insert(table, endpoint1.clone());
+        let routes = route_client.route(&tables, &bypass_ctx).await.unwrap();
+        assert_eq!(routes[0].as_ref().unwrap(), &endpoint1);
+        let routes = route_client.route(&tables, &ctx).await.unwrap();
+        assert_eq!(routes[0].as_ref().unwrap(), &endpoint2);
+    }
+
+    #[tokio::test]
+    async fn test_route_cache_ttl() {
+        let table = "table1".to_string();
+        let endpoint1 = Endpoint::new("192.168.0.1".to_string(), 11);
+        let endpoint2 = Endpoint::new("192.168.0.2".to_string(), 12);
+        let default_endpoint = Endpoint::new("192.168.0.5".to_string(), 15);
+        let route_table = Arc::new(DashMap::default());
+        route_table.insert(table.clone(), endpoint1.clone());
+        let mock_rpc_client = MockRpcClient {
+            route_table: route_table.clone(),
+            ..Default::default()
+        };
+        let route_client = RouterImpl::new(default_endpoint, Arc::new(mock_rpc_client))
+            .with_cache_ttl(Some(Duration::from_millis(50)));
+
+        let ctx = RpcContext::default().database("db".to_string());
+        let tables = vec![table.clone()];
+        let routes = route_client.route(&tables, &ctx).await.unwrap();
+        assert_eq!(routes[0].as_ref().unwrap(), &endpoint1);
+
+        // The cached route is returned before expiry.
+        route_table.insert(table, endpoint2.clone());
+        let routes = route_client.route(&tables, &ctx).await.unwrap();
+        assert_eq!(routes[0].as_ref().unwrap(), &endpoint1);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let routes = route_client.route(&tables, &ctx).await.unwrap();
+        assert_eq!(routes[0].as_ref().unwrap(), &endpoint2);
+    }
 }
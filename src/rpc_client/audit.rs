@@ -0,0 +1,45 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Audit the completed rpcs.
+
+use std::{fmt::Debug, time::Duration};
+
+use crate::{errors::Error, rpc_client::RpcOperation};
+
+/// The outcome of a completed rpc.
+#[derive(Debug)]
+pub enum OperationOutcome<'a> {
+    Success,
+    Failure(&'a Error),
+}
+
+/// Auditor notified after each rpc completes, no matter whether it
+/// succeeds, which can be registered by
+/// [`Builder::operation_auditor`](crate::Builder::operation_auditor).
+///
+/// It is called in the path of the rpc, so it should be cheap, e.g. just
+/// sending the record to a channel.
+pub trait OperationAuditor: Debug + Send + Sync {
+    fn on_operation_complete(
+        &self,
+        operation: RpcOperation,
+        database: Option<&str>,
+        outcome: OperationOutcome<'_>,
+        duration: Duration,
+    );
+}
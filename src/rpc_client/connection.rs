@@ -0,0 +1,134 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Track the connections established by the grpc channels.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use hyper::{client::HttpConnector, Uri};
+use tower::Service;
+
+/// Statistics of the connections to an endpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// The number of the connections established, including the first one.
+    pub connects: u64,
+    /// The number of the failed connecting attempts.
+    pub failed_connects: u64,
+}
+
+impl ConnectionStats {
+    /// The number of the connections re-established after the first one.
+    pub fn reconnects(&self) -> u64 {
+        self.connects.saturating_sub(1)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionCounter {
+    connects: AtomicU64,
+    failed_connects: AtomicU64,
+}
+
+impl ConnectionCounter {
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            connects: self.connects.load(Ordering::Relaxed),
+            failed_connects: self.failed_connects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Connector counting the connecting attempts.
+///
+/// The channel uses the connector to establish the first connection and to
+/// re-establish the dropped one, so all the attempts are counted.
+#[derive(Clone)]
+pub(crate) struct CountingConnector {
+    inner: HttpConnector,
+    counter: Arc<ConnectionCounter>,
+}
+
+impl CountingConnector {
+    pub fn new(inner: HttpConnector, counter: Arc<ConnectionCounter>) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl Service<Uri> for CountingConnector {
+    type Error = <HttpConnector as Service<Uri>>::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+    type Response = <HttpConnector as Service<Uri>>::Response;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.inner.call(uri);
+        let counter = self.counter.clone();
+        Box::pin(async move {
+            let res = connecting.await;
+            match &res {
+                Ok(_) => counter.connects.fetch_add(1, Ordering::Relaxed),
+                Err(_) => counter.failed_connects.fetch_add(1, Ordering::Relaxed),
+            };
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use hyper::client::HttpConnector;
+    use tower::Service;
+
+    use super::{ConnectionCounter, ConnectionStats, CountingConnector};
+
+    #[tokio::test]
+    async fn test_count_connects() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let counter = Arc::new(ConnectionCounter::default());
+        let mut connector = CountingConnector::new(HttpConnector::new(), counter.clone());
+
+        let uri = format!("http://{addr}").parse().unwrap();
+        connector.call(uri).await.unwrap();
+        drop(listener);
+        let uri = format!("http://{addr}").parse().unwrap();
+        assert!(connector.call(uri).await.is_err());
+
+        let stats = counter.stats();
+        assert_eq!(
+            stats,
+            ConnectionStats {
+                connects: 1,
+                failed_connects: 1,
+            }
+        );
+        assert_eq!(stats.reconnects(), 0);
+    }
+}
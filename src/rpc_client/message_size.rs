@@ -0,0 +1,45 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Record the encoded sizes of the rpc messages.
+
+use std::fmt::Debug;
+
+/// The kind of the rpc.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RpcOperation {
+    SqlQuery,
+    Write,
+    Route,
+}
+
+/// The encoded sizes of the protobuf messages of one rpc.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageSize {
+    pub operation: RpcOperation,
+    pub request_bytes: usize,
+    /// It is `None` if no response is received, e.g. the rpc fails.
+    pub response_bytes: Option<usize>,
+}
+
+/// Recorder of the [`MessageSize`]s, which can be registered by
+/// [`Builder::message_size_recorder`](crate::Builder::message_size_recorder).
+///
+/// The sizes are computed only if a recorder is registered.
+pub trait MessageSizeRecorder: Debug + Send + Sync {
+    fn record(&self, size: &MessageSize);
+}
@@ -15,39 +15,249 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
+use arrow::{
+    array::Int64Array,
+    datatypes::{DataType, Field, Schema},
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
 use async_trait::async_trait;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
+use futures::{stream::BoxStream, StreamExt};
 use horaedbproto::storage::{
+    arrow_payload::Compression, sql_query_response::Output as OutputPb, ArrowPayload,
     Endpoint as EndpointPb, Route as RoutePb, RouteRequest as RouteRequestPb,
     RouteResponse as RouteResponsePb, SqlQueryRequest as QueryRequestPb,
     SqlQueryResponse as QueryResponsePb, WriteRequest as WriteRequestPb,
     WriteResponse as WriteResponsePb,
 };
+use tonic::{
+    metadata::{MetadataMap, MetadataValue},
+    Status,
+};
 
 use crate::{
-    model::route::Endpoint,
-    rpc_client::{RpcClient, RpcContext},
-    Result,
+    errors::{ConnectErrorKind, ServerError},
+    model::{capabilities::ServerCapabilities, route::Endpoint},
+    rpc_client::{ResponseMeta, RpcClient, RpcClientFactory, RpcContext},
+    Error, Result,
 };
 
 /// Rpc client used for testing.
+#[derive(Default)]
 pub struct MockRpcClient {
+    pub endpoint: String,
     pub route_table: Arc<DashMap<String, Endpoint>>,
+    /// The tables of each handled route request.
+    pub route_requests: Arc<Mutex<Vec<Vec<String>>>>,
+    /// The delay before responding the route request.
+    pub route_delay: Option<Duration>,
+    /// Whether the route requests fail as the route service is unavailable.
+    pub route_unavailable: Arc<AtomicBool>,
+    /// Whether the route requests are rejected by the server.
+    pub route_rejected: Arc<AtomicBool>,
+    /// The number of the sql queries and writes handled by each endpoint.
+    pub request_counts: Arc<DashMap<String, usize>>,
+    /// The delay before responding the sql query.
+    pub sql_query_delay: Option<Duration>,
+    /// The affected rows responded for the sql queries keyed by the sql, and
+    /// it is zero for the others.
+    pub affected_rows: Arc<DashMap<String, u32>>,
+    /// The number of the rows responded for the sql queries keyed by the sql,
+    /// which are the values of an `Int64` column `value` from 0, and it takes
+    /// precedence over the `affected_rows`.
+    pub rows: Arc<DashMap<String, usize>>,
+    /// The codes of the server errors responded for the sql queries keyed by
+    /// the sql, and it takes precedence over the `rows`.
+    pub server_errors: Arc<DashMap<String, u32>>,
+    /// The max number of the sql queries in flight at the same time.
+    pub max_in_flight_sql_queries: Arc<AtomicUsize>,
+    pub in_flight_sql_queries: Arc<AtomicUsize>,
+    /// Whether the connection is broken, failing the sql queries and writes.
+    pub broken: bool,
+    /// The capabilities responded, and none is supported by default.
+    pub capabilities: ServerCapabilities,
+}
+
+impl MockRpcClient {
+    fn count_request(&self) {
+        *self
+            .request_counts
+            .entry(self.endpoint.clone())
+            .or_default() += 1;
+    }
+
+    fn make_rows_output(rows: usize) -> OutputPb {
+        let schema = Schema::new(vec![Field::new("value", DataType::Int64, false)]);
+        let record_batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(Int64Array::from_iter_values(0..rows as i64))],
+        )
+        .unwrap();
+        let mut writer = StreamWriter::try_new(Vec::new(), &record_batch.schema()).unwrap();
+        writer.write(&record_batch).unwrap();
+        writer.finish().unwrap();
+        OutputPb::Arrow(ArrowPayload {
+            record_batches: vec![writer.into_inner().unwrap()],
+            compression: Compression::None as i32,
+        })
+    }
+
+    fn check_broken(&self) -> Result<()> {
+        if self.broken {
+            return Err(Error::Rpc(Status::unavailable("connection is broken")));
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl RpcClient for MockRpcClient {
-    async fn sql_query(&self, _ctx: &RpcContext, _req: QueryRequestPb) -> Result<QueryResponsePb> {
-        todo!()
+    async fn sql_query(&self, _ctx: &RpcContext, req: QueryRequestPb) -> Result<QueryResponsePb> {
+        let in_flight = self.in_flight_sql_queries.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight_sql_queries
+            .fetch_max(in_flight, Ordering::SeqCst);
+        if let Some(delay) = self.sql_query_delay {
+            tokio::time::sleep(delay).await;
+        }
+        self.in_flight_sql_queries.fetch_sub(1, Ordering::SeqCst);
+
+        self.check_broken()?;
+        self.count_request();
+        if let Some(code) = self.server_errors.get(&req.sql) {
+            return Err(Error::Server(ServerError {
+                code: *code,
+                msg: format!("failed to execute sql:{}", req.sql),
+            }));
+        }
+        if let Some(rows) = self.rows.get(&req.sql) {
+            return Ok(QueryResponsePb {
+                header: None,
+                output: Some(Self::make_rows_output(*rows)),
+            });
+        }
+        let affected_rows = self
+            .affected_rows
+            .get(&req.sql)
+            .map(|affected_rows| *affected_rows)
+            .unwrap_or(0);
+        Ok(QueryResponsePb {
+            header: None,
+            output: Some(OutputPb::AffectedRows(affected_rows)),
+        })
+    }
+
+    /// Set the endpoint as the `x-request-id` trailer, along with a binary one
+    /// which is dropped.
+    async fn sql_query_with_meta(
+        &self,
+        ctx: &RpcContext,
+        req: QueryRequestPb,
+    ) -> Result<(QueryResponsePb, ResponseMeta)> {
+        let resp = self.sql_query(ctx, req).await?;
+        let mut trailers = MetadataMap::new();
+        trailers.insert("x-request-id", self.endpoint.parse().unwrap());
+        trailers.insert_bin("x-trace-bin", MetadataValue::from_bytes(b"trace"));
+        Ok((resp, ResponseMeta::from(&trailers)))
+    }
+
+    /// Stream three responses of one affected row each, and then a server
+    /// error.
+    async fn sql_query_stream(
+        &self,
+        _ctx: &RpcContext,
+        req: QueryRequestPb,
+    ) -> Result<BoxStream<'static, Result<QueryResponsePb>>> {
+        self.count_request();
+        // The rows are responded twice if set, and the stream ends normally.
+        if let Some(rows) = self.rows.get(&req.sql) {
+            let chunks: Vec<_> = (0..2)
+                .map(|_| {
+                    Ok(QueryResponsePb {
+                        header: None,
+                        output: Some(Self::make_rows_output(*rows)),
+                    })
+                })
+                .collect();
+            return Ok(futures::stream::iter(chunks).boxed());
+        }
+        let mut chunks: Vec<_> = (0..3)
+            .map(|_| {
+                Ok(QueryResponsePb {
+                    header: None,
+                    output: Some(OutputPb::AffectedRows(1)),
+                })
+            })
+            .collect();
+        chunks.push(Err(Error::Server(ServerError {
+            code: 500,
+            msg: "stream is interrupted".to_string(),
+        })));
+        Ok(futures::stream::iter(chunks).boxed())
     }
 
-    async fn write(&self, _ctx: &RpcContext, _req: WriteRequestPb) -> Result<WriteResponsePb> {
-        todo!()
+    async fn write(&self, _ctx: &RpcContext, req: WriteRequestPb) -> Result<WriteResponsePb> {
+        self.check_broken()?;
+        self.count_request();
+        let success = req
+            .table_requests
+            .iter()
+            .flat_map(|table_request| &table_request.entries)
+            .map(|entry| entry.field_groups.len() as u32)
+            .sum();
+        Ok(WriteResponsePb {
+            header: None,
+            success,
+            failed: 0,
+        })
+    }
+
+    /// Consume all the streamed requests as one request, and respond the sum.
+    async fn write_stream(
+        &self,
+        _ctx: &RpcContext,
+        reqs: BoxStream<'static, WriteRequestPb>,
+    ) -> Result<WriteResponsePb> {
+        self.count_request();
+        let success = reqs
+            .map(|req| {
+                req.table_requests
+                    .iter()
+                    .flat_map(|table_request| &table_request.entries)
+                    .map(|entry| entry.field_groups.len() as u32)
+                    .sum::<u32>()
+            })
+            .fold(0, |sum, success| async move { sum + success })
+            .await;
+        Ok(WriteResponsePb {
+            header: None,
+            success,
+            failed: 0,
+        })
     }
 
     async fn route(&self, _ctx: &RpcContext, req: RouteRequestPb) -> Result<RouteResponsePb> {
+        self.route_requests.lock().unwrap().push(req.tables.clone());
+        if let Some(delay) = self.route_delay {
+            tokio::time::sleep(delay).await;
+        }
+        if self.route_unavailable.load(Ordering::Relaxed) {
+            return Err(Error::Rpc(Status::unavailable("route service unavailable")));
+        }
+        if self.route_rejected.load(Ordering::Relaxed) {
+            return Err(Error::Server(ServerError {
+                code: 403,
+                msg: "route is not permitted".to_string(),
+            }));
+        }
         let route_tables = self.route_table.clone();
         let routes: Vec<_> = req
             .tables
@@ -73,4 +283,68 @@ impl RpcClient for MockRpcClient {
         };
         Ok(route_resp)
     }
+
+    async fn capabilities(&self, _ctx: &RpcContext) -> Result<ServerCapabilities> {
+        self.check_broken()?;
+        Ok(self.capabilities.clone())
+    }
+}
+
+/// Rpc client factory used for testing.
+///
+/// All the built [`MockRpcClient`]s share the same route table, route requests
+/// and request counts.
+#[derive(Default)]
+pub struct MockRpcClientFactory {
+    pub route_table: Arc<DashMap<String, Endpoint>>,
+    pub route_requests: Arc<Mutex<Vec<Vec<String>>>>,
+    pub route_delay: Option<Duration>,
+    pub route_unavailable: Arc<AtomicBool>,
+    pub route_rejected: Arc<AtomicBool>,
+    pub request_counts: Arc<DashMap<String, usize>>,
+    /// The number of the clients built for each endpoint.
+    pub build_counts: Arc<DashMap<String, usize>>,
+    /// The endpoints failing to connect.
+    pub unreachable_endpoints: Arc<DashSet<String>>,
+    /// The endpoints whose built clients have the broken connections.
+    pub broken_endpoints: Arc<DashSet<String>>,
+    pub sql_query_delay: Option<Duration>,
+    pub affected_rows: Arc<DashMap<String, u32>>,
+    pub rows: Arc<DashMap<String, usize>>,
+    pub server_errors: Arc<DashMap<String, u32>>,
+    pub max_in_flight_sql_queries: Arc<AtomicUsize>,
+    pub in_flight_sql_queries: Arc<AtomicUsize>,
+    pub capabilities: ServerCapabilities,
+}
+
+#[async_trait]
+impl RpcClientFactory for MockRpcClientFactory {
+    async fn build(&self, endpoint: String) -> Result<Arc<dyn RpcClient>> {
+        if self.unreachable_endpoints.contains(&endpoint) {
+            return Err(Error::Connect {
+                addr: endpoint,
+                kind: ConnectErrorKind::Refused,
+                source: "connection refused".into(),
+            });
+        }
+
+        *self.build_counts.entry(endpoint.clone()).or_default() += 1;
+        Ok(Arc::new(MockRpcClient {
+            broken: self.broken_endpoints.contains(&endpoint),
+            endpoint,
+            route_table: self.route_table.clone(),
+            route_requests: self.route_requests.clone(),
+            route_delay: self.route_delay,
+            route_unavailable: self.route_unavailable.clone(),
+            route_rejected: self.route_rejected.clone(),
+            request_counts: self.request_counts.clone(),
+            sql_query_delay: self.sql_query_delay,
+            affected_rows: self.affected_rows.clone(),
+            rows: self.rows.clone(),
+            server_errors: self.server_errors.clone(),
+            max_in_flight_sql_queries: self.max_in_flight_sql_queries.clone(),
+            in_flight_sql_queries: self.in_flight_sql_queries.clone(),
+            capabilities: self.capabilities.clone(),
+        }))
+    }
 }
@@ -0,0 +1,193 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Grpc server of the storage service used for testing the real rpcs.
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+use horaedbproto::{
+    common::ResponseHeader,
+    storage::{
+        sql_query_response::Output,
+        storage_service_server::{StorageService, StorageServiceServer},
+        PrometheusQueryRequest, PrometheusQueryResponse, PrometheusRemoteQueryRequest,
+        PrometheusRemoteQueryResponse, RouteRequest, RouteResponse, SqlQueryRequest,
+        SqlQueryResponse, WriteRequest, WriteResponse,
+    },
+};
+use tonic::{
+    codec::CompressionEncoding, metadata::MetadataMap, Request, Response, Status, Streaming,
+};
+
+/// The request id set in the metadata of each response.
+pub(crate) const MOCK_REQUEST_ID: &str = "mock-request";
+
+/// Decide the header of the response, or fail the rpc by the status, given the
+/// metadata of the request and the number of the requests handled before.
+pub(crate) type Respond =
+    Arc<dyn Fn(&MetadataMap, usize) -> Result<Option<ResponseHeader>, Status> + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct MockStorageService {
+    /// The metadata of each handled request.
+    pub requests: Arc<Mutex<Vec<MetadataMap>>>,
+    respond: Respond,
+}
+
+impl MockStorageService {
+    /// Serve on a local port, and return the address of it.
+    ///
+    /// The server accepting the gzip-compressed requests is stopped along with
+    /// the runtime.
+    pub async fn serve(
+        respond: impl Fn(&MetadataMap, usize) -> Result<Option<ResponseHeader>, Status>
+            + Send
+            + Sync
+            + 'static,
+    ) -> (String, Self) {
+        let service = Self {
+            requests: Arc::default(),
+            respond: Arc::new(respond),
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let incoming = futures::stream::unfold(listener, |listener| async move {
+            let conn = listener.accept().await.map(|(conn, _)| conn);
+            Some((conn, listener))
+        });
+        let server =
+            StorageServiceServer::new(service.clone()).accept_compressed(CompressionEncoding::Gzip);
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(server)
+                .serve_with_incoming(incoming),
+        );
+
+        (addr, service)
+    }
+
+    /// Record the request, and build the response of the decided header.
+    fn respond<T>(
+        &self,
+        metadata: &MetadataMap,
+        make_response: impl FnOnce(Option<ResponseHeader>) -> T,
+    ) -> Result<Response<T>, Status> {
+        let handled = {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(metadata.clone());
+            requests.len() - 1
+        };
+        let header = (self.respond)(metadata, handled)?;
+        let mut resp = Response::new(make_response(header));
+        resp.metadata_mut()
+            .insert("x-request-id", MOCK_REQUEST_ID.parse().unwrap());
+        Ok(resp)
+    }
+}
+
+#[async_trait]
+impl StorageService for MockStorageService {
+    type StreamSqlQueryStream = BoxStream<'static, Result<SqlQueryResponse, Status>>;
+
+    async fn route(
+        &self,
+        request: Request<RouteRequest>,
+    ) -> Result<Response<RouteResponse>, Status> {
+        self.respond(request.metadata(), |header| RouteResponse {
+            header,
+            routes: vec![],
+        })
+    }
+
+    /// Respond the number of the written rows as succeeded.
+    async fn write(
+        &self,
+        request: Request<WriteRequest>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        let success = request
+            .get_ref()
+            .table_requests
+            .iter()
+            .flat_map(|table_request| &table_request.entries)
+            .map(|entry| entry.field_groups.len() as u32)
+            .sum();
+        self.respond(request.metadata(), |header| WriteResponse {
+            header,
+            success,
+            failed: 0,
+        })
+    }
+
+    async fn stream_write(
+        &self,
+        request: Request<Streaming<WriteRequest>>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        let metadata = request.metadata().clone();
+        let mut reqs = request.into_inner();
+        while reqs.message().await?.is_some() {}
+        self.respond(&metadata, |header| WriteResponse {
+            header,
+            success: 0,
+            failed: 0,
+        })
+    }
+
+    /// Respond one affected row, and no output if the header is not ok.
+    async fn sql_query(
+        &self,
+        request: Request<SqlQueryRequest>,
+    ) -> Result<Response<SqlQueryResponse>, Status> {
+        self.respond(request.metadata(), |header| SqlQueryResponse {
+            output: match &header {
+                Some(header) if header.code != 200 => None,
+                _ => Some(Output::AffectedRows(1)),
+            },
+            header,
+        })
+    }
+
+    async fn stream_sql_query(
+        &self,
+        request: Request<SqlQueryRequest>,
+    ) -> Result<Response<Self::StreamSqlQueryStream>, Status> {
+        let resp = self.respond(request.metadata(), |header| SqlQueryResponse {
+            header,
+            output: Some(Output::AffectedRows(1)),
+        })?;
+        let metadata = resp.metadata().clone();
+        let resp = resp.into_inner();
+        let mut stream = Response::new(futures::stream::once(async { Ok(resp) }).boxed());
+        *stream.metadata_mut() = metadata;
+        Ok(stream)
+    }
+
+    async fn prom_remote_query(
+        &self,
+        _request: Request<PrometheusRemoteQueryRequest>,
+    ) -> Result<Response<PrometheusRemoteQueryResponse>, Status> {
+        Err(Status::unimplemented("prom_remote_query"))
+    }
+
+    async fn prom_query(
+        &self,
+        _request: Request<PrometheusQueryRequest>,
+    ) -> Result<Response<PrometheusQueryResponse>, Status> {
+        Err(Status::unimplemented("prom_query"))
+    }
+}
@@ -15,27 +15,108 @@
 // specific language governing permissions and limitations
 // under the License.
 
+mod audit;
+mod connection;
+mod message_size;
 mod mock_rpc_client;
+#[cfg(test)]
+mod mock_server;
+mod observer;
+mod resolver;
 mod rpc_client_impl;
+#[cfg(feature = "tracing")]
+mod trace;
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
+pub use audit::{OperationAuditor, OperationOutcome};
+pub use connection::ConnectionStats;
+use futures::{
+    future::{self, Either},
+    stream::BoxStream,
+    StreamExt,
+};
 use horaedbproto::storage::{
     RouteRequest as RouteRequestPb, RouteResponse as RouteResponsePb,
     SqlQueryRequest as QueryRequestPb, SqlQueryResponse as QueryResponsePb,
     WriteRequest as WriteRequestPb, WriteResponse as WriteResponsePb,
 };
-pub use mock_rpc_client::MockRpcClient;
+pub use message_size::{MessageSize, MessageSizeRecorder, RpcOperation};
+pub use mock_rpc_client::{MockRpcClient, MockRpcClientFactory};
+#[cfg(test)]
+pub(crate) use mock_server::{MockStorageService, MOCK_REQUEST_ID};
+pub(crate) use observer::ObservedRpcClientFactory;
+pub use observer::{ObservedRequest, Observer};
+pub use resolver::{Resolver, SystemResolver};
 pub use rpc_client_impl::RpcClientImplFactory;
+use tokio_util::sync::CancellationToken;
+use tonic::metadata::{KeyAndValueRef, MetadataMap};
+#[cfg(feature = "tracing")]
+pub use trace::{TraceContext, TraceContextInjector};
 
-use crate::errors::Result;
+use crate::{
+    errors::{Error, Result},
+    model::capabilities::ServerCapabilities,
+};
 
 /// Context for rpc request.
+///
+/// There are several timeouts applied to the different stages of a request:
+/// - `connect_timeout` bounds the connecting to the server, which only happens
+///   when the connection is not established yet. The smaller one of it and
+///   [`RpcConfig::connect_timeout`](crate::RpcConfig::connect_timeout) takes
+///   effect.
+/// - `timeout` is the grpc timeout of one rpc, and the default timeout in
+///   [`RpcConfig`](crate::RpcConfig) is used if not set.
+/// - `first_response_timeout` bounds the waiting for the first response from
+///   the server. The first response is the whole response for the unary rpc, so
+///   the smaller one of it and `timeout` takes effect.
+/// - `total_timeout` bounds the whole request, including the routing,
+///   connecting and all the rpcs issued for the request. Once it is exceeded,
+///   the request fails with [`Error::DeadlineExceeded`].
+/// - `deadline` bounds the whole request like `total_timeout`, and the timeout
+///   of each rpc is clamped to the time left until it, so the retries never
+///   outlive it. Once it has passed, the request fails with
+///   [`Error::DeadlineExceeded`] without issuing the rpc.
+///
+/// And `expected_rows` is a hint of the number of rows in the query result,
+/// which is used to pre-allocate the buffer for the decoded rows.
+///
+/// The route cache is bypassed if `bypass_route_cache` is set, that is, the
+/// tables are always routed by the server and the routes are not cached.
+///
+/// The `headers` are attached to the grpc metadata of each rpc, e.g. the tenant
+/// or the trace id, and the `authorization` header is reserved for the
+/// credentials set on the client.
+///
+/// The response is considered successful if its code is OK(200), or any one
+/// in `accepted_codes`. The response accepted by `accepted_codes` carries no
+/// result, and the sql query returns zero affected rows for it.
+///
+/// The request is aborted with [`Error::Cancelled`] once the
+/// `cancellation_token` is cancelled, independently of the timeouts. For the
+/// streamed sql query, only the establishing of the stream is aborted, and the
+/// stream can be just dropped afterwards.
 #[derive(Clone, Debug, Default)]
 pub struct RpcContext {
     pub database: Option<String>,
     pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub first_response_timeout: Option<Duration>,
+    pub total_timeout: Option<Duration>,
+    pub deadline: Option<Instant>,
+    pub expected_rows: Option<usize>,
+    pub accepted_codes: Vec<u32>,
+    pub bypass_route_cache: bool,
+    pub headers: HashMap<String, String>,
+    pub cancellation_token: Option<CancellationToken>,
 }
 
 impl RpcContext {
@@ -48,12 +129,180 @@ impl RpcContext {
         self.timeout = Some(timeout);
         self
     }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn first_response_timeout(mut self, timeout: Duration) -> Self {
+        self.first_response_timeout = Some(timeout);
+        self
+    }
+
+    pub fn total_timeout(mut self, timeout: Duration) -> Self {
+        self.total_timeout = Some(timeout);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn expected_rows(mut self, expected_rows: usize) -> Self {
+        self.expected_rows = Some(expected_rows);
+        self
+    }
+
+    pub fn bypass_route_cache(mut self, bypass_route_cache: bool) -> Self {
+        self.bypass_route_cache = bypass_route_cache;
+        self
+    }
+
+    /// Attach the header to each rpc issued for this call.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Accept the response of the `code` besides OK(200) for this call.
+    pub fn accept_code(mut self, code: u32) -> Self {
+        self.accepted_codes.push(code);
+        self
+    }
+
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
+    /// Race the `request` against the `cancellation_token` if any.
+    pub(crate) async fn cancellable<T>(
+        &self,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let token = match &self.cancellation_token {
+            Some(token) => token,
+            None => return request.await,
+        };
+
+        match future::select(pin!(token.cancelled()), pin!(request)).await {
+            Either::Left(_) => Err(Error::Cancelled),
+            Either::Right((res, _)) => res,
+        }
+    }
+
+    /// The grpc timeout of an unary rpc.
+    pub(crate) fn rpc_timeout(&self, default_timeout: Duration) -> Duration {
+        let timeout = self.timeout.unwrap_or(default_timeout);
+        let timeout = match self.first_response_timeout {
+            Some(first_response_timeout) => timeout.min(first_response_timeout),
+            None => timeout,
+        };
+        self.clamp_timeout(timeout)
+    }
+
+    /// The time left until the `deadline` if any.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Clamp the `timeout` to the time left until the `deadline`.
+    pub(crate) fn clamp_timeout(&self, timeout: Duration) -> Duration {
+        match self.remaining() {
+            Some(remaining) => timeout.min(remaining),
+            None => timeout,
+        }
+    }
+
+    pub(crate) fn check_deadline(&self) -> Result<()> {
+        match self.remaining() {
+            Some(remaining) if remaining.is_zero() => Err(Error::DeadlineExceeded),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The metadata of the response, i.e. the ascii headers and trailers, e.g. the
+/// `x-request-id` for correlating with the server logs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResponseMeta {
+    pub entries: HashMap<String, String>,
+}
+
+impl ResponseMeta {
+    /// Get the value of the header or trailer by its lowercase name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.get(name).map(String::as_str)
+    }
+}
+
+impl From<&MetadataMap> for ResponseMeta {
+    fn from(metadata: &MetadataMap) -> Self {
+        let entries = metadata
+            .iter()
+            .filter_map(|entry| match entry {
+                KeyAndValueRef::Ascii(key, value) => value
+                    .to_str()
+                    .ok()
+                    .map(|value| (key.as_str().to_string(), value.to_string())),
+                KeyAndValueRef::Binary(..) => None,
+            })
+            .collect();
+
+        Self { entries }
+    }
 }
+
 #[async_trait]
 pub trait RpcClient: Send + Sync {
     async fn sql_query(&self, ctx: &RpcContext, req: QueryRequestPb) -> Result<QueryResponsePb>;
+    /// Issue the sql query, and keep the metadata of the response.
+    async fn sql_query_with_meta(
+        &self,
+        ctx: &RpcContext,
+        req: QueryRequestPb,
+    ) -> Result<(QueryResponsePb, ResponseMeta)> {
+        let resp = self.sql_query(ctx, req).await?;
+        Ok((resp, ResponseMeta::default()))
+    }
+    /// Issue the sql query whose result is streamed in multiple responses, and
+    /// the status in the header of each response is checked.
+    async fn sql_query_stream(
+        &self,
+        ctx: &RpcContext,
+        req: QueryRequestPb,
+    ) -> Result<BoxStream<'static, Result<QueryResponsePb>>>;
     async fn write(&self, ctx: &RpcContext, req: WriteRequestPb) -> Result<WriteResponsePb>;
+    /// Issue the streamed write requests over one client-streaming rpc, and
+    /// the status in the header of the final response is checked.
+    ///
+    /// The requests are issued by [`RpcClient::write`] one by one by default.
+    async fn write_stream(
+        &self,
+        ctx: &RpcContext,
+        mut reqs: BoxStream<'static, WriteRequestPb>,
+    ) -> Result<WriteResponsePb> {
+        let mut merged = WriteResponsePb::default();
+        while let Some(req) = reqs.next().await {
+            let resp = self.write(ctx, req).await?;
+            merged.success += resp.success;
+            merged.failed += resp.failed;
+            if resp.header.is_some() {
+                merged.header = resp.header;
+            }
+        }
+
+        Ok(merged)
+    }
     async fn route(&self, ctx: &RpcContext, req: RouteRequestPb) -> Result<RouteResponsePb>;
+    /// Probe the optional features supported by the server.
+    ///
+    /// Each optional rpc is probed by issuing it with an empty request, i.e.
+    /// a `stream_sql_query` of an empty sql and a `stream_write` of no request.
+    async fn capabilities(&self, ctx: &RpcContext) -> Result<ServerCapabilities>;
 }
 
 #[async_trait]
@@ -63,4 +312,53 @@ pub trait RpcClientFactory: Send + Sync {
     /// It may fail because of invalid endpoint. Any caller calls this method
     /// should handle the potential error.
     async fn build(&self, endpoint: String) -> Result<Arc<dyn RpcClient>>;
+
+    /// Get the statistics of the connections to each endpoint.
+    fn connection_stats(&self) -> HashMap<String, ConnectionStats> {
+        HashMap::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::RpcContext;
+    use crate::Error;
+
+    #[test]
+    fn test_rpc_timeout() {
+        let default_timeout = Duration::from_secs(5);
+        let ctx = RpcContext::default();
+        assert_eq!(ctx.rpc_timeout(default_timeout), default_timeout);
+
+        let ctx = RpcContext::default().timeout(Duration::from_secs(10));
+        assert_eq!(ctx.rpc_timeout(default_timeout), Duration::from_secs(10));
+
+        let ctx = ctx.first_response_timeout(Duration::from_secs(1));
+        assert_eq!(ctx.rpc_timeout(default_timeout), Duration::from_secs(1));
+
+        let ctx = RpcContext::default().first_response_timeout(Duration::from_secs(8));
+        assert_eq!(ctx.rpc_timeout(default_timeout), default_timeout);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let default_timeout = Duration::from_secs(5);
+        let ctx = RpcContext::default().deadline(Instant::now() + Duration::from_secs(2));
+        let timeout = ctx.rpc_timeout(default_timeout);
+        assert!(timeout <= Duration::from_secs(2) && timeout > Duration::from_secs(1));
+        ctx.check_deadline().unwrap();
+
+        // The timeout shorter than the time left is kept.
+        let ctx = ctx.timeout(Duration::from_millis(500));
+        assert_eq!(ctx.rpc_timeout(default_timeout), Duration::from_millis(500));
+
+        let ctx = RpcContext::default().deadline(Instant::now() + Duration::from_secs(60));
+        assert_eq!(ctx.rpc_timeout(default_timeout), default_timeout);
+
+        let ctx = RpcContext::default().deadline(Instant::now() - Duration::from_secs(1));
+        assert_eq!(ctx.rpc_timeout(default_timeout), Duration::ZERO);
+        assert!(matches!(ctx.check_deadline(), Err(Error::DeadlineExceeded)));
+    }
 }
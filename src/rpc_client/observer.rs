@@ -0,0 +1,312 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Observe the rpcs issued to each endpoint.
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use horaedbproto::storage::{
+    RouteRequest as RouteRequestPb, RouteResponse as RouteResponsePb,
+    SqlQueryRequest as QueryRequestPb, SqlQueryResponse as QueryResponsePb,
+    WriteRequest as WriteRequestPb, WriteResponse as WriteResponsePb,
+};
+
+use crate::{
+    errors::{Error, Result},
+    model::capabilities::ServerCapabilities,
+    rpc_client::{
+        ConnectionStats, OperationOutcome, ResponseMeta, RpcClient, RpcClientFactory, RpcContext,
+        RpcOperation,
+    },
+};
+
+/// The rpc to observe.
+#[derive(Clone, Copy, Debug)]
+pub struct ObservedRequest<'a> {
+    pub operation: RpcOperation,
+    /// The endpoint which the rpc is sent to, in the form: `{ip_addr}:{port}`.
+    pub endpoint: &'a str,
+    pub database: Option<&'a str>,
+}
+
+/// Observer notified at the start and the end of each rpc, which can be
+/// registered by [`Builder::observer`](crate::Builder::observer), e.g. for
+/// exporting the metrics.
+///
+/// The failure tells the error code, e.g. [`Error::Server`] or [`Error::Rpc`],
+/// and the rpc dropped before completion, e.g. by the `total_timeout`, ends
+/// with [`Error::Cancelled`]. It is called in the path of the rpc, so it should
+/// be cheap.
+pub trait Observer: Debug + Send + Sync {
+    fn on_request_start(&self, request: &ObservedRequest<'_>);
+
+    fn on_request_end(
+        &self,
+        request: &ObservedRequest<'_>,
+        outcome: OperationOutcome<'_>,
+        elapsed: Duration,
+    );
+}
+
+/// Factory wrapping the built clients with the [`Observer`].
+pub(crate) struct ObservedRpcClientFactory<F: RpcClientFactory + ?Sized> {
+    factory: Arc<F>,
+    observer: Arc<dyn Observer>,
+}
+
+impl<F: RpcClientFactory + ?Sized> ObservedRpcClientFactory<F> {
+    pub fn new(factory: Arc<F>, observer: Arc<dyn Observer>) -> Self {
+        Self { factory, observer }
+    }
+}
+
+#[async_trait]
+impl<F: RpcClientFactory + ?Sized> RpcClientFactory for ObservedRpcClientFactory<F> {
+    async fn build(&self, endpoint: String) -> Result<Arc<dyn RpcClient>> {
+        let client = self.factory.build(endpoint.clone()).await?;
+        Ok(Arc::new(ObservedRpcClient {
+            client,
+            endpoint,
+            observer: self.observer.clone(),
+        }))
+    }
+
+    fn connection_stats(&self) -> HashMap<String, ConnectionStats> {
+        self.factory.connection_stats()
+    }
+}
+
+struct ObservedRpcClient {
+    client: Arc<dyn RpcClient>,
+    endpoint: String,
+    observer: Arc<dyn Observer>,
+}
+
+impl ObservedRpcClient {
+    async fn observe<T>(
+        &self,
+        operation: RpcOperation,
+        ctx: &RpcContext,
+        call: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let mut guard = ObserveGuard {
+            observer: self.observer.as_ref(),
+            request: ObservedRequest {
+                operation,
+                endpoint: &self.endpoint,
+                database: ctx.database.as_deref(),
+            },
+            start: Instant::now(),
+            finished: false,
+        };
+        guard.observer.on_request_start(&guard.request);
+
+        let result = call.await;
+        let outcome = match &result {
+            Ok(_) => OperationOutcome::Success,
+            Err(e) => OperationOutcome::Failure(e),
+        };
+        guard.finish(outcome);
+
+        result
+    }
+}
+
+/// Make sure the end of the rpc is observed even if it is dropped.
+struct ObserveGuard<'a> {
+    observer: &'a dyn Observer,
+    request: ObservedRequest<'a>,
+    start: Instant,
+    finished: bool,
+}
+
+impl ObserveGuard<'_> {
+    fn finish(&mut self, outcome: OperationOutcome<'_>) {
+        self.finished = true;
+        self.observer
+            .on_request_end(&self.request, outcome, self.start.elapsed());
+    }
+}
+
+impl Drop for ObserveGuard<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish(OperationOutcome::Failure(&Error::Cancelled));
+        }
+    }
+}
+
+#[async_trait]
+impl RpcClient for ObservedRpcClient {
+    async fn sql_query(&self, ctx: &RpcContext, req: QueryRequestPb) -> Result<QueryResponsePb> {
+        self.observe(RpcOperation::SqlQuery, ctx, self.client.sql_query(ctx, req))
+            .await
+    }
+
+    async fn sql_query_with_meta(
+        &self,
+        ctx: &RpcContext,
+        req: QueryRequestPb,
+    ) -> Result<(QueryResponsePb, ResponseMeta)> {
+        self.observe(
+            RpcOperation::SqlQuery,
+            ctx,
+            self.client.sql_query_with_meta(ctx, req),
+        )
+        .await
+    }
+
+    /// Only the establishing of the stream is observed.
+    async fn sql_query_stream(
+        &self,
+        ctx: &RpcContext,
+        req: QueryRequestPb,
+    ) -> Result<BoxStream<'static, Result<QueryResponsePb>>> {
+        self.observe(
+            RpcOperation::SqlQuery,
+            ctx,
+            self.client.sql_query_stream(ctx, req),
+        )
+        .await
+    }
+
+    async fn write(&self, ctx: &RpcContext, req: WriteRequestPb) -> Result<WriteResponsePb> {
+        self.observe(RpcOperation::Write, ctx, self.client.write(ctx, req))
+            .await
+    }
+
+    async fn write_stream(
+        &self,
+        ctx: &RpcContext,
+        reqs: BoxStream<'static, WriteRequestPb>,
+    ) -> Result<WriteResponsePb> {
+        self.observe(
+            RpcOperation::Write,
+            ctx,
+            self.client.write_stream(ctx, reqs),
+        )
+        .await
+    }
+
+    async fn route(&self, ctx: &RpcContext, req: RouteRequestPb) -> Result<RouteResponsePb> {
+        self.observe(RpcOperation::Route, ctx, self.client.route(ctx, req))
+            .await
+    }
+
+    async fn capabilities(&self, ctx: &RpcContext) -> Result<ServerCapabilities> {
+        self.client.capabilities(ctx).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{ObservedRequest, Observer};
+    use crate::{
+        model::route::Endpoint,
+        rpc_client::{MockRpcClientFactory, OperationOutcome, RpcContext, RpcOperation},
+        Builder, Mode, SqlQueryRequest,
+    };
+
+    #[derive(Debug, Default)]
+    struct CountingObserver {
+        started: Mutex<Vec<(RpcOperation, String)>>,
+        ended: Mutex<Vec<(RpcOperation, String, bool)>>,
+    }
+
+    impl Observer for CountingObserver {
+        fn on_request_start(&self, request: &ObservedRequest<'_>) {
+            self.started
+                .lock()
+                .unwrap()
+                .push((request.operation, request.endpoint.to_string()));
+        }
+
+        fn on_request_end(
+            &self,
+            request: &ObservedRequest<'_>,
+            outcome: OperationOutcome<'_>,
+            _elapsed: Duration,
+        ) {
+            self.ended.lock().unwrap().push((
+                request.operation,
+                request.endpoint.to_string(),
+                matches!(outcome, OperationOutcome::Success),
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observe_requests() {
+        let factory = Arc::new(MockRpcClientFactory {
+            sql_query_delay: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+        factory.route_table.insert(
+            "table1".to_string(),
+            Endpoint::new("192.168.0.1".to_string(), 11),
+        );
+        let observer = Arc::new(CountingObserver::default());
+        let client = Builder::new("192.168.0.2:12".to_string(), Mode::Direct)
+            .default_database("public")
+            .observer(observer.clone())
+            .build_with_factory(factory);
+        let req = SqlQueryRequest {
+            tables: vec!["table1".to_string()],
+            sql: "select * from table1".to_string(),
+        };
+
+        client
+            .sql_query(&RpcContext::default(), &req)
+            .await
+            .unwrap();
+        // The end of the timed out request is observed too.
+        let ctx = RpcContext::default().total_timeout(Duration::from_millis(10));
+        client.sql_query(&ctx, &req).await.unwrap_err();
+
+        let started = observer.started.lock().unwrap().clone();
+        assert_eq!(
+            started,
+            vec![
+                (RpcOperation::Route, "192.168.0.2:12".to_string()),
+                (RpcOperation::SqlQuery, "192.168.0.1:11".to_string()),
+                (RpcOperation::SqlQuery, "192.168.0.1:11".to_string()),
+            ]
+        );
+        let ended = observer.ended.lock().unwrap().clone();
+        assert_eq!(
+            ended,
+            vec![
+                (RpcOperation::Route, "192.168.0.2:12".to_string(), true),
+                (RpcOperation::SqlQuery, "192.168.0.1:11".to_string(), true),
+                (RpcOperation::SqlQuery, "192.168.0.1:11".to_string(), false),
+            ]
+        );
+    }
+}
@@ -15,38 +15,151 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
 use async_trait::async_trait;
 use base64::{prelude::BASE64_STANDARD, Engine};
+use dashmap::DashMap;
+use futures::{
+    future::{join_all, select_ok},
+    stream::BoxStream,
+    FutureExt, Stream, StreamExt,
+};
 use horaedbproto::{
     common::ResponseHeader,
     storage::{
-        storage_service_client::StorageServiceClient, RouteRequest as RouteRequestPb,
-        RouteResponse as RouteResponsePb, SqlQueryRequest, SqlQueryResponse,
-        WriteRequest as WriteRequestPb, WriteResponse as WriteResponsePb,
+        sql_query_response::Output, storage_service_client::StorageServiceClient, RequestContext,
+        RouteRequest as RouteRequestPb, RouteResponse as RouteResponsePb, SqlQueryRequest,
+        SqlQueryResponse, WriteRequest as WriteRequestPb, WriteResponse as WriteResponsePb,
     },
 };
+use hyper::client::HttpConnector;
+use prost::Message;
 use tonic::{
-    metadata::{Ascii, MetadataValue},
-    transport::{Channel, Endpoint},
-    Request,
+    body::BoxBody,
+    client::GrpcService,
+    codegen::{Body, Bytes, StdError},
+    metadata::{Ascii, AsciiMetadataKey, AsciiMetadataValue, MetadataMap, MetadataValue},
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity},
+    Code, Request, Response, Status,
 };
 
+#[cfg(feature = "tracing")]
+use crate::rpc_client::{trace::RpcTracer, TraceContextInjector};
 use crate::{
-    config::RpcConfig,
-    errors::{Error, Result, ServerError},
-    rpc_client::{RpcClient, RpcClientFactory, RpcContext},
-    util::is_ok,
+    config::{
+        Compression, KeepAlive, KeepAliveOverride, RpcConfig, StatusSource, StatusTrailerKeys,
+        TimeoutScaling, TlsConfig,
+    },
+    errors::{ConnectErrorKind, Error, Result, ServerError},
+    model::{capabilities::ServerCapabilities, route::Endpoint as RouteEndpoint},
+    rpc_client::{
+        connection::{ConnectionCounter, ConnectionStats, CountingConnector},
+        MessageSize, MessageSizeRecorder, OperationAuditor, OperationOutcome, Resolver,
+        ResponseMeta, RpcClient, RpcClientFactory, RpcContext, RpcOperation, SystemResolver,
+    },
+    util::{is_ok, StatusCode},
     Authorization,
 };
 
+/// The key of the metadata carrying the credentials.
+const AUTHORIZATION_KEY: &str = "authorization";
+/// The key of the metadata identifying the client.
+const CLIENT_VERSION_KEY: &str = "x-client-version";
+/// The name and the version of this crate.
+const CLIENT_VERSION: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// The stream which is `Sync` by being exclusively polled, otherwise the
+/// future of the client-streaming rpc can't be proved `Send`.
+struct SyncStream<T>(std::sync::Mutex<BoxStream<'static, T>>);
+
+impl<T> SyncStream<T> {
+    fn new(stream: BoxStream<'static, T>) -> Self {
+        Self(std::sync::Mutex::new(stream))
+    }
+}
+
+impl<T> Stream for SyncStream<T> {
+    type Item = T;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<T>> {
+        self.get_mut().0.get_mut().unwrap().poll_next_unpin(cx)
+    }
+}
+
+/// The value of the `authorization` metadata, and the token of the provider is
+/// fetched on each call of it, i.e. once the client is built and again each
+/// time the token is refreshed, rather than on each rpc.
+fn authorization_metadata(auth: &Authorization) -> Result<MetadataValue<Ascii>> {
+    let value = match auth {
+        Authorization::Basic { username, password } => {
+            let mut buf = Vec::with_capacity(username.len() + password.len() + 1);
+            buf.extend_from_slice(username.as_bytes());
+            buf.push(b':');
+            buf.extend_from_slice(password.as_bytes());
+            format!("Basic {}", BASE64_STANDARD.encode(&buf))
+        }
+        Authorization::Bearer { token } => format!("Bearer {token}"),
+        Authorization::BearerProvider(provider) => format!("Bearer {}", provider()),
+    };
+
+    Ok(value.parse().context("invalid grpc metadata")?)
+}
+
+/// The value of the `x-client-version` metadata, prefixed by the `user_agent`
+/// if any.
+fn client_version_metadata(user_agent: Option<&str>) -> Result<MetadataValue<Ascii>> {
+    let value = match user_agent {
+        Some(user_agent) => format!("{user_agent} {CLIENT_VERSION}"),
+        None => CLIENT_VERSION.to_string(),
+    };
+
+    Ok(value.parse().context("invalid user agent")?)
+}
+
+/// The custom headers in the [`RpcContext`] parsed into the grpc metadata.
+type CustomMetadata = Vec<(AsciiMetadataKey, AsciiMetadataValue)>;
+
+#[cfg(feature = "tracing")]
+type RpcSpan = tracing::Span;
+/// No span is created without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+struct RpcSpan;
+
 struct RpcClientImpl {
-    channel: Channel,
+    /// The pooled channels to the endpoint, which are used in round-robin.
+    channels: Vec<Channel>,
+    next_channel: AtomicUsize,
     default_read_timeout: Duration,
     default_write_timeout: Duration,
-    metadata: Option<MetadataValue<Ascii>>,
+    sql_query_timeout_scaling: Option<TimeoutScaling>,
+    /// The authorization metadata, which is replaced after the token is
+    /// refreshed.
+    metadata: RwLock<Option<MetadataValue<Ascii>>>,
+    /// The authorization providing the refreshed token.
+    refreshable_authorization: Option<Authorization>,
+    message_size_recorder: Option<Arc<dyn MessageSizeRecorder>>,
+    status_source: StatusSource,
+    status_trailer_keys: StatusTrailerKeys,
+    operation_auditor: Option<Arc<dyn OperationAuditor>>,
+    compression: Option<Compression>,
+    compression_min_size: Option<usize>,
+    client_version: MetadataValue<Ascii>,
+    #[cfg(feature = "tracing")]
+    tracer: Option<RpcTracer>,
 }
 
 impl RpcClientImpl {
@@ -54,99 +167,784 @@ impl RpcClientImpl {
         channel: Channel,
         default_read_timeout: Duration,
         default_write_timeout: Duration,
+        sql_query_timeout_scaling: Option<TimeoutScaling>,
         metadata: Option<MetadataValue<Ascii>>,
+        message_size_recorder: Option<Arc<dyn MessageSizeRecorder>>,
+        status_source: StatusSource,
     ) -> Self {
         Self {
-            channel,
+            channels: vec![channel],
+            next_channel: AtomicUsize::new(0),
             default_read_timeout,
             default_write_timeout,
-            metadata,
+            sql_query_timeout_scaling,
+            metadata: RwLock::new(metadata),
+            refreshable_authorization: None,
+            message_size_recorder,
+            status_source,
+            status_trailer_keys: StatusTrailerKeys::default(),
+            operation_auditor: None,
+            compression: None,
+            compression_min_size: None,
+            client_version: MetadataValue::from_static(CLIENT_VERSION),
+            #[cfg(feature = "tracing")]
+            tracer: None,
         }
     }
 
-    fn check_status(header: ResponseHeader) -> Result<()> {
-        if !is_ok(header.code) {
-            return Err(Error::Server(ServerError {
-                code: header.code,
-                msg: header.error,
-            }));
+    /// Pool the `channels` along with the one the client is created with.
+    fn with_pooled_channels(mut self, channels: Vec<Channel>) -> Self {
+        self.channels.extend(channels);
+        self
+    }
+
+    fn with_client_version(mut self, client_version: MetadataValue<Ascii>) -> Self {
+        self.client_version = client_version;
+        self
+    }
+
+    #[cfg(feature = "tracing")]
+    fn with_tracer(mut self, tracer: RpcTracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// The span of the rpc, carrying the endpoint, the database and the tables.
+    #[cfg(feature = "tracing")]
+    fn rpc_span<'a>(
+        &self,
+        operation: RpcOperation,
+        ctx: &RpcContext,
+        tables: impl Iterator<Item = &'a str>,
+    ) -> RpcSpan {
+        match &self.tracer {
+            Some(tracer) => tracer.span(operation, ctx, tables),
+            None => tracing::Span::none(),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn rpc_span<'a>(
+        &self,
+        _operation: RpcOperation,
+        _ctx: &RpcContext,
+        _tables: impl Iterator<Item = &'a str>,
+    ) -> RpcSpan {
+        RpcSpan
+    }
+
+    #[cfg(feature = "tracing")]
+    async fn traced<T>(span: RpcSpan, call: impl Future<Output = T>) -> T {
+        tracing::Instrument::instrument(call, span).await
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn traced<F: Future>(_span: RpcSpan, call: F) -> F {
+        call
+    }
+
+    fn with_status_trailer_keys(mut self, status_trailer_keys: StatusTrailerKeys) -> Self {
+        self.status_trailer_keys = status_trailer_keys;
+        self
+    }
+
+    fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    fn with_compression_min_size(mut self, compression_min_size: Option<usize>) -> Self {
+        self.compression_min_size = compression_min_size;
+        self
+    }
+
+    /// The client to send the request encoded in `request_bytes`, which is
+    /// unknown for the streamed requests.
+    fn storage_client(&self, request_bytes: Option<usize>) -> StorageServiceClient<Channel> {
+        let idx = self.next_channel.fetch_add(1, Ordering::Relaxed) % self.channels.len();
+        Self::compress(
+            StorageServiceClient::new(self.channels[idx].clone()),
+            self.compression,
+            self.should_send_compressed(request_bytes),
+        )
+    }
+
+    /// Whether to compress the request encoded in `request_bytes`.
+    fn should_send_compressed(&self, request_bytes: Option<usize>) -> bool {
+        match (self.compression_min_size, request_bytes) {
+            (Some(min_size), Some(request_bytes)) => request_bytes > min_size,
+            _ => true,
+        }
+    }
+
+    fn compress<T>(
+        client: StorageServiceClient<T>,
+        compression: Option<Compression>,
+        send_compressed: bool,
+    ) -> StorageServiceClient<T>
+    where
+        T: GrpcService<BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        match compression {
+            Some(compression) if send_compressed => client
+                .send_compressed(compression.encoding())
+                .accept_compressed(compression.encoding()),
+            Some(compression) => client.accept_compressed(compression.encoding()),
+            None => client,
+        }
+    }
+
+    /// Refresh the token by the `authorization` once it is rejected, and only
+    /// the [`Authorization::BearerProvider`] is refreshable.
+    fn with_refreshable_authorization(mut self, authorization: Option<Authorization>) -> Self {
+        self.refreshable_authorization =
+            authorization.filter(|auth| matches!(auth, Authorization::BearerProvider(_)));
+        self
+    }
+
+    /// Issue the rpc, and retry it once with the refreshed token if the token
+    /// is rejected.
+    ///
+    /// The token is refreshed once for the concurrent rpcs rejected by it.
+    ///
+    /// [`Error::Auth`] is returned if the refreshed token is rejected too.
+    async fn with_auth_refresh<Req, T, Fut>(&self, req: Req, call: impl Fn(Req) -> Fut) -> Result<T>
+    where
+        Req: Clone,
+        Fut: Future<Output = Result<T>>,
+    {
+        let auth = match &self.refreshable_authorization {
+            Some(auth) => auth,
+            None => return call(req).await,
+        };
+
+        let rejected = self.metadata.read().unwrap().clone();
+        match call(req.clone()).await {
+            Err(e) if Self::is_unauthenticated(&e) => {
+                // The concurrent rpcs rejected by the same token refresh it only
+                // once, and the others reuse the refreshed token.
+                let mut metadata = self.metadata.write().unwrap();
+                if *metadata == rejected {
+                    *metadata = Some(authorization_metadata(auth)?);
+                }
+            }
+            result => return result,
+        }
+        match call(req).await {
+            Err(e) if Self::is_unauthenticated(&e) => Err(Error::Auth(e.to_string())),
+            result => result,
+        }
+    }
+
+    /// Tell whether the token is rejected, by either the grpc status or the
+    /// code in the response header.
+    fn is_unauthenticated(err: &Error) -> bool {
+        match err {
+            Error::Unauthenticated(_) => true,
+            Error::WithMeta { source, .. } => Self::is_unauthenticated(source),
+            _ => false,
+        }
+    }
+
+    /// Convert the failed grpc status, and the authentication failures are
+    /// distinguished from the others.
+    fn rpc_error(status: Status) -> Error {
+        match status.code() {
+            Code::Unauthenticated => Error::Unauthenticated(status.message().to_string()),
+            Code::PermissionDenied => Error::PermissionDenied(status.message().to_string()),
+            _ => Error::Rpc(status),
+        }
+    }
+
+    fn with_operation_auditor(mut self, auditor: Option<Arc<dyn OperationAuditor>>) -> Self {
+        self.operation_auditor = auditor;
+        self
+    }
+
+    /// Issue the rpc, and notify the auditor after it completes if the auditor
+    /// is registered.
+    async fn audit<T>(
+        &self,
+        operation: RpcOperation,
+        ctx: &RpcContext,
+        call: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        // The cancelled call is audited as a failure too, and so is the call
+        // which is not issued because the deadline has passed.
+        let call = ctx.cancellable(async {
+            ctx.check_deadline()?;
+            call.await
+        });
+        let auditor = match &self.operation_auditor {
+            Some(auditor) => auditor,
+            None => return call.await,
+        };
+
+        let start = Instant::now();
+        let result = call.await;
+        let outcome = match &result {
+            Ok(_) => OperationOutcome::Success,
+            Err(e) => OperationOutcome::Failure(e),
+        };
+        auditor.on_operation_complete(operation, ctx.database.as_deref(), outcome, start.elapsed());
+
+        result
+    }
+
+    /// Issue the rpc, and record the sizes of the messages if the recorder is
+    /// registered.
+    async fn record_message_size<Req, Resp, Fut>(
+        &self,
+        operation: RpcOperation,
+        req: Req,
+        call: impl FnOnce(Req) -> Fut,
+    ) -> std::result::Result<Response<Resp>, Status>
+    where
+        Req: Message,
+        Resp: Message,
+        Fut: Future<Output = std::result::Result<Response<Resp>, Status>>,
+    {
+        let recorder = match &self.message_size_recorder {
+            Some(recorder) => recorder,
+            None => return call(req).await,
+        };
+
+        let request_bytes = req.encoded_len();
+        let resp = call(req).await;
+        recorder.record(&MessageSize {
+            operation,
+            request_bytes,
+            response_bytes: resp.as_ref().ok().map(|resp| resp.get_ref().encoded_len()),
+        });
+
+        resp
+    }
+
+    fn check_status(ctx: &RpcContext, header: ResponseHeader) -> Result<()> {
+        if !is_ok(header.code) && !ctx.accepted_codes.contains(&header.code) {
+            let err = if header.code == StatusCode::Unauthorized.as_u32() {
+                Error::Unauthenticated(header.error)
+            } else if header.code == StatusCode::Forbidden.as_u32() {
+                Error::PermissionDenied(header.error)
+            } else {
+                Error::Server(ServerError {
+                    code: header.code,
+                    msg: header.error,
+                })
+            };
+            return Err(err);
         }
 
         Ok(())
     }
 
-    fn make_request<T>(&self, ctx: &RpcContext, req: T, default_timeout: Duration) -> Request<T> {
-        let timeout = ctx.timeout.unwrap_or(default_timeout);
+    fn check_write_status(
+        &self,
+        ctx: &RpcContext,
+        mut resp: Response<WriteResponsePb>,
+    ) -> Result<WriteResponsePb> {
+        let header = self.select_status(resp.get_mut().header.take(), resp.metadata())?;
+        let mut resp = resp.into_inner();
+
+        if let Some(header) = header {
+            Self::check_status(ctx, header.clone())?;
+            // Keep the header for the error message of the failed rows.
+            resp.header = Some(header);
+        }
+
+        Ok(resp)
+    }
+
+    fn check_sql_query_status(
+        ctx: &RpcContext,
+        header: Option<ResponseHeader>,
+        mut resp: SqlQueryResponse,
+    ) -> Result<SqlQueryResponse> {
+        if let Some(header) = header {
+            let code = header.code;
+            Self::check_status(ctx, header.clone())?;
+            // The response of the accepted failure carries no output.
+            if !is_ok(code) && resp.output.is_none() {
+                resp.output = Some(Output::AffectedRows(0));
+            }
+            // Keep the header for the warning message of the succeeded response.
+            resp.header = Some(header);
+        }
+
+        Ok(resp)
+    }
+
+    /// Check the status in the header of each streamed response.
+    fn check_stream_status<S>(
+        ctx: RpcContext,
+        stream: S,
+    ) -> BoxStream<'static, Result<SqlQueryResponse>>
+    where
+        S: Stream<Item = std::result::Result<SqlQueryResponse, Status>> + Send + 'static,
+    {
+        stream
+            .map(move |resp| {
+                let mut resp = resp.map_err(Self::rpc_error)?;
+                let header = resp.header.take();
+                Self::check_sql_query_status(&ctx, header, resp)
+            })
+            .boxed()
+    }
+
+    /// Pick the status to check from the header and the trailers according to
+    /// the [`StatusSource`].
+    fn select_status(
+        &self,
+        header: Option<ResponseHeader>,
+        trailers: &MetadataMap,
+    ) -> Result<Option<ResponseHeader>> {
+        let trailer = match self.status_source {
+            StatusSource::Header => return Ok(header),
+            StatusSource::Trailer | StatusSource::Both => self.trailer_status(trailers)?,
+        };
+
+        let status = match (self.status_source, header, trailer) {
+            (StatusSource::Trailer, header, None) => header,
+            (StatusSource::Trailer, _, trailer) => trailer,
+            (_, Some(header), _) if !is_ok(header.code) => Some(header),
+            (_, header, None) => header,
+            (_, _, trailer) => trailer,
+        };
+
+        Ok(status)
+    }
+
+    fn trailer_status(&self, trailers: &MetadataMap) -> Result<Option<ResponseHeader>> {
+        let keys = &self.status_trailer_keys;
+        let code = match trailers.get(keys.code.as_str()) {
+            Some(code) => code,
+            None => return Ok(None),
+        };
+        let code = code
+            .to_str()
+            .ok()
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| Error::MalformedResponse {
+                reason: format!("invalid {} in trailers, value:{code:?}", keys.code),
+            })?;
+        let error = trailers
+            .get(keys.error.as_str())
+            .and_then(|error| error.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        Ok(Some(ResponseHeader { code, error }))
+    }
+
+    /// Tell whether the probed rpc is supported by the server.
+    ///
+    /// Any response, even the failed one, means the rpc is implemented except
+    /// the transport errors and the rejected credentials, which are returned
+    /// as errors because the probe tells nothing.
+    fn is_supported<T>(probe_result: std::result::Result<T, Status>) -> Result<bool> {
+        match probe_result {
+            Ok(_) => Ok(true),
+            Err(status) => match status.code() {
+                Code::Unimplemented => Ok(false),
+                Code::Unavailable
+                | Code::DeadlineExceeded
+                | Code::Cancelled
+                | Code::Unauthenticated
+                | Code::PermissionDenied => Err(Self::rpc_error(status)),
+                _ => Ok(true),
+            },
+        }
+    }
+
+    /// Probe the `stream_sql_query` with an empty sql, and the returned stream
+    /// is just dropped.
+    async fn probe_stream_sql_query(&self, ctx: &RpcContext) -> Result<bool> {
+        let custom_metadata = Self::custom_metadata(ctx)?;
+        let client = self.storage_client(None);
+
+        self.with_auth_refresh((), |()| {
+            let mut client = client.clone();
+            let custom_metadata = &custom_metadata;
+            async move {
+                let req = SqlQueryRequest {
+                    context: Some(RequestContext {
+                        database: ctx.database.clone().unwrap(),
+                    }),
+                    tables: vec![],
+                    sql: String::new(),
+                };
+                let req = self.make_query_request(ctx, custom_metadata, req);
+                Self::is_supported(client.stream_sql_query(req).await)
+            }
+        })
+        .await
+    }
+
+    /// Probe the `stream_write` with an empty stream, so nothing will be
+    /// written.
+    async fn probe_stream_write(&self, ctx: &RpcContext) -> Result<bool> {
+        let custom_metadata = Self::custom_metadata(ctx)?;
+        let client = self.storage_client(None);
+
+        self.with_auth_refresh((), |()| {
+            let mut client = client.clone();
+            let custom_metadata = &custom_metadata;
+            async move {
+                let reqs = futures::stream::empty::<WriteRequestPb>();
+                let req = self.make_write_request(ctx, custom_metadata, reqs);
+                Self::is_supported(client.stream_write(req).await)
+            }
+        })
+        .await
+    }
+
+    /// Parse the custom headers in the `ctx`, and the authorization header is
+    /// reserved for the credentials set on the client.
+    fn custom_metadata(ctx: &RpcContext) -> Result<CustomMetadata> {
+        ctx.headers
+            .iter()
+            .map(|(name, value)| {
+                if name.eq_ignore_ascii_case(AUTHORIZATION_KEY) {
+                    return Err(Error::Client(format!(
+                        "Header:{name} is reserved for the authorization"
+                    )));
+                }
+                let key = AsciiMetadataKey::from_bytes(name.as_bytes())
+                    .map_err(|e| Error::Client(format!("Invalid header name:{name}, err:{e}")))?;
+                let value = value.parse::<AsciiMetadataValue>().map_err(|e| {
+                    Error::Client(format!("Invalid value of header:{name}, err:{e}"))
+                })?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn make_request<T>(
+        &self,
+        ctx: &RpcContext,
+        custom_metadata: &CustomMetadata,
+        req: T,
+        default_timeout: Duration,
+    ) -> Request<T> {
+        let timeout = ctx.rpc_timeout(default_timeout);
         let mut req = Request::new(req);
         req.set_timeout(timeout);
-        if let Some(md) = &self.metadata {
-            req.metadata_mut().insert("authorization", md.clone());
+        req.metadata_mut()
+            .insert(CLIENT_VERSION_KEY, self.client_version.clone());
+        for (key, value) in custom_metadata {
+            req.metadata_mut().insert(key.clone(), value.clone());
+        }
+        #[cfg(feature = "tracing")]
+        if let Some(tracer) = &self.tracer {
+            tracer.inject(req.metadata_mut());
+        }
+        if let Some(md) = self.metadata.read().unwrap().as_ref() {
+            req.metadata_mut().insert(AUTHORIZATION_KEY, md.clone());
         }
         req
     }
 
-    fn make_query_request<T>(&self, ctx: &RpcContext, req: T) -> Request<T> {
-        self.make_request(ctx, req, self.default_read_timeout)
+    fn make_query_request<T>(
+        &self,
+        ctx: &RpcContext,
+        custom_metadata: &CustomMetadata,
+        req: T,
+    ) -> Request<T> {
+        let default_timeout = match (&self.sql_query_timeout_scaling, ctx.expected_rows) {
+            (Some(scaling), Some(expected_rows)) => {
+                scaling.scale(self.default_read_timeout, expected_rows)
+            }
+            _ => self.default_read_timeout,
+        };
+        self.make_request(ctx, custom_metadata, req, default_timeout)
     }
 
-    fn make_write_request<T>(&self, ctx: &RpcContext, req: T) -> Request<T> {
-        self.make_request(ctx, req, self.default_write_timeout)
+    fn make_write_request<T>(
+        &self,
+        ctx: &RpcContext,
+        custom_metadata: &CustomMetadata,
+        req: T,
+    ) -> Request<T> {
+        self.make_request(ctx, custom_metadata, req, self.default_write_timeout)
     }
 }
 
 #[async_trait]
 impl RpcClient for RpcClientImpl {
     async fn sql_query(&self, ctx: &RpcContext, req: SqlQueryRequest) -> Result<SqlQueryResponse> {
-        let mut client = StorageServiceClient::<Channel>::new(self.channel.clone());
-
-        let resp = client
-            .sql_query(self.make_query_request(ctx, req))
+        self.sql_query_with_meta(ctx, req)
             .await
-            .map_err(Error::Rpc)?;
-        let mut resp = resp.into_inner();
+            .map(|(resp, _)| resp)
+            .map_err(Error::without_meta)
+    }
 
-        if let Some(header) = resp.header.take() {
-            Self::check_status(header)?;
-        }
+    async fn sql_query_with_meta(
+        &self,
+        ctx: &RpcContext,
+        req: SqlQueryRequest,
+    ) -> Result<(SqlQueryResponse, ResponseMeta)> {
+        Self::traced(
+            self.rpc_span(
+                RpcOperation::SqlQuery,
+                ctx,
+                req.tables.iter().map(String::as_str),
+            ),
+            self.audit(RpcOperation::SqlQuery, ctx, async move {
+                let custom_metadata = Self::custom_metadata(ctx)?;
+                let client = self.storage_client(Some(req.encoded_len()));
 
-        Ok(resp)
+                // The status is checked in the refreshed call too, so the token
+                // rejected by the code in the header is refreshed as well.
+                self.with_auth_refresh(req, |req| {
+                    let mut client = client.clone();
+                    let custom_metadata = &custom_metadata;
+                    async move {
+                        let mut resp = self
+                            .record_message_size(RpcOperation::SqlQuery, req, |req| async {
+                                client
+                                    .sql_query(self.make_query_request(ctx, custom_metadata, req))
+                                    .await
+                            })
+                            .await
+                            .map_err(Self::rpc_error)?;
+                        // The metadata is kept for the response failed by its status too.
+                        let meta = ResponseMeta::from(resp.metadata());
+                        let with_meta = |source| Error::WithMeta {
+                            meta: meta.clone(),
+                            source: Box::new(source),
+                        };
+                        let header = self
+                            .select_status(resp.get_mut().header.take(), resp.metadata())
+                            .map_err(with_meta)?;
+                        let resp = Self::check_sql_query_status(ctx, header, resp.into_inner())
+                            .map_err(with_meta)?;
+                        Ok((resp, meta))
+                    }
+                })
+                .await
+            }),
+        )
+        .await
+    }
+
+    async fn sql_query_stream(
+        &self,
+        ctx: &RpcContext,
+        req: SqlQueryRequest,
+    ) -> Result<BoxStream<'static, Result<SqlQueryResponse>>> {
+        Self::traced(
+            self.rpc_span(
+                RpcOperation::SqlQuery,
+                ctx,
+                req.tables.iter().map(String::as_str),
+            ),
+            self.audit(RpcOperation::SqlQuery, ctx, async move {
+                let custom_metadata = Self::custom_metadata(ctx)?;
+                let client = self.storage_client(Some(req.encoded_len()));
+
+                let resp = self
+                    .with_auth_refresh(req, |req| {
+                        let mut client = client.clone();
+                        // The `first_response_timeout` only bounds the first response, and
+                        // the whole stream is bounded by the rpc timeout.
+                        let mut query_req = self.make_query_request(ctx, &custom_metadata, req);
+                        query_req.set_timeout(
+                            ctx.clamp_timeout(ctx.timeout.unwrap_or(self.default_read_timeout)),
+                        );
+                        async move {
+                            let call = client.stream_sql_query(query_req);
+                            match ctx.first_response_timeout {
+                                Some(timeout) => {
+                                    tokio::time::timeout(timeout, call).await.map_err(|_| {
+                                        Error::Rpc(Status::deadline_exceeded(format!(
+                                            "first response timeout:{timeout:?} is exceeded"
+                                        )))
+                                    })?
+                                }
+                                None => call.await,
+                            }
+                            .map_err(Self::rpc_error)
+                        }
+                    })
+                    .await?;
+
+                Ok(Self::check_stream_status(ctx.clone(), resp.into_inner()))
+            }),
+        )
+        .await
     }
 
     async fn write(&self, ctx: &RpcContext, req: WriteRequestPb) -> Result<WriteResponsePb> {
-        let mut client = StorageServiceClient::<Channel>::new(self.channel.clone());
+        Self::traced(
+            self.rpc_span(
+                RpcOperation::Write,
+                ctx,
+                req.table_requests.iter().map(|req| req.table.as_str()),
+            ),
+            self.audit(RpcOperation::Write, ctx, async move {
+                let custom_metadata = Self::custom_metadata(ctx)?;
+                let client = self.storage_client(Some(req.encoded_len()));
 
-        let resp = client
-            .write(self.make_write_request(ctx, req))
-            .await
-            .map_err(Error::Rpc)?;
-        let mut resp = resp.into_inner();
+                self.with_auth_refresh(req, |req| {
+                    let mut client = client.clone();
+                    let custom_metadata = &custom_metadata;
+                    async move {
+                        let resp = self
+                            .record_message_size(RpcOperation::Write, req, |req| async {
+                                client
+                                    .write(self.make_write_request(ctx, custom_metadata, req))
+                                    .await
+                            })
+                            .await
+                            .map_err(Self::rpc_error)?;
+                        self.check_write_status(ctx, resp)
+                    }
+                })
+                .await
+            }),
+        )
+        .await
+    }
 
-        if let Some(header) = resp.header.take() {
-            Self::check_status(header)?;
-        }
+    /// The stream is bounded by the rpc timeout as a whole, so set a larger
+    /// `timeout` in the `ctx` for the long-lived stream.
+    async fn write_stream(
+        &self,
+        ctx: &RpcContext,
+        reqs: BoxStream<'static, WriteRequestPb>,
+    ) -> Result<WriteResponsePb> {
+        // The tables are unknown until the requests are streamed.
+        Self::traced(
+            self.rpc_span(RpcOperation::Write, ctx, std::iter::empty::<&str>()),
+            self.audit(RpcOperation::Write, ctx, async move {
+                let custom_metadata = Self::custom_metadata(ctx)?;
+                let mut client = self.storage_client(None);
 
-        Ok(resp)
+                let req = self.make_write_request(ctx, &custom_metadata, SyncStream::new(reqs));
+                let resp = client.stream_write(req).await.map_err(Self::rpc_error)?;
+                self.check_write_status(ctx, resp)
+            }),
+        )
+        .await
     }
 
     async fn route(&self, ctx: &RpcContext, req: RouteRequestPb) -> Result<RouteResponsePb> {
-        let mut client = StorageServiceClient::<Channel>::new(self.channel.clone());
+        Self::traced(
+            self.rpc_span(
+                RpcOperation::Route,
+                ctx,
+                req.tables.iter().map(String::as_str),
+            ),
+            self.audit(RpcOperation::Route, ctx, async move {
+                let custom_metadata = Self::custom_metadata(ctx)?;
+                let client = self.storage_client(Some(req.encoded_len()));
 
-        // use the write timeout for the route request.
-        let route_req = self.make_request(ctx, req, self.default_write_timeout);
-        let resp = client.route(route_req).await.map_err(Error::Rpc)?;
-        let mut resp = resp.into_inner();
+                self.with_auth_refresh(req, |req| {
+                    let mut client = client.clone();
+                    let custom_metadata = &custom_metadata;
+                    async move {
+                        let mut resp = self
+                            .record_message_size(RpcOperation::Route, req, |req| async {
+                                // use the write timeout for the route request.
+                                let route_req = self.make_request(
+                                    ctx,
+                                    custom_metadata,
+                                    req,
+                                    self.default_write_timeout,
+                                );
+                                client.route(route_req).await
+                            })
+                            .await
+                            .map_err(Self::rpc_error)?;
+                        let header =
+                            self.select_status(resp.get_mut().header.take(), resp.metadata())?;
+                        let resp = resp.into_inner();
 
-        if let Some(header) = resp.header.take() {
-            Self::check_status(header)?;
-        }
+                        if let Some(header) = header {
+                            Self::check_status(ctx, header)?;
+                        }
 
-        Ok(resp)
+                        Ok(resp)
+                    }
+                })
+                .await
+            }),
+        )
+        .await
+    }
+
+    async fn capabilities(&self, ctx: &RpcContext) -> Result<ServerCapabilities> {
+        assert!(ctx.database.is_some());
+
+        // Each probe is a real rpc, so it is traced and audited as the
+        // operation it probes.
+        let stream_sql_query = Self::traced(
+            self.rpc_span(RpcOperation::SqlQuery, ctx, std::iter::empty::<&str>()),
+            self.audit(
+                RpcOperation::SqlQuery,
+                ctx,
+                self.probe_stream_sql_query(ctx),
+            ),
+        )
+        .await?;
+        let stream_write = Self::traced(
+            self.rpc_span(RpcOperation::Write, ctx, std::iter::empty::<&str>()),
+            self.audit(RpcOperation::Write, ctx, self.probe_stream_write(ctx)),
+        )
+        .await?;
+
+        Ok(ServerCapabilities {
+            stream_sql_query,
+            stream_write,
+        })
     }
 }
 
 pub struct RpcClientImplFactory {
     rpc_config: RpcConfig,
     authorization: Option<Authorization>,
+    connection_counters: DashMap<String, Arc<ConnectionCounter>>,
+    message_size_recorder: Option<Arc<dyn MessageSizeRecorder>>,
+    operation_auditor: Option<Arc<dyn OperationAuditor>>,
+    keep_alive_overrides: HashMap<String, KeepAliveOverride>,
+    resolver: Arc<dyn Resolver>,
+    #[cfg(feature = "tracing")]
+    trace_context_injector: Option<Arc<dyn TraceContextInjector>>,
+}
+
+/// Pool the connected channels, and the failed ones are left out unless all of
+/// them fail, in which case the first error is returned.
+fn pool_connected(results: Vec<Result<Channel>>) -> Result<Vec<Channel>> {
+    let mut channels = Vec::with_capacity(results.len());
+    let mut first_err = None;
+    for res in results {
+        match res {
+            Ok(channel) => channels.push(channel),
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+    match first_err {
+        Some(e) if channels.is_empty() => Err(e),
+        _ => Ok(channels),
+    }
+}
+
+/// The addresses to connect for an endpoint.
+#[derive(Debug, PartialEq, Eq)]
+enum ConnectTarget {
+    /// Connect the endpoint as it is, e.g. an ip address, or a hostname which
+    /// is resolved to only one address.
+    Single,
+    /// Balance across the addresses resolved from the hostname.
+    Balanced {
+        host: String,
+        addrs: Vec<SocketAddr>,
+    },
 }
 
 impl RpcClientImplFactory {
@@ -154,63 +952,1496 @@ impl RpcClientImplFactory {
         Self {
             rpc_config,
             authorization,
+            connection_counters: DashMap::new(),
+            message_size_recorder: None,
+            operation_auditor: None,
+            keep_alive_overrides: HashMap::new(),
+            resolver: Arc::new(SystemResolver),
+            #[cfg(feature = "tracing")]
+            trace_context_injector: None,
         }
     }
 
+    /// Resolve the hostnames of the endpoints by the `resolver` instead of the
+    /// [`SystemResolver`].
+    pub fn with_resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    #[cfg(feature = "tracing")]
+    pub fn with_trace_context_injector(mut self, injector: Arc<dyn TraceContextInjector>) -> Self {
+        self.trace_context_injector = Some(injector);
+        self
+    }
+
+    pub fn with_operation_auditor(mut self, auditor: Arc<dyn OperationAuditor>) -> Self {
+        self.operation_auditor = Some(auditor);
+        self
+    }
+
+    /// Override the keep-alive settings for the endpoints, keyed by the
+    /// endpoint in the form: `{ip_addr}:{port}`.
+    pub fn with_keep_alive_overrides(
+        mut self,
+        keep_alive_overrides: HashMap<String, KeepAliveOverride>,
+    ) -> Self {
+        self.keep_alive_overrides = keep_alive_overrides;
+        self
+    }
+
+    pub fn with_message_size_recorder(mut self, recorder: Arc<dyn MessageSizeRecorder>) -> Self {
+        self.message_size_recorder = Some(recorder);
+        self
+    }
+
+    /// The `endpoint` is assumed to be normalized into `{host}:{port}` without
+    /// a scheme, as the [`Builder`](crate::Builder) does.
     #[inline]
-    fn make_endpoint_with_scheme(endpoint: &str) -> String {
-        format!("http://{endpoint}")
+    fn make_endpoint_with_scheme(endpoint: &str, tls: bool) -> String {
+        match tls {
+            true => format!("https://{endpoint}"),
+            false => format!("http://{endpoint}"),
+        }
     }
-}
 
-#[async_trait]
-impl RpcClientFactory for RpcClientImplFactory {
-    /// The endpoint should be in the form: `{ip_addr}:{port}`.
-    async fn build(&self, endpoint: String) -> Result<Arc<dyn RpcClient>> {
-        let endpoint_with_scheme = Self::make_endpoint_with_scheme(&endpoint);
+    /// Configure the endpoint by the scheme, TLS, keep-alive and transport
+    /// tuning settings.
+    ///
+    /// The `resolved` address is connected instead if provided, along with the
+    /// hostname it is resolved from, which is verified by TLS.
+    fn make_endpoint(
+        &self,
+        endpoint: &str,
+        resolved: Option<(&str, SocketAddr)>,
+    ) -> Result<Endpoint> {
+        let addr = match resolved {
+            Some((_, addr)) => addr.to_string(),
+            None => endpoint.to_string(),
+        };
+        let endpoint_with_scheme =
+            Self::make_endpoint_with_scheme(&addr, self.rpc_config.tls.is_some());
         let configured_endpoint =
-            Endpoint::from_shared(endpoint_with_scheme).map_err(|e| Error::Connect {
-                addr: endpoint.clone(),
-                source: Box::new(e),
-            })?;
+            Endpoint::from_shared(endpoint_with_scheme).map_err(|e| Error::connect(endpoint, e))?;
+        let configured_endpoint = match &self.rpc_config.tls {
+            Some(tls) => {
+                let host = resolved.map(|(host, _)| host);
+                let client_tls_config =
+                    Self::make_client_tls_config(tls, host).map_err(|e| Error::Connect {
+                        addr: endpoint.to_string(),
+                        kind: ConnectErrorKind::Tls,
+                        source: Box::new(e),
+                    })?;
+                configured_endpoint
+                    .tls_config(client_tls_config)
+                    .map_err(|e| Error::Connect {
+                        addr: endpoint.to_string(),
+                        kind: ConnectErrorKind::Tls,
+                        source: Box::new(e),
+                    })?
+            }
+            None => configured_endpoint,
+        };
+
+        let configured_endpoint = match &self.rpc_config.user_agent {
+            Some(user_agent) => configured_endpoint
+                .user_agent(user_agent.clone())
+                .map_err(|e| Error::connect(endpoint, e))?,
+            None => configured_endpoint,
+        };
 
-        let configured_endpoint = match self.rpc_config.keep_alive_while_idle {
+        let keep_alive =
+            KeepAlive::resolve(&self.rpc_config, self.keep_alive_overrides.get(endpoint));
+        let configured_endpoint = match keep_alive.while_idle {
             true => configured_endpoint
                 .connect_timeout(self.rpc_config.connect_timeout)
-                .keep_alive_timeout(self.rpc_config.keep_alive_timeout)
+                .keep_alive_timeout(keep_alive.timeout)
                 .keep_alive_while_idle(true)
-                .http2_keep_alive_interval(self.rpc_config.keep_alive_interval),
+                .http2_keep_alive_interval(keep_alive.interval),
             false => configured_endpoint
                 .connect_timeout(self.rpc_config.connect_timeout)
                 .keep_alive_while_idle(false),
         };
-        let channel = configured_endpoint
-            .connect()
+
+        // The unset ones are left as the defaults of the endpoint.
+        let configured_endpoint = configured_endpoint
+            .initial_stream_window_size(self.rpc_config.initial_stream_window_size)
+            .initial_connection_window_size(self.rpc_config.initial_connection_window_size)
+            .tcp_keepalive(self.rpc_config.tcp_keepalive);
+        let configured_endpoint = match self.rpc_config.tcp_nodelay {
+            Some(tcp_nodelay) => configured_endpoint.tcp_nodelay(tcp_nodelay),
+            None => configured_endpoint,
+        };
+
+        Ok(configured_endpoint)
+    }
+
+    /// The `host` is verified unless the domain name is configured.
+    fn make_client_tls_config(
+        tls: &TlsConfig,
+        host: Option<&str>,
+    ) -> std::io::Result<ClientTlsConfig> {
+        let ca_certificate = std::fs::read(&tls.ca_certificate)?;
+        let mut client_tls_config =
+            ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_certificate));
+        if let Some(identity) = &tls.client_identity {
+            let certificate = std::fs::read(&identity.certificate)?;
+            let key = std::fs::read(&identity.key)?;
+            client_tls_config = client_tls_config.identity(Identity::from_pem(certificate, key));
+        }
+        if let Some(domain_name) = tls.domain_name.as_deref().or(host) {
+            client_tls_config = client_tls_config.domain_name(domain_name);
+        }
+
+        Ok(client_tls_config)
+    }
+
+    /// Connect the endpoint as it is, by the connector counting the
+    /// connections.
+    async fn connect(&self, endpoint: &str) -> Result<Channel> {
+        let configured_endpoint = self.make_endpoint(endpoint, None)?;
+        // Same as the connector used by `Endpoint::connect`, which ignores the
+        // TCP settings of the endpoint.
+        let mut http_connector = HttpConnector::new();
+        http_connector.enforce_http(false);
+        http_connector.set_nodelay(self.rpc_config.tcp_nodelay.unwrap_or(true));
+        http_connector.set_keepalive(self.rpc_config.tcp_keepalive);
+        let counter = self
+            .connection_counters
+            .entry(endpoint.to_string())
+            .or_default()
+            .clone();
+        configured_endpoint
+            .connect_with_connector(CountingConnector::new(http_connector, counter))
+            .await
+            .map_err(|e| Error::connect(endpoint, e))
+    }
+
+    /// Resolve the hostname of the endpoint to find the addresses to connect.
+    async fn resolve(&self, endpoint: &str) -> Result<ConnectTarget> {
+        // The malformed endpoint is left to be reported by connecting.
+        let parsed: RouteEndpoint = match endpoint.parse() {
+            Ok(parsed) => parsed,
+            Err(_) => return Ok(ConnectTarget::Single),
+        };
+        let host = parsed.addr.trim_start_matches('[').trim_end_matches(']');
+        if host.parse::<IpAddr>().is_ok() {
+            return Ok(ConnectTarget::Single);
+        }
+
+        let addrs = self
+            .resolver
+            .resolve(host, parsed.port as u16)
             .await
             .map_err(|e| Error::Connect {
-                addr: endpoint,
+                addr: endpoint.to_string(),
+                kind: ConnectErrorKind::Dns,
                 source: Box::new(e),
             })?;
+        match addrs.len() {
+            0 => Err(Error::Connect {
+                addr: endpoint.to_string(),
+                kind: ConnectErrorKind::Dns,
+                source: format!("no address is resolved from host:{host}").into(),
+            }),
+            1 => Ok(ConnectTarget::Single),
+            _ => Ok(ConnectTarget::Balanced {
+                host: host.to_string(),
+                addrs,
+            }),
+        }
+    }
+}
 
-        let metadata = if let Some(auth) = &self.authorization {
-            let mut buf = Vec::with_capacity(auth.username.len() + auth.password.len() + 1);
-            buf.extend_from_slice(auth.username.as_bytes());
-            buf.push(b':');
-            buf.extend_from_slice(auth.password.as_bytes());
-            let auth = BASE64_STANDARD.encode(&buf);
-            let metadata: MetadataValue<Ascii> = format!("Basic {}", auth)
-                .parse()
-                .context("invalid grpc metadata")?;
+#[async_trait]
+impl RpcClientFactory for RpcClientImplFactory {
+    /// The endpoint should be in the form: `{ip_addr}:{port}` or
+    /// `{hostname}:{port}`.
+    ///
+    /// The hostname resolved to multiple addresses is connected by a channel
+    /// balanced across them, which avoids the dead addresses. Such a channel
+    /// connects lazily, so one of the addresses is connected up front to fail
+    /// fast if none is reachable. Its connections are not counted in the
+    /// [`ConnectionStats`].
+    ///
+    /// The [`RpcConfig::connections_per_endpoint`] channels are pooled, and the
+    /// ones failing to connect are left out unless all of them fail.
+    async fn build(&self, endpoint: String) -> Result<Arc<dyn RpcClient>> {
+        #[cfg(feature = "tracing")]
+        let tracer = RpcTracer::new(endpoint.clone(), self.trace_context_injector.clone());
 
-            Some(metadata)
-        } else {
-            None
+        let pool_size = self.rpc_config.connections_per_endpoint.max(1);
+        let mut channels = match self.resolve(&endpoint).await? {
+            ConnectTarget::Single => {
+                let results = join_all((0..pool_size).map(|_| self.connect(&endpoint))).await;
+                pool_connected(results)?
+            }
+            ConnectTarget::Balanced { host, addrs } => {
+                let configured_endpoints = addrs
+                    .into_iter()
+                    .map(|addr| self.make_endpoint(&endpoint, Some((&host, addr))))
+                    .collect::<Result<Vec<_>>>()?;
+                // The probed connection is dropped, and the channel connects
+                // the addresses on its own.
+                select_ok(configured_endpoints.iter().map(|e| e.connect().boxed()))
+                    .await
+                    .map_err(|e| Error::connect(endpoint.clone(), e))?;
+                (0..pool_size)
+                    .map(|_| Channel::balance_list(configured_endpoints.clone().into_iter()))
+                    .collect()
+            }
         };
-        Ok(Arc::new(RpcClientImpl::new(
+        let channel = channels.remove(0);
+
+        let metadata = self
+            .authorization
+            .as_ref()
+            .map(authorization_metadata)
+            .transpose()?;
+        let client_version = client_version_metadata(self.rpc_config.user_agent.as_deref())?;
+        let client = RpcClientImpl::new(
             channel,
             self.rpc_config.default_sql_query_timeout,
             self.rpc_config.default_write_timeout,
+            self.rpc_config.sql_query_timeout_scaling.clone(),
             metadata,
-        )))
+            self.message_size_recorder.clone(),
+            self.rpc_config.status_source,
+        )
+        .with_refreshable_authorization(self.authorization.clone())
+        .with_operation_auditor(self.operation_auditor.clone())
+        .with_status_trailer_keys(self.rpc_config.status_trailer_keys.clone())
+        .with_compression(self.rpc_config.compression)
+        .with_compression_min_size(self.rpc_config.compression_min_size)
+        .with_client_version(client_version)
+        .with_pooled_channels(channels);
+        #[cfg(feature = "tracing")]
+        let client = client.with_tracer(tracer);
+
+        Ok(Arc::new(client))
+    }
+
+    fn connection_stats(&self) -> HashMap<String, ConnectionStats> {
+        self.connection_counters
+            .iter()
+            .map(|pair| (pair.key().clone(), pair.value().stats()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        net::SocketAddr,
+        sync::{atomic::Ordering, Arc, Mutex, RwLock},
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+    use futures::StreamExt;
+    use horaedbproto::{
+        common::ResponseHeader,
+        storage::{
+            sql_query_response::Output, storage_service_client::StorageServiceClient,
+            RequestContext, RouteRequest as RouteRequestPb, RouteResponse as RouteResponsePb,
+            SqlQueryRequest as SqlQueryRequestPb, SqlQueryResponse, WriteRequest as WriteRequestPb,
+        },
+    };
+    use hyper::client::HttpConnector;
+    use prost::Message;
+    use tokio::io::AsyncReadExt;
+    use tokio_util::sync::CancellationToken;
+    use tonic::{
+        body::BoxBody, codegen::http, metadata::MetadataMap, transport::Endpoint, Code, Status,
+    };
+
+    use super::{
+        authorization_metadata, client_version_metadata, ConnectTarget, RpcClientImpl,
+        RpcClientImplFactory,
+    };
+    use crate::{
+        config::{
+            Authorization, BasicAuthorization, Compression, StatusSource, StatusTrailerKeys,
+            TlsConfig,
+        },
+        rpc_client::{
+            connection::{ConnectionCounter, CountingConnector},
+            MessageSize, MessageSizeRecorder, MockStorageService, OperationAuditor,
+            OperationOutcome, Resolver, RpcClient, RpcClientFactory, RpcOperation, MOCK_REQUEST_ID,
+        },
+        ConnectErrorKind, Error, RpcConfig, RpcContext,
+    };
+
+    /// The client to the `addr` with the default settings, which connects
+    /// lazily.
+    fn test_client_to(addr: impl std::fmt::Display) -> RpcClientImpl {
+        let channel = Endpoint::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect_lazy();
+        RpcClientImpl::new(
+            channel,
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+            None,
+            None,
+            None,
+            StatusSource::default(),
+        )
+    }
+
+    fn test_client() -> RpcClientImpl {
+        test_client_to("127.0.0.1:8831")
+    }
+
+    #[derive(Debug, Default)]
+    struct MockRecorder {
+        sizes: Mutex<Vec<MessageSize>>,
+    }
+
+    impl MessageSizeRecorder for MockRecorder {
+        fn record(&self, size: &MessageSize) {
+            self.sizes.lock().unwrap().push(size.clone());
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct MockAuditor {
+        records: Mutex<Vec<(RpcOperation, Option<String>, bool)>>,
+    }
+
+    impl OperationAuditor for MockAuditor {
+        fn on_operation_complete(
+            &self,
+            operation: RpcOperation,
+            database: Option<&str>,
+            outcome: OperationOutcome<'_>,
+            _duration: Duration,
+        ) {
+            let success = matches!(outcome, OperationOutcome::Success);
+            self.records.lock().unwrap().push((
+                operation,
+                database.map(|db| db.to_string()),
+                success,
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_operation() {
+        let auditor = Arc::new(MockAuditor::default());
+        let client = test_client().with_operation_auditor(Some(auditor.clone()));
+
+        let ctx = RpcContext::default().database("public".to_string());
+        client
+            .audit(RpcOperation::Write, &ctx, async { Ok(()) })
+            .await
+            .unwrap();
+        client
+            .audit(RpcOperation::SqlQuery, &ctx, async {
+                Err::<(), _>(Error::Client("failed".to_string()))
+            })
+            .await
+            .unwrap_err();
+
+        let records = auditor.records.lock().unwrap().clone();
+        assert_eq!(
+            records,
+            vec![
+                (RpcOperation::Write, Some("public".to_string()), true),
+                (RpcOperation::SqlQuery, Some("public".to_string()), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_tls_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_certificate = dir.path().join("bogus_ca.pem");
+        std::fs::write(
+            &ca_certificate,
+            "-----BEGIN CERTIFICATE-----\nYm9ndXM=\n-----END CERTIFICATE-----\n",
+        )
+        .unwrap();
+        let rpc_config = RpcConfig {
+            tls: Some(TlsConfig {
+                ca_certificate: ca_certificate.clone(),
+                client_identity: None,
+                domain_name: None,
+            }),
+            ..Default::default()
+        };
+        let factory = RpcClientImplFactory::new(rpc_config, None);
+        let res = factory.make_endpoint("127.0.0.1:8831", None);
+        assert!(matches!(res, Err(Error::Connect { addr, .. }) if addr == "127.0.0.1:8831"));
+
+        // The missing file.
+        std::fs::remove_file(&ca_certificate).unwrap();
+        let res = factory.make_endpoint("127.0.0.1:8831", None);
+        assert!(matches!(res, Err(Error::Connect { .. })));
+
+        let factory = RpcClientImplFactory::new(RpcConfig::default(), None);
+        assert!(factory.make_endpoint("127.0.0.1:8831", None).is_ok());
+    }
+
+    /// Resolve all the hostnames to the `addrs`, and fail if it is empty.
+    #[derive(Debug, Default)]
+    struct StubResolver {
+        addrs: Vec<SocketAddr>,
+        hosts: Mutex<Vec<(String, u16)>>,
+    }
+
+    #[async_trait]
+    impl Resolver for StubResolver {
+        async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+            self.hosts.lock().unwrap().push((host.to_string(), port));
+            if self.addrs.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "unknown host"));
+            }
+            Ok(self.addrs.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_endpoint() {
+        let addrs: Vec<SocketAddr> = vec![
+            "127.0.0.1:1".parse().unwrap(),
+            "127.0.0.2:1".parse().unwrap(),
+        ];
+        let resolver = Arc::new(StubResolver {
+            addrs: addrs.clone(),
+            ..Default::default()
+        });
+        let factory =
+            RpcClientImplFactory::new(RpcConfig::default(), None).with_resolver(resolver.clone());
+
+        // The ip addresses are not resolved.
+        for endpoint in ["127.0.0.1:8831", "[::1]:8831"] {
+            let target = factory.resolve(endpoint).await.unwrap();
+            assert_eq!(target, ConnectTarget::Single);
+        }
+        assert!(resolver.hosts.lock().unwrap().is_empty());
+
+        let target = factory.resolve("horaedb.local:8831").await.unwrap();
+        assert_eq!(
+            target,
+            ConnectTarget::Balanced {
+                host: "horaedb.local".to_string(),
+                addrs: addrs.clone(),
+            }
+        );
+        assert_eq!(
+            *resolver.hosts.lock().unwrap(),
+            vec![("horaedb.local".to_string(), 8831)]
+        );
+        // None of the addresses is reachable.
+        let err = match factory.build("horaedb.local:8831".to_string()).await {
+            Ok(_) => panic!("connecting should fail"),
+            Err(e) => e,
+        };
+        assert!(matches!(err, Error::Connect { addr, .. } if addr == "horaedb.local:8831"));
+
+        // The balanced channel is built if any address is reachable.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let factory = RpcClientImplFactory::new(RpcConfig::default(), None).with_resolver(
+            Arc::new(StubResolver {
+                addrs: vec![
+                    SocketAddr::from(([127, 0, 0, 2], port)),
+                    SocketAddr::from(([127, 0, 0, 1], port)),
+                ],
+                ..Default::default()
+            }),
+        );
+        factory
+            .build(format!("horaedb.local:{port}"))
+            .await
+            .unwrap();
+
+        // One address is connected as it is.
+        let factory = RpcClientImplFactory::new(RpcConfig::default(), None).with_resolver(
+            Arc::new(StubResolver {
+                addrs: addrs[..1].to_vec(),
+                ..Default::default()
+            }),
+        );
+        let target = factory.resolve("horaedb.local:8831").await.unwrap();
+        assert_eq!(target, ConnectTarget::Single);
+
+        // The resolution fails.
+        let factory = RpcClientImplFactory::new(RpcConfig::default(), None)
+            .with_resolver(Arc::new(StubResolver::default()));
+        let err = match factory.build("horaedb.local:8831".to_string()).await {
+            Ok(_) => panic!("resolution should fail"),
+            Err(e) => e,
+        };
+        assert!(matches!(err, Error::Connect { addr, .. } if addr == "horaedb.local:8831"));
+    }
+
+    #[tokio::test]
+    async fn test_check_stream_status() {
+        let resp = |code| SqlQueryResponse {
+            header: Some(ResponseHeader {
+                code,
+                error: String::new(),
+            }),
+            output: Some(Output::AffectedRows(1)),
+        };
+        let stream = futures::stream::iter(vec![
+            Ok(resp(200)),
+            Ok(resp(500)),
+            Ok(resp(200)),
+            Err(Status::unavailable("")),
+        ]);
+
+        let results: Vec<_> = RpcClientImpl::check_stream_status(RpcContext::default(), stream)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        assert!(matches!(&results[1], Err(Error::Server(e)) if e.code == 500));
+        assert!(results[2].is_ok());
+        assert!(matches!(&results[3], Err(Error::Rpc(_))));
+    }
+
+    #[test]
+    fn test_response_warning() {
+        let resp_pb = SqlQueryResponse {
+            header: None,
+            output: Some(Output::AffectedRows(1)),
+        };
+        let header = ResponseHeader {
+            code: 200,
+            error: "result is truncated".to_string(),
+        };
+        let resp_pb =
+            RpcClientImpl::check_sql_query_status(&RpcContext::default(), Some(header), resp_pb)
+                .unwrap();
+        let resp = crate::SqlQueryResponse::try_from(resp_pb).unwrap();
+        assert_eq!(resp.warnings(), ["result is truncated"]);
+
+        // The message of the accepted failure is kept as the warning too.
+        let header = ResponseHeader {
+            code: 404,
+            error: "table not found".to_string(),
+        };
+        let ctx = RpcContext::default().accept_code(404);
+        let resp_pb = SqlQueryResponse {
+            header: None,
+            output: None,
+        };
+        let resp_pb = RpcClientImpl::check_sql_query_status(&ctx, Some(header), resp_pb).unwrap();
+        let resp = crate::SqlQueryResponse::try_from(resp_pb).unwrap();
+        assert_eq!(resp.affected_rows, 0);
+        assert_eq!(resp.warnings(), ["table not found"]);
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let headers = Arc::new(Mutex::new(None));
+        let captured = headers.clone();
+        let service = tower::service_fn(move |req: http::Request<BoxBody>| {
+            *captured.lock().unwrap() = Some(req.headers().clone());
+            async { Err::<http::Response<BoxBody>, _>(Status::unavailable("mock")) }
+        });
+        let mut client = RpcClientImpl::compress(
+            StorageServiceClient::new(service),
+            Some(Compression::Gzip),
+            true,
+        );
+
+        let req = WriteRequestPb {
+            context: None,
+            table_requests: vec![],
+        };
+        assert!(client.write(req).await.is_err());
+
+        let headers = headers.lock().unwrap().take().unwrap();
+        assert_eq!(headers["grpc-encoding"], "gzip");
+        assert!(headers["grpc-accept-encoding"]
+            .to_str()
+            .unwrap()
+            .contains("gzip"));
+    }
+
+    #[tokio::test]
+    async fn test_compression_min_size() {
+        let (addr, service) = MockStorageService::serve(|_, _| Ok(None)).await;
+        let client = test_client_to(&addr)
+            .with_compression(Some(Compression::Gzip))
+            .with_compression_min_size(Some(64));
+        let ctx = RpcContext::default().database("public".to_string());
+
+        let make_req = |database_len| WriteRequestPb {
+            context: Some(RequestContext {
+                database: "d".repeat(database_len),
+            }),
+            table_requests: vec![],
+        };
+        let small_req = WriteRequestPb {
+            context: None,
+            table_requests: vec![],
+        };
+        // Only the request exceeding the threshold is compressed.
+        let exact_req = make_req(60);
+        assert_eq!(exact_req.encoded_len(), 64);
+        let large_req = make_req(61);
+        for (req, compressed) in [(small_req, false), (exact_req, false), (large_req, true)] {
+            client.write(&ctx, req).await.unwrap();
+
+            // The compressed responses are accepted anyway.
+            let metadata = service.requests.lock().unwrap().pop().unwrap();
+            assert_eq!(metadata.contains_key("grpc-encoding"), compressed);
+            assert!(metadata
+                .get("grpc-accept-encoding")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .contains("gzip"));
+        }
+
+        // The streamed requests of unknown sizes are always compressed.
+        let reqs = futures::stream::iter(vec![WriteRequestPb::default()]).boxed();
+        client.write_stream(&ctx, reqs).await.unwrap();
+        let metadata = service.requests.lock().unwrap().pop().unwrap();
+        assert!(metadata.contains_key("grpc-encoding"));
+    }
+
+    #[tokio::test]
+    async fn test_record_message_size() {
+        let recorder = Arc::new(MockRecorder::default());
+        let client = RpcClientImpl {
+            message_size_recorder: Some(recorder.clone()),
+            ..test_client()
+        };
+
+        let req = RouteRequestPb {
+            context: None,
+            tables: vec!["demo".to_string()],
+        };
+        let request_bytes = req.encoded_len();
+        let resp = RouteResponsePb::default();
+        let response_bytes = resp.encoded_len();
+        client
+            .record_message_size(RpcOperation::Route, req.clone(), |_| async {
+                Ok(tonic::Response::new(resp))
+            })
+            .await
+            .unwrap();
+        client
+            .record_message_size(RpcOperation::Route, req, |_| async {
+                Err::<tonic::Response<RouteResponsePb>, _>(Status::unavailable(""))
+            })
+            .await
+            .unwrap_err();
+
+        let sizes = recorder.sizes.lock().unwrap().clone();
+        assert_eq!(
+            sizes,
+            vec![
+                MessageSize {
+                    operation: RpcOperation::Route,
+                    request_bytes,
+                    response_bytes: Some(response_bytes),
+                },
+                MessageSize {
+                    operation: RpcOperation::Route,
+                    request_bytes,
+                    response_bytes: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_status() {
+        let header = |code| ResponseHeader {
+            code,
+            error: String::new(),
+        };
+
+        let ctx = RpcContext::default();
+        assert!(RpcClientImpl::check_status(&ctx, header(200)).is_ok());
+        assert!(matches!(
+            RpcClientImpl::check_status(&ctx, header(400)),
+            Err(Error::Server(e)) if e.code == 400
+        ));
+
+        let ctx = ctx.accept_code(400);
+        assert!(RpcClientImpl::check_status(&ctx, header(400)).is_ok());
+        assert!(RpcClientImpl::check_status(&ctx, header(500)).is_err());
+    }
+
+    #[test]
+    fn test_auth_errors() {
+        let header = |code| ResponseHeader {
+            code,
+            error: "denied".to_string(),
+        };
+        let ctx = RpcContext::default();
+        assert!(matches!(
+            RpcClientImpl::check_status(&ctx, header(401)),
+            Err(Error::Unauthenticated(msg)) if msg == "denied"
+        ));
+        assert!(matches!(
+            RpcClientImpl::check_status(&ctx, header(403)),
+            Err(Error::PermissionDenied(msg)) if msg == "denied"
+        ));
+
+        assert!(matches!(
+            RpcClientImpl::rpc_error(Status::unauthenticated("invalid token")),
+            Error::Unauthenticated(msg) if msg == "invalid token"
+        ));
+        assert!(matches!(
+            RpcClientImpl::rpc_error(Status::permission_denied("no privilege")),
+            Error::PermissionDenied(msg) if msg == "no privilege"
+        ));
+        assert!(matches!(
+            RpcClientImpl::rpc_error(Status::unavailable("")),
+            Error::Rpc(status) if status.code() == Code::Unavailable
+        ));
+
+        // The credential errors are not retried.
+        let retry_config = crate::RetryConfig::default();
+        assert!(!retry_config.is_retryable(&Error::Unauthenticated(String::new())));
+        assert!(!retry_config.is_retryable(&Error::PermissionDenied(String::new())));
+    }
+
+    #[tokio::test]
+    async fn test_select_status() {
+        let header = |code| ResponseHeader {
+            code,
+            error: String::new(),
+        };
+        let make_client = |status_source| RpcClientImpl {
+            status_source,
+            ..test_client()
+        };
+        let mut trailers = MetadataMap::new();
+        trailers.insert("x-horaedb-code", "500".parse().unwrap());
+        trailers.insert("x-horaedb-error", "failed in trailer".parse().unwrap());
+
+        let status = make_client(StatusSource::Header)
+            .select_status(Some(header(200)), &trailers)
+            .unwrap();
+        assert_eq!(status.unwrap().code, 200);
+
+        let status = make_client(StatusSource::Both)
+            .select_status(Some(header(200)), &trailers)
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.code, 500);
+        assert_eq!(status.error, "failed in trailer");
+        let status = make_client(StatusSource::Both)
+            .select_status(Some(header(400)), &trailers)
+            .unwrap();
+        assert_eq!(status.unwrap().code, 400);
+
+        let status = make_client(StatusSource::Trailer)
+            .select_status(Some(header(400)), &trailers)
+            .unwrap();
+        assert_eq!(status.unwrap().code, 500);
+        let status = make_client(StatusSource::Trailer)
+            .select_status(Some(header(400)), &MetadataMap::new())
+            .unwrap();
+        assert_eq!(status.unwrap().code, 400);
+
+        trailers.insert("x-horaedb-code", "abc".parse().unwrap());
+        assert!(matches!(
+            make_client(StatusSource::Both).select_status(None, &trailers),
+            Err(Error::MalformedResponse { .. })
+        ));
+
+        // The status is read from the configured trailer keys.
+        let client =
+            make_client(StatusSource::Trailer).with_status_trailer_keys(StatusTrailerKeys {
+                code: "x-proxy-code".to_string(),
+                error: "x-proxy-error".to_string(),
+            });
+        let status = client.select_status(Some(header(400)), &trailers).unwrap();
+        assert_eq!(status.unwrap().code, 400);
+        let mut trailers = MetadataMap::new();
+        trailers.insert("x-proxy-code", "503".parse().unwrap());
+        trailers.insert("x-proxy-error", "failed in proxy".parse().unwrap());
+        let status = client
+            .select_status(Some(header(200)), &trailers)
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.code, 503);
+        assert_eq!(status.error, "failed in proxy");
+    }
+
+    #[tokio::test]
+    async fn test_sql_query_meta_of_failed_status() {
+        let (addr, _service) = MockStorageService::serve(|_, _| {
+            Ok(Some(ResponseHeader {
+                code: 500,
+                error: "failed to execute".to_string(),
+            }))
+        })
+        .await;
+        let client = test_client_to(&addr);
+        let ctx = RpcContext::default().database("public".to_string());
+        let req = SqlQueryRequestPb {
+            context: None,
+            tables: vec![],
+            sql: "select 1".to_string(),
+        };
+
+        let err = client
+            .sql_query_with_meta(&ctx, req.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.response_meta().unwrap().get("x-request-id"),
+            Some(MOCK_REQUEST_ID)
+        );
+        assert!(
+            matches!(err, Error::WithMeta { source, .. } if matches!(*source, Error::Server(ref e) if e.code == 500))
+        );
+
+        // The metadata is dropped if not asked for.
+        let err = client.sql_query(&ctx, req).await.unwrap_err();
+        assert!(matches!(err, Error::Server(e) if e.code == 500));
+    }
+
+    #[test]
+    fn test_probe_supported() {
+        assert!(RpcClientImpl::is_supported(Ok(())).unwrap());
+        assert!(!RpcClientImpl::is_supported::<()>(Err(Status::unimplemented("")),).unwrap());
+        // The rpc is implemented although the probe request is rejected.
+        assert!(
+            RpcClientImpl::is_supported::<()>(Err(Status::invalid_argument("empty sql"))).unwrap()
+        );
+
+        let transport_err = RpcClientImpl::is_supported::<()>(Err(Status::unavailable("")));
+        assert!(
+            matches!(transport_err, Err(Error::Rpc(status)) if status.code() == Code::Unavailable)
+        );
+        // The rejected credentials tell nothing about the rpc.
+        assert!(matches!(
+            RpcClientImpl::is_supported::<()>(Err(Status::unauthenticated(""))),
+            Err(Error::Unauthenticated(_))
+        ));
+        assert!(matches!(
+            RpcClientImpl::is_supported::<()>(Err(Status::permission_denied(""))),
+            Err(Error::PermissionDenied(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_probe_with_rejected_credentials() {
+        let (addr, service) =
+            MockStorageService::serve(|_, _| Err(Status::unauthenticated("token is expired")))
+                .await;
+        let client = test_client_to(&addr);
+        let ctx = RpcContext::default().database("public".to_string());
+
+        let err = client.capabilities(&ctx).await.unwrap_err();
+        assert!(matches!(err, Error::Unauthenticated(_)), "err:{err}");
+        // The probe stops at the first rejection.
+        assert_eq!(service.requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_custom_headers() {
+        let client = RpcClientImpl {
+            metadata: RwLock::new(Some("Basic secret".parse().unwrap())),
+            ..test_client()
+        };
+
+        let ctx = RpcContext::default()
+            .header("x-tenant-id", "tenant1")
+            .header("X-Trace-Id", "abc");
+        let custom_metadata = RpcClientImpl::custom_metadata(&ctx).unwrap();
+        let req = client.make_write_request(&ctx, &custom_metadata, ());
+        let metadata = req.metadata();
+        assert_eq!(metadata.get("x-tenant-id").unwrap(), "tenant1");
+        assert_eq!(metadata.get("x-trace-id").unwrap(), "abc");
+        assert_eq!(metadata.get("authorization").unwrap(), "Basic secret");
+
+        for (name, value) in [
+            ("Authorization", "Basic other"),
+            ("invalid name", "value"),
+            ("x-tenant-id", "invalid\nvalue"),
+        ] {
+            let ctx = RpcContext::default().header(name, value);
+            assert!(matches!(
+                RpcClientImpl::custom_metadata(&ctx),
+                Err(Error::Client(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_version() {
+        let client = RpcClientImpl {
+            metadata: RwLock::new(Some("Basic secret".parse().unwrap())),
+            ..test_client()
+        };
+        let version = concat!("horaedb-client/", env!("CARGO_PKG_VERSION"));
+
+        let ctx = RpcContext::default();
+        let req = client.make_write_request(&ctx, &vec![], ());
+        assert_eq!(req.metadata().get("x-client-version").unwrap(), version);
+
+        let client = client.with_client_version(client_version_metadata(Some("app/1.0")).unwrap());
+        let req = client.make_write_request(&ctx, &vec![], ());
+        let metadata = req.metadata();
+        assert_eq!(
+            metadata.get("x-client-version").unwrap(),
+            format!("app/1.0 {version}").as_str()
+        );
+        assert_eq!(metadata.get("authorization").unwrap(), "Basic secret");
+
+        // Both the user agent and the client version are sent to the server.
+        let (addr, service) = MockStorageService::serve(|_, _| Ok(None)).await;
+        let rpc_config = RpcConfig {
+            user_agent: Some("app/1.0".to_string()),
+            ..Default::default()
+        };
+        let factory = RpcClientImplFactory::new(rpc_config, None);
+        let client = factory.build(addr).await.unwrap();
+        let ctx = RpcContext::default().database("public".to_string());
+        client.write(&ctx, WriteRequestPb::default()).await.unwrap();
+        let metadata = service.requests.lock().unwrap().pop().unwrap();
+        let user_agent = metadata.get("user-agent").unwrap().to_str().unwrap();
+        assert!(user_agent.starts_with("app/1.0"), "{user_agent}");
+        assert_eq!(
+            metadata.get("x-client-version").unwrap(),
+            format!("app/1.0 {version}").as_str()
+        );
+
+        let rpc_config = RpcConfig {
+            user_agent: Some("invalid\napp".to_string()),
+            ..Default::default()
+        };
+        let factory = RpcClientImplFactory::new(rpc_config, None);
+        let res = factory.make_endpoint("127.0.0.1:8831", None);
+        assert!(matches!(res, Err(Error::Connect { addr, .. }) if addr == "127.0.0.1:8831"));
+        assert!(client_version_metadata(Some("invalid\napp")).is_err());
+    }
+
+    #[test]
+    fn test_authorization_metadata() {
+        let token = Arc::new(Mutex::new("token1".to_string()));
+        let provided_token = token.clone();
+        let provider =
+            Authorization::bearer_provider(move || provided_token.lock().unwrap().clone());
+
+        for (auth, expected) in [
+            (Authorization::basic("user", "pass"), "Basic dXNlcjpwYXNz"),
+            (
+                BasicAuthorization {
+                    username: "user".to_string(),
+                    password: "pass".to_string(),
+                }
+                .into(),
+                "Basic dXNlcjpwYXNz",
+            ),
+            (Authorization::bearer("secret"), "Bearer secret"),
+            (provider.clone(), "Bearer token1"),
+        ] {
+            assert_eq!(authorization_metadata(&auth).unwrap(), expected);
+        }
+
+        // The token is renewed by the provider.
+        *token.lock().unwrap() = "token2".to_string();
+        assert_eq!(authorization_metadata(&provider).unwrap(), "Bearer token2");
+
+        assert!(authorization_metadata(&Authorization::bearer("invalid\ntoken")).is_err());
+        assert!(!format!("{:?}", Authorization::basic("user", "pass")).contains("pass"));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_auth() {
+        // The mocked server only accepts the token in `valid_token`.
+        async fn mock_call(
+            client: &RpcClientImpl,
+            valid_token: &str,
+            calls: &Mutex<usize>,
+        ) -> crate::Result<()> {
+            *calls.lock().unwrap() += 1;
+            let ctx = RpcContext::default();
+            let req = client.make_write_request(&ctx, &vec![], ());
+            if req.metadata().get("authorization").unwrap() == valid_token {
+                Ok(())
+            } else {
+                Err(RpcClientImpl::rpc_error(Status::unauthenticated(
+                    "token is expired",
+                )))
+            }
+        }
+
+        let token = Arc::new(Mutex::new("token1".to_string()));
+        let provided_token = token.clone();
+        let auth = Authorization::bearer_provider(move || provided_token.lock().unwrap().clone());
+        let client = RpcClientImpl {
+            metadata: RwLock::new(Some(authorization_metadata(&auth).unwrap())),
+            ..test_client()
+        }
+        .with_refreshable_authorization(Some(auth));
+
+        // Refresh and succeed.
+        *token.lock().unwrap() = "token2".to_string();
+        let calls = Mutex::new(0);
+        client
+            .with_auth_refresh((), |_| mock_call(&client, "Bearer token2", &calls))
+            .await
+            .unwrap();
+        assert_eq!(*calls.lock().unwrap(), 2);
+        // The refreshed token is kept for the following rpcs.
+        client
+            .with_auth_refresh((), |_| mock_call(&client, "Bearer token2", &calls))
+            .await
+            .unwrap();
+        assert_eq!(*calls.lock().unwrap(), 3);
+
+        // Still fail after the refresh.
+        let calls = Mutex::new(0);
+        let err = client
+            .with_auth_refresh((), |_| mock_call(&client, "Bearer token3", &calls))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Auth(_)));
+        assert_eq!(*calls.lock().unwrap(), 2);
+
+        // The static token is not refreshed.
+        let client = RpcClientImpl {
+            metadata: RwLock::new(Some("Bearer token1".parse().unwrap())),
+            ..test_client()
+        }
+        .with_refreshable_authorization(Some(Authorization::bearer("token1")));
+        let calls = Mutex::new(0);
+        let err = client
+            .with_auth_refresh((), |_| mock_call(&client, "Bearer token2", &calls))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unauthenticated(_)));
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_auth_once_concurrently() {
+        let provided = Arc::new(Mutex::new(0));
+        let provided_clone = provided.clone();
+        let auth = Authorization::bearer_provider(move || {
+            let mut provided = provided_clone.lock().unwrap();
+            *provided += 1;
+            format!("token{provided}")
+        });
+        let client = RpcClientImpl {
+            metadata: RwLock::new(Some(authorization_metadata(&auth).unwrap())),
+            ..test_client()
+        }
+        .with_refreshable_authorization(Some(auth));
+
+        // Both rpcs are rejected by the first token before either refreshes it.
+        let rejected = tokio::sync::Barrier::new(2);
+        let call = |_| async {
+            let req = client.make_write_request(&RpcContext::default(), &vec![], ());
+            let token = req.metadata().get("authorization").unwrap().clone();
+            if token == "Bearer token1" {
+                rejected.wait().await;
+                return Err(RpcClientImpl::rpc_error(Status::unauthenticated(
+                    "token is expired",
+                )));
+            }
+            Ok(token)
+        };
+        let (first, second) = futures::join!(
+            client.with_auth_refresh((), &call),
+            client.with_auth_refresh((), &call)
+        );
+        assert_eq!(first.unwrap(), "Bearer token2");
+        assert_eq!(second.unwrap(), "Bearer token2");
+        assert_eq!(*provided.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_auth_on_header() {
+        // The server rejects the token by the code in the response header.
+        let (addr, service) = MockStorageService::serve(|metadata, _| {
+            if metadata.get("authorization").unwrap() == "Bearer token2" {
+                return Ok(None);
+            }
+            Ok(Some(ResponseHeader {
+                code: 401,
+                error: "token is expired".to_string(),
+            }))
+        })
+        .await;
+        let token = Arc::new(Mutex::new("token1".to_string()));
+        let provided_token = token.clone();
+        let auth = Authorization::bearer_provider(move || provided_token.lock().unwrap().clone());
+        let client = RpcClientImpl {
+            metadata: RwLock::new(Some(authorization_metadata(&auth).unwrap())),
+            ..test_client_to(&addr)
+        }
+        .with_refreshable_authorization(Some(auth));
+        let ctx = RpcContext::default().database("public".to_string());
+
+        *token.lock().unwrap() = "token2".to_string();
+        client.write(&ctx, WriteRequestPb::default()).await.unwrap();
+        assert_eq!(service.requests.lock().unwrap().len(), 2);
+        client.route(&ctx, RouteRequestPb::default()).await.unwrap();
+        client
+            .sql_query_with_meta(&ctx, SqlQueryRequestPb::default())
+            .await
+            .unwrap();
+        assert_eq!(service.requests.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_connect_error_kind() {
+        // Nothing listens on the port after the listener is dropped.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let factory = RpcClientImplFactory::new(RpcConfig::default(), None);
+        let err = factory.build(addr).await.err().unwrap();
+        assert_eq!(err.connect_error_kind(), Some(ConnectErrorKind::Refused));
+
+        let factory = RpcClientImplFactory::new(RpcConfig::default(), None).with_resolver(
+            Arc::new(StubResolver {
+                addrs: vec![],
+                hosts: Mutex::default(),
+            }),
+        );
+        let err = factory
+            .build("horaedb:8831".to_string())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.connect_error_kind(), Some(ConnectErrorKind::Dns));
+    }
+
+    #[tokio::test]
+    async fn test_pooled_channels() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let factory = RpcClientImplFactory::new(
+            RpcConfig {
+                connections_per_endpoint: 3,
+                ..Default::default()
+            },
+            None,
+        );
+        factory.build(addr.clone()).await.unwrap();
+        assert_eq!(factory.connection_stats()[&addr].connects, 3);
+
+        // The lazy channels connect on their first rpcs, and each rpc takes the
+        // next channel.
+        let counter = Arc::new(ConnectionCounter::default());
+        let lazy_channel = || {
+            let mut http_connector = HttpConnector::new();
+            http_connector.enforce_http(false);
+            Endpoint::from_shared(format!("http://{addr}"))
+                .unwrap()
+                .connect_with_connector_lazy(CountingConnector::new(
+                    http_connector,
+                    counter.clone(),
+                ))
+        };
+        let client = RpcClientImpl {
+            channels: vec![lazy_channel()],
+            default_read_timeout: Duration::from_millis(50),
+            default_write_timeout: Duration::from_millis(50),
+            ..test_client()
+        }
+        .with_pooled_channels(vec![lazy_channel(), lazy_channel()]);
+        let ctx = RpcContext::default().database("public".to_string());
+        for _ in 0..3 {
+            assert!(client.write(&ctx, WriteRequestPb::default()).await.is_err());
+        }
+        assert_eq!(counter.stats().connects, 3);
+        assert_eq!(client.next_channel.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_pool_partially_connected() {
+        let (addr, service) = MockStorageService::serve(|_, _| Ok(None)).await;
+        let connected = || {
+            Ok(Endpoint::from_shared(format!("http://{addr}"))
+                .unwrap()
+                .connect_lazy())
+        };
+        let failed = |i: usize| {
+            Err(Error::connect(
+                format!("failed{i}"),
+                io::Error::from(io::ErrorKind::ConnectionRefused),
+            ))
+        };
+
+        // The pool shrinks to the connected channels, and the rpcs go through
+        // them only.
+        let channels = super::pool_connected(vec![failed(0), connected(), failed(1)]).unwrap();
+        assert_eq!(channels.len(), 1);
+        let mut channels =
+            super::pool_connected(vec![connected(), failed(0), connected()]).unwrap();
+        assert_eq!(channels.len(), 2);
+        let client = RpcClientImpl {
+            channels: vec![channels.remove(0)],
+            ..test_client()
+        }
+        .with_pooled_channels(channels);
+        let ctx = RpcContext::default().database("public".to_string());
+        for _ in 0..4 {
+            client.write(&ctx, WriteRequestPb::default()).await.unwrap();
+        }
+        assert_eq!(service.requests.lock().unwrap().len(), 4);
+
+        // The first error is returned if none is connected.
+        let err = super::pool_connected(vec![failed(0), failed(1)]).unwrap_err();
+        assert!(matches!(err, Error::Connect { addr, .. } if addr == "failed0"));
+    }
+
+    /// Read the HTTP/2 frames sent by the client until the initial stream
+    /// window in the settings and the connection window update are found.
+    async fn read_initial_windows(conn: &mut tokio::net::TcpStream) -> (u32, u32) {
+        const PREFACE_LEN: usize = 24;
+        const SETTINGS: u8 = 0x4;
+        const WINDOW_UPDATE: u8 = 0x8;
+        const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+
+        let mut buf = Vec::new();
+        let mut stream_window = None;
+        let mut connection_window = None;
+        let mut pos = PREFACE_LEN;
+        loop {
+            while buf.len() >= pos + 9 {
+                let len = u32::from_be_bytes([0, buf[pos], buf[pos + 1], buf[pos + 2]]) as usize;
+                if buf.len() < pos + 9 + len {
+                    break;
+                }
+                let (frame_type, flags) = (buf[pos + 3], buf[pos + 4]);
+                let stream_id = u32::from_be_bytes(buf[pos + 5..pos + 9].try_into().unwrap());
+                let payload = &buf[pos + 9..pos + 9 + len];
+                match frame_type {
+                    SETTINGS if flags & 0x1 == 0 => {
+                        for param in payload.chunks(6) {
+                            let id = u16::from_be_bytes([param[0], param[1]]);
+                            if id == SETTINGS_INITIAL_WINDOW_SIZE {
+                                stream_window =
+                                    Some(u32::from_be_bytes(param[2..6].try_into().unwrap()));
+                            }
+                        }
+                    }
+                    WINDOW_UPDATE if stream_id == 0 => {
+                        connection_window =
+                            Some(u32::from_be_bytes(payload.try_into().unwrap()) + 65535);
+                    }
+                    _ => (),
+                }
+                pos += 9 + len;
+            }
+            if let (Some(stream_window), Some(connection_window)) =
+                (stream_window, connection_window)
+            {
+                return (stream_window, connection_window);
+            }
+
+            let mut chunk = [0; 1024];
+            let n = tokio::time::timeout(Duration::from_secs(1), conn.read(&mut chunk))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0, "connection is closed before the windows are sent");
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transport_tuning() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let factory = RpcClientImplFactory::new(
+            RpcConfig {
+                initial_stream_window_size: Some(4 << 20),
+                initial_connection_window_size: Some(8 << 20),
+                tcp_nodelay: Some(false),
+                tcp_keepalive: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+            None,
+        );
+        let _client = factory.build(addr.clone()).await.unwrap();
+        assert_eq!(factory.connection_stats()[&addr].connects, 1);
+
+        let (mut conn, _) = listener.accept().await.unwrap();
+        assert_eq!(read_initial_windows(&mut conn).await, (4 << 20, 8 << 20));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pending_rpc() {
+        // The server accepting no connection keeps the rpc pending.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = RpcClientImpl {
+            default_read_timeout: Duration::from_secs(10),
+            default_write_timeout: Duration::from_secs(10),
+            ..test_client_to(addr)
+        };
+
+        let token = CancellationToken::new();
+        let ctx = RpcContext::default()
+            .database("public".to_string())
+            .cancellation_token(token.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            token.cancel();
+        });
+        let start = Instant::now();
+        let err = client
+            .write(&ctx, WriteRequestPb::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_exceeded() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let recorder = Arc::new(MockRecorder::default());
+        let client = RpcClientImpl {
+            default_read_timeout: Duration::from_secs(10),
+            default_write_timeout: Duration::from_secs(10),
+            message_size_recorder: Some(recorder.clone()),
+            ..test_client_to(addr)
+        };
+
+        // The rpc is not issued after the deadline.
+        let ctx = RpcContext::default()
+            .database("public".to_string())
+            .deadline(Instant::now() - Duration::from_millis(1));
+        let err = client
+            .write(&ctx, WriteRequestPb::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DeadlineExceeded));
+        assert!(recorder.sizes.lock().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[derive(Debug)]
+    struct StubTraceContextInjector;
+
+    #[cfg(feature = "tracing")]
+    impl crate::rpc_client::TraceContextInjector for StubTraceContextInjector {
+        fn trace_context(&self) -> Option<crate::rpc_client::TraceContext> {
+            Some(crate::rpc_client::TraceContext {
+                traceparent: "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string(),
+                tracestate: Some("vendor=value".to_string()),
+            })
+        }
+    }
+
+    /// The names and the fields of the created spans.
+    #[cfg(feature = "tracing")]
+    type RecordedSpans = Arc<Mutex<Vec<(String, std::collections::HashMap<String, String>)>>>;
+
+    #[cfg(feature = "tracing")]
+    struct SpanRecorder {
+        spans: RecordedSpans,
+    }
+
+    #[cfg(feature = "tracing")]
+    #[derive(Default)]
+    struct FieldRecorder(std::collections::HashMap<String, String>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for FieldRecorder {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = FieldRecorder::default();
+            span.record(&mut fields);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((span.metadata().name().to_string(), fields.0));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_trace_rpc() {
+        use horaedbproto::storage::WriteTableRequest;
+
+        use crate::rpc_client::trace::RpcTracer;
+
+        let spans = RecordedSpans::default();
+        let _guard = tracing::subscriber::set_default(SpanRecorder {
+            spans: spans.clone(),
+        });
+        let client = test_client().with_tracer(RpcTracer::new(
+            "127.0.0.1:8831".to_string(),
+            Some(Arc::new(StubTraceContextInjector)),
+        ));
+
+        let ctx = RpcContext::default().database("public".to_string());
+        let req = WriteRequestPb {
+            context: None,
+            table_requests: vec![
+                WriteTableRequest {
+                    table: "t1".to_string(),
+                    ..Default::default()
+                },
+                WriteTableRequest {
+                    table: "t2".to_string(),
+                    ..Default::default()
+                },
+            ],
+        };
+        // The span is emitted no matter whether the rpc succeeds.
+        let _ = client.write(&ctx, req).await;
+        let spans = spans.lock().unwrap().clone();
+        let (name, fields) = spans
+            .iter()
+            .find(|(name, _)| name.starts_with("horaedb."))
+            .unwrap();
+        assert_eq!(name, "horaedb.write");
+        assert_eq!(fields["endpoint"], "127.0.0.1:8831");
+        assert_eq!(fields["database"], "public");
+        assert_eq!(fields["tables"], r#"["t1", "t2"]"#);
+
+        let custom_metadata = RpcClientImpl::custom_metadata(&ctx).unwrap();
+        let req = client.make_write_request(&ctx, &custom_metadata, ());
+        let metadata = req.metadata();
+        assert_eq!(
+            metadata.get("traceparent").unwrap(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+        );
+        assert_eq!(metadata.get("tracestate").unwrap(), "vendor=value");
+
+        // Nothing is injected without the injector.
+        let client = test_client().with_tracer(RpcTracer::new("127.0.0.1:8831".to_string(), None));
+        let req = client.make_write_request(&ctx, &custom_metadata, ());
+        assert!(req.metadata().get("traceparent").is_none());
     }
 }
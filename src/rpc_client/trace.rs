@@ -0,0 +1,112 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Trace the rpcs by the `tracing` spans, and propagate the trace context to
+//! the server.
+//!
+//! It is enabled by the `tracing` feature.
+
+use std::{fmt::Debug, sync::Arc};
+
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tracing::Span;
+
+use crate::rpc_client::{RpcContext, RpcOperation};
+
+/// The key of the metadata carrying the W3C trace parent.
+const TRACEPARENT_KEY: &str = "traceparent";
+/// The key of the metadata carrying the W3C trace state.
+const TRACESTATE_KEY: &str = "tracestate";
+
+/// The W3C trace context, see <https://www.w3.org/TR/trace-context/>.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub traceparent: String,
+    pub tracestate: Option<String>,
+}
+
+/// Provider of the current trace context injected into each rpc, which can be
+/// registered by
+/// [`Builder::trace_context_injector`](crate::Builder::trace_context_injector),
+/// e.g. the one reading the OpenTelemetry context of the current span.
+pub trait TraceContextInjector: Debug + Send + Sync {
+    /// The trace context of the current rpc, and nothing is injected if it is
+    /// `None`.
+    fn trace_context(&self) -> Option<TraceContext>;
+}
+
+/// Tracer of the rpcs to one endpoint.
+pub(crate) struct RpcTracer {
+    endpoint: String,
+    injector: Option<Arc<dyn TraceContextInjector>>,
+}
+
+impl RpcTracer {
+    pub fn new(endpoint: String, injector: Option<Arc<dyn TraceContextInjector>>) -> Self {
+        Self { endpoint, injector }
+    }
+
+    /// The span of the rpc, named by the operation.
+    pub fn span<'a>(
+        &self,
+        operation: RpcOperation,
+        ctx: &RpcContext,
+        tables: impl Iterator<Item = &'a str>,
+    ) -> Span {
+        let tables: Vec<_> = tables.collect();
+        let database = ctx.database.as_deref().unwrap_or_default();
+        match operation {
+            RpcOperation::SqlQuery => tracing::info_span!(
+                "horaedb.sql_query",
+                endpoint = %self.endpoint,
+                database,
+                tables = ?tables,
+            ),
+            RpcOperation::Write => tracing::info_span!(
+                "horaedb.write",
+                endpoint = %self.endpoint,
+                database,
+                tables = ?tables,
+            ),
+            RpcOperation::Route => tracing::info_span!(
+                "horaedb.route",
+                endpoint = %self.endpoint,
+                database,
+                tables = ?tables,
+            ),
+        }
+    }
+
+    /// Inject the trace context into the `metadata`, and the invalid values
+    /// are skipped.
+    pub fn inject(&self, metadata: &mut MetadataMap) {
+        let trace_context = match self.injector.as_ref().and_then(|i| i.trace_context()) {
+            Some(trace_context) => trace_context,
+            None => return,
+        };
+
+        if let Ok(value) = trace_context.traceparent.parse::<AsciiMetadataValue>() {
+            metadata.insert(TRACEPARENT_KEY, value);
+        }
+        if let Some(Ok(value)) = trace_context
+            .tracestate
+            .map(|tracestate| tracestate.parse::<AsciiMetadataValue>())
+        {
+            metadata.insert(TRACESTATE_KEY, value);
+        }
+    }
+}
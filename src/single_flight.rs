@@ -0,0 +1,208 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::{future::Future, hash::Hash};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::{
+    channel::oneshot,
+    future::{join_all, BoxFuture, Shared},
+    FutureExt,
+};
+
+type SharedWork<V> = Shared<BoxFuture<'static, V>>;
+
+/// Deduplicate the concurrent executions of the same work.
+///
+/// The callers running the work with the same key while it is in flight will
+/// share its output instead of executing it again. Once the work is finished,
+/// the key is released and the next call will execute the work again.
+pub(crate) struct SingleFlight<K, V: Clone> {
+    in_flight: DashMap<K, SharedWork<V>>,
+}
+
+/// Release the keys of the execution once it is finished or the caller is
+/// dropped.
+struct Release<'a, K: Eq + Hash, V: Clone> {
+    in_flight: &'a DashMap<K, SharedWork<V>>,
+    keys: Vec<K>,
+    work: SharedWork<V>,
+}
+
+impl<K: Eq + Hash, V: Clone> Drop for Release<'_, K, V> {
+    fn drop(&mut self) {
+        for key in &self.keys {
+            self.in_flight
+                .remove_if(key, |_, in_flight| in_flight.ptr_eq(&self.work));
+        }
+    }
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            in_flight: DashMap::new(),
+        }
+    }
+
+    /// Execute `work` for `key`, or join the in flight execution of the same
+    /// `key` if any.
+    pub async fn run<F, Fut>(&self, key: K, work: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V> + Send + 'static,
+    {
+        let shared = self
+            .in_flight
+            .entry(key.clone())
+            .or_insert_with(|| work().boxed().shared())
+            .clone();
+        // The key is released even if the caller is dropped before finished,
+        // e.g. cancelled or timed out, otherwise the half polled work would be
+        // left in flight and joined by the later calls.
+        let _release = Release {
+            in_flight: &self.in_flight,
+            keys: vec![key],
+            work: shared.clone(),
+        };
+
+        shared.await
+    }
+
+    /// Execute `work` once for all the `keys` not in flight, and join the in
+    /// flight executions of the others.
+    ///
+    /// The `work` is given the keys it executes for, and the output of the
+    /// execution each key belongs to is returned in the order of `keys`.
+    pub async fn run_many<F, Fut>(&self, keys: &[K], work: F) -> Vec<V>
+    where
+        K: Send + 'static,
+        F: FnOnce(Vec<K>) -> Fut + Send + 'static,
+        Fut: Future<Output = V> + Send + 'static,
+    {
+        let (keys_tx, keys_rx) = oneshot::channel();
+        let own_work = async move {
+            // The keys are sent right after they are taken.
+            let keys = keys_rx.await.unwrap_or_default();
+            work(keys).await
+        }
+        .boxed()
+        .shared();
+
+        let mut own_keys = Vec::new();
+        let shared: Vec<_> = keys
+            .iter()
+            .map(|key| match self.in_flight.entry(key.clone()) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => {
+                    own_keys.push(key.clone());
+                    entry.insert(own_work.clone()).clone()
+                }
+            })
+            .collect();
+        let _ = keys_tx.send(own_keys.clone());
+        let _release = Release {
+            in_flight: &self.in_flight,
+            keys: own_keys,
+            work: own_work,
+        };
+
+        join_all(shared).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use futures::future::join_all;
+
+    use super::SingleFlight;
+
+    #[tokio::test]
+    async fn test_share_in_flight_work() {
+        let single_flight = SingleFlight::new();
+        let executed = Arc::new(AtomicUsize::new(0));
+
+        let runs = (0..10).map(|_| {
+            let executed = executed.clone();
+            single_flight.run("key", move || async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                executed.fetch_add(1, Ordering::SeqCst) + 1
+            })
+        });
+        let outputs = join_all(runs).await;
+
+        assert_eq!(executed.load(Ordering::SeqCst), 1);
+        assert!(outputs.iter().all(|output| *output == 1));
+
+        // The key is released after finished, so it will be executed again.
+        let executed_clone = executed.clone();
+        let output = single_flight
+            .run("key", move || async move {
+                executed_clone.fetch_add(1, Ordering::SeqCst) + 1
+            })
+            .await;
+        assert_eq!(output, 2);
+        assert!(single_flight.in_flight.is_empty());
+
+        // The key is released if the caller is dropped.
+        let run = single_flight.run("key", || async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            0
+        });
+        let _ = tokio::time::timeout(Duration::from_millis(10), run).await;
+        assert!(single_flight.in_flight.is_empty());
+
+        // So the next call executes the work again rather than joining the
+        // dropped one.
+        let output = single_flight.run("key", || async { 3 }).await;
+        assert_eq!(output, 3);
+    }
+
+    #[tokio::test]
+    async fn test_share_in_flight_keys() {
+        let single_flight = SingleFlight::new();
+        let work = |keys: Vec<&'static str>| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            keys.join(",")
+        };
+
+        let (first, second) = futures::join!(
+            single_flight.run_many(&["a", "b"], work),
+            single_flight.run_many(&["b", "c", "a"], work)
+        );
+        assert_eq!(first, ["a,b", "a,b"]);
+        assert_eq!(second, ["a,b", "c", "a,b"]);
+        assert!(single_flight.in_flight.is_empty());
+
+        // The keys are released if the caller is dropped.
+        let run = single_flight.run_many(&["a"], work);
+        let _ = tokio::time::timeout(Duration::from_millis(10), run).await;
+        assert!(single_flight.in_flight.is_empty());
+    }
+}
@@ -15,12 +15,24 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tonic::Code;
+
+use crate::Error;
+
 /// Server status code
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 pub enum StatusCode {
     Ok = 200,
     InvalidArgument = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
     NotFound = 404,
     TooManyRequests = 429,
     InternalError = 500,
@@ -37,9 +49,37 @@ pub fn is_ok(code: u32) -> bool {
     code == StatusCode::Ok.as_u32()
 }
 
+/// A random number within `[0, 1)`, which is not cryptographically secure.
+pub(crate) fn random_ratio() -> f64 {
+    // Each `RandomState` is seeded differently, and the counter tells apart the
+    // ones created in a row whose seeds may be close.
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Tell whether the error is caused by the transport, e.g. the server is
+/// restarting, and the request may succeed if retried.
+///
+/// The [`Error::CircuitOpen`] is included, so that the request is retried on
+/// an alternative endpoint.
+pub(crate) fn is_transport_error(err: &Error) -> bool {
+    match err {
+        Error::Rpc(status) => matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded),
+        Error::Connect { .. } | Error::CircuitOpen { .. } => true,
+        _ => false,
+    }
+}
+
 // TODO may change in future.
 #[inline]
 pub fn should_refresh(code: u32, msg: &str) -> bool {
+    is_table_not_found(code, msg)
+}
+
+#[inline]
+pub fn is_table_not_found(code: u32, msg: &str) -> bool {
     code == StatusCode::InvalidArgument.as_u32()
         && msg.contains("Table")
         && msg.contains("not found")